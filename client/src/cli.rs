@@ -0,0 +1,488 @@
+//! `swap-cli recover <trade_id>`
+//!
+//! Inspect a persisted trade (client or server side), explain what happened
+//! and run the chosen recovery step.
+//!
+//! `swap-cli lock-proof <trade_id> <txid> <tx_key>`
+//!
+//! Record the XMR lock transaction and its key (`get_tx_key`) so the client
+//! forwards them to Bob.
+//!
+//! `swap-cli transcript <trade_id>`
+//!
+//! Print every transition applied to the trade as json, see `Transcript`.
+//!
+//! `swap-cli presigned <trade_id>`
+//!
+//! Print the signed contract spends kept with the trade as hex, each after
+//! its kind, for any node or explorer to relay. See `PresignedTxs`.
+//!
+//! `swap-cli statechart [dot|mermaid]`
+//!
+//! Print the Alice and Bob state machines as a graph, mermaid by default.
+
+use std::{
+    env,
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context};
+use protocol::{
+    alice,
+    bitcoincash::Transaction,
+    blockchain::{
+        backend::ChainBackend, bchn::BchnRpc, policy::MempoolPolicy, transport::CertVerification,
+        ElectrumConfig, TcpElectrum,
+    },
+    bob, monero,
+    monero_config::MoneroConfig,
+    monero_rpc,
+    persist::TradePersist,
+    protocol::{describe_timelock, SwapEvents, SwapWrapper, Transition, XmrLockProof},
+    statechart,
+    xmr_wallet::{self, FeePriority, SweepAmount, XmrRecovery},
+};
+use tokio::{fs, sync::Mutex};
+
+const USAGE: &str =
+    "Usage: swap-cli recover <trade_id> | swap-cli lock-proof <trade_id> <txid> <tx_key> | swap-cli transcript <trade_id> | swap-cli presigned <trade_id> | swap-cli statechart [dot|mermaid]";
+
+fn env_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or(default.to_owned())
+}
+
+fn find_trade_file(trade_id: &str) -> Option<String> {
+    ["client", "server"]
+        .iter()
+        .map(|side| format!("./.trades/ongoing/{trade_id}-{side}.json"))
+        .find(|path| Path::new(path).is_file())
+}
+
+fn ask(options: &[&str]) -> anyhow::Result<usize> {
+    println!();
+    for (i, option) in options.iter().enumerate() {
+        println!("  [{}] {}", i + 1, option);
+    }
+
+    loop {
+        print!("Choose an option: ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        match line.trim().parse::<usize>() {
+            Ok(n) if n >= 1 && n <= options.len() => return Ok(n - 1),
+            _ => println!("Invalid option"),
+        }
+    }
+}
+
+async fn broadcast(bch: &dyn ChainBackend, tx: &Transaction) -> anyhow::Result<()> {
+    println!("Broadcasting {}", tx.txid());
+    let txid = bch.broadcast(tx).await?;
+    println!("Broadcasted {txid}");
+    Ok(())
+}
+
+async fn sweep_xmr(
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    trade_id: &str,
+    network: monero::Network,
+    keypair: monero::KeyPair,
+    restore_height: u64,
+) -> anyhow::Result<()> {
+    print!("Destination xmr address: ");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let destination: monero::Address = line.trim().parse()?;
+
+    let amount = match ask(&["Everything, fee deducted", "An exact amount, fee on top"])? {
+        0 => SweepAmount::All,
+        _ => {
+            print!("Amount in xmr: ");
+            io::stdout().flush()?;
+            let mut line = String::new();
+            io::stdin().read_line(&mut line)?;
+            SweepAmount::Exact(monero::Amount::from_str_in(
+                line.trim(),
+                monero::Denomination::Monero,
+            )?)
+        }
+    };
+
+    println!("Fee priority:");
+    let priority = match ask(&["Default", "Low (slower, cheaper)", "High (faster)"])? {
+        0 => FeePriority::Default,
+        1 => FeePriority::Low,
+        _ => FeePriority::High,
+    };
+
+    let txids = xmr_wallet::sweep(
+        wallet,
+        &format!("{trade_id}_recover"),
+        network,
+        keypair,
+        restore_height,
+        destination,
+        amount,
+        priority,
+        |estimate| {
+            println!(
+                "Sweeping {} to {destination}, fee {}",
+                estimate.amount, estimate.fee
+            );
+            matches!(ask(&["Broadcast", "Cancel"]), Ok(0))
+        },
+    )
+    .await?;
+    match txids.is_empty() {
+        true => println!("Sweep cancelled"),
+        false => println!("Sweep txs: {txids:?}"),
+    }
+    Ok(())
+}
+
+fn print_xmr_keys(keypair: &monero::KeyPair, address: &monero::Address, restore_height: u64) {
+    println!("Address: {address}");
+    println!("Spend key: {}", keypair.spend);
+    println!("View key: {}", keypair.view);
+    println!("Restore height: {restore_height}");
+}
+
+async fn export_recovery(recovery: Option<XmrRecovery>) -> anyhow::Result<()> {
+    let recovery = recovery.context("No xmr to recover in this state")?;
+    let path = format!("./.trades/{}-xmr-recovery.json", recovery.trade_id);
+    fs::write(&path, recovery.to_json()).await?;
+    println!("Recovery document written to {path}");
+    Ok(())
+}
+
+async fn print_recoverable(
+    recovery: Option<XmrRecovery>,
+    wallet: &Mutex<monero_rpc::WalletClient>,
+) -> anyhow::Result<()> {
+    let recovery = recovery.context("No xmr to recover in this state")?;
+    println!(
+        "Restoring the wallet from height {}...",
+        recovery.restore_height
+    );
+    let balance = recovery.restore(wallet).await?;
+    println!("Balance: {}", balance.balance);
+    println!("Spendable now: {}", balance.unlocked_balance);
+    Ok(())
+}
+
+async fn recover_alice(
+    mut runner: alice::Runner<'_>,
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    trade_id: &str,
+) -> anyhow::Result<alice::Alice> {
+    let _ = runner.check_bch().await;
+    let network = runner.inner.swap.xmr_network;
+
+    println!("State: {}", runner.inner.state);
+    match runner.inner.state.clone() {
+        alice::State::Init | alice::State::WithBobKeys(_) | alice::State::ContractMatch(_) => {
+            println!("Bob did not lock bch and you did not need to lock xmr.");
+            println!("Nothing is at risk, the trade can be deleted.");
+            if ask(&["Abort the trade", "Exit"])? == 0 {
+                runner.priv_transition(Transition::Abort).await?;
+                println!("Aborted. The client tells Bob with its next message.");
+            }
+        }
+        alice::State::Aborted => {
+            println!("The trade was aborted before anything was locked. It can be deleted.");
+        }
+        alice::State::BchLocked(_) => {
+            println!("Bob locked bch on the SwapLock contract.");
+            println!("If you already locked xmr, wait for Bob to send the encrypted signature.");
+            println!("If Bob refunds after the timelock, his spend key is revealed and you can sweep the xmr back.");
+            println!("If he stops halfway, the bch can be claimed once the Refund contract timelock expires.");
+            println!("Rerun this command after new blocks to rescan.");
+        }
+        alice::State::Punish(_) => {
+            println!("Bob did not finish his refund before the timelock. The bch can be claimed.");
+            println!("The xmr stays locked, neither side can spend it.");
+            if ask(&["Broadcast punish transaction", "Exit"])? == 0 {
+                let txs = runner.inner.punish();
+                if txs.is_empty() {
+                    bail!("punish tx not available");
+                }
+                for tx in txs {
+                    broadcast(runner.bch, &tx).await?;
+                }
+            }
+        }
+        alice::State::ValidEncSig(_) => {
+            println!("You have a valid signature to claim the bch.");
+            if ask(&["Broadcast claim transaction", "Exit"])? == 0 {
+                let txs = runner.inner.get_unlock_normal_txs();
+                if txs.is_empty() {
+                    bail!("claim tx not available");
+                }
+                for tx in txs {
+                    broadcast(runner.bch, &tx).await?;
+                }
+            }
+        }
+        alice::State::Refund(address, keypair) => {
+            println!("Bob refunded the bch, revealing his xmr spend key.");
+            println!("The shared xmr wallet is fully controlled by you.");
+            match ask(&[
+                "Sweep xmr to an address",
+                "Show wallet keys",
+                "Export recovery document",
+                "Check recoverable balance",
+                "Exit",
+            ])? {
+                0 => sweep_xmr(wallet, trade_id, network, keypair, 0).await?,
+                1 => print_xmr_keys(&keypair, &address, 0),
+                2 => export_recovery(runner.inner.get_xmr_recovery()).await?,
+                3 => print_recoverable(runner.inner.get_xmr_recovery(), wallet).await?,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(runner.inner)
+}
+
+async fn recover_bob(mut runner: bob::Runner<'_>, trade_id: &str) -> anyhow::Result<bob::Bob> {
+    let _ = runner.check_bch().await;
+    let network = runner.inner.swap.xmr_network;
+
+    println!("State: {}", runner.inner.state);
+    if let Some(payout) = runner.inner.get_xmr_payout_recovery() {
+        match runner.inner.xmr_payout_txids.is_empty() {
+            true => println!("The xmr will be swept to the wallet generated for the trade."),
+            false => println!("The xmr was swept to the wallet generated for the trade."),
+        }
+        println!("Payout wallet: {}", payout.address);
+    }
+    match runner.inner.state.clone() {
+        bob::State::Init | bob::State::WithAliceKey(_) | bob::State::ContractMatch(_) => {
+            println!("You did not lock bch yet. Nothing is at risk, the trade can be deleted.");
+            if ask(&["Abort the trade", "Exit"])? == 0 {
+                runner.priv_transition(Transition::Abort).await?;
+                println!("Aborted. Alice is told with the next message.");
+            }
+        }
+        bob::State::Aborted(_) => {
+            println!("The trade was aborted before anything was locked. It can be deleted.");
+        }
+        bob::State::VerifiedEncSig(_) => {
+            println!("You may have locked bch, Alice did not lock xmr yet.");
+            println!(
+                "{} after the SwapLock funding, the refund path opens automatically.",
+                describe_timelock(runner.inner.swap.timelock1)
+            );
+            println!("Rerun this command after new blocks to rescan.");
+        }
+        bob::State::MoneroLocked(_) => {
+            println!("Alice locked xmr, waiting for her to claim the bch.");
+            println!("Her claim reveals the key you need for the xmr.");
+        }
+        bob::State::ProceedRefund(_) => {
+            println!("The timelock expired. The bch can be refunded.");
+            if ask(&["Broadcast refund transactions", "Exit"])? == 0 {
+                let refunds = runner.inner.refund().context("refund not available")?;
+                for (tx1, tx2) in refunds {
+                    broadcast(runner.bch, &tx1).await?;
+                    broadcast(runner.bch, &tx2).await?;
+                }
+            }
+        }
+        bob::State::SwapSuccess(keypair, address, restore_height) => {
+            println!("Alice claimed the bch. The shared xmr wallet is fully controlled by you.");
+            match ask(&[
+                "Sweep xmr to an address",
+                "Show wallet keys",
+                "Export recovery document",
+                "Check recoverable balance",
+                "Exit",
+            ])? {
+                0 => {
+                    sweep_xmr(
+                        runner.monero_wallet,
+                        trade_id,
+                        network,
+                        keypair,
+                        restore_height,
+                    )
+                    .await?
+                }
+                1 => print_xmr_keys(&keypair, &address, restore_height),
+                2 => {
+                    export_recovery(runner.inner.get_xmr_recovery()).await?;
+                    if let Some(payout) = runner.inner.get_xmr_payout_recovery() {
+                        export_recovery(Some(payout)).await?;
+                    }
+                }
+                3 => {
+                    if let Some(balance) = runner.recover_xmr().await? {
+                        println!("Balance: {}", balance.balance);
+                        println!("Spendable now: {}", balance.unlocked_balance);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(runner.inner)
+}
+
+async fn print_transcript(trade_id: &str) -> anyhow::Result<()> {
+    let file_path = find_trade_file(trade_id).context("Trade not found")?;
+    let trade = TradePersist::restore(file_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+    let transcript = match trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.transcript,
+        SwapWrapper::Alice(alice) => alice.transcript,
+    };
+    println!("{}", transcript.to_json());
+    Ok(())
+}
+
+async fn print_presigned(trade_id: &str) -> anyhow::Result<()> {
+    let file_path = find_trade_file(trade_id).context("Trade not found")?;
+    let trade = TradePersist::restore(file_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+    let presigned = match trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.presigned,
+        SwapWrapper::Alice(alice) => alice.presigned,
+    };
+    if presigned.is_empty() {
+        bail!("No signed transaction yet");
+    }
+    for (name, txs) in [("refund", presigned.refund), ("claim", presigned.claims)] {
+        for tx in txs {
+            println!("{name} {tx}");
+        }
+    }
+    Ok(())
+}
+
+async fn set_lock_proof(trade_id: &str, proof: XmrLockProof) -> anyhow::Result<()> {
+    let file_path = find_trade_file(trade_id).context("Trade not found")?;
+    let mut trade = TradePersist::restore(file_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+    let alice = match trade.config.swap {
+        SwapWrapper::Alice(alice) => alice,
+        SwapWrapper::Bob(_) => bail!("Only the XMR sender has a lock proof"),
+    };
+    let (alice, _, error) = alice.transition(Transition::XmrLockProof(proof));
+    if let Some(error) = error {
+        bail!("Can't record the proof in state {}: {error}", alice.state);
+    }
+
+    trade.config.swap = SwapWrapper::Alice(alice);
+    trade.save().await;
+    println!("Lock proof recorded, it is sent with the next client poll");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let mut args = env::args().skip(1);
+    let trade_id = match (args.next().as_deref(), args.next()) {
+        (Some("recover"), Some(trade_id)) => trade_id,
+        (Some("lock-proof"), Some(trade_id)) => {
+            let (txid, tx_key) = match (args.next(), args.next()) {
+                (Some(txid), Some(tx_key)) => (txid, tx_key),
+                _ => bail!(USAGE),
+            };
+            return set_lock_proof(&trade_id, XmrLockProof { txid, tx_key }).await;
+        }
+        (Some("transcript"), Some(trade_id)) => return print_transcript(&trade_id).await,
+        (Some("presigned"), Some(trade_id)) => return print_presigned(&trade_id).await,
+        (Some("statechart"), format) => {
+            let format = format.as_deref().unwrap_or("mermaid");
+            let format = format.parse().map_err(|e: String| anyhow::anyhow!(e))?;
+            print!("{}", statechart::statechart(format));
+            return Ok(());
+        }
+        _ => bail!(USAGE),
+    };
+
+    let fullcrum_tcp = env_or("ELECTRUM", "localhost:50001");
+    // MONERO_NETWORK, MONEROD.. see `MoneroConfig::from_env`
+    let monero_config = MoneroConfig::from_env()?;
+    let monero_wallet_addr = env_or("MONERO_WALLET_RPC", "http://localhost:8081");
+
+    // ===================================================
+
+    let file_path = find_trade_file(&trade_id).context("Trade not found")?;
+    let mut trade = TradePersist::restore(file_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("{:?}", e))?;
+
+    // a full node replaces electrum when BCHN_RPC is set
+    let bch: Box<dyn ChainBackend> = match env::var("BCHN_RPC") {
+        Ok(url) => Box::new(BchnRpc::new(
+            url,
+            env_or("BCHN_RPC_USER", ""),
+            env_or("BCHN_RPC_PASSWORD", ""),
+        )),
+        Err(_) => {
+            let mut electrum_config = ElectrumConfig {
+                // SOCKS5_PROXY, e.g. tor at 127.0.0.1:9050
+                proxy: monero_config.proxy.clone(),
+                ..Default::default()
+            };
+            if let Ok(fingerprint) = env::var("ELECTRUM_CERT_SHA256") {
+                electrum_config.cert_verification = CertVerification::pinned(&fingerprint)?;
+            }
+            Box::new(TcpElectrum::connect_with(&fullcrum_tcp, electrum_config).await?)
+        }
+    };
+    let monerod = monero_config.monerod()?;
+    let monero_wallet = Mutex::new(monero_config.wallet(&monero_wallet_addr)?);
+
+    trade.config.swap = match trade.config.swap {
+        SwapWrapper::Alice(inner) => {
+            let runner = alice::Runner {
+                inner,
+                bch: bch.as_ref(),
+                monero_wallet: None,
+                xmr_lock_options: Default::default(),
+                min_bch_conf: 1,
+                zero_conf: false,
+                mempool_policy: Some(MempoolPolicy::default()),
+                store: None,
+                events: None,
+                effects: None,
+                clock: None,
+            };
+            SwapWrapper::Alice(recover_alice(runner, &monero_wallet, &trade_id).await?)
+        }
+        SwapWrapper::Bob(inner) => {
+            let runner = bob::Runner {
+                inner,
+                trade_id: trade_id.clone(),
+                bch: bch.as_ref(),
+                monerod: &monerod,
+                monero_wallet: &monero_wallet,
+                xmr_scanner: None,
+                min_bch_conf: 1,
+                mempool_policy: Some(MempoolPolicy::default()),
+                store: None,
+                events: None,
+                effects: None,
+                clock: None,
+            };
+            SwapWrapper::Bob(recover_bob(runner, &trade_id).await?)
+        }
+    };
+    trade.save().await;
+
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    env,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::bail;
 use reqwest::StatusCode;
@@ -7,35 +12,55 @@ use serde_json::json;
 use protocol::{
     alice,
     bitcoincash::{self},
-    blockchain::{self},
-    keys::{
-        bitcoin::{self, random_private_key},
-        KeyPrivate,
-    },
+    blockchain::{self, fee::fee_estimator, policy::MempoolPolicy, Notification},
+    bob,
+    contract::token::CashToken,
+    keys::bitcoin::{self, random_private_key},
     monero::{self},
-    persist::{Config, TradePersist},
+    monero_config::{MoneroConfig, MoneroNetwork},
+    outbound::{Outbound, Outbox},
+    persist::{Config, FileStore, TradePersist, SCHEMA_VERSION},
     protocol::Swap,
-    protocol::{SwapEvents, SwapWrapper, Transition},
+    protocol::{
+        parse_timelocks, xmr_restore_height, OperatorFee, SignedTransition, SwapWrapper,
+        DEFAULT_XMR_CONFIRMATIONS,
+    },
+    shutdown::{pending_swaps, Shutdown},
+    watchdog::Watchdog,
+    xmr_wallet::{FeePriority, LockOptions},
 };
-use tokio::{fs, io::AsyncWriteExt, net::TcpStream, time::sleep};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
 
 const BASE_URL: &str = "http://localhost:8080";
 
 async fn create_new_trade(
     client: &reqwest::Client,
+    path: &str,
     timelock1: u32,
     timelock2: u32,
     bch_amount: bitcoincash::Amount,
     xmr_amount: monero::Amount,
+    mining_fee: u64,
+    mining_fee_rate: u64,
+    key_commitment: bool,
+    bch_token: Option<&CashToken>,
+    operator_fee: Option<&OperatorFee>,
+    refund_expiry: Option<u32>,
 ) -> anyhow::Result<String> {
     let response = client
         .post(format!("{BASE_URL}/trader"))
         .json(&json!({
-           "path": "xmr->bch",
+           "path": path,
            "timelock1": timelock1,
            "timelock2": timelock2,
            "bch_amount": bch_amount.to_sat(),
-           "xmr_amount": xmr_amount.as_pico()
+           "xmr_amount": xmr_amount.as_pico(),
+           "mining_fee": mining_fee,
+           "mining_fee_rate": mining_fee_rate,
+           "key_commitment": key_commitment,
+           "bch_token": bch_token,
+           "operator_fee": operator_fee,
+           "refund_expiry": refund_expiry
         }))
         .send()
         .await?;
@@ -55,14 +80,14 @@ async fn create_new_trade(
 async fn get_server_transition(
     client: &reqwest::Client,
     trade_id: &str,
-) -> anyhow::Result<Option<Transition>> {
+) -> anyhow::Result<Option<SignedTransition>> {
     let response = client
         .get(format!("{BASE_URL}/trader/{trade_id}"))
         .send()
         .await?;
 
     match response.status() {
-        StatusCode::OK => Ok(response.json::<Option<Transition>>().await?),
+        StatusCode::OK => Ok(response.json::<Option<SignedTransition>>().await?),
         code => {
             let body = response.text().await?;
             bail!("[ERROR]: {code} - {body}");
@@ -70,10 +95,12 @@ async fn get_server_transition(
     }
 }
 
+/// `swap_id` ours, checked against the one the server derived
 async fn send_transition(
     client: &reqwest::Client,
     trade_id: &str,
-    transition: &Transition,
+    transition: &SignedTransition,
+    swap_id: Option<&str>,
 ) -> anyhow::Result<()> {
     let response = client
         .patch(format!("{BASE_URL}/trader/{trade_id}"))
@@ -82,7 +109,15 @@ async fn send_transition(
         .await?;
 
     match response.status() {
-        StatusCode::OK => Ok(()),
+        StatusCode::OK => {
+            let body = response.json::<serde_json::Value>().await?;
+            match (swap_id, body["swap_id"].as_str()) {
+                (Some(ours), Some(theirs)) if ours != theirs => {
+                    bail!("[ERROR] swap id {theirs} of the server, ours is {ours}")
+                }
+                _ => Ok(()),
+            }
+        }
         code => {
             let body = response.text().await?;
             bail!("[ERROR] {code} - {body}");
@@ -90,6 +125,30 @@ async fn send_transition(
     }
 }
 
+/// Send the message of `state` when `outbox` says it is due
+async fn send_outbound(
+    client: &reqwest::Client,
+    trade_id: &str,
+    outbox: &mut Outbox,
+    state: String,
+    transition: Option<SignedTransition>,
+    swap_id: Option<String>,
+) {
+    match outbox.poll(&state, transition, Instant::now()) {
+        Outbound::Send(transition) => {
+            let sent = send_transition(client, trade_id, &transition, swap_id.as_deref()).await;
+            if let Err(e) = sent {
+                println!("{:?}", e);
+            }
+        }
+        Outbound::Exhausted {
+            transition,
+            attempts,
+        } => println!("[WARN] {transition} unanswered after {attempts} attempts, not sent again"),
+        Outbound::Idle => {}
+    }
+}
+
 pub fn get_file_path(trade_id: &str) -> String {
     format!("./.trades/ongoing/{trade_id}-client.json")
 }
@@ -97,16 +156,57 @@ pub fn get_file_path(trade_id: &str) -> String {
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let bch_min_confirmation = 1;
+    // Accept unconfirmed SwapLock funding, aborting on double spend proof
+    let zero_conf = false;
 
     let fullcrum_tcp = "localhost:50001";
-    let monero_network = monero::Network::Mainnet;
+    // MONERO_NETWORK=stagenet|testnet|regtest, mainnet by default
+    let monero_config = MoneroConfig::from_env()?;
+    let monero_network = monero_config.network.network();
+    // SWAP_PATH=bch->xmr to lock BCH for the server XMR, the client plays Bob then.
+    // xmr->bch by default, the client locks XMR as Alice
+    let swap_path = env::var("SWAP_PATH").unwrap_or("xmr->bch".to_owned());
+    let bch_to_xmr = match swap_path.as_str() {
+        "xmr->bch" => false,
+        "bch->xmr" => true,
+        path => bail!("Unknown swap path {path}"),
+    };
+    // MONERO_WALLET_RPC=http://localhost:18083 sends the xmr lock from the wallet
+    // open there, it is printed for a manual transfer otherwise.
+    // On bch->xmr the XMR is received there instead
+    let monero_wallet = match env::var("MONERO_WALLET_RPC") {
+        Ok(url) => Some(Arc::new(Mutex::new(monero_config.wallet(&url)?))),
+        Err(_) => None,
+    };
+    // XMR_FEE_PRIORITY=low|high and XMR_RING_SIZE of the lock
+    let xmr_lock_options = LockOptions {
+        priority: match env::var("XMR_FEE_PRIORITY").as_deref() {
+            Ok("low") => FeePriority::Low,
+            Ok("high") => FeePriority::High,
+            _ => FeePriority::Default,
+        },
+        ring_size: env::var("XMR_RING_SIZE")
+            .ok()
+            .and_then(|size| size.parse().ok()),
+    };
     let bch_network = bitcoin::Network::Regtest;
+    if bch_to_xmr && monero_wallet.is_none() {
+        bail!("MONERO_WALLET_RPC is required to receive the XMR");
+    }
+    let monerod = Arc::new(monero_config.monerod()?);
+    let store = Arc::new(FileStore::new("./.trades/checkpoints/client"));
+    // SWAP_SILENCE_TIMEOUT=minutes the server may stay silent in a state, 30 by default
+    let watchdog = match env::var("SWAP_SILENCE_TIMEOUT") {
+        Ok(minutes) => Watchdog {
+            timeout: Duration::from_secs(minutes.parse::<u64>()? * 60),
+        },
+        Err(_) => Watchdog::default(),
+    };
 
     // ===================================================
 
     let req_client = reqwest::Client::new();
-    let socket = TcpStream::connect(fullcrum_tcp).await?;
-    let bch_server = Arc::new(blockchain::TcpElectrum::new(socket));
+    let bch_server = Arc::new(blockchain::TcpElectrum::connect(fullcrum_tcp).await?);
 
     println!("Subscribing for new block");
     let _ = bch_server
@@ -121,55 +221,189 @@ async fn main() -> anyhow::Result<()> {
     let recv_addr = recv_pub.pubkey_hash();
     let recv_script = bitcoincash::Script::new_p2pkh(&recv_addr);
 
-    let timelock1 = 20;
-    let timelock2 = 20;
+    // SWAP_TIMELOCKS as given to the server, e.g. 20,40 or 2h,4h
+    let (timelock1, timelock2) = match env::var("SWAP_TIMELOCKS") {
+        Ok(timelocks) => parse_timelocks(&timelocks).map_err(anyhow::Error::msg)?,
+        Err(_) => (20, 40),
+    };
 
     let bch_amount = bitcoincash::Amount::from_sat(100000);
     let xmr_amount = monero::Amount::from_pico(100000);
 
-    let swap = alice::Alice {
-        state: alice::State::Init,
-        swap: Swap {
-            id: "".to_owned(),
-            keys: KeyPrivate::random(bch_network),
-
-            bch_amount,
-            xmr_amount,
-
-            xmr_network: monero_network,
-            bch_network,
-
-            bch_recv: recv_script,
+    let fee = fee_estimator(&bch_server, 6).await?;
+    let (mining_fee, mining_fee_rate) = (fee.mining_fee(), fee.fee_rate());
+    println!(
+        "Fee rate: {:.2} sat/byte. Mining fee: up to {mining_fee}",
+        fee.sat_per_byte
+    );
+
+    // only bob waits for these, before anything of the trade can reach the chain
+    let xmr_restore_height = match bch_to_xmr {
+        true => xmr_restore_height(monerod.block_count().await?),
+        false => 0,
+    };
+    // SWAP_KEY_COMMITMENT=1 commits to the keys before exchanging them
+    let key_commitment = env::var("SWAP_KEY_COMMITMENT").is_ok();
+    // BCH_TOKEN=category:amount swaps fungible CashTokens along with bch_amount
+    let bch_token = match env::var("BCH_TOKEN") {
+        Ok(token) => Some(token.parse::<CashToken>().map_err(anyhow::Error::msg)?),
+        Err(_) => None,
+    };
+    // OPERATOR_FEE as on the server, it refuses trades without its fee
+    let operator_fee = match env::var("OPERATOR_FEE") {
+        Ok(fee) => Some(fee.parse::<OperatorFee>().map_err(anyhow::Error::msg)?),
+        Err(_) => None,
+    };
+    // REFUND_EXPIRY=seconds from now, after which Alice may punish a refund
+    // Bob left unfinished, whenever he moved the BCH to the Refund contract
+    let refund_expiry = match env::var("REFUND_EXPIRY") {
+        Ok(seconds) => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            Some(u32::try_from(now + seconds.parse::<u64>()?)?)
+        }
+        Err(_) => None,
+    };
+    let xmr_confirmations = match monero_config.network {
+        MoneroNetwork::Mainnet => DEFAULT_XMR_CONFIRMATIONS,
+        _ => 1,
+    };
 
-            timelock1,
-            timelock2,
-        },
+    let mut builder = Swap::builder("".to_owned(), bch_network, monero_network)
+        .amounts(bch_amount, xmr_amount)
+        .timelocks(timelock1, timelock2)
+        .bch_recv(recv_script)
+        .mining_fee(mining_fee)
+        .mining_fee_rate(mining_fee_rate)
+        .xmr_confirmations(xmr_confirmations)
+        .xmr_restore_height(xmr_restore_height)
+        .key_commitment(key_commitment)
+        .bch_token(bch_token.clone())
+        .operator_fee(operator_fee.clone())
+        .refund_expiry(refund_expiry)
+        // STRICT_CONTRACT=1 offers the strict SwapLock, used if the server offers it too
+        .strict_contract(env::var("STRICT_CONTRACT").is_ok());
+    // XMR_PAYOUT_ADDRESS=address sweeps the xmr received on bch->xmr there,
+    // `fresh` to a wallet generated for the trade, exported with `swap-cli recover`
+    builder = match env::var("XMR_PAYOUT_ADDRESS").as_deref() {
+        Ok("fresh") => builder.fresh_xmr_payout(),
+        Ok(address) => builder.xmr_payout_address(Some(address.parse()?)),
+        Err(_) => builder,
     };
+    let mut swap = builder.build()?;
 
-    let string_json = serde_json::to_string_pretty(&swap.swap.keys).unwrap();
+    let string_json = serde_json::to_string_pretty(&swap.keys).unwrap();
     println!("Private Keys: {string_json}");
     println!("Bch recv private key: {}", recv_privkey);
-
-    let swap = SwapWrapper::Alice(swap);
+    if swap.xmr_payout_keys.is_some() {
+        let address = swap.xmr_payout_address.unwrap();
+        println!("Xmr payout wallet generated for the trade: {address}");
+    }
 
     println!("========================================");
 
     println!("Creating new trade...");
-    let trade_id =
-        create_new_trade(&req_client, timelock1, timelock2, bch_amount, xmr_amount).await?;
+    let trade_id = create_new_trade(
+        &req_client,
+        &swap_path,
+        timelock1,
+        timelock2,
+        bch_amount,
+        xmr_amount,
+        mining_fee,
+        mining_fee_rate,
+        key_commitment,
+        bch_token.as_ref(),
+        operator_fee.as_ref(),
+        refund_expiry,
+    )
+    .await?;
     println!("Trade id: {trade_id}");
 
-    tokio::spawn({
+    // bob names his XMR wallets after the trade
+    swap.id = trade_id.clone();
+    let swap = match bch_to_xmr {
+        true => SwapWrapper::Bob(bob::Bob::new(swap)),
+        false => SwapWrapper::Alice(alice::Alice::new(swap)),
+    };
+
+    // ctrl-c stops both loops between two passes, the trade file is saved by then
+    let shutdown = Shutdown::on_ctrl_c();
+    let subscription = tokio::spawn({
         // process subscription
+        let shutdown = shutdown.clone();
         let bch_server = bch_server.clone();
         let trade_id = trade_id.clone();
+        let monero_wallet = monero_wallet.clone();
+        let monerod = monerod.clone();
+        let store = store.clone();
 
         async move {
             let mut receiver = bch_server.subscribe();
 
             loop {
-                let data = receiver.recv().await.unwrap();
-                let data = serde_json::from_str::<serde_json::Value>(&data).unwrap();
+                let notification = tokio::select! {
+                    notification = receiver.recv() => notification,
+                    _ = shutdown.triggered() => break,
+                };
+                let raw = match notification {
+                    Some(Notification::Message(raw)) => raw,
+                    Some(Notification::ResyncRequired) => {
+                        println!("Missed notifications. Rescanning addresses");
+                        let mut trade = TradePersist::restore(get_file_path(&trade_id))
+                            .await
+                            .unwrap();
+                        if let SwapWrapper::Alice(alice) = trade.config.swap {
+                            let mut runner = alice::Runner {
+                                inner: alice,
+                                bch: &*bch_server,
+                                monero_wallet: monero_wallet.as_deref(),
+                                xmr_lock_options: xmr_lock_options,
+                                min_bch_conf: bch_min_confirmation,
+                                zero_conf,
+                                mempool_policy: Some(MempoolPolicy::default()),
+                                store: Some(&*store),
+                                events: None,
+                                effects: None,
+                                clock: None,
+                            };
+                            if let Err(e) = runner.resync().await {
+                                eprintln!("[ABORT] {e}");
+                                std::process::exit(1);
+                            }
+                            trade.config.swap = SwapWrapper::Alice(runner.inner);
+                            trade.save().await;
+                        }
+                        continue;
+                    }
+                    None => break,
+                };
+                let data = serde_json::from_str::<serde_json::Value>(&raw).unwrap();
+
+                if let Some(txid) = blockchain::dsproof_notification_txid(&raw) {
+                    let trade = TradePersist::restore(get_file_path(&trade_id))
+                        .await
+                        .unwrap();
+                    if let SwapWrapper::Alice(alice) = trade.config.swap {
+                        let runner = alice::Runner {
+                            inner: alice,
+                            bch: &*bch_server,
+                            monero_wallet: monero_wallet.as_deref(),
+                            xmr_lock_options: xmr_lock_options,
+                            min_bch_conf: bch_min_confirmation,
+                            zero_conf,
+                            mempool_policy: Some(MempoolPolicy::default()),
+                            store: Some(&*store),
+                            events: None,
+                            effects: None,
+                            clock: None,
+                        };
+                        if let Err(e) = runner.on_dsproof(&txid) {
+                            eprintln!("[ABORT] {e}");
+                            std::process::exit(1);
+                        }
+                    }
+                    continue;
+                }
 
                 let method = data["method"].as_str().unwrap();
                 if method != "blockchain.headers.subscribe" {
@@ -183,14 +417,48 @@ async fn main() -> anyhow::Result<()> {
                     .await
                     .unwrap();
                 match trade.config.swap {
-                    SwapWrapper::Bob(_) => {}
+                    SwapWrapper::Bob(bob) => {
+                        let mut runner = bob::Runner {
+                            inner: bob,
+                            trade_id: trade_id.clone(),
+                            bch: &*bch_server,
+                            monerod: &monerod,
+                            monero_wallet: monero_wallet.as_deref().expect("checked on startup"),
+                            xmr_scanner: None,
+                            min_bch_conf: bch_min_confirmation,
+                            mempool_policy: Some(MempoolPolicy::default()),
+                            store: Some(&*store),
+                            events: None,
+                            effects: None,
+                            clock: None,
+                        };
+                        let _ = runner.check_bch().await;
+                        let reorg = &mut trade.config.reorg;
+                        for event in runner.check_reorg(reorg).await.unwrap_or_default() {
+                            println!("[WARN] {event}");
+                        }
+                        trade.config.swap = SwapWrapper::Bob(runner.inner);
+                        trade.save().await;
+                    }
                     SwapWrapper::Alice(alice) => {
                         let mut runner = alice::Runner {
                             inner: alice,
-                            bch: &bch_server,
+                            bch: &*bch_server,
+                            monero_wallet: monero_wallet.as_deref(),
+                            xmr_lock_options: xmr_lock_options,
                             min_bch_conf: bch_min_confirmation,
+                            zero_conf,
+                            mempool_policy: Some(MempoolPolicy::default()),
+                            store: Some(&*store),
+                            events: None,
+                            effects: None,
+                            clock: None,
                         };
                         let _ = runner.check_bch().await;
+                        let reorg = &mut trade.config.reorg;
+                        for event in runner.check_reorg(reorg).await.unwrap_or_default() {
+                            println!("[WARN] {event}");
+                        }
                         trade.config.swap = SwapWrapper::Alice(runner.inner);
                         trade.save().await;
                     }
@@ -200,8 +468,11 @@ async fn main() -> anyhow::Result<()> {
     });
 
     let serialized = serde_json::to_vec_pretty(&Config {
+        version: SCHEMA_VERSION,
         swap,
         refund_private_key: recv_privkey,
+        accounting: Default::default(),
+        reorg: Default::default(),
     })?;
     fs::OpenOptions::new()
         .create_new(true)
@@ -213,22 +484,103 @@ async fn main() -> anyhow::Result<()> {
 
     println!("========================================");
 
+    let mut reported_events = HashSet::new();
+    // our current message, sent again until the server answers it
+    let mut outbox = Outbox::default();
     loop {
-        let trade = TradePersist::restore(get_file_path(&trade_id))
+        let mut trade = TradePersist::restore(get_file_path(&trade_id))
             .await
             .unwrap();
         match &trade.config.swap {
-            SwapWrapper::Bob(_) => {}
+            SwapWrapper::Bob(inner) => {
+                let transition = inner.get_signed_transition();
+                let swap_id = inner.swap.swap_id.clone();
+                let state = inner.state.to_string();
+                let mut runner = bob::Runner {
+                    inner: inner.clone(),
+                    trade_id: trade_id.clone(),
+                    bch: &*bch_server,
+                    monerod: &monerod,
+                    monero_wallet: monero_wallet.as_deref().expect("checked on startup"),
+                    xmr_scanner: None,
+                    min_bch_conf: bch_min_confirmation,
+                    mempool_policy: None,
+                    store: Some(&*store),
+                    events: None,
+                    effects: None,
+                    clock: None,
+                };
+
+                for event in runner.mempool_events().await.unwrap_or_default() {
+                    if reported_events.insert(event.clone()) {
+                        println!("[INFO] {event}");
+                    }
+                }
+                for event in runner.check_xmr().await.unwrap_or_default() {
+                    if reported_events.insert(event.clone()) {
+                        println!("[INFO] {event}");
+                    }
+                }
+                match runner.check_deadlines(&watchdog).await {
+                    Ok(Some(event)) => println!("[WARN] {event}"),
+                    Ok(None) => {}
+                    Err(e) => println!("[WARN] Deadline check failed: {e}"),
+                }
+                trade.config.swap = SwapWrapper::Bob(runner.inner);
+                trade.save().await;
+                drop(trade);
+
+                send_outbound(
+                    &req_client,
+                    &trade_id,
+                    &mut outbox,
+                    state,
+                    transition,
+                    swap_id,
+                )
+                .await;
+            }
             SwapWrapper::Alice(inner) => {
-                let transition = inner.get_transition();
+                let transition = inner.get_signed_transition();
+                let swap_id = inner.swap.swap_id.clone();
+                let state = inner.state.to_string();
+                let mut runner = alice::Runner {
+                    inner: inner.clone(),
+                    bch: &*bch_server,
+                    monero_wallet: monero_wallet.as_deref(),
+                    xmr_lock_options: xmr_lock_options,
+                    min_bch_conf: bch_min_confirmation,
+                    zero_conf,
+                    mempool_policy: None,
+                    store: Some(&*store),
+                    events: None,
+                    effects: None,
+                    clock: None,
+                };
+                match runner.check_deadlines(&watchdog).await {
+                    Ok(Some(event)) => println!("[WARN] {event}"),
+                    Ok(None) => {}
+                    Err(e) => println!("[WARN] Deadline check failed: {e}"),
+                }
+                trade.config.swap = SwapWrapper::Alice(runner.inner.clone());
+                trade.save().await;
                 drop(trade);
 
-                if let Some(transition) = transition {
-                    if let Err(e) = send_transition(&req_client, &trade_id, &transition).await {
-                        println!("{:?}", e);
-                        sleep(Duration::from_secs(10)).await;
+                for event in runner.mempool_events().await.unwrap_or_default() {
+                    if reported_events.insert(event.clone()) {
+                        println!("[INFO] {event}");
                     }
                 }
+
+                send_outbound(
+                    &req_client,
+                    &trade_id,
+                    &mut outbox,
+                    state,
+                    transition,
+                    swap_id,
+                )
+                .await;
             }
         };
 
@@ -236,7 +588,7 @@ async fn main() -> anyhow::Result<()> {
             Err(e) => println!("============= {:?}", e),
             Ok(transition) => match transition {
                 None => {
-                    sleep(Duration::from_secs(5)).await;
+                    shutdown.sleep(Duration::from_secs(5)).await;
                 }
                 Some(transition) => {
                     let mut trade = TradePersist::restore(get_file_path(&trade_id))
@@ -246,19 +598,60 @@ async fn main() -> anyhow::Result<()> {
                         SwapWrapper::Alice(alice) => {
                             let mut runner = alice::Runner {
                                 inner: alice,
+                                monero_wallet: monero_wallet.as_deref(),
+                                xmr_lock_options: xmr_lock_options,
                                 min_bch_conf: bch_min_confirmation,
-                                bch: &bch_server,
+                                bch: &*bch_server,
+                                zero_conf,
+                                mempool_policy: Some(MempoolPolicy::default()),
+                                store: Some(&*store),
+                                events: None,
+                                effects: None,
+                                clock: None,
                             };
                             runner.pub_transition(transition).await?;
                             trade.config.swap = SwapWrapper::Alice(runner.inner);
                             trade.save().await;
                         }
-                        SwapWrapper::Bob(_) => {}
+                        SwapWrapper::Bob(bob) => {
+                            let mut runner = bob::Runner {
+                                inner: bob,
+                                trade_id: trade_id.clone(),
+                                bch: &*bch_server,
+                                monerod: &monerod,
+                                monero_wallet: monero_wallet
+                                    .as_deref()
+                                    .expect("checked on startup"),
+                                xmr_scanner: None,
+                                min_bch_conf: bch_min_confirmation,
+                                mempool_policy: Some(MempoolPolicy::default()),
+                                store: Some(&*store),
+                                events: None,
+                                effects: None,
+                                clock: None,
+                            };
+                            runner.pub_transition(transition).await?;
+                            trade.config.swap = SwapWrapper::Bob(runner.inner);
+                            trade.save().await;
+                        }
                     }
                 }
             },
         };
 
-        sleep(Duration::from_secs(5)).await;
+        if shutdown.sleep(Duration::from_secs(5)).await {
+            break;
+        }
+    }
+
+    let _ = subscription.await;
+    if let Some(wallet) = &monero_wallet {
+        let _ = wallet.lock().await.close_wallet().await;
+    }
+    let trade = TradePersist::restore(get_file_path(&trade_id)).await?;
+    match pending_swaps([(trade_id.clone(), trade.config.swap)]).pop() {
+        Some(pending) => println!("Stopped before the end of the trade: {pending}"),
+        None => println!("Stopped, the trade is finished"),
     }
+    Ok(())
 }
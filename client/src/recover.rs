@@ -1,6 +1,11 @@
 use std::{env, sync::Arc};
 
-use protocol::{alice, blockchain, persist::TradePersist, protocol::SwapWrapper};
+use protocol::{
+    alice,
+    blockchain::{self, policy::MempoolPolicy},
+    persist::{FileStore, SwapStore, TradePersist},
+    protocol::SwapWrapper,
+};
 use tokio::net::TcpStream;
 
 pub fn get_file_path(trade_id: &str) -> String {
@@ -18,15 +23,31 @@ async fn main() -> anyhow::Result<()> {
     let mut trade = TradePersist::restore(get_file_path(&trade_id))
         .await
         .unwrap();
+    let store = FileStore::new("./.trades/checkpoints/client");
     match trade.config.swap {
         SwapWrapper::Bob(_) => {}
         SwapWrapper::Alice(inner) => {
-            let mut runner = alice::Runner {
-                inner,
-                bch: &bch_server,
-                min_bch_conf: 0,
+            // the checkpoint is at least as recent as the trade file
+            let runner = match store.load(&trade_id).await? {
+                Some(_) => alice::Runner::resume(&trade_id, &store, &*bch_server, None).await?,
+                None => {
+                    let mut runner = alice::Runner {
+                        inner,
+                        bch: &*bch_server,
+                        monero_wallet: None,
+                        xmr_lock_options: Default::default(),
+                        min_bch_conf: 0,
+                        zero_conf: false,
+                        mempool_policy: Some(MempoolPolicy::default()),
+                        store: Some(&store),
+                        events: None,
+                        effects: None,
+                        clock: None,
+                    };
+                    let _ = runner.check_bch().await;
+                    runner
+                }
             };
-            let _ = runner.check_bch().await;
             trade.config.swap = SwapWrapper::Alice(runner.inner);
             trade.save().await;
         }
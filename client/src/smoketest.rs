@@ -0,0 +1,286 @@
+//! Runs a tiny swap between an in-process Alice and Bob on chipnet + stagenet.
+//!
+//! Both peers use faucet funded wallets:
+//! - SMOKE_BCH_WIF: chipnet key funding the SwapLock (Bob) and receiving both outputs
+//! - SMOKE_XMR_WALLET / SMOKE_XMR_WALLET_PASSWORD: stagenet wallet opened on
+//!   SMOKE_ALICE_WALLET_RPC used to lock xmr (Alice)
+//!
+//! Bob needs its own wallet rpc (SMOKE_BOB_WALLET_RPC) because it opens/closes view wallets.
+
+use std::{
+    env,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde_json::json;
+use tokio::{sync::Mutex, time::sleep};
+
+use protocol::{
+    alice, bitcoincash,
+    bitcoincash::{
+        consensus::Encodable, OutPoint, PackedLockTime, Script, Transaction, TxIn, TxOut,
+    },
+    blockchain::{fee::fee_estimator, policy::MempoolPolicy, ElectrumConfig, TcpElectrum},
+    bob,
+    keys::{
+        bitcoin::{self, p2pkh},
+        KeyPrivate,
+    },
+    monero,
+    monerod::{self, MonerodPool, RpcOptions},
+    protocol::{Swap, SwapEvents},
+    xmr_wallet::FeePriority,
+};
+
+const BCH_AMOUNT: u64 = 20000;
+const XMR_AMOUNT: u64 = 100_000_000;
+const TIMELOCK: u32 = 10;
+const DEADLINE: Duration = Duration::from_secs(3 * 60 * 60);
+const POLL: Duration = Duration::from_secs(30);
+
+fn env_or(key: &str, default: &str) -> String {
+    env::var(key).unwrap_or(default.to_owned())
+}
+
+async fn broadcast(bch: &TcpElectrum, tx: &Transaction) -> anyhow::Result<()> {
+    let mut buffer = Vec::new();
+    tx.consensus_encode(&mut buffer)?;
+    let response = bch
+        .send(
+            "blockchain.transaction.broadcast",
+            json!([hex::encode(buffer)]),
+        )
+        .await?;
+    let response: serde_json::Value = serde_json::from_str(&response)?;
+    if !response["error"].is_null() {
+        bail!("broadcast failed: {}", response["error"]);
+    }
+
+    Ok(())
+}
+
+/// Send `amount` to `script` using every utxo of `key`, change goes back to `key`
+async fn fund_bch(
+    bch: &TcpElectrum,
+    key: &bitcoincash::PrivateKey,
+    script: Script,
+    amount: u64,
+) -> anyhow::Result<()> {
+    let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+    let pubkey = key.public_key(&secp);
+    let address = p2pkh::cash_address(&pubkey, bitcoin::Network::Testnet);
+
+    let response = bch
+        .send("blockchain.address.listunspent", json!([address]))
+        .await?;
+    let response: serde_json::Value = serde_json::from_str(&response)?;
+    let utxos = response["result"]
+        .as_array()
+        .context("invalid listunspent response")?;
+
+    let mut inputs = Vec::new();
+    let mut values = Vec::new();
+    for utxo in utxos {
+        let txid = utxo["tx_hash"].as_str().context("missing tx_hash")?;
+        inputs.push(TxIn {
+            previous_output: OutPoint::new(
+                txid.parse()?,
+                utxo["tx_pos"].as_u64().context("missing tx_pos")? as u32,
+            ),
+            ..Default::default()
+        });
+        values.push(utxo["value"].as_u64().context("missing value")?);
+    }
+
+    // 1 sat/byte
+    let fee = 10 + 148 * inputs.len() as u64 + 34 * 2;
+    let total: u64 = values.iter().sum();
+    if total < amount + fee {
+        bail!("{address} has {total} sats, need {}", amount + fee);
+    }
+
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: inputs,
+        output: vec![
+            TxOut {
+                value: amount,
+                script_pubkey: script,
+                token: None,
+            },
+            TxOut {
+                value: total - amount - fee,
+                script_pubkey: Script::new_p2pkh(&pubkey.pubkey_hash()),
+                token: None,
+            },
+        ],
+    };
+    p2pkh::sign_inputs(&mut tx, key, &values);
+
+    println!("[SMOKE] Funding SwapLock: {}", tx.txid());
+    broadcast(bch, &tx).await
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let electrum = env_or("SMOKE_ELECTRUM", "chipnet.imaginary.cash:50001");
+    let monerod = env_or("SMOKE_MONEROD", "http://stagenet.xmr-tw.org:38081");
+    let alice_wallet_rpc = env_or("SMOKE_ALICE_WALLET_RPC", "http://localhost:38083");
+    let bob_wallet_rpc = env_or("SMOKE_BOB_WALLET_RPC", "http://localhost:38084");
+    let xmr_wallet = env::var("SMOKE_XMR_WALLET").context("SMOKE_XMR_WALLET required")?;
+    let xmr_wallet_password = env_or("SMOKE_XMR_WALLET_PASSWORD", "");
+    let proxy = env::var("SMOKE_SOCKS5_PROXY").ok();
+    let bch_key = bitcoincash::PrivateKey::from_wif(
+        &env::var("SMOKE_BCH_WIF").context("SMOKE_BCH_WIF required")?,
+    )?;
+
+    let bch_network = bitcoin::Network::Testnet;
+    let xmr_network = monero::Network::Stagenet;
+
+    // ===================================================
+
+    let electrum_config = ElectrumConfig {
+        proxy: proxy.clone(),
+        ..Default::default()
+    };
+    let bch = TcpElectrum::connect_with(&electrum, electrum_config).await?;
+    let rpc_options = RpcOptions {
+        proxy: proxy.clone(),
+        login: None,
+    };
+    let monerod = MonerodPool::new(vec![monerod], &rpc_options)?;
+    let alice_wallet = Mutex::new(monerod::rpc_client(&alice_wallet_rpc, &rpc_options)?.wallet());
+    let bob_wallet = Mutex::new(monerod::rpc_client(&bob_wallet_rpc, &rpc_options)?.wallet());
+    alice_wallet
+        .lock()
+        .await
+        .open_wallet(xmr_wallet, Some(xmr_wallet_password))
+        .await?;
+
+    let fee = fee_estimator(&bch, 1).await?;
+    let (mining_fee, mining_fee_rate) = (fee.mining_fee(), fee.fee_rate());
+    println!("[SMOKE] Mining fee: {mining_fee_rate} sats/kB, up to {mining_fee} sats");
+
+    let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+    let recv = Script::new_p2pkh(&bch_key.public_key(&secp).pubkey_hash());
+    let trade_id = format!(
+        "smoke{}",
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()
+    );
+
+    let swap = |keys: KeyPrivate| Swap {
+        id: trade_id.clone(),
+        xmr_network,
+        bch_network,
+        keys,
+        bch_recv: recv.clone(),
+        xmr_amount: monero::Amount::from_pico(XMR_AMOUNT),
+        bch_amount: bitcoincash::Amount::from_sat(BCH_AMOUNT),
+        timelock1: TIMELOCK,
+        timelock2: TIMELOCK,
+        mining_fee,
+        mining_fee_rate: Some(mining_fee_rate),
+        // stagenet, speed over safety
+        xmr_confirmations: 1,
+        xmr_overpayment: Default::default(),
+        bch_confirmations: Default::default(),
+        token_policy: Default::default(),
+        bch_token: None,
+        operator_fee: None,
+        strict_contract: false,
+        contract_version: None,
+        refund_expiry: None,
+        xmr_payout_address: None,
+        xmr_payout_keys: None,
+        xmr_fee_priority: FeePriority::Default,
+        xmr_restore_height: 0,
+        peer: None,
+        protocol_version: None,
+        key_commitment: false,
+        peer_commitment: None,
+        swap_id: None,
+    };
+
+    let mut alice = alice::Runner {
+        inner: alice::Alice::new(swap(KeyPrivate::random(bch_network))),
+        bch: &bch,
+        monero_wallet: Some(&alice_wallet),
+        xmr_lock_options: Default::default(),
+        min_bch_conf: 1,
+        zero_conf: false,
+        mempool_policy: Some(MempoolPolicy::default()),
+        store: None,
+        events: None,
+        effects: None,
+        clock: None,
+    };
+    let mut bob = bob::Runner {
+        inner: bob::Bob::new(swap(KeyPrivate::random(bch_network))),
+        trade_id: trade_id.clone(),
+        bch: &bch,
+        monerod: &monerod,
+        monero_wallet: &bob_wallet,
+        xmr_scanner: None,
+        min_bch_conf: 1,
+        mempool_policy: Some(MempoolPolicy::default()),
+        store: None,
+        events: None,
+        effects: None,
+        clock: None,
+    };
+
+    println!("[SMOKE] Trade id: {trade_id}");
+
+    let started = Instant::now();
+    let mut bch_funded = false;
+    loop {
+        if started.elapsed() > DEADLINE {
+            bail!(
+                "[SMOKE] Timeout. Alice: {} Bob: {}",
+                alice.inner.state,
+                bob.inner.state
+            );
+        }
+
+        // Exchanging messages. Replayed messages are rejected by the state machine
+        if let Some(transition) = alice.inner.get_signed_transition() {
+            let _ = bob.pub_transition(transition).await;
+        }
+        if let Some(transition) = bob.inner.get_signed_transition() {
+            let _ = alice.pub_transition(transition).await;
+        }
+
+        if !bch_funded {
+            if let bob::State::VerifiedEncSig(_) = bob.inner.state {
+                let contract = bob.inner.get_contract_pair().unwrap();
+                let script = Script::from(contract.swaplock.locking_script());
+                fund_bch(&bch, &bch_key, script, BCH_AMOUNT).await?;
+                bch_funded = true;
+            }
+        }
+
+        let _ = alice.check_bch().await;
+        // the runner sends the xmr lock when bob's bch gets confirmed
+        if let alice::State::BchLocked(_) = alice.inner.state {
+            if alice.inner.get_transition().is_none() {
+                bail!("[SMOKE] Xmr lock failed");
+            }
+        }
+        let _ = bob.check_bch().await;
+        let _ = bob.check_xmr().await;
+
+        if let bob::State::SwapSuccess(_, address, _) = &bob.inner.state {
+            println!("[SMOKE] Success after {:?}", started.elapsed());
+            println!("[SMOKE] Xmr claimable at {address}");
+            return Ok(());
+        }
+
+        println!(
+            "[SMOKE] Alice: {} Bob: {}",
+            alice.inner.state, bob.inner.state
+        );
+        sleep(POLL).await;
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::Swap;
+
+/// Market price of 1 XMR in BCH, as reported by `source`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateSnapshot {
+    pub source: String,
+    pub bch_per_xmr: f64,
+    /// unix seconds
+    pub timestamp: u64,
+}
+
+/// Rates fed to the daemon, oldest first. Only the last `capacity` are kept
+pub struct RateHistory {
+    capacity: usize,
+    snapshots: VecDeque<RateSnapshot>,
+}
+
+impl RateHistory {
+    pub fn new(capacity: usize) -> Self {
+        RateHistory {
+            capacity: capacity.max(1),
+            snapshots: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, source: String, bch_per_xmr: f64) -> RateSnapshot {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.push(RateSnapshot {
+            source,
+            bch_per_xmr,
+            timestamp,
+        })
+    }
+
+    pub fn push(&mut self, snapshot: RateSnapshot) -> RateSnapshot {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(snapshot.clone());
+        snapshot
+    }
+
+    pub fn latest(&self) -> Option<RateSnapshot> {
+        self.snapshots.back().cloned()
+    }
+
+    /// Last snapshot taken at or before `timestamp`
+    pub fn at(&self, timestamp: u64) -> Option<RateSnapshot> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|s| s.timestamp <= timestamp)
+            .cloned()
+    }
+
+    pub fn snapshots(&self) -> impl Iterator<Item = &RateSnapshot> {
+        self.snapshots.iter()
+    }
+}
+
+/// Market rates recorded along a swap, persisted with the trade
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwapAccounting {
+    /// When our side locked funds
+    pub lock: Option<RateSnapshot>,
+    /// When the swap completed
+    pub settlement: Option<RateSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Pnl {
+    /// bch_amount / xmr_amount of the swap
+    pub executed_rate: f64,
+    pub lock_rate: f64,
+    pub settlement_rate: f64,
+    /// Spread taken at lock time, positive when in our favor
+    pub spread: f64,
+    /// Market move between lock and settlement, positive when in our favor
+    pub slippage: f64,
+    /// Value received minus value given, in sats at the settlement rate
+    pub realized_sats: i64,
+}
+
+impl SwapAccounting {
+    /// `sells_bch` is true when we play Bob. None until both rates are recorded
+    pub fn pnl(&self, swap: &Swap, sells_bch: bool) -> Option<Pnl> {
+        let lock_rate = self.lock.as_ref()?.bch_per_xmr;
+        let settlement_rate = self.settlement.as_ref()?.bch_per_xmr;
+
+        let bch = swap.bch_amount.to_sat() as f64;
+        let xmr = swap.xmr_amount.as_xmr();
+        if xmr == 0.0 || lock_rate == 0.0 {
+            return None;
+        }
+        let executed_rate = bch / 100_000_000.0 / xmr;
+
+        // bob pays executed_rate for xmr and profits when xmr is cheap
+        let side = if sells_bch { 1.0 } else { -1.0 };
+        let spread = side * (lock_rate - executed_rate) / lock_rate;
+        let slippage = side * (settlement_rate - lock_rate) / lock_rate;
+        let realized_sats = side * (xmr * settlement_rate * 100_000_000.0 - bch);
+
+        Some(Pnl {
+            executed_rate,
+            lock_rate,
+            settlement_rate,
+            spread,
+            slippage,
+            realized_sats: realized_sats.round() as i64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RateHistory, RateSnapshot};
+
+    fn snapshot(bch_per_xmr: f64, timestamp: u64) -> RateSnapshot {
+        RateSnapshot {
+            source: "test".to_owned(),
+            bch_per_xmr,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn should_find_rate_at_time() {
+        let mut history = RateHistory::new(2);
+        history.push(snapshot(0.3, 10));
+        history.push(snapshot(0.4, 20));
+        history.push(snapshot(0.5, 30));
+
+        assert_eq!(history.at(5), None);
+        assert_eq!(history.at(25), Some(snapshot(0.4, 20)));
+        assert_eq!(history.latest(), Some(snapshot(0.5, 30)));
+    }
+}
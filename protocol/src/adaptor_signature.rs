@@ -95,9 +95,9 @@ mod test {
     #[test]
     fn test() {
         let bob = keys::KeyPrivate::random(keys::bitcoin::Network::Testnet);
-        let bobpub = keys::KeyPublic::from(bob.clone());
+        let bobpub = keys::KeyPublic::from(&bob);
         let alice = keys::KeyPrivate::random(keys::bitcoin::Network::Testnet);
-        let alicepub = keys::KeyPublic::from(alice.clone());
+        let alicepub = keys::KeyPublic::from(&alice);
         let message = [0u8; 32];
 
         // bob signed alice output
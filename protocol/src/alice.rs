@@ -1,27 +1,42 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
-use anyhow::bail;
 use bitcoin_hashes::{sha256::Hash as sha256, Hash};
-use bitcoincash::{
-    consensus::Encodable, OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut,
-};
+use bitcoincash::{OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut};
 use ecdsa_fun::adaptor::EncryptedSignature;
-use hex::ToHex;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use tokio::sync::Mutex;
 
 use crate::{
     adaptor_signature::AdaptorSignature,
     bitcoincash::secp256k1::ecdsa,
-    blockchain::{scan_address_conf_tx, TcpElectrum},
-    contract::{ContractPair, TransactionType},
+    blockchain::{
+        backend::{broadcast_txs, timelock_block, with_retry, ChainBackend},
+        policy::MempoolPolicy,
+        reorg::ReorgWatch,
+    },
+    clock::{Clock, SystemClock},
+    contract::{ContractPair, ContractTokens, TransactionType},
+    error::SwapError,
     keys::{KeyPublic, KeyPublicWithoutProof},
+    outcome::{OutcomeKind, SwapOutcome, SwapTxids},
+    peer::{PeerInfo, PROTOCOL_VERSION},
+    persist::SwapStore,
+    presigned::PresignedTxs,
+    progress::{Chain, Confirmations, Progress},
     proof,
-    protocol::{Action, Error, Swap, SwapEvents, Transition},
+    protocol::{
+        check_msg0, funding_events, print_event, Action, BchBlock, Error, Event, EventSink,
+        SignedTransition, Swap, SwapEvents, SwapWrapper, Transition, XmrLockProof,
+    },
+    redact::Redacted,
+    runner::SwapRunner,
+    transcript::{Transcript, TranscriptEntry},
     utils::{get_signature, monero_key_pair, monero_view_pair},
+    watchdog::{unix_now, Watchdog},
+    xmr_wallet::{self, LockOptions, RecoveredBalance, SweepAmount, XmrRecovery},
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value0 {
     bob_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -32,7 +47,18 @@ pub struct Value0 {
     shared_keypair: monero::ViewPair,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value0 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value0")
+            .field("bob_keys", &self.bob_keys)
+            .field("bob_bch_recv", &self.bob_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value1 {
     bob_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -42,9 +68,30 @@ pub struct Value1 {
     shared_keypair: monero::ViewPair,
 
     outpoint: OutPoint,
+    /// Parts of a split SwapLock funding with their sats, empty when `outpoint`
+    /// holds `Swap::bch_amount`
+    #[serde(default)]
+    parts: Vec<(OutPoint, u64)>,
+    /// Set once our XMR lock was sent, forwarded to Bob
+    #[serde(default)]
+    xmr_lock_proof: Option<XmrLockProof>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value1")
+            .field("bob_keys", &self.bob_keys)
+            .field("bob_bch_recv", &self.bob_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("outpoint", &self.outpoint)
+            .field("parts", &self.parts)
+            .field("xmr_lock_proof", &self.xmr_lock_proof)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Value2 {
     bob_keys: KeyPublicWithoutProof,
@@ -54,21 +101,97 @@ pub struct Value2 {
     #[serde(with = "monero_view_pair")]
     shared_keypair: monero::ViewPair,
     outpoint: OutPoint,
+    /// Same as `Value1::parts`
+    #[serde(default)]
+    parts: Vec<(OutPoint, u64)>,
 
     dec_sig: ecdsa::Signature,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value2")
+            .field("bob_keys", &self.bob_keys)
+            .field("bob_bch_recv", &self.bob_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("outpoint", &self.outpoint)
+            .field("parts", &self.parts)
+            .field("dec_sig", &self.dec_sig)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Value3 {
+    bob_keys: KeyPublicWithoutProof,
+    #[serde(with = "hex")]
+    bob_bch_recv: Vec<u8>,
+    contract_pair: ContractPair,
+    #[serde(with = "monero_view_pair")]
+    shared_keypair: monero::ViewPair,
+    /// Of the Refund contract
+    outpoint: OutPoint,
+    /// Refund contract outputs of a split funding with their sats, added as Bob
+    /// moves each part. Empty when `outpoint` holds the whole refund
+    #[serde(default)]
+    parts: Vec<(OutPoint, u64)>,
+    /// Reached the Refund expiry, the punish spends take it rather than the
+    /// relative timelock
+    #[serde(default)]
+    expired: bool,
+}
+
+impl fmt::Debug for Value3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value3")
+            .field("bob_keys", &self.bob_keys)
+            .field("bob_bch_recv", &self.bob_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("outpoint", &self.outpoint)
+            .field("parts", &self.parts)
+            .field("expired", &self.expired)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum State {
     Init,
     WithBobKeys(Value0),
     ContractMatch(Value0),
     BchLocked(Value1),
     ValidEncSig(Value2),
+    /// Bob moved the bch to the Refund contract but did not claim it before
+    /// timelock2. Alice takes the bch, the xmr stays locked for both
+    Punish(Value3),
     Refund(
         monero::Address,
         #[serde(with = "monero_key_pair")] monero::KeyPair,
     ),
+    /// Backed out before locking
+    Aborted,
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Init => write!(f, "Init"),
+            State::WithBobKeys(v) => f.debug_tuple("WithBobKeys").field(v).finish(),
+            State::ContractMatch(v) => f.debug_tuple("ContractMatch").field(v).finish(),
+            State::BchLocked(v) => f.debug_tuple("BchLocked").field(v).finish(),
+            State::ValidEncSig(v) => f.debug_tuple("ValidEncSig").field(v).finish(),
+            State::Punish(v) => f.debug_tuple("Punish").field(v).finish(),
+            // the keys spending the refunded XMR
+            State::Refund(address, _) => f
+                .debug_tuple("Refund")
+                .field(address)
+                .field(&Redacted)
+                .finish(),
+            State::Aborted => write!(f, "Aborted"),
+        }
+    }
 }
 
 impl fmt::Display for State {
@@ -79,7 +202,9 @@ impl fmt::Display for State {
             State::ContractMatch(_) => write!(f, "AliceState:ContractMatch"),
             State::BchLocked(_) => write!(f, "AliceState:BchLocked"),
             State::ValidEncSig(_) => write!(f, "AliceState:ValidEncSig"),
+            State::Punish(_) => write!(f, "AliceState:Punish"),
             State::Refund(_, _) => write!(f, "AliceState:Refund"),
+            State::Aborted => write!(f, "AliceState:Aborted"),
         }
     }
 }
@@ -88,11 +213,103 @@ impl fmt::Display for State {
 pub struct Alice {
     pub state: State,
     pub swap: Swap,
+    /// Sweep of the XMR recovered after Bob refunded to
+    /// `Swap::xmr_payout_address`, empty until done
+    #[serde(default)]
+    pub xmr_payout_txids: Vec<String>,
+    /// Unix time the state was entered, 0 when unknown. See `Watchdog`
+    #[serde(default)]
+    pub state_since: u64,
+    /// Unix time of the trade creation, 0 when unknown
+    #[serde(default)]
+    pub started_at: u64,
+    #[serde(default)]
+    pub txids: SwapTxids,
+    /// Carried by our spends, see `TokenPolicy`
+    #[serde(default)]
+    pub tokens: ContractTokens,
+    /// Our spends, kept for a broadcast from the trade file alone
+    #[serde(default)]
+    pub presigned: PresignedTxs,
+    /// Reset on every state change
+    #[serde(default)]
+    pub confirmations: Option<Confirmations>,
+    /// Filled by the runners
+    #[serde(default)]
+    pub transcript: Transcript,
 }
 
 impl Alice {
+    pub fn new(swap: Swap) -> Self {
+        let now = unix_now();
+        Alice {
+            state: State::Init,
+            swap,
+            xmr_payout_txids: vec![],
+            state_since: now,
+            started_at: now,
+            txids: SwapTxids::default(),
+            tokens: ContractTokens::default(),
+            presigned: PresignedTxs::default(),
+            confirmations: None,
+            transcript: Transcript::default(),
+        }
+    }
+
+    /// Stage of the trade and the confirmations it waits for, the SwapLock
+    /// funding in `ContractMatch`
+    pub fn progress(&self) -> Progress {
+        let stage = match self.state {
+            State::Init => 0,
+            State::WithBobKeys(_) => 1,
+            State::ContractMatch(_) => 2,
+            State::BchLocked(_) => 3,
+            State::ValidEncSig(_) | State::Punish(_) | State::Refund(..) | State::Aborted => 4,
+        };
+        Progress::new(stage, 4, self.confirmations)
+    }
+
+    /// Summary once the trade reached a final state, None before. Our claim
+    /// is counted as soon as it can be sent
+    pub fn outcome(&self) -> Option<SwapOutcome> {
+        let mut txids = self.txids.clone();
+        let xmr_amount = self.swap.xmr_amount.as_pico();
+        let (kind, spends, xmr_sent, xmr_received) = match &self.state {
+            State::ValidEncSig(_) => {
+                let claims = self.get_unlock_normal_txs();
+                txids.add_claims(&claims);
+                (OutcomeKind::Success, claims, xmr_amount, 0)
+            }
+            // the xmr went back to the shared wallet we fully control
+            State::Refund(..) => (OutcomeKind::Refunded, vec![], xmr_amount, xmr_amount),
+            State::Punish(_) => {
+                let punish = self.punish();
+                txids.add_refunds(&punish);
+                (OutcomeKind::Punished, punish, xmr_amount, 0)
+            }
+            State::Aborted => (OutcomeKind::Aborted, vec![], 0, 0),
+            _ => return None,
+        };
+        let mining_fee = match &self.state {
+            State::ValidEncSig(props) => props.contract_pair.swaplock.mining_fee,
+            State::Punish(props) => props.contract_pair.refund.mining_fee,
+            _ => 0,
+        };
+
+        Some(SwapOutcome {
+            kind,
+            bch_sent: 0,
+            bch_received: spends.iter().map(|tx| tx.output[0].value).sum(),
+            xmr_sent,
+            xmr_received,
+            txids,
+            bch_fees: mining_fee * spends.len() as u64,
+            duration_secs: SwapOutcome::duration_secs(self.started_at, self.state_since),
+        })
+    }
+
     pub fn get_public_keys(&self) -> KeyPublic {
-        KeyPublic::from(self.swap.keys.clone())
+        KeyPublic::from(&self.swap.keys)
     }
 
     pub fn get_contract(&self) -> Option<(String, monero::Address)> {
@@ -106,10 +323,97 @@ impl Alice {
         return None;
     }
 
+    /// Bob's key signing his messages, known from his Msg0
+    pub fn get_peer_ves(&self) -> Option<bitcoincash::PublicKey> {
+        match &self.state {
+            State::WithBobKeys(v) | State::ContractMatch(v) => Some(v.bob_keys.ves),
+            State::BchLocked(v) => Some(v.bob_keys.ves),
+            State::ValidEncSig(v) => Some(v.bob_keys.ves),
+            State::Punish(v) => Some(v.bob_keys.ves),
+            _ => None,
+        }
+    }
+
+    /// `get_transition` signed for Bob
+    pub fn get_signed_transition(&self) -> Option<SignedTransition> {
+        self.get_transition()
+            .map(|transition| SignedTransition::sign(transition, &self.swap))
+    }
+
+    /// Message from Bob already applied, redelivered by the network.
+    /// Those leave the state as is instead of failing the transition
+    fn is_replay(&self, transition: &Transition) -> bool {
+        if let Transition::KeyCommitment(commitment) = transition {
+            return self.swap.peer_commitment == Some(*commitment);
+        }
+
+        let (bob_keys, bob_bch_recv, contract_pair, shared_keypair, dec_sig) = match &self.state {
+            State::WithBobKeys(v) | State::ContractMatch(v) => (
+                &v.bob_keys,
+                &v.bob_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                None,
+            ),
+            State::BchLocked(v) => (
+                &v.bob_keys,
+                &v.bob_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                None,
+            ),
+            State::ValidEncSig(v) => (
+                &v.bob_keys,
+                &v.bob_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                Some(v.dec_sig),
+            ),
+            State::Punish(v) => (
+                &v.bob_keys,
+                &v.bob_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                None,
+            ),
+            _ => return false,
+        };
+
+        match transition {
+            Transition::Msg0 {
+                keys, receiving, ..
+            } => {
+                KeyPublicWithoutProof::from(keys.clone()) == *bob_keys
+                    && receiving.as_bytes() == bob_bch_recv.as_slice()
+            }
+            Transition::Contract {
+                bch_address,
+                xmr_address,
+            } => {
+                !matches!(self.state, State::WithBobKeys(_))
+                    && contract_pair.swaplock.cash_address() == *bch_address
+                    && monero::Address::from_viewpair(self.swap.xmr_network, shared_keypair)
+                        == *xmr_address
+            }
+            // the signature is deterministic, a redelivery decrypts to the same one
+            Transition::EncSig(enc_sig) => {
+                let decrypted = AdaptorSignature::decrypt_signature(
+                    &self.swap.keys.monero_spend,
+                    enc_sig.clone(),
+                );
+                dec_sig.is_some()
+                    && ecdsa::Signature::from_compact(&decrypted.to_bytes()).ok() == dec_sig
+            }
+            _ => false,
+        }
+    }
+
     pub fn get_refunc_enc_sig(&self) -> Option<EncryptedSignature> {
         let (spend, recv) = match &self.state {
             State::ContractMatch(props) => (props.bob_keys.spend_bch, &props.bob_bch_recv),
             State::BchLocked(props) => (props.bob_keys.spend_bch, &props.bob_bch_recv),
+            State::ValidEncSig(props) => (props.bob_keys.spend_bch, &props.bob_bch_recv),
+            State::Punish(props) => (props.bob_keys.spend_bch, &props.bob_bch_recv),
             _ => return None,
         };
 
@@ -125,38 +429,201 @@ impl Alice {
             State::ContractMatch(v) => Some(v.contract_pair),
             State::BchLocked(v) => Some(v.contract_pair),
             State::ValidEncSig(v) => Some(v.contract_pair),
+            State::Punish(v) => Some(v.contract_pair),
             _ => None,
         }
     }
 
-    pub fn get_unlock_normal_tx(&self) -> Option<Transaction> {
-        if let State::ValidEncSig(props) = &self.state {
-            let unlocker = props
-                .contract_pair
-                .swaplock
-                .unlocking_script(&props.dec_sig.serialize_der());
-
-            let mining_fee = props.contract_pair.swaplock.mining_fee;
-            let transaction = Transaction {
-                version: 2,
-                lock_time: PackedLockTime(0), // TODO: Should we use current time?
-                input: vec![TxIn {
-                    sequence: Sequence(0),
-                    previous_output: props.outpoint,
-                    script_sig: Script::from(unlocker),
-                    ..Default::default()
-                }],
-                output: vec![TxOut {
-                    value: self.swap.bch_amount.to_sat() - mining_fee,
+    /// Export of the XMR wallet recovered after Bob refunded
+    pub fn get_xmr_recovery(&self) -> Option<XmrRecovery> {
+        match &self.state {
+            State::Refund(_, keypair) => Some(XmrRecovery::new(
+                &self.swap.id,
+                self.swap.xmr_network,
+                keypair,
+                self.swap.xmr_restore_height,
+            )),
+            _ => None,
+        }
+    }
+
+    /// A claim per SwapLock output, once the encrypted signature is valid.
+    /// Parts too small to cover the fee are left
+    pub fn get_unlock_normal_txs(&self) -> Vec<Transaction> {
+        let props = match &self.state {
+            State::ValidEncSig(props) => props,
+            _ => return vec![],
+        };
+        let parts = match props.parts.is_empty() {
+            true => vec![(props.outpoint, self.swap.bch_amount.to_sat())],
+            false => props.parts.clone(),
+        };
+
+        let unlocker = props
+            .contract_pair
+            .swaplock
+            .unlocking_script(&props.dec_sig.serialize_der());
+        let mining_fee = props.contract_pair.swaplock.mining_fee;
+        // the covenant wants the operator's output right after ours
+        let claim_fee = props.contract_pair.swaplock.claim_fee.as_ref();
+        let fees = mining_fee + claim_fee.map_or(0, |fee| fee.amount);
+        parts
+            .into_iter()
+            .filter(|(_, value)| *value > fees)
+            .map(|(outpoint, value)| {
+                let mut output = TxOut {
+                    value: value - fees,
                     script_pubkey: self.swap.bch_recv.clone(),
                     token: None,
-                }],
-            };
+                };
+                self.tokens.carry(&outpoint, &mut output);
+                let mut outputs = vec![output];
+                outputs.extend(claim_fee.map(|fee| TxOut {
+                    value: fee.amount,
+                    script_pubkey: Script::from(fee.output.clone()),
+                    token: None,
+                }));
+                Transaction {
+                    version: 2,
+                    lock_time: PackedLockTime(0), // TODO: Should we use current time?
+                    input: vec![TxIn {
+                        sequence: Sequence(0),
+                        previous_output: outpoint,
+                        script_sig: Script::from(unlocker.clone()),
+                        ..Default::default()
+                    }],
+                    output: outputs,
+                }
+            })
+            .collect()
+    }
 
-            return Some(transaction);
+    /// Bob moving the SwapLock to the Refund, then either spending it and
+    /// revealing his key share or letting timelock2 pass
+    fn follow_refund(
+        mut self,
+        props: Value1,
+        transaction: &Transaction,
+        conf: u32,
+        block: BchBlock,
+    ) -> (Self, Vec<Action>, Option<Error>) {
+        match props.contract_pair.analyze_tx(transaction) {
+            Some((outpoint, TransactionType::ToRefund)) => {
+                // Bob had until timelock2, or the expiry, to refund and
+                // reveal his spend key
+                let refund = &props.contract_pair.refund;
+                let expired = refund.expiry.is_some_and(|expiry| block.expired(expiry));
+                if !expired && !block.timelock_expired(refund.timelock, conf) {
+                    return (self, vec![], None);
+                }
+
+                // the other parts are added as they reach the timelock
+                let parts = match props.parts.is_empty() {
+                    true => vec![],
+                    false => vec![(outpoint, transaction.output[0].value)],
+                };
+                self.state = State::Punish(Value3 {
+                    bob_keys: props.bob_keys,
+                    bob_bch_recv: props.bob_bch_recv,
+                    contract_pair: props.contract_pair,
+                    shared_keypair: props.shared_keypair,
+                    outpoint,
+                    parts,
+                    expired,
+                });
+                let actions = self.punish().into_iter().map(Action::BroadcastTx);
+                (self, actions.collect(), None)
+            }
+            Some((_, TransactionType::ToBob)) => {
+                self.recover_bob_key(&props.bob_keys, &props.shared_keypair, transaction, conf)
+            }
+            _ => (self, vec![], None),
+        }
+    }
+
+    /// Bob's spend of the Refund holds the signature our enc sig encrypted,
+    /// with it his share of the XMR spend key
+    fn recover_bob_key(
+        mut self,
+        bob_keys: &KeyPublicWithoutProof,
+        shared_keypair: &monero::ViewPair,
+        transaction: &Transaction,
+        conf: u32,
+    ) -> (Self, Vec<Action>, Option<Error>) {
+        if conf < self.swap.bch_confirmations.claim {
+            return (self, vec![], None);
         }
+        let script = transaction.input[0].script_sig.clone();
+        let decsig = match get_signature(script) {
+            Some(sig) => match ecdsa_fun::Signature::from_bytes(sig.serialize_compact()) {
+                Some(v) => v,
+                None => return (self, vec![], Some(Error::InvalidTransaction)),
+            },
+            _ => return (self, vec![], Some(Error::InvalidTransaction)),
+        };
+
+        let bob_spend = AdaptorSignature::recover_decryption_key(
+            bob_keys.spend_bch,
+            decsig,
+            self.get_refunc_enc_sig()
+                .expect("Enc sig should be open until the Refund is spent"),
+        );
 
-        None
+        let key_pair = monero::KeyPair {
+            view: shared_keypair.view,
+            spend: self.swap.keys.monero_spend + bob_spend,
+        };
+
+        self.state = State::Refund(
+            monero::Address::from_keypair(self.swap.xmr_network, &key_pair),
+            key_pair,
+        );
+        (self, vec![], None)
+    }
+
+    /// Spends of the Refund contract through its timelock path, mirror of
+    /// `Bob::refund`. Only relayed after timelock2 confirmations of the Refund funding
+    pub fn punish(&self) -> Vec<Transaction> {
+        let props = match &self.state {
+            State::Punish(props) => props,
+            _ => return vec![],
+        };
+        // the Refund outputs, the SwapLock spends paid their fee
+        let swaplock_fee = props.contract_pair.swaplock.mining_fee;
+        let parts = match props.parts.is_empty() {
+            true => vec![(props.outpoint, self.swap.bch_amount.to_sat() - swaplock_fee)],
+            false => props.parts.clone(),
+        };
+
+        let refund = &props.contract_pair.refund;
+        let mining_fee = refund.mining_fee;
+        let (sequence, lock_time) = match refund.expiry_lock() {
+            Some(lock) if props.expired => lock,
+            _ => (refund.sequence(), PackedLockTime(0)),
+        };
+        parts
+            .into_iter()
+            .filter(|(_, value)| *value > mining_fee)
+            .map(|(outpoint, value)| {
+                let mut output = TxOut {
+                    value: value - mining_fee,
+                    script_pubkey: Script::from(refund.failed_output.clone()),
+                    token: None,
+                };
+                self.tokens.carry(&outpoint, &mut output);
+                Transaction {
+                    version: 2,
+                    lock_time,
+                    input: vec![TxIn {
+                        sequence,
+                        previous_output: outpoint,
+                        script_sig: Script::from(refund.unlocking_script(&[])),
+                        ..Default::default()
+                    }],
+                    output: vec![output],
+                }
+            })
+            .collect()
     }
 }
 
@@ -165,32 +632,89 @@ impl SwapEvents for Alice {
     type State = Alice;
 
     fn transition(mut self, transition: Transition) -> (Self::State, Vec<Action>, Option<Error>) {
-        println!("{} - {}", &self.state, &transition);
+        if let (Transition::BchConfirmedTx(transaction, ..), Some(contract)) =
+            (&transition, self.get_contract_pair())
+        {
+            self.txids.observe(&contract, transaction);
+            self.tokens.observe(&contract, transaction);
+        }
+        if self.is_replay(&transition) {
+            return (self, vec![], None);
+        }
 
         let current_state = self.state.clone();
         match (current_state, transition) {
-            (State::Init, Transition::Msg0 { keys, receiving }) => {
+            (State::Init, Transition::KeyCommitment(commitment))
+                if self.swap.key_commitment && self.swap.peer_commitment.is_none() =>
+            {
+                self.swap.peer_commitment = Some(commitment);
+                return (self, vec![], None);
+            }
+            (
+                State::Init,
+                Transition::Msg0 {
+                    keys,
+                    receiving,
+                    peer,
+                },
+            ) => {
+                let ours = self.get_public_keys();
+                if let Err(e) = check_msg0(&ours, &keys, &receiving) {
+                    return (self, vec![Action::SafeDelete], Some(e));
+                }
                 let is_valid_keys = proof::verify(&keys.proof, keys.spend_bch, keys.monero_spend);
                 if !is_valid_keys {
                     return (self, vec![Action::SafeDelete], Some(Error::InvalidProof));
                 }
 
+                let version = match PROTOCOL_VERSION.negotiate(&peer.protocol_version) {
+                    Some(version) => version,
+                    None => {
+                        return (
+                            self,
+                            vec![Action::SafeDelete],
+                            Some(Error::IncompatibleVersion),
+                        )
+                    }
+                };
+                if self.swap.key_commitment && self.swap.peer_commitment != Some(keys.commitment())
+                {
+                    return (
+                        self,
+                        vec![Action::SafeDelete],
+                        Some(Error::InvalidCommitment),
+                    );
+                }
+
+                self.swap.protocol_version = Some(version);
+                self.swap.contract_version =
+                    PeerInfo::for_swap(self.swap.strict_contract).common_contract_version(&peer);
+                self.swap.peer = Some(peer);
+                self.swap.swap_id = Some(self.swap.derive_id(&ours, &keys));
+
                 let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
-                let contract = ContractPair::create(
-                    1000,
-                    receiving.clone().into_bytes(),
-                    keys.ves.clone(),
-                    self.swap.bch_recv.to_bytes().clone(),
-                    self.swap.keys.ves.public_key(&secp),
-                    self.swap.timelock1,
-                    self.swap.timelock2,
-                    self.swap.bch_network,
-                    self.swap.bch_amount,
-                );
+                let contract = self
+                    .swap
+                    .contract_options()
+                    .and_then(|options| {
+                        ContractPair::create(
+                            self.swap.contract_mining_fee(),
+                            receiving.clone().into_bytes(),
+                            keys.ves.clone(),
+                            self.swap.bch_recv.to_bytes().clone(),
+                            self.swap.keys.ves.public_key(&secp),
+                            self.swap.timelock1,
+                            self.swap.timelock2,
+                            self.swap.bch_network,
+                            self.swap.bch_amount,
+                            options,
+                        )
+                    })
+                    .map(|contract| contract.with_token_policy(self.swap.token_policy));
 
                 match contract {
-                    None => return (self, vec![Action::SafeDelete], Some(Error::InvalidTimelock)),
-                    Some(contract) => {
+                    Err(e) => return (self, vec![Action::SafeDelete], Some(e.into())),
+                    Ok(contract) => {
                         self.state = State::WithBobKeys(Value0 {
                             bob_bch_recv: receiving.into_bytes(),
                             contract_pair: contract,
@@ -236,9 +760,15 @@ impl SwapEvents for Alice {
                 );
             }
 
-            (State::ContractMatch(props), Transition::BchConfirmedTx(transaction, _)) => {
+            (State::ContractMatch(props), Transition::BchConfirmedTx(transaction, conf, _)) => {
                 match props.contract_pair.analyze_tx(&transaction) {
+                    Some((_, TransactionType::ToSwapLock))
+                        if conf < self.swap.bch_confirmations.funding =>
+                    {
+                        return (self, vec![], None);
+                    }
                     Some((outpoint, TransactionType::ToSwapLock)) => {
+                        let parts = props.contract_pair.funding_parts(&transaction);
                         self.state = State::BchLocked(Value1 {
                             bob_keys: props.bob_keys,
                             bob_bch_recv: props.bob_bch_recv,
@@ -246,6 +776,8 @@ impl SwapEvents for Alice {
                             shared_keypair: props.shared_keypair,
 
                             outpoint,
+                            parts,
+                            xmr_lock_proof: None,
                         });
 
                         let xmr_amount = self.swap.xmr_amount;
@@ -255,51 +787,85 @@ impl SwapEvents for Alice {
                         );
                         return (self, vec![Action::LockXmr(xmr_amount, address)], None);
                     }
+                    // never locking XMR for it, bob refunds it after timelock1
+                    Some((_, TransactionType::Underfunded(_)))
+                    | Some((_, TransactionType::Overfunded(_))) => {
+                        return (self, vec![], Some(Error::InvalidBchAmount))
+                    }
                     _ => return (self, vec![], Some(Error::InvalidTransaction)),
                 }
             }
 
-            (State::BchLocked(props), Transition::BchConfirmedTx(transaction, _)) => {
-                if let Some((_, TransactionType::ToBob)) =
-                    props.contract_pair.analyze_tx(&transaction)
-                {
-                    let script = transaction.input[0].script_sig.clone();
-                    let decsig = match get_signature(script) {
-                        Some(sig) => {
-                            match ecdsa_fun::Signature::from_bytes(sig.serialize_compact()) {
-                                Some(v) => v,
-                                None => return (self, vec![], Some(Error::InvalidTransaction)),
-                            }
-                        }
-                        _ => return (self, vec![], Some(Error::InvalidTransaction)),
-                    };
-
-                    let bob_spend = AdaptorSignature::recover_decryption_key(
-                        props.bob_keys.spend_bch,
-                        decsig,
-                        self.get_refunc_enc_sig()
-                            .expect("Enc sig should be open at State::BchLocked"),
-                    );
-
-                    let key_pair = monero::KeyPair {
-                        view: props.shared_keypair.view,
-                        spend: self.swap.keys.monero_spend + bob_spend,
-                    };
+            (State::BchLocked(props), Transition::BchConfirmedTx(transaction, conf, block)) => {
+                return self.follow_refund(props, &transaction, conf, block);
+            }
 
-                    self.state = State::Refund(
-                        monero::Address::from_keypair(self.swap.xmr_network, &key_pair),
-                        key_pair,
-                    );
+            (State::BchLocked(mut props), Transition::XmrLockProof(proof)) => {
+                self.txids.xmr_lock = Some(proof.txid.clone());
+                props.xmr_lock_proof = Some(proof);
+                self.state = State::BchLocked(props);
+                return (self, vec![], None);
+            }
 
+            // Bob's refund confirmed before our punish, his key share is
+            // worth more than racing a spent output
+            (State::Punish(props), Transition::BchConfirmedTx(transaction, conf, _))
+                if matches!(
+                    props.contract_pair.analyze_tx(&transaction),
+                    Some((_, TransactionType::ToBob))
+                ) =>
+            {
+                return self.recover_bob_key(
+                    &props.bob_keys,
+                    &props.shared_keypair,
+                    &transaction,
+                    conf,
+                );
+            }
+            // Another part of a split funding moved to the Refund contract
+            (State::Punish(mut props), Transition::BchConfirmedTx(transaction, conf, block))
+                if !props.parts.is_empty() =>
+            {
+                let outpoint = match props.contract_pair.analyze_tx(&transaction) {
+                    Some((outpoint, TransactionType::ToRefund)) => outpoint,
+                    _ => return (self, vec![], None),
+                };
+                let known = props.parts.iter().any(|(part, _)| *part == outpoint);
+                let refund = &props.contract_pair.refund;
+                let expired = refund.expiry.is_some_and(|expiry| block.expired(expiry));
+                if known || (!expired && !block.timelock_expired(refund.timelock, conf)) {
                     return (self, vec![], None);
                 }
 
+                // every part left takes the expiry once reached
+                props.expired |= expired;
+                props.parts.push((outpoint, transaction.output[0].value));
+                self.state = State::Punish(props);
+                let punish = self.punish().pop().map(Action::BroadcastTx);
+                return (self, punish.into_iter().collect(), None);
+            }
+            // Replayed history, or our own punish confirming
+            (State::Punish(_), Transition::BchConfirmedTx(..)) => {
                 return (self, vec![], None);
             }
 
             (State::ValidEncSig(_), Transition::EncSig(_)) => {
                 return (self, vec![], None);
             }
+            // Our claim may lose the race to Bob's move to the Refund once
+            // timelock1 passed, then only his refund gives us his key share
+            (State::ValidEncSig(props), Transition::BchConfirmedTx(transaction, conf, block)) => {
+                let props = Value1 {
+                    bob_keys: props.bob_keys,
+                    bob_bch_recv: props.bob_bch_recv,
+                    contract_pair: props.contract_pair,
+                    shared_keypair: props.shared_keypair,
+                    outpoint: props.outpoint,
+                    parts: props.parts,
+                    xmr_lock_proof: None,
+                };
+                return self.follow_refund(props, &transaction, conf, block);
+            }
 
             (State::BchLocked(props), Transition::EncSig(encsig)) => {
                 let dec_sig = AdaptorSignature::decrypt_signature(
@@ -330,9 +896,21 @@ impl SwapEvents for Alice {
                     contract_pair: props.contract_pair,
                     shared_keypair: props.shared_keypair,
                     outpoint: props.outpoint,
+                    parts: props.parts,
                     dec_sig,
                 });
-                return (self, vec![Action::UnlockBchNormal], None);
+                let claims = self.get_unlock_normal_txs();
+                self.presigned.record_claims(&claims);
+                let actions = claims.into_iter().map(Action::BroadcastTx);
+                return (self, actions.collect(), None);
+            }
+            // before the SwapLock is funded alice has nothing to lock
+            (
+                State::Init | State::WithBobKeys(_) | State::ContractMatch(_) | State::Aborted,
+                Transition::Abort,
+            ) => {
+                self.state = State::Aborted;
+                return (self, vec![], None);
             }
             (_, _) => return (self, vec![], Some(Error::InvalidStateTransition)),
         }
@@ -342,8 +920,16 @@ impl SwapEvents for Alice {
         match &self.state {
             State::Init => {
                 let keys = self.get_public_keys();
+                // revealed once Bob committed to his
+                if self.swap.key_commitment && self.swap.peer_commitment.is_none() {
+                    return Some(Transition::KeyCommitment(keys.commitment()));
+                }
                 let receiving = self.swap.bch_recv.clone();
-                Some(Transition::Msg0 { keys, receiving })
+                Some(Transition::Msg0 {
+                    keys,
+                    receiving,
+                    peer: PeerInfo::for_swap(self.swap.strict_contract),
+                })
             }
             State::WithBobKeys(_) => {
                 let (bch_address, xmr_address) = self.get_contract().unwrap();
@@ -356,6 +942,8 @@ impl SwapEvents for Alice {
                 let enc_sig = self.get_refunc_enc_sig().unwrap();
                 Some(Transition::EncSig(enc_sig))
             }
+            State::BchLocked(props) => props.xmr_lock_proof.clone().map(Transition::XmrLockProof),
+            State::Aborted => Some(Transition::Abort),
             _ => None,
         }
     }
@@ -363,77 +951,485 @@ impl SwapEvents for Alice {
 
 pub struct Runner<'a> {
     pub inner: Alice,
-    pub bch: &'a TcpElectrum,
-    // pub monerod: &'a monero_rpc::DaemonJsonRpcClient,
-    // pub monero_wallet: &'a Mutex<monero_rpc::WalletClient>,
+    pub bch: &'a dyn ChainBackend,
+    /// Sends the XMR lock once Bob locked. None to only print it for a manual transfer
+    pub monero_wallet: Option<&'a Mutex<monero_rpc::WalletClient>>,
+    pub xmr_lock_options: LockOptions,
     pub min_bch_conf: u32,
+    /// Accept SwapLock funding from mempool as long as no double spend proof exist.
+    /// Caller must forward dsproof notifications to `Runner::on_dsproof`
+    pub zero_conf: bool,
+    /// Validate the claim transaction before broadcasting. None to skip
+    pub mempool_policy: Option<MempoolPolicy>,
+    /// Checkpointed after every applied transition. None to leave it to the caller
+    pub store: Option<&'a dyn SwapStore>,
+    /// Progress, broadcasts and failures. None prints them
+    pub events: Option<&'a dyn EventSink>,
+    /// Takes over the side effects of the transitions. None handles them here
+    pub effects: Option<&'a dyn SwapRunner>,
+    /// Time of the deadlines and the transcript. None for `SystemClock`
+    pub clock: Option<&'a dyn Clock>,
 }
 
-impl Runner<'_> {
-    pub async fn check_bch(&mut self) -> anyhow::Result<()> {
+impl<'a> Runner<'a> {
+    /// Continue `trade_id` from its checkpoint in `store`, rescanning the
+    /// contract addresses. The optional fields start at their defaults
+    pub async fn resume(
+        trade_id: &str,
+        store: &'a dyn SwapStore,
+        bch: &'a dyn ChainBackend,
+        monero_wallet: Option<&'a Mutex<monero_rpc::WalletClient>>,
+    ) -> Result<Runner<'a>, SwapError> {
+        let loaded = store.load(trade_id).await;
+        let inner = match loaded.map_err(|e| SwapError::Store(e.into()))? {
+            Some(SwapWrapper::Alice(inner)) => inner,
+            Some(SwapWrapper::Bob(_)) => return Err(SwapError::WrongSide(trade_id.to_owned())),
+            None => return Err(SwapError::NoCheckpoint(trade_id.to_owned())),
+        };
+
+        let mut runner = Runner {
+            inner,
+            bch,
+            monero_wallet,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: Some(MempoolPolicy::default()),
+            store: Some(store),
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        runner.resync().await?;
+        Ok(runner)
+    }
+
+    /// Unix time from `clock`
+    pub fn now(&self) -> u64 {
+        self.clock.unwrap_or(&SystemClock).now()
+    }
+
+    /// Wait `duration` on `clock`, for the polling loops
+    pub async fn sleep(&self, duration: Duration) {
+        self.clock.unwrap_or(&SystemClock).sleep(duration).await
+    }
+
+    fn emit(&self, event: Event) {
+        match self.events {
+            Some(events) => events.emit(event),
+            None => print_event("", &event),
+        }
+    }
+
+    pub async fn check_bch(&mut self) -> Result<(), SwapError> {
         let contract = self.inner.get_contract_pair();
         if let Some(contract) = contract {
             let swaplock = contract.swaplock.cash_address();
             let refund = contract.refund.cash_address();
-            for address in [swaplock, refund].into_iter() {
-                let txs = scan_address_conf_tx(&self.bch, &address, self.min_bch_conf).await;
-                println!("{}txs address {}", txs.len(), address);
-                for (tx, conf) in txs {
-                    let _ = self
-                        .priv_transition(Transition::BchConfirmedTx(tx, conf))
-                        .await;
+            let (bch, min_conf) = (self.bch, self.min_bch_conf);
+            let mut txs = Vec::new();
+            for address in [swaplock.clone(), refund].into_iter() {
+                let history = with_retry(|| bch.address_history(&address, 0)).await?;
+                txs.extend(
+                    history
+                        .into_iter()
+                        .map(|(tx, conf)| (address.clone(), tx, conf)),
+                );
+            }
+            // spends first, a move to the Refund past timelock2 must not start
+            // a punish once bob's refund confirmed
+            txs.sort_by_key(|(_, tx, _)| match contract.analyze_tx(tx) {
+                Some((_, TransactionType::ToBob | TransactionType::RefundToAlice)) => 0,
+                Some((_, TransactionType::ToRefund | TransactionType::SwapLockToAlice)) => 1,
+                _ => 2,
+            });
+            for (address, tx, conf) in txs {
+                // the funding we wait for, still too shallow
+                let awaited = matches!(
+                    (&self.inner.state, contract.analyze_tx(&tx)),
+                    (
+                        State::ContractMatch(_),
+                        Some((_, TransactionType::ToSwapLock))
+                    )
+                );
+                let required = min_conf.max(self.inner.swap.bch_confirmations.funding);
+                if awaited && conf < required {
+                    self.inner.confirmations = Some(Confirmations {
+                        chain: Chain::Bch,
+                        seen: conf.into(),
+                        required: required.into(),
+                    });
+                }
+                if conf < min_conf {
+                    continue;
+                }
+                self.emit(Event::FundingSeen {
+                    address,
+                    txid: tx.txid().to_string(),
+                    confirmations: conf.into(),
+                });
+                let timelock = contract.refund.timelock;
+                let txid = tx.txid().to_string();
+                // the expiry is checked against the tip median time past,
+                // whatever the timelock counts
+                let block = match contract.refund.expiry {
+                    Some(_) => with_retry(|| bch.block_info(&txid)).await?,
+                    None => timelock_block(bch, &txid, timelock).await?,
+                };
+                let _ = self
+                    .priv_transition(Transition::BchConfirmedTx(tx, conf, block))
+                    .await;
+            }
+
+            match self.inner.state {
+                State::ValidEncSig(_) => {
+                    let claims = self.inner.get_unlock_normal_txs();
+                    self.rebroadcast(claims, &swaplock).await?;
+                }
+                State::Punish(_) => {
+                    let punish = self.inner.punish();
+                    self.rebroadcast(punish, &contract.refund.cash_address())
+                        .await?;
+                }
+                _ => {}
+            }
+
+            if self.zero_conf {
+                if let State::ContractMatch(_) = self.inner.state {
+                    let bch = self.bch;
+                    for tx in with_retry(|| bch.address_mempool(&swaplock)).await? {
+                        self.emit(Event::FundingSeen {
+                            address: swaplock.clone(),
+                            txid: tx.txid().to_string(),
+                            confirmations: 0,
+                        });
+                        let _ = self
+                            .priv_transition(Transition::BchConfirmedTx(tx, 0, BchBlock::default()))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The claim or punish spends sent by `Action::BroadcastTx` can be lost
+    /// (server down, mempool eviction). Sent again until they confirm.
+    /// `address` is the contract they spend from
+    async fn rebroadcast(&self, txs: Vec<Transaction>, address: &str) -> Result<(), SwapError> {
+        if txs.is_empty() {
+            return Ok(());
+        }
+        let mempool = self.bch.address_mempool(address).await?;
+
+        for tx in txs {
+            let txid = tx.txid();
+            if self.bch.block_hash(&txid.to_string()).await?.is_some() {
+                continue;
+            }
+            if mempool.iter().any(|seen| seen.txid() == txid) {
+                continue;
+            }
+
+            // not seen anywhere, sent again
+            for event in self.broadcast(&[tx]).await {
+                self.emit(event);
+            }
+        }
+        Ok(())
+    }
+
+    /// Unconfirmed SwapLock funding while waiting for Bob to lock
+    pub async fn mempool_events(&self) -> Result<Vec<Event>, SwapError> {
+        let contract = match (&self.inner.state, self.inner.get_contract_pair()) {
+            (State::ContractMatch(_), Some(contract)) => contract,
+            _ => return Ok(vec![]),
+        };
+
+        let swaplock = contract.swaplock.cash_address();
+        Ok(funding_events(
+            &contract,
+            self.bch.address_mempool(&swaplock).await?,
+        ))
+    }
+
+    /// Track the confirmed swap transactions and report the ones reorganized out.
+    /// Confirmations are then verified again before any further transition
+    pub async fn check_reorg(&mut self, watch: &mut ReorgWatch) -> Result<Vec<Event>, SwapError> {
+        let contract = match self.inner.get_contract_pair() {
+            Some(contract) => contract,
+            None => return Ok(vec![]),
+        };
+
+        let addresses = [
+            contract.swaplock.cash_address(),
+            contract.refund.cash_address(),
+        ];
+        let events = watch.update(self.bch, &addresses).await?;
+        if !events.is_empty() {
+            self.check_bch().await?;
+        }
+
+        Ok(events)
+    }
+
+    /// Rescan everything after notifications were lost
+    pub async fn resync(&mut self) -> Result<(), SwapError> {
+        if self.zero_conf {
+            if let State::BchLocked(props) = &self.inner.state {
+                let txid = props.outpoint.txid.to_string();
+                if self.bch.has_dsproof(&txid).await.unwrap_or(false) {
+                    self.on_dsproof(&txid)?;
                 }
             }
         }
 
+        self.check_bch().await
+    }
+
+    /// Back out once Bob stayed silent past `watchdog`: abort before the
+    /// SwapLock is funded, rescan after so the punish path or a refund is
+    /// seen without waiting for a notification.
+    /// Returns `Event::DeadlineExpired` when it acted
+    pub async fn check_deadlines(
+        &mut self,
+        watchdog: &Watchdog,
+    ) -> Result<Option<Event>, SwapError> {
+        let now = self.now();
+        if self.inner.state_since == 0 {
+            // trades from before the watchdog get the full timeout from now
+            self.inner.state_since = now;
+        }
+        if !watchdog.expired(self.inner.state_since, now) {
+            return Ok(None);
+        }
+
+        // the SwapLock funding may not have been noticed yet
+        let state = self.inner.state.to_string();
+        self.resync().await?;
+        if self.inner.state.to_string() != state {
+            return Ok(None);
+        }
+
+        match self.inner.state {
+            State::Init | State::WithBobKeys(_) | State::ContractMatch(_) => {
+                self.priv_transition(Transition::Abort).await?
+            }
+            State::BchLocked(_) => {}
+            _ => return Ok(None),
+        }
+        Ok(Some(Event::DeadlineExpired { state }))
+    }
+
+    /// Errors if the double spend proof is for the SwapLock funding we rely on.
+    /// The swap must be aborted, xmr must not be locked
+    pub fn on_dsproof(&self, txid: &str) -> Result<(), SwapError> {
+        if let State::BchLocked(props) = &self.inner.state {
+            if props.outpoint.txid.to_string() == txid {
+                return Err(SwapError::DoubleSpendProof(txid.to_owned()));
+            }
+        }
+
         Ok(())
     }
 
-    pub async fn pub_transition(&mut self, transition: Transition) -> anyhow::Result<()> {
+    /// Proof of the XMR lock sent by `effects` or `monero_wallet`.
+    /// None when it is left to the user
+    async fn lock_xmr(
+        &self,
+        amount: monero::Amount,
+        address: monero::Address,
+    ) -> Option<Result<XmrLockProof, SwapError>> {
+        if let Some(effects) = self.effects {
+            match effects
+                .lock_xmr(&self.inner.swap.id, amount, &address)
+                .await
+            {
+                Ok(Some(proof)) => return Some(Ok(proof)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        let wallet = self.monero_wallet?;
+        let locked = xmr_wallet::lock(wallet, address, amount, self.xmr_lock_options).await;
+        Some(locked.map_err(SwapError::from))
+    }
+
+    async fn broadcast(&self, txs: &[Transaction]) -> Vec<Event> {
+        if txs.is_empty() {
+            return vec![];
+        }
+        if let Some(effects) = self.effects {
+            if let Some(events) = effects.broadcast(&self.inner.swap.id, txs).await {
+                return events;
+            }
+        }
+        let contracts = self.inner.get_contract_pair();
+        broadcast_txs(
+            self.bch,
+            txs,
+            self.mempool_policy.as_ref(),
+            contracts.as_ref(),
+        )
+        .await
+    }
+
+    /// Network delivered transition, checked against the counterparty's key
+    pub async fn pub_transition(&mut self, signed: SignedTransition) -> Result<(), SwapError> {
+        let signer = self.inner.get_peer_ves();
+        signed.verify(&self.inner.swap, signer.as_ref())?;
+
+        let transition = signed.transition;
         match &transition {
+            Transition::KeyCommitment(_) => {}
             Transition::Msg0 { .. } => {}
             Transition::Contract { .. } => {}
             Transition::EncSig(_) => {}
-            _ => bail!("priv transition"),
+            Transition::Abort => {}
+            _ => return Err(SwapError::NotFromPeer(transition.to_string())),
         }
 
         self.priv_transition(transition).await
     }
 
-    pub async fn priv_transition(&mut self, transition: Transition) -> anyhow::Result<()> {
-        let (new_state, actions, error) = self.inner.clone().transition(transition);
+    pub async fn priv_transition(&mut self, transition: Transition) -> Result<(), SwapError> {
+        let entry = TranscriptEntry::new(self.now(), &transition);
+        let (mut new_state, actions, error) = self.inner.clone().transition(transition);
         if let Some(err) = error {
-            bail!(err);
+            return Err(err.into());
         }
 
+        let mut broadcasts = Vec::new();
         for action in actions {
             match action {
                 Action::LockXmr(amount, addr) => {
-                    let msg = format!("  Send {} to {}  ", amount, addr.to_string());
-                    println!("|{:=^width$}|", "", width = msg.len());
-                    println!("|{msg}|");
-                    println!("|{:=^width$}|", "", width = msg.len());
+                    // never retried, the transfer may have gone out before the failure
+                    if let Some(locked) = self.lock_xmr(amount, addr).await {
+                        match locked {
+                            Ok(proof) => {
+                                self.emit(Event::Broadcasted {
+                                    txid: proof.txid.clone(),
+                                });
+                                // handed to Bob with the next transition
+                                new_state = new_state.transition(Transition::XmrLockProof(proof)).0;
+                                continue;
+                            }
+                            Err(e) => self.emit(Event::Error {
+                                message: format!("XMR lock failed: {e}"),
+                            }),
+                        }
+                    }
+
+                    self.emit(Event::NeedsUserAction {
+                        action: format!("Send {amount} to {addr}"),
+                    });
                 }
-                Action::UnlockBchNormal => {
-                    let mut buffer = Vec::new();
-                    let transaction = new_state.get_unlock_normal_tx().unwrap();
-                    transaction.consensus_encode(&mut buffer).unwrap();
-                    let tx_hex: String = buffer.encode_hex();
-
-                    println!("Broadcasting tx. Expected txid: {}", transaction.txid());
-                    println!("Hex: {}", tx_hex);
-                    let transaction_resp = self
-                        .bch
-                        .send("blockchain.transaction.broadcast", json!([tx_hex]))
-                        .await
-                        .unwrap();
-                    dbg!(transaction_resp);
+                Action::WatchBchAddress { swaplock, refund } => {
+                    if let Some(effects) = self.effects {
+                        let trade_id = &self.inner.swap.id;
+                        effects.watch_bch(trade_id, &swaplock, &refund).await;
+                    }
                 }
+                Action::BroadcastTx(transaction) => broadcasts.push(transaction),
                 _ => {}
             }
         }
+        for event in self.broadcast(&broadcasts).await {
+            self.emit(event);
+        }
 
+        let (from, to) = (self.inner.state.to_string(), new_state.state.to_string());
+        if from != to {
+            new_state.state_since = self.now();
+            new_state.confirmations = None;
+        }
+        new_state
+            .transcript
+            .record(entry, &self.inner.state, &new_state.state);
+        let advertised = match (&self.inner.swap.peer, &new_state.swap.peer) {
+            (None, Some(peer)) => Some(peer.clone()),
+            _ => None,
+        };
         self.inner = new_state;
+        if let Some(store) = self.store {
+            let swap = SwapWrapper::Alice(self.inner.clone());
+            let (trade_id, swap) = (&self.inner.swap.id, &swap);
+            let save = || async move {
+                let saved = store.save(trade_id, swap).await;
+                saved.map_err(|e| SwapError::Store(e.into()))
+            };
+            if let Err(e) = with_retry(save).await {
+                self.emit(Event::Error {
+                    message: format!("Checkpoint failed: {e}"),
+                });
+            }
+        }
+        if let Some(peer) = advertised {
+            self.emit(Event::PeerAdvertised(peer));
+        }
+        if from != to {
+            self.emit(Event::StateChanged { from, to });
+        }
         Ok(())
     }
+
+    /// Sweep the XMR recovered after Bob refunded to `Swap::xmr_payout_address`
+    /// once. `wallet` opens the `{swap_id}_refund` wallet, not the one
+    /// `monero_wallet` locks from. Returns the sweep txids when it happened on this call
+    pub async fn sweep_payout(
+        &mut self,
+        wallet: &Mutex<monero_rpc::WalletClient>,
+    ) -> Result<Option<Vec<String>>, SwapError> {
+        let destination = match self.inner.swap.xmr_payout_address {
+            Some(destination) => destination,
+            None => return Ok(None),
+        };
+        let keypair = match self.inner.state {
+            State::Refund(_, keypair) => keypair,
+            _ => return Ok(None),
+        };
+        if !self.inner.xmr_payout_txids.is_empty() {
+            return Ok(None);
+        }
+
+        let txids = xmr_wallet::sweep(
+            wallet,
+            &self.wallet_name("refund"),
+            self.inner.swap.xmr_network,
+            keypair,
+            self.inner.swap.xmr_restore_height,
+            destination,
+            SweepAmount::All,
+            self.inner.swap.xmr_fee_priority,
+            |_| true,
+        )
+        .await?;
+        for txid in &txids {
+            self.emit(Event::Broadcasted { txid: txid.clone() });
+        }
+
+        self.inner.xmr_payout_txids = txids.clone();
+        Ok(Some(txids))
+    }
+
+    /// Regenerate the XMR wallet recovered after Bob refunded and return its
+    /// balance, see `XmrRecovery::restore`. None in other states
+    pub async fn recover_xmr(
+        &self,
+        wallet: &Mutex<monero_rpc::WalletClient>,
+    ) -> Result<Option<RecoveredBalance>, SwapError> {
+        match self.inner.get_xmr_recovery() {
+            Some(recovery) => Ok(Some(recovery.restore(wallet).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `{swap_id}_{suffix}`, the trade id on older trades
+    fn wallet_name(&self, suffix: &str) -> String {
+        match &self.inner.swap.swap_id {
+            Some(swap_id) => format!("{swap_id}_{suffix}"),
+            None => format!("{}_{suffix}", self.inner.swap.id),
+        }
+    }
 }
@@ -0,0 +1,322 @@
+use std::{fmt::Display, future::Future, time::Duration};
+
+use async_trait::async_trait;
+use bitcoincash::{consensus::Encodable, Transaction};
+use hex::ToHex;
+use serde_json::json;
+use tokio::time::sleep;
+
+use crate::{
+    contract::ContractPair,
+    error::{Classify, ErrorClass},
+    protocol::{is_time_lock, BchBlock, Event},
+};
+
+use super::{
+    policy::MempoolPolicy, scan_address_conf_tx, scan_address_mempool_tx, subscribe_dsproof,
+    tip_height, ScanError, TcpElectrum, TcpElectrumError,
+};
+
+/// Attempts made by `with_retry`
+pub const RETRIES: u32 = 3;
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Failures of the BCH backends, whatever the server behind them
+#[derive(Debug, thiserror::Error)]
+pub enum ChainError {
+    #[error("electrum: {0}")]
+    Electrum(#[from] TcpElectrumError),
+    /// Error answered by the server, e.g. a rejected broadcast
+    #[error("chain rpc: {0}")]
+    Rpc(String),
+    /// The http request to the node failed
+    #[error("chain transport: {0}")]
+    Transport(String),
+    #[error("invalid chain response: {0}")]
+    InvalidResponse(String),
+}
+
+impl Classify for ChainError {
+    /// The server answers the same error again, connection drops and garbled
+    /// answers pass
+    fn class(&self) -> ErrorClass {
+        match self {
+            ChainError::Rpc(_) => ErrorClass::Fatal,
+            _ => ErrorClass::Recoverable,
+        }
+    }
+}
+
+impl From<ScanError> for ChainError {
+    fn from(value: ScanError) -> Self {
+        match value {
+            ScanError::Electrum(e) => ChainError::Electrum(e),
+            ScanError::Server(e) => ChainError::Rpc(e.to_string()),
+            e => ChainError::InvalidResponse(e.to_string()),
+        }
+    }
+}
+
+/// Run `request` until it succeeds, up to `RETRIES` times with a growing delay.
+/// Servers answer garbage or drop requests now and then, the failure is only
+/// returned when it persists. Fatal errors are returned at once
+pub async fn with_retry<T, E, F, Fut>(mut request: F) -> Result<T, E>
+where
+    E: Classify + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Ok(result) => return Ok(result),
+            Err(e) if attempt >= RETRIES || e.class() == ErrorClass::Fatal => return Err(e),
+            Err(e) => {
+                eprintln!("Chain request failed ({e}), attempt {attempt}/{RETRIES}");
+                sleep(RETRY_INTERVAL * attempt).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// BCH chain access needed by the runners
+#[async_trait]
+pub trait ChainBackend: Send + Sync {
+    /// Transactions paying to or spending from `address` with at least `min_conf` confirmations
+    async fn address_history(
+        &self,
+        address: &str,
+        min_conf: u32,
+    ) -> Result<Vec<(Transaction, u32)>, ChainError>;
+
+    /// Unconfirmed transactions of `address` without any known double spend proof
+    async fn address_mempool(&self, address: &str) -> Result<Vec<Transaction>, ChainError>;
+
+    /// Transaction and its confirmations, 0 when in mempool
+    async fn get_transaction(&self, txid: &str) -> Result<(Transaction, u32), ChainError>;
+
+    /// Returns the txid
+    async fn broadcast(&self, tx: &Transaction) -> Result<String, ChainError>;
+
+    async fn has_dsproof(&self, txid: &str) -> Result<bool, ChainError>;
+
+    /// Hash of the block including `txid`, None while unconfirmed
+    async fn block_hash(&self, txid: &str) -> Result<Option<String>, ChainError>;
+
+    /// Block including `txid` with the tip's median time past, for the time
+    /// based timelocks. All 0 while unconfirmed
+    async fn block_info(&self, txid: &str) -> Result<BchBlock, ChainError>;
+}
+
+/// `block_info` of a `BchConfirmedTx` when `timelock` needs it, block based
+/// timelocks only count confirmations
+pub async fn timelock_block(
+    bch: &dyn ChainBackend,
+    txid: &str,
+    timelock: u32,
+) -> Result<BchBlock, ChainError> {
+    if !is_time_lock(timelock) {
+        return Ok(BchBlock::default());
+    }
+    with_retry(|| bch.block_info(txid)).await
+}
+
+/// Handles every `Action::BroadcastTx` of a transition, in order since a tx may
+/// spend an earlier one. Each is checked against `policy` first, the spent
+/// values coming from the batch or the chain, and its spends of `contracts`
+/// with `ContractPair::check_spend`. Never fails, broadcasts are
+/// retried with `with_retry` and failures reported as `Event::Error` with the
+/// tx hex for a manual broadcast
+pub async fn broadcast_txs(
+    bch: &dyn ChainBackend,
+    txs: &[Transaction],
+    policy: Option<&MempoolPolicy>,
+    contracts: Option<&ContractPair>,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+    for tx in txs {
+        let txid = tx.txid();
+        if let Some(policy) = policy {
+            match spent_values(bch, tx, txs).await {
+                Some(values) => {
+                    let checked = policy.check(tx, &values).and_then(|_| {
+                        contracts.map_or(Ok(()), |contracts| contracts.check_spend(tx, policy))
+                    });
+                    if let Err(e) = checked {
+                        events.push(Event::Error {
+                            message: format!("{txid} may be rejected: {e}"),
+                        });
+                    }
+                }
+                None => events.push(Event::Error {
+                    message: format!("{txid} not checked, unknown spent output"),
+                }),
+            }
+        }
+
+        match with_retry(|| bch.broadcast(tx)).await {
+            Ok(txid) => events.push(Event::Broadcasted { txid }),
+            Err(e) => events.push(Event::Error {
+                message: format!("Broadcast of {txid} failed: {e}. Hex: {}", encode_tx(tx)),
+            }),
+        }
+    }
+
+    events
+}
+
+/// Value of each output `tx` spends, looked up in `batch` then on chain
+async fn spent_values(
+    bch: &dyn ChainBackend,
+    tx: &Transaction,
+    batch: &[Transaction],
+) -> Option<Vec<u64>> {
+    let mut values = Vec::new();
+    for input in &tx.input {
+        let previous = input.previous_output;
+        let parent = match batch.iter().find(|parent| parent.txid() == previous.txid) {
+            Some(parent) => parent.clone(),
+            None => {
+                bch.get_transaction(&previous.txid.to_string())
+                    .await
+                    .ok()?
+                    .0
+            }
+        };
+        values.push(parent.output.get(previous.vout as usize)?.value);
+    }
+    Some(values)
+}
+
+pub(crate) fn decode_tx(tx_hex: &str) -> Result<Transaction, ChainError> {
+    let bytes = hex::decode(tx_hex).map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+    bitcoincash::consensus::deserialize(&bytes)
+        .map_err(|e| ChainError::InvalidResponse(e.to_string()))
+}
+
+pub(crate) fn encode_tx(tx: &Transaction) -> String {
+    let mut buffer = Vec::new();
+    tx.consensus_encode(&mut buffer).unwrap();
+    buffer.encode_hex()
+}
+
+/// Median time past of the block at the end of `times`, its 11 last header times
+fn median_time_past(times: &[u32]) -> u32 {
+    let mut times = times[times.len().saturating_sub(11)..].to_vec();
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or(0)
+}
+
+impl TcpElectrum {
+    /// Header time of the blocks from `start`, `count` of them at most
+    async fn header_times(&self, start: u32, count: u32) -> Result<Vec<u32>, ChainError> {
+        let response = self
+            .send("blockchain.block.headers", json!([start, count]))
+            .await?;
+        let result = electrum_result(&response)?;
+        let headers = result["hex"]
+            .as_str()
+            .ok_or(ChainError::InvalidResponse("missing hex".to_owned()))?;
+        let headers =
+            hex::decode(headers).map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+
+        // 80 bytes headers, the time is at 68
+        Ok(headers
+            .chunks_exact(80)
+            .map(|header| u32::from_le_bytes([header[68], header[69], header[70], header[71]]))
+            .collect())
+    }
+}
+
+fn electrum_result(response: &str) -> Result<serde_json::Value, ChainError> {
+    let mut response = serde_json::from_str::<serde_json::Value>(response)
+        .map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+    if !response["error"].is_null() {
+        return Err(ChainError::Rpc(response["error"].to_string()));
+    }
+    Ok(response["result"].take())
+}
+
+#[async_trait]
+impl ChainBackend for TcpElectrum {
+    async fn address_history(
+        &self,
+        address: &str,
+        min_conf: u32,
+    ) -> Result<Vec<(Transaction, u32)>, ChainError> {
+        let txs = scan_address_conf_tx(self, address, min_conf).await?;
+        Ok(txs
+            .into_iter()
+            .map(|(tx, conf)| (tx, conf as u32))
+            .collect())
+    }
+
+    async fn address_mempool(&self, address: &str) -> Result<Vec<Transaction>, ChainError> {
+        Ok(scan_address_mempool_tx(self, address).await?)
+    }
+
+    async fn get_transaction(&self, txid: &str) -> Result<(Transaction, u32), ChainError> {
+        let response = self
+            .send("blockchain.transaction.get", json!([txid, true]))
+            .await?;
+        let result = electrum_result(&response)?;
+
+        let tx_hex = result["hex"]
+            .as_str()
+            .ok_or(ChainError::InvalidResponse("missing hex".to_owned()))?;
+        let confirmations = result["confirmations"].as_u64().unwrap_or(0);
+        Ok((decode_tx(tx_hex)?, confirmations as u32))
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<String, ChainError> {
+        let response = self
+            .send("blockchain.transaction.broadcast", json!([encode_tx(tx)]))
+            .await?;
+        let result = electrum_result(&response)?;
+
+        result
+            .as_str()
+            .map(|txid| txid.to_owned())
+            .ok_or(ChainError::InvalidResponse(result.to_string()))
+    }
+
+    async fn has_dsproof(&self, txid: &str) -> Result<bool, ChainError> {
+        Ok(subscribe_dsproof(self, txid).await?)
+    }
+
+    async fn block_hash(&self, txid: &str) -> Result<Option<String>, ChainError> {
+        let response = self
+            .send("blockchain.transaction.get", json!([txid, true]))
+            .await?;
+        let result = electrum_result(&response)?;
+
+        Ok(result["blockhash"].as_str().map(|hash| hash.to_owned()))
+    }
+
+    async fn block_info(&self, txid: &str) -> Result<BchBlock, ChainError> {
+        let (_, conf) = self.get_transaction(txid).await?;
+        if conf == 0 {
+            return Ok(BchBlock::default());
+        }
+        let tip = tip_height(self).await? as u32;
+        let height = (tip + 1).saturating_sub(conf);
+
+        // the block and the 11 before, then the 11 last
+        let start = height.saturating_sub(11);
+        let times = self.header_times(start, height - start + 1).await?;
+        let (previous, time) = match times.split_last() {
+            Some((time, previous)) => (previous, *time),
+            None => return Err(ChainError::InvalidResponse("missing headers".to_owned())),
+        };
+        let tip_start = tip.saturating_sub(10);
+        let tip_times = self.header_times(tip_start, tip - tip_start + 1).await?;
+
+        Ok(BchBlock {
+            height,
+            time,
+            mtp: median_time_past(previous),
+            tip_mtp: median_time_past(&tip_times),
+        })
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+use bitcoincash::{Script, Transaction, Txid};
+use serde_json::{json, Value};
+use tokio::sync::Mutex;
+
+use super::backend::{decode_tx, encode_tx, ChainBackend, ChainError};
+use crate::protocol::BchBlock;
+
+/// How many wallet transactions are listed per history scan
+const LIST_TRANSACTIONS_COUNT: u64 = 1000;
+
+/// Bitcoin Cash Node (or any bitcoind compatible) JSON-RPC.
+/// Full nodes don't index addresses, so watched addresses are imported as
+/// watch-only into the node wallet. The node must run with a wallet loaded
+pub struct BchnRpc {
+    url: String,
+    user: String,
+    password: String,
+    client: reqwest::Client,
+    id: AtomicU64,
+    imported: Mutex<HashSet<String>>,
+}
+
+impl BchnRpc {
+    /// `url` e.g. http://localhost:8332/wallet/swap
+    pub fn new(url: String, user: String, password: String) -> Self {
+        BchnRpc {
+            url,
+            user,
+            password,
+            client: reqwest::Client::new(),
+            id: AtomicU64::new(0),
+            imported: Mutex::new(HashSet::new()),
+        }
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ChainError> {
+        let id = self.id.fetch_add(1, Ordering::Relaxed);
+        let payload = json!({"jsonrpc": "1.0", "id": id, "method": method, "params": params});
+
+        // errors come back with an http error status and a json body
+        let mut response = self
+            .client
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.password))
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| ChainError::Transport(e.to_string()))?
+            .json::<Value>()
+            .await
+            .map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+
+        if !response["error"].is_null() {
+            return Err(ChainError::Rpc(response["error"].to_string()));
+        }
+        Ok(response["result"].take())
+    }
+
+    /// Import `address` as watch-only. No rescan, swap addresses are fresh
+    async fn watch(&self, address: &str) -> Result<(), ChainError> {
+        let mut imported = self.imported.lock().await;
+        if imported.contains(address) {
+            return Ok(());
+        }
+
+        self.call("importaddress", json!([address, "", false]))
+            .await?;
+        imported.insert(address.to_owned());
+        Ok(())
+    }
+
+    async fn script_pubkey(&self, address: &str) -> Result<Script, ChainError> {
+        let info = self.call("validateaddress", json!([address])).await?;
+        let script = info["scriptPubKey"]
+            .as_str()
+            .ok_or(ChainError::InvalidResponse(format!(
+                "invalid address {address}"
+            )))?;
+        let script = hex::decode(script).map_err(|e| ChainError::InvalidResponse(e.to_string()))?;
+        Ok(Script::from(script))
+    }
+
+    /// Every wallet transaction of `address`, confirmed or not
+    async fn wallet_history(&self, address: &str) -> Result<Vec<(Transaction, u32)>, ChainError> {
+        self.watch(address).await?;
+        let script = self.script_pubkey(address).await?;
+
+        let entries = self
+            .call(
+                "listtransactions",
+                json!(["*", LIST_TRANSACTIONS_COUNT, 0, true]),
+            )
+            .await?;
+        let mut txids = Vec::new();
+        for entry in entries.as_array().into_iter().flatten() {
+            if let Some(txid) = entry["txid"].as_str() {
+                if !txids.contains(&txid.to_owned()) {
+                    txids.push(txid.to_owned());
+                }
+            }
+        }
+
+        let mut txs = Vec::new();
+        for txid in txids {
+            txs.push(self.get_transaction(&txid).await?);
+        }
+
+        // funding first, then whatever spends it
+        let funding: HashSet<Txid> = txs
+            .iter()
+            .filter(|(tx, _)| tx.output.iter().any(|o| o.script_pubkey == script))
+            .map(|(tx, _)| tx.txid())
+            .collect();
+        let txs = txs
+            .into_iter()
+            .filter(|(tx, _)| {
+                funding.contains(&tx.txid())
+                    || tx
+                        .input
+                        .iter()
+                        .any(|i| funding.contains(&i.previous_output.txid))
+            })
+            .collect();
+
+        Ok(txs)
+    }
+}
+
+#[async_trait]
+impl ChainBackend for BchnRpc {
+    async fn address_history(
+        &self,
+        address: &str,
+        min_conf: u32,
+    ) -> Result<Vec<(Transaction, u32)>, ChainError> {
+        let txs = self.wallet_history(address).await?;
+        Ok(txs
+            .into_iter()
+            .filter(|(_, conf)| *conf > 0 && *conf >= min_conf)
+            .collect())
+    }
+
+    async fn address_mempool(&self, address: &str) -> Result<Vec<Transaction>, ChainError> {
+        let mut txs = Vec::new();
+        for (tx, conf) in self.wallet_history(address).await? {
+            if conf != 0 {
+                continue;
+            }
+            if self
+                .has_dsproof(&tx.txid().to_string())
+                .await
+                .unwrap_or(true)
+            {
+                continue;
+            }
+            txs.push(tx);
+        }
+
+        Ok(txs)
+    }
+
+    async fn get_transaction(&self, txid: &str) -> Result<(Transaction, u32), ChainError> {
+        // wallet txs don't need -txindex
+        let result = match self.call("gettransaction", json!([txid, true])).await {
+            Ok(result) => result,
+            Err(_) => self.call("getrawtransaction", json!([txid, true])).await?,
+        };
+
+        let tx_hex = result["hex"]
+            .as_str()
+            .ok_or(ChainError::InvalidResponse("missing hex".to_owned()))?;
+        // negative when conflicted
+        let confirmations = result["confirmations"].as_i64().unwrap_or(0).max(0);
+        Ok((decode_tx(tx_hex)?, confirmations as u32))
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<String, ChainError> {
+        let result = self
+            .call("sendrawtransaction", json!([encode_tx(tx)]))
+            .await?;
+        result
+            .as_str()
+            .map(|txid| txid.to_owned())
+            .ok_or(ChainError::InvalidResponse(result.to_string()))
+    }
+
+    async fn has_dsproof(&self, txid: &str) -> Result<bool, ChainError> {
+        // null when no proof is known
+        let result = self.call("getdsproof", json!([txid])).await?;
+        Ok(!result.is_null())
+    }
+
+    async fn block_hash(&self, txid: &str) -> Result<Option<String>, ChainError> {
+        let result = match self.call("gettransaction", json!([txid, true])).await {
+            Ok(result) => result,
+            Err(_) => self.call("getrawtransaction", json!([txid, true])).await?,
+        };
+
+        // conflicted txs keep the hash of the block they were removed from
+        if result["confirmations"].as_i64().unwrap_or(0) <= 0 {
+            return Ok(None);
+        }
+        Ok(result["blockhash"].as_str().map(|hash| hash.to_owned()))
+    }
+
+    async fn block_info(&self, txid: &str) -> Result<BchBlock, ChainError> {
+        let hash = match self.block_hash(txid).await? {
+            Some(hash) => hash,
+            None => return Ok(BchBlock::default()),
+        };
+        let header = self.call("getblockheader", json!([hash])).await?;
+        let field = |value: &Value, name: &str| {
+            value[name]
+                .as_u64()
+                .map(|field| field as u32)
+                .ok_or(ChainError::InvalidResponse(format!("missing {name}")))
+        };
+
+        // mediantime of a header is its own, locks count from the one before
+        let mtp = match header["previousblockhash"].as_str() {
+            Some(previous) => {
+                let previous = self.call("getblockheader", json!([previous])).await?;
+                field(&previous, "mediantime")?
+            }
+            None => 0,
+        };
+        let info = self.call("getblockchaininfo", json!([])).await?;
+
+        Ok(BchBlock {
+            height: field(&header, "height")?,
+            time: field(&header, "time")?,
+            mtp,
+            tip_mtp: field(&info, "mediantime")?,
+        })
+    }
+}
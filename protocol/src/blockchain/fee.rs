@@ -0,0 +1,90 @@
+use serde_json::json;
+
+use super::{TcpElectrum, TcpElectrumError};
+
+/// Upper bound size of a SwapLock or Refund spend, the biggest transactions of a swap
+pub const SPEND_TX_SIZE: u64 = 400;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FeeEstimate {
+    /// To confirm within the requested number of blocks
+    pub sat_per_byte: f64,
+    /// Minimum accepted by the server mempool
+    pub relay_sat_per_byte: f64,
+}
+
+impl FeeEstimate {
+    pub fn fee(&self, size: u64) -> u64 {
+        let rate = self.sat_per_byte.max(self.relay_sat_per_byte);
+        (rate * size as f64).ceil() as u64
+    }
+
+    /// Sats per 1000 bytes, see `Swap::mining_fee_rate`
+    pub fn fee_rate(&self) -> u64 {
+        (self.sat_per_byte.max(self.relay_sat_per_byte) * 1000.0).ceil() as u64
+    }
+
+    /// Fee of the biggest spend at the estimated rate. Every spend pays the
+    /// fee committed in its contract, it can't be bumped later
+    pub fn mining_fee(&self) -> u64 {
+        self.fee(SPEND_TX_SIZE)
+    }
+
+    /// Below this the claim and refund transactions won't relay
+    pub fn min_mining_fee(&self) -> u64 {
+        (self.relay_sat_per_byte * SPEND_TX_SIZE as f64).ceil() as u64
+    }
+}
+
+/// Electrum reports BCH/kB
+fn to_sat_per_byte(bch_per_kb: f64) -> f64 {
+    bch_per_kb * 100_000_000.0 / 1000.0
+}
+
+async fn request_rate(
+    bch_server: &TcpElectrum,
+    method: &str,
+    params: serde_json::Value,
+) -> Result<Option<f64>, TcpElectrumError> {
+    let response = bch_server.send(method, params).await?;
+    let response = serde_json::from_str::<serde_json::Value>(&response).unwrap_or_default();
+    // -1 when the server has not enough data
+    Ok(response["result"]
+        .as_f64()
+        .filter(|rate| *rate > 0.0)
+        .map(to_sat_per_byte))
+}
+
+/// Fee rate to confirm within `blocks`, falls back to the relay fee when unknown
+pub async fn fee_estimator(
+    bch_server: &TcpElectrum,
+    blocks: u32,
+) -> Result<FeeEstimate, TcpElectrumError> {
+    let relay_sat_per_byte = request_rate(bch_server, "blockchain.relayfee", json!([]))
+        .await?
+        .unwrap_or(1.0);
+    let sat_per_byte = request_rate(bch_server, "blockchain.estimatefee", json!([blocks]))
+        .await?
+        .unwrap_or(relay_sat_per_byte);
+
+    Ok(FeeEstimate {
+        sat_per_byte,
+        relay_sat_per_byte,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeeEstimate, SPEND_TX_SIZE};
+
+    #[test]
+    fn should_never_go_below_relay() {
+        let estimate = FeeEstimate {
+            sat_per_byte: 0.5,
+            relay_sat_per_byte: 1.0,
+        };
+        assert_eq!(estimate.mining_fee(), SPEND_TX_SIZE);
+        assert_eq!(estimate.min_mining_fee(), SPEND_TX_SIZE);
+        assert_eq!(estimate.fee_rate(), 1000);
+    }
+}
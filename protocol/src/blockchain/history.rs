@@ -0,0 +1,81 @@
+use bitcoincash::Transaction;
+use serde_json::json;
+
+use super::{fetch_tx, scan_result, tip_height, ScanError, TcpElectrum};
+
+/// Blocks requested per `blockchain.address.get_history` call
+pub const DEFAULT_WINDOW: i64 = 1000;
+
+/// Walks the confirmed history of an address a window of blocks at a time,
+/// instead of requesting and holding the whole history at once.
+/// Requires a server supporting `from_height` / `to_height` (protocol 1.5, Fulcrum)
+pub struct HistoryScanner {
+    pub address: String,
+    /// Last height fully processed. Only later transactions are fetched
+    pub cursor: i64,
+    pub window: i64,
+}
+
+impl HistoryScanner {
+    /// Start after `cursor`, 0 to scan from genesis
+    pub fn new(address: String, cursor: i64) -> Self {
+        HistoryScanner {
+            address,
+            cursor,
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Call `on_tx` with every confirmed transaction above the cursor, oldest first,
+    /// with the height it was mined at. The cursor moves after each window so
+    /// an interrupted scan resumes where it stopped, including after an error
+    pub async fn scan<F>(&mut self, bch_server: &TcpElectrum, mut on_tx: F) -> Result<(), ScanError>
+    where
+        F: FnMut(Transaction, i64),
+    {
+        let tip = tip_height(bch_server).await?;
+
+        while self.cursor < tip {
+            let end = (self.cursor + self.window.max(1)).min(tip);
+
+            // to_height is exclusive
+            let response = bch_server
+                .send(
+                    "blockchain.address.get_history",
+                    json!([self.address, self.cursor + 1, end + 1]),
+                )
+                .await?;
+            let history = scan_result(&response)?;
+            let mut entries = Vec::new();
+            for entry in history
+                .as_array()
+                .ok_or(ScanError::MissingField("result"))?
+            {
+                let height = entry["height"]
+                    .as_i64()
+                    .ok_or(ScanError::MissingField("height"))?;
+                let tx_hash = entry["tx_hash"]
+                    .as_str()
+                    .ok_or(ScanError::MissingField("tx_hash"))?;
+                entries.push((height, tx_hash.to_owned()));
+            }
+            entries.sort_by_key(|(height, _)| *height);
+
+            // fetched before calling on_tx so a failure leaves the window unprocessed
+            let mut txs = Vec::new();
+            for (height, tx_hash) in entries {
+                if height <= self.cursor || height > end {
+                    continue;
+                }
+                txs.push((fetch_tx(bch_server, &tx_hash, height).await?, height));
+            }
+            for (tx, height) in txs {
+                on_tx(tx, height);
+            }
+
+            self.cursor = end;
+        }
+
+        Ok(())
+    }
+}
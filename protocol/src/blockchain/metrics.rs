@@ -0,0 +1,108 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// Hooks called by `TcpElectrum`. Must be cheap, they run on every request
+pub trait ElectrumMetrics: Send + Sync {
+    fn request(&self, method: &str, latency: Duration, success: bool);
+    fn reconnect(&self, success: bool);
+}
+
+/// Upper bound of each latency bucket, in milliseconds. Last bucket is everything above
+pub const LATENCY_BUCKETS_MS: [u64; 6] = [10, 50, 100, 500, 1000, 5000];
+
+#[derive(Debug, Clone, Default)]
+pub struct MethodStats {
+    pub count: u64,
+    pub failures: u64,
+    pub total_latency: Duration,
+    pub max_latency: Duration,
+    /// Same order as `LATENCY_BUCKETS_MS` plus the overflow bucket
+    pub latency_histogram: [u64; LATENCY_BUCKETS_MS.len() + 1],
+}
+
+impl MethodStats {
+    pub fn average_latency(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total_latency / count as u32,
+        }
+    }
+}
+
+/// In memory counters, used by default
+#[derive(Default)]
+pub struct ElectrumStats {
+    methods: Mutex<HashMap<String, MethodStats>>,
+    reconnects: AtomicU64,
+    reconnect_failures: AtomicU64,
+}
+
+impl ElectrumStats {
+    /// Stats per method
+    pub fn snapshot(&self) -> HashMap<String, MethodStats> {
+        self.methods.lock().unwrap().clone()
+    }
+
+    /// (successful, failed)
+    pub fn reconnects(&self) -> (u64, u64) {
+        (
+            self.reconnects.load(Ordering::Relaxed),
+            self.reconnect_failures.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl ElectrumMetrics for ElectrumStats {
+    fn request(&self, method: &str, latency: Duration, success: bool) {
+        let mut methods = self.methods.lock().unwrap();
+        let stats = methods.entry(method.to_owned()).or_default();
+
+        stats.count += 1;
+        if !success {
+            stats.failures += 1;
+        }
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+
+        let millis = latency.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| millis <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        stats.latency_histogram[bucket] += 1;
+    }
+
+    fn reconnect(&self, success: bool) {
+        match success {
+            true => self.reconnects.fetch_add(1, Ordering::Relaxed),
+            false => self.reconnect_failures.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{ElectrumMetrics, ElectrumStats};
+
+    #[test]
+    fn should_bucket_latency() {
+        let stats = ElectrumStats::default();
+        stats.request("server.ping", Duration::from_millis(5), true);
+        stats.request("server.ping", Duration::from_millis(700), false);
+        stats.request("server.ping", Duration::from_secs(10), true);
+
+        let ping = stats.snapshot().remove("server.ping").unwrap();
+        assert_eq!(ping.count, 3);
+        assert_eq!(ping.failures, 1);
+        assert_eq!(ping.latency_histogram, [1, 0, 0, 0, 1, 0, 1]);
+        assert_eq!(ping.max_latency, Duration::from_secs(10));
+    }
+}
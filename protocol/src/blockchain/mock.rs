@@ -0,0 +1,1748 @@
+use std::{collections::HashSet, sync::Mutex};
+
+use async_trait::async_trait;
+use bitcoincash::{hashes::Hash, OutPoint, PackedLockTime, Script, Transaction, TxIn, TxOut, Txid};
+
+use super::backend::{ChainBackend, ChainError};
+use crate::{keys::bitcoin::address, protocol::BchBlock};
+
+/// Seconds between two mock blocks
+const BLOCK_INTERVAL: u32 = 600;
+
+struct MockTx {
+    tx: Transaction,
+    /// None while in mempool
+    height: Option<u32>,
+}
+
+#[derive(Default)]
+struct MockState {
+    height: u32,
+    txs: Vec<MockTx>,
+    dsproofs: HashSet<Txid>,
+    broadcasted: Vec<Transaction>,
+    /// First height replaced by each `reorg`
+    forks: Vec<u32>,
+}
+
+impl MockState {
+    /// Changes for every height at or above a fork
+    fn block_hash(&self, height: u32) -> String {
+        let fork = self.forks.iter().filter(|fork| **fork <= height).count();
+        format!("{height:056x}{fork:08x}")
+    }
+}
+
+/// Blocks come every `BLOCK_INTERVAL`, the median of the 11 last is the 6th
+fn median_time_past(height: u32) -> u32 {
+    height.saturating_sub(5) * BLOCK_INTERVAL
+}
+
+/// In memory chain to run the runners without any server.
+/// Broadcasted transactions land in the mempool until the next `mine_block`
+#[derive(Default)]
+pub struct MockChain {
+    state: Mutex<MockState>,
+}
+
+fn script_pubkey(cash_address: &str) -> Result<Script, ChainError> {
+    address::script_pubkey(cash_address)
+        .ok_or_else(|| ChainError::InvalidResponse(format!("invalid address {cash_address}")))
+}
+
+impl MockChain {
+    pub fn new() -> Self {
+        MockChain::default()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.state.lock().unwrap().height
+    }
+
+    pub fn add_mempool_tx(&self, tx: Transaction) {
+        self.state
+            .lock()
+            .unwrap()
+            .txs
+            .push(MockTx { tx, height: None });
+    }
+
+    /// Mine every mempool tx in a new block. Returns the new height
+    pub fn mine_block(&self) -> u32 {
+        let mut state = self.state.lock().unwrap();
+        state.height += 1;
+        let height = state.height;
+        for tx in state.txs.iter_mut().filter(|tx| tx.height.is_none()) {
+            tx.height = Some(height);
+        }
+        height
+    }
+
+    pub fn mine_blocks(&self, count: u32) -> u32 {
+        for _ in 0..count {
+            self.mine_block();
+        }
+        self.height()
+    }
+
+    /// Move an known tx to the given depth, 0 puts it back in mempool.
+    /// The chain grows if it is too short
+    pub fn set_confirmations(&self, txid: &Txid, confirmations: u32) {
+        let mut state = self.state.lock().unwrap();
+        state.height = state.height.max(confirmations);
+        let height = match confirmations {
+            0 => None,
+            conf => Some(state.height - conf + 1),
+        };
+        for tx in state.txs.iter_mut().filter(|tx| tx.tx.txid() == *txid) {
+            tx.height = height;
+        }
+    }
+
+    /// Replace the last `depth` blocks by an empty chain of the same length.
+    /// Their txs go back to mempool
+    pub fn reorg(&self, depth: u32) {
+        let mut state = self.state.lock().unwrap();
+        let fork = state.height.saturating_sub(depth) + 1;
+        for tx in state.txs.iter_mut() {
+            if tx.height.is_some_and(|height| height >= fork) {
+                tx.height = None;
+            }
+        }
+        state.forks.push(fork);
+    }
+
+    /// A mempool tx paying `amount` to `address` out of nowhere, as a wallet would
+    pub fn fund(
+        &self,
+        address: &str,
+        amount: bitcoincash::Amount,
+    ) -> Result<Transaction, ChainError> {
+        let script_pubkey = script_pubkey(address)?;
+        let mut state = self.state.lock().unwrap();
+        let mut seed = address.as_bytes().to_vec();
+        seed.extend(state.txs.len().to_le_bytes());
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&seed), 0),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: amount.to_sat(),
+                script_pubkey,
+                token: None,
+            }],
+        };
+        state.txs.push(MockTx {
+            tx: tx.clone(),
+            height: None,
+        });
+        Ok(tx)
+    }
+
+    pub fn add_dsproof(&self, txid: Txid) {
+        self.state.lock().unwrap().dsproofs.insert(txid);
+    }
+
+    /// Everything received through `ChainBackend::broadcast`, oldest first
+    pub fn broadcasted(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().broadcasted.clone()
+    }
+
+    /// Txs paying to `address` then txs spending those, with their confirmations
+    fn history(&self, address: &str) -> Result<Vec<(Transaction, u32)>, ChainError> {
+        let script = script_pubkey(address)?;
+        let state = self.state.lock().unwrap();
+
+        let funding: HashSet<Txid> = state
+            .txs
+            .iter()
+            .filter(|tx| tx.tx.output.iter().any(|o| o.script_pubkey == script))
+            .map(|tx| tx.tx.txid())
+            .collect();
+
+        Ok(state
+            .txs
+            .iter()
+            .filter(|tx| {
+                funding.contains(&tx.tx.txid())
+                    || tx
+                        .tx
+                        .input
+                        .iter()
+                        .any(|i| funding.contains(&i.previous_output.txid))
+            })
+            .map(|tx| {
+                let conf = tx.height.map(|h| state.height - h + 1).unwrap_or(0);
+                (tx.tx.clone(), conf)
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl ChainBackend for MockChain {
+    async fn address_history(
+        &self,
+        address: &str,
+        min_conf: u32,
+    ) -> Result<Vec<(Transaction, u32)>, ChainError> {
+        Ok(self
+            .history(address)?
+            .into_iter()
+            .filter(|(_, conf)| *conf > 0 && *conf >= min_conf)
+            .collect())
+    }
+
+    async fn address_mempool(&self, address: &str) -> Result<Vec<Transaction>, ChainError> {
+        let dsproofs = self.state.lock().unwrap().dsproofs.clone();
+        Ok(self
+            .history(address)?
+            .into_iter()
+            .filter(|(tx, conf)| *conf == 0 && !dsproofs.contains(&tx.txid()))
+            .map(|(tx, _)| tx)
+            .collect())
+    }
+
+    async fn get_transaction(&self, txid: &str) -> Result<(Transaction, u32), ChainError> {
+        let state = self.state.lock().unwrap();
+        state
+            .txs
+            .iter()
+            .find(|tx| tx.tx.txid().to_string() == txid)
+            .map(|tx| {
+                let conf = tx.height.map(|h| state.height - h + 1).unwrap_or(0);
+                (tx.tx.clone(), conf)
+            })
+            .ok_or(ChainError::Rpc(format!("unknown tx {txid}")))
+    }
+
+    async fn broadcast(&self, tx: &Transaction) -> Result<String, ChainError> {
+        let mut state = self.state.lock().unwrap();
+        let txid = tx.txid();
+        if state.txs.iter().any(|known| known.tx.txid() == txid) {
+            return Err(ChainError::Rpc("txn-already-known".to_owned()));
+        }
+
+        state.broadcasted.push(tx.clone());
+        state.txs.push(MockTx {
+            tx: tx.clone(),
+            height: None,
+        });
+        Ok(txid.to_string())
+    }
+
+    async fn has_dsproof(&self, txid: &str) -> Result<bool, ChainError> {
+        let state = self.state.lock().unwrap();
+        Ok(state.dsproofs.iter().any(|proof| proof.to_string() == txid))
+    }
+
+    async fn block_hash(&self, txid: &str) -> Result<Option<String>, ChainError> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .txs
+            .iter()
+            .find(|tx| tx.tx.txid().to_string() == txid)
+            .and_then(|tx| tx.height)
+            .map(|height| state.block_hash(height)))
+    }
+
+    async fn block_info(&self, txid: &str) -> Result<BchBlock, ChainError> {
+        let state = self.state.lock().unwrap();
+        let height = state
+            .txs
+            .iter()
+            .find(|tx| tx.tx.txid().to_string() == txid)
+            .and_then(|tx| tx.height);
+        Ok(match height {
+            Some(height) => BchBlock {
+                height,
+                time: height * BLOCK_INTERVAL,
+                mtp: median_time_past(height.saturating_sub(1)),
+                tip_mtp: median_time_past(state.height),
+            },
+            None => BchBlock::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use bitcoincash::{
+        hashes::Hash, OutPoint, PackedLockTime, PubkeyHash, Script, Transaction, TxIn, TxOut,
+    };
+    use tokio::sync::Mutex;
+
+    use super::MockChain;
+    use crate::{
+        alice::{self, Alice},
+        blockchain::{
+            backend::{broadcast_txs, encode_tx},
+            policy::MempoolPolicy,
+        },
+        bob::{self, Bob},
+        clock::{Clock, MockClock},
+        contract::{Rejection, TransactionType},
+        error::SwapError,
+        keys::{bitcoin::address, bitcoin::random_private_key, bitcoin::Network, KeyPrivate},
+        manager::{ManagerOptions, SwapManager},
+        monerod::{MonerodPool, RpcOptions},
+        outcome::OutcomeKind,
+        peer::{PROTOCOL_VERSION, STRICT_CONTRACT_VERSION},
+        persist::MemoryStore,
+        protocol::{
+            Action, BchBlock, Error, Event, FeeAmount, OperatorFee, SignedTransition, Swap,
+            SwapEvents, SwapWrapper, Transition, XmrLockBlock, XmrLockProof, DEFAULT_MINING_FEE,
+            DEFAULT_XMR_CONFIRMATIONS,
+        },
+        runner::{DryRun, SwapRunner},
+        wallet_pool::WalletPool,
+        watchdog::Watchdog,
+        xmr_wallet::FeePriority,
+    };
+
+    fn swap(bch_network: Network) -> Swap {
+        let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+        let recv = random_private_key(bch_network)
+            .public_key(&secp)
+            .pubkey_hash();
+        Swap {
+            id: "mock".to_owned(),
+            xmr_network: monero::Network::Mainnet,
+            bch_network,
+            keys: KeyPrivate::random(bch_network),
+            bch_recv: Script::new_p2pkh(&recv),
+            xmr_amount: monero::Amount::from_pico(100000),
+            bch_amount: bitcoincash::Amount::from_sat(100000),
+            timelock1: 2,
+            timelock2: 2,
+            mining_fee: DEFAULT_MINING_FEE,
+            mining_fee_rate: None,
+            xmr_confirmations: DEFAULT_XMR_CONFIRMATIONS,
+            xmr_overpayment: Default::default(),
+            bch_confirmations: Default::default(),
+            token_policy: Default::default(),
+            bch_token: None,
+            operator_fee: None,
+            strict_contract: false,
+            contract_version: None,
+            refund_expiry: None,
+            xmr_payout_address: None,
+            xmr_payout_keys: None,
+            xmr_fee_priority: FeePriority::Default,
+            xmr_restore_height: 0,
+            peer: None,
+            protocol_version: None,
+            key_commitment: false,
+            peer_commitment: None,
+            swap_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_complete_swap_on_mock_chain() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+
+        // messages until bob can lock
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+        assert!(matches!(bob.state, bob::State::VerifiedEncSig(_)));
+        assert_eq!((bob.progress().stage, alice.progress().stage), (3, 2));
+        assert_eq!(bob.swap.protocol_version, Some(PROTOCOL_VERSION));
+        assert_eq!(alice.swap.protocol_version, Some(PROTOCOL_VERSION));
+        // both derived the same id from the keys they exchanged
+        assert!(bob.swap.swap_id.is_some());
+        assert_eq!(bob.swap.swap_id, alice.swap.swap_id);
+        // redelivered messages leave the states as they are
+        let msg0 = Transition::Msg0 {
+            keys: alice.get_public_keys(),
+            receiving: alice.swap.bch_recv.clone(),
+            peer: Default::default(),
+        };
+        let (replayed, _, error) = bob.clone().transition(msg0);
+        assert!(error.is_none());
+        assert!(matches!(replayed.state, bob::State::VerifiedEncSig(_)));
+        let enc_sig = alice.get_transition().unwrap();
+        assert!(bob.clone().transition(enc_sig).2.is_none());
+        // bob may have locked, only alice can still back out
+        assert!(bob.clone().transition(Transition::Abort).2.is_some());
+        let aborted = alice.clone().transition(Transition::Abort).0;
+        assert!(matches!(aborted.get_transition(), Some(Transition::Abort)));
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        chain.add_mempool_tx(funding.clone());
+        chain.mine_block();
+
+        let store = MemoryStore::default();
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: Some(&store),
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        alice.check_bch().await.unwrap();
+        assert!(matches!(alice.inner.state, alice::State::BchLocked(_)));
+
+        // alice locked xmr, bob sends the swaplock signature
+        let amount = bob.swap.xmr_amount;
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        // the lock reorged out before alice got the signature
+        let reverted = bob.clone().transition(Transition::XmrLockReorged).0;
+        assert!(matches!(reverted.state, bob::State::VerifiedEncSig(_)));
+        assert!(reverted.get_swaplock_enc_sig().is_none());
+        // alice never claims, bob refunds once timelock1 expired
+        let (stuck, actions, error) = bob.clone().transition(Transition::BchConfirmedTx(
+            funding.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        assert!(error.is_none() && actions.is_empty());
+        let timelock1 = bob.swap.timelock1;
+        let (refunded, actions, _) = stuck.transition(Transition::BchConfirmedTx(
+            funding.clone(),
+            timelock1,
+            BchBlock::default(),
+        ));
+        assert!(matches!(refunded.state, bob::State::ProceedRefund(_)));
+        assert_eq!(actions.len(), 2);
+        // same message signed by someone else
+        let mut forged = bob.get_signed_transition().unwrap();
+        forged.signature =
+            SignedTransition::sign(forged.transition.clone(), &swap(Network::Regtest)).signature;
+        assert!(alice.pub_transition(forged).await.is_err());
+        alice
+            .pub_transition(bob.get_signed_transition().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(chain.broadcasted().len(), 1);
+        chain.mine_block();
+        // confirmed, not sent again
+        alice.check_bch().await.unwrap();
+        assert_eq!(chain.broadcasted().len(), 1);
+        // the funding then the signature, rescans changed nothing
+        let transcript = alice.inner.transcript.entries();
+        assert_eq!(transcript.len(), 2);
+        assert_eq!(transcript[0].bch_tx, Some((funding.txid().to_string(), 1)));
+        assert_eq!(transcript[1].to, alice.inner.state.to_string());
+        assert!(transcript[1].message.is_some());
+        // checkpointed with the signature, a restarted alice picks up from there
+        let resumed = alice::Runner::resume("mock", &store, &chain, None)
+            .await
+            .unwrap();
+        assert!(matches!(resumed.inner.state, alice::State::ValidEncSig(..)));
+        assert_eq!(chain.broadcasted().len(), 1);
+
+        // never contacted, bob only reads the chain from now on
+        let rpc = || {
+            monero_rpc::RpcClientBuilder::new()
+                .build("http://127.0.0.1:1")
+                .unwrap()
+        };
+        let monerod = MonerodPool::new(
+            vec!["http://127.0.0.1:1".to_owned()],
+            &RpcOptions::default(),
+        )
+        .unwrap();
+        let monero_wallet = Mutex::new(rpc().wallet());
+        let mut bob = bob::Runner {
+            inner: bob,
+            trade_id: "mock".to_owned(),
+            bch: &chain,
+            monerod: &monerod,
+            monero_wallet: &monero_wallet,
+            xmr_scanner: None,
+            min_bch_conf: 1,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        bob.check_bch().await.unwrap();
+        assert!(matches!(bob.inner.state, bob::State::SwapSuccess(..)));
+        assert!(bob.inner.progress().is_finished());
+        let outcome = bob.inner.outcome().unwrap();
+        assert_eq!(outcome.kind, OutcomeKind::Success);
+        assert_eq!(outcome.xmr_received, amount.as_pico());
+        assert_eq!(outcome.txids.lock, Some(funding.txid().to_string()));
+        assert_eq!(outcome.txids.claims.len(), 1);
+    }
+
+    #[test]
+    fn should_reveal_keys_after_commitments() {
+        let committed = || Swap {
+            key_commitment: true,
+            ..swap(Network::Regtest)
+        };
+        let mut alice = Alice::new(committed());
+        let mut bob = Bob::new(committed());
+
+        // keys sent before bob committed are refused
+        let msg0 = Transition::Msg0 {
+            keys: alice.get_public_keys(),
+            receiving: alice.swap.bch_recv.clone(),
+            peer: Default::default(),
+        };
+        assert!(matches!(
+            bob.clone().transition(msg0).2,
+            Some(Error::InvalidCommitment)
+        ));
+
+        assert!(matches!(
+            alice.get_transition(),
+            Some(Transition::KeyCommitment(_))
+        ));
+        for _ in 0..4 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+        assert!(matches!(bob.state, bob::State::VerifiedEncSig(_)));
+    }
+
+    #[tokio::test]
+    async fn should_punish_stalled_refund() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        chain.add_mempool_tx(funding.clone());
+        chain.mine_block();
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        alice.check_bch().await.unwrap();
+        assert!(matches!(alice.inner.state, alice::State::BchLocked(_)));
+
+        // bob moves the bch to the refund contract after timelock1, then stalls
+        let timelock1 = bob.swap.timelock1;
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding,
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        let (to_refund, _) = bob.refund().unwrap().remove(0);
+        chain.add_mempool_tx(to_refund);
+        chain.mine_block();
+        alice.check_bch().await.unwrap();
+        assert!(matches!(alice.inner.state, alice::State::BchLocked(_)));
+
+        chain.mine_blocks(bob.swap.timelock2);
+        alice.check_bch().await.unwrap();
+        assert!(matches!(alice.inner.state, alice::State::Punish(_)));
+        let punish = chain.broadcasted().pop().unwrap();
+        assert!(matches!(
+            contract.analyze_tx(&punish),
+            Some((_, TransactionType::RefundToAlice))
+        ));
+        // in the mempool then mined, not sent again
+        let sent = chain.broadcasted().len();
+        alice.check_bch().await.unwrap();
+        chain.mine_block();
+        alice.check_bch().await.unwrap();
+        assert_eq!(chain.broadcasted().len(), sent);
+    }
+
+    #[tokio::test]
+    async fn should_recover_key_after_late_refund() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(alice.state, alice::State::BchLocked(_)));
+        let timelock1 = bob.swap.timelock1;
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        let (to_refund, to_bob) = bob.refund().unwrap().remove(0);
+
+        // already punishing when bob's refund confirms
+        let timelock2 = bob.swap.timelock2;
+        let punishing = alice
+            .clone()
+            .transition(Transition::BchConfirmedTx(
+                to_refund.clone(),
+                timelock2,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(punishing.state, alice::State::Punish(_)));
+        let claim = punishing.swap.bch_confirmations.claim;
+        let refunded = punishing
+            .transition(Transition::BchConfirmedTx(
+                to_bob.clone(),
+                claim,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(refunded.state, alice::State::Refund(..)));
+
+        // offline until both are deep, the refund history is read first
+        chain.add_mempool_tx(funding);
+        chain.mine_block();
+        chain.add_mempool_tx(to_refund);
+        chain.add_mempool_tx(to_bob);
+        chain.mine_block();
+        chain.mine_blocks(timelock2);
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        alice.check_bch().await.unwrap();
+        assert!(matches!(alice.inner.state, alice::State::Refund(..)));
+        assert!(chain.broadcasted().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_refund_after_foreign_move_to_refund() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        assert!(matches!(bob.state, bob::State::MoneroLocked(_)));
+
+        // the timelock path takes no signature, anyone sends it with its own fee
+        let timelock1 = bob.swap.timelock1;
+        let refunding = bob
+            .clone()
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        let (mut foreign, _) = refunding.refund().unwrap().remove(0);
+        foreign.output[0].value -= 500;
+        let spends_foreign = |tx: &Transaction| {
+            assert_eq!(
+                tx.input[0].previous_output,
+                OutPoint::new(foreign.txid(), 0)
+            );
+            assert_eq!(
+                tx.output[0].value,
+                foreign.output[0].value - contract.refund.mining_fee
+            );
+            assert!(matches!(
+                contract.analyze_tx(tx),
+                Some((_, TransactionType::ToBob))
+            ));
+            contract.check_spend(tx, &MempoolPolicy::default()).unwrap();
+        };
+
+        // confirmed before ours, only the Refund spend is left
+        let (refunding, actions, error) = refunding.transition(Transition::BchConfirmedTx(
+            foreign.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        assert!(error.is_none());
+        match &actions[..] {
+            [Action::BroadcastTx(tx)] => spends_foreign(tx),
+            _ => panic!("bob should only spend the Refund"),
+        }
+        let (_, to_bob) = refunding.refund().unwrap().remove(0);
+        spends_foreign(&to_bob);
+
+        // or before bob even started refunding
+        chain.add_mempool_tx(funding);
+        chain.mine_block();
+        chain.add_mempool_tx(foreign.clone());
+        chain.mine_block();
+        chain.mine_blocks(timelock1);
+        let rpc = || {
+            monero_rpc::RpcClientBuilder::new()
+                .build("http://127.0.0.1:1")
+                .unwrap()
+        };
+        let monerod = MonerodPool::new(
+            vec!["http://127.0.0.1:1".to_owned()],
+            &RpcOptions::default(),
+        )
+        .unwrap();
+        let monero_wallet = Mutex::new(rpc().wallet());
+        let mut bob = bob::Runner {
+            inner: bob,
+            trade_id: "mock".to_owned(),
+            bch: &chain,
+            monerod: &monerod,
+            monero_wallet: &monero_wallet,
+            xmr_scanner: None,
+            min_bch_conf: 1,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        bob.check_bch().await.unwrap();
+        assert!(matches!(bob.inner.state, bob::State::ProceedRefund(_)));
+        match &chain.broadcasted()[..] {
+            [tx] => spends_foreign(tx),
+            sent => panic!("bob should only spend the Refund, got {sent:?}"),
+        }
+    }
+
+    #[test]
+    fn should_recover_key_when_refund_beats_claim() {
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let (alice, actions, _) = alice.transition(Transition::BchConfirmedTx(
+            funding.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        let lock_address = match &actions[..] {
+            [Action::LockXmr(_, address)] => *address,
+            _ => panic!("alice should lock xmr"),
+        };
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        let alice = alice.transition(bob.get_transition().unwrap()).0;
+        assert!(matches!(alice.state, alice::State::ValidEncSig(..)));
+
+        // alice's claim is still unconfirmed when bob refunds after timelock1
+        let timelock1 = bob.swap.timelock1;
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding,
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(bob.state, bob::State::ProceedRefund(_)));
+        let (to_refund, to_bob) = bob.refund().unwrap().remove(0);
+
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(
+                to_refund,
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(alice.state, alice::State::ValidEncSig(..)));
+        let claim = alice.swap.bch_confirmations.claim;
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(
+                to_bob,
+                claim,
+                BchBlock::default(),
+            ))
+            .0;
+        match alice.state {
+            alice::State::Refund(address, _) => assert_eq!(address, lock_address),
+            state => panic!("alice should recover bob's key, got {state}"),
+        }
+    }
+
+    /// Locks xmr without a wallet and keeps the broadcasts to itself
+    #[derive(Default)]
+    struct Effects {
+        broadcasted: std::sync::Mutex<Vec<Transaction>>,
+    }
+
+    #[async_trait]
+    impl SwapRunner for Effects {
+        async fn lock_xmr(
+            &self,
+            _trade_id: &str,
+            _amount: monero::Amount,
+            _address: &monero::Address,
+        ) -> Result<Option<XmrLockProof>, SwapError> {
+            Ok(Some(XmrLockProof {
+                txid: "00".repeat(32),
+                tx_key: "11".repeat(32),
+            }))
+        }
+
+        async fn broadcast(&self, _trade_id: &str, txs: &[Transaction]) -> Option<Vec<Event>> {
+            self.broadcasted.lock().unwrap().extend_from_slice(txs);
+            Some(vec![])
+        }
+    }
+
+    #[tokio::test]
+    async fn should_hand_actions_to_effects() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        chain.add_mempool_tx(Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        });
+        chain.mine_block();
+        let effects = Effects::default();
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: Some(&effects),
+            clock: None,
+        };
+        alice.check_bch().await.unwrap();
+        // locked by the effects, the proof goes to bob
+        match alice.inner.get_transition() {
+            Some(Transition::XmrLockProof(proof)) => assert_eq!(proof.txid, "00".repeat(32)),
+            other => panic!("expected the xmr lock proof, got {other:?}"),
+        }
+
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        alice
+            .pub_transition(bob.get_signed_transition().unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(alice.inner.state, alice::State::ValidEncSig(..)));
+        // the claim never reached the chain backend
+        assert_eq!(effects.broadcasted.lock().unwrap().len(), 1);
+        assert!(chain.broadcasted().is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_dry_run_side_effects() {
+        let chain = MockChain::new();
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let dry_run = DryRun::new(Some(&chain));
+        let contract = bob.get_contract_pair().unwrap();
+        let swaplock = contract.swaplock.cash_address();
+        assert!(dry_run
+            .lock_bch("dry", bob.swap.bch_amount, &swaplock)
+            .await
+            .unwrap());
+        chain.mine_block();
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: Some(&dry_run),
+            clock: None,
+        };
+        alice.check_bch().await.unwrap();
+        let proof = match alice.inner.get_transition() {
+            Some(Transition::XmrLockProof(proof)) => proof,
+            other => panic!("expected the xmr lock proof, got {other:?}"),
+        };
+
+        let block = XmrLockBlock {
+            txid: proof.txid,
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        alice
+            .pub_transition(bob.get_signed_transition().unwrap())
+            .await
+            .unwrap();
+        assert!(matches!(alice.inner.state, alice::State::ValidEncSig(..)));
+        // the claim went to the mock chain only
+        let claim = chain.broadcasted().pop().unwrap();
+        assert!(matches!(
+            contract.analyze_tx(&claim),
+            Some((_, TransactionType::SwapLockToAlice))
+        ));
+        let actions = dry_run.actions();
+        assert_eq!(actions.len(), 3);
+        assert!(actions[2].starts_with("broadcast"));
+    }
+
+    #[tokio::test]
+    async fn should_abort_on_clock_deadline() {
+        let chain = MockChain::new();
+        let alice = Alice::new(swap(Network::Regtest));
+        let clock = MockClock::new(alice.state_since);
+        let watchdog = Watchdog::default();
+        let mut alice = alice::Runner {
+            inner: alice,
+            bch: &chain,
+            monero_wallet: None,
+            xmr_lock_options: Default::default(),
+            min_bch_conf: 1,
+            zero_conf: false,
+            mempool_policy: None,
+            store: None,
+            events: None,
+            effects: None,
+            clock: Some(&clock),
+        };
+        assert!(alice.check_deadlines(&watchdog).await.unwrap().is_none());
+
+        // a polling loop sleeping through the timeout, without waiting for it
+        alice.sleep(watchdog.timeout).await;
+        assert!(alice.check_deadlines(&watchdog).await.unwrap().is_none());
+        alice.sleep(Duration::from_secs(1)).await;
+        assert!(matches!(
+            alice.check_deadlines(&watchdog).await.unwrap(),
+            Some(Event::DeadlineExpired { .. })
+        ));
+        assert!(matches!(alice.inner.state, alice::State::Aborted));
+        assert_eq!(alice.inner.state_since, clock.now());
+    }
+
+    #[tokio::test]
+    async fn should_route_messages_of_managed_swaps() {
+        let chain = Arc::new(MockChain::new());
+        let monerod = MonerodPool::new(
+            vec!["http://127.0.0.1:1".to_owned()],
+            &RpcOptions::default(),
+        )
+        .unwrap();
+        let wallet = monero_rpc::RpcClientBuilder::new()
+            .build("http://127.0.0.1:1")
+            .unwrap()
+            .wallet();
+        let manager = Arc::new(SwapManager::new(
+            chain.clone(),
+            monerod,
+            WalletPool::new(vec![wallet]),
+            Box::new(MemoryStore::default()),
+            ManagerOptions::default(),
+        ));
+        manager
+            .add(SwapWrapper::Alice(Alice::new(swap(Network::Regtest))))
+            .await
+            .unwrap();
+        assert_eq!(manager.trade_ids(), ["mock"]);
+
+        // by trade id until the keys are exchanged, then by swap id
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            let id = bob
+                .swap
+                .swap_id
+                .clone()
+                .unwrap_or_else(|| "mock".to_owned());
+            if let Some(signed) = manager.outbound(&id).await.unwrap() {
+                bob = bob.transition(signed.transition).0;
+            }
+            if let Some(signed) = bob.get_signed_transition() {
+                manager.deliver(&id, signed).await.unwrap();
+            }
+        }
+        assert!(matches!(bob.state, bob::State::VerifiedEncSig(_)));
+        let swap_id = bob.swap.swap_id.clone().unwrap();
+        assert!(matches!(
+            manager.swap(&swap_id).await.unwrap(),
+            SwapWrapper::Alice(alice) if alice.swap.swap_id == Some(swap_id.clone())
+        ));
+
+        let swaplock = bob.get_contract_pair().unwrap().swaplock.cash_address();
+        chain.fund(&swaplock, bob.swap.bch_amount).unwrap();
+        chain.mine_block();
+        let checked = manager.check_all().await;
+        assert_eq!(checked.len(), 1);
+        assert!(checked[0].1.is_ok());
+        assert!(matches!(
+            manager.swap("mock").await.unwrap(),
+            SwapWrapper::Alice(alice) if matches!(alice.state, alice::State::BchLocked(_))
+        ));
+
+        let signed = bob.get_signed_transition().unwrap();
+        assert!(matches!(
+            manager.deliver("other", signed).await,
+            Err(SwapError::UnknownSwap(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_watch_bch_while_monerod_is_down() {
+        let chain = Arc::new(MockChain::new());
+        let monerod = MonerodPool::new(
+            vec!["http://127.0.0.1:1".to_owned()],
+            &RpcOptions::default(),
+        )
+        .unwrap();
+        let wallet = monero_rpc::RpcClientBuilder::new()
+            .build("http://127.0.0.1:1")
+            .unwrap()
+            .wallet();
+        let manager = SwapManager::new(
+            chain.clone(),
+            monerod,
+            WalletPool::new(vec![wallet]),
+            Box::new(MemoryStore::default()),
+            ManagerOptions::default(),
+        );
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+        assert!(matches!(bob.state, bob::State::VerifiedEncSig(_)));
+        let swaplock = bob.get_contract_pair().unwrap().swaplock.cash_address();
+        let timelock1 = bob.swap.timelock1;
+        chain.fund(&swaplock, bob.swap.bch_amount).unwrap();
+        manager.add(SwapWrapper::Bob(bob)).await.unwrap();
+
+        // the XMR check fails, bob still refunds at timelock1
+        chain.mine_block();
+        chain.mine_blocks(timelock1);
+        let events = manager.check("mock").await.unwrap();
+        assert!(events
+            .iter()
+            .any(|event| matches!(event, Event::Error { .. })));
+        assert!(matches!(
+            manager.swap("mock").await.unwrap(),
+            SwapWrapper::Bob(bob) if matches!(bob.state, bob::State::ProceedRefund(_))
+        ));
+        assert_eq!(chain.broadcasted().len(), 2);
+    }
+
+    #[test]
+    fn should_refund_misfunded_swaplock() {
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let value = bob.swap.bch_amount.to_sat() - 1;
+        let deposit = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        assert!(matches!(
+            contract.analyze_tx(&deposit),
+            Some((_, TransactionType::Underfunded(v))) if v == value
+        ));
+
+        // alice never locks xmr for it
+        let (alice, actions, error) = alice.transition(Transition::BchConfirmedTx(
+            deposit.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        assert!(matches!(alice.state, alice::State::ContractMatch(_)));
+        assert!(actions.is_empty());
+        assert!(matches!(error, Some(Error::InvalidBchAmount)));
+
+        let (bob, actions, error) = bob.transition(Transition::BchConfirmedTx(
+            deposit.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        assert!(actions.is_empty());
+        assert!(matches!(error, Some(Error::InvalidBchAmount)));
+        // refunding reveals bob's key share, the trade can't go on after it
+        let timelock1 = bob.swap.timelock1;
+        let (bob, actions, _) = bob.transition(Transition::BchConfirmedTx(
+            deposit.clone(),
+            timelock1,
+            BchBlock::default(),
+        ));
+        assert!(matches!(bob.state, bob::State::ProceedRefund(_)));
+        match &actions[..] {
+            [Action::BroadcastTx(tx1), Action::BroadcastTx(tx2)] => {
+                assert_eq!(tx1.input[0].previous_output.txid, deposit.txid());
+                assert_eq!(tx2.output[0].value, value - contract.refund_path_fee());
+                assert_eq!(tx2.output[0].script_pubkey, bob.swap.bch_recv);
+            }
+            _ => panic!("expected the deposit refund"),
+        }
+    }
+
+    #[test]
+    fn should_wait_for_funding_confirmations() {
+        let mut swap_alice = swap(Network::Regtest);
+        swap_alice.bch_confirmations = "3,0,0".parse().unwrap();
+        let mut alice = Alice::new(swap_alice);
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let (alice, actions, error) = alice.transition(Transition::BchConfirmedTx(
+            funding.clone(),
+            2,
+            BchBlock::default(),
+        ));
+        assert!(matches!(alice.state, alice::State::ContractMatch(_)));
+        assert!(actions.is_empty() && error.is_none());
+
+        let (alice, actions, _) =
+            alice.transition(Transition::BchConfirmedTx(funding, 3, BchBlock::default()));
+        assert!(matches!(alice.state, alice::State::BchLocked(_)));
+        assert!(matches!(actions[..], [Action::LockXmr(..)]));
+    }
+
+    #[test]
+    fn should_claim_split_funding() {
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        let amount = bob.swap.bch_amount.to_sat();
+        let part = |value| TxOut {
+            value,
+            script_pubkey: Script::from(contract.swaplock.locking_script()),
+            token: None,
+        };
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![part(amount / 2), part(amount - amount / 2)],
+        };
+        assert!(matches!(
+            contract.analyze_tx(&funding),
+            Some((_, TransactionType::ToSwapLock))
+        ));
+        assert_eq!(contract.funding_parts(&funding).len(), 2);
+        // a part too small to pay the fees of its own spend
+        let minimum = contract.minimum_amount();
+        let mut dust = funding.clone();
+        dust.output = vec![part(minimum - 1), part(amount - minimum + 1)];
+        assert!(matches!(
+            contract.analyze_tx(&dust),
+            Some((_, TransactionType::Rejected(Rejection::PartBelowMinimum)))
+        ));
+
+        let (alice, actions, _) = alice.transition(Transition::BchConfirmedTx(
+            funding.clone(),
+            1,
+            BchBlock::default(),
+        ));
+        assert!(matches!(alice.state, alice::State::BchLocked(_)));
+        assert!(matches!(actions[..], [Action::LockXmr(..)]));
+
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        let (alice, actions, _) = alice.transition(bob.get_transition().unwrap());
+        assert!(matches!(alice.state, alice::State::ValidEncSig(..)));
+        // one claim per part, each pays the fee
+        let claims = alice.get_unlock_normal_txs();
+        assert_eq!(actions.len(), 2);
+        assert_eq!(claims.len(), 2);
+        let outcome = alice.outcome().unwrap();
+        assert_eq!(outcome.kind, OutcomeKind::Success);
+        assert_eq!(outcome.bch_fees, contract.swaplock.mining_fee * 2);
+        assert_eq!(outcome.txids.claims.len(), 2);
+        for (claim, part) in claims.iter().zip(&funding.output) {
+            assert_eq!(
+                claim.output[0].value,
+                part.value - contract.swaplock.mining_fee
+            );
+            assert!(matches!(
+                contract.analyze_tx(claim),
+                Some((_, TransactionType::SwapLockToAlice))
+            ));
+        }
+
+        // the first claim already reveals alice's key
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                claims[0].clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(bob.state, bob::State::SwapSuccess(..)));
+    }
+
+    #[test]
+    fn should_pay_operator_fee_in_claims() {
+        let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+        let operator = random_private_key(Network::Regtest)
+            .public_key(&secp)
+            .pubkey_hash();
+        let fee = OperatorFee {
+            address: address::encode(&operator[..], "bchreg", address::P2PKH_VERSION),
+            amount: FeeAmount::BasisPoints(100),
+        };
+        let with_fee = || Swap {
+            operator_fee: Some(fee.clone()),
+            ..swap(Network::Regtest)
+        };
+        let mut alice = Alice::new(with_fee());
+        let mut bob = Bob::new(with_fee());
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        assert!(contract.refund.claim_fee.is_none());
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(funding, 1, BchBlock::default()))
+            .0;
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        let alice = alice.transition(bob.get_transition().unwrap()).0;
+
+        // 1% of the swap to the operator, right after alice's output
+        let claims = alice.get_unlock_normal_txs();
+        assert_eq!(claims.len(), 1);
+        let claim = &claims[0];
+        assert_eq!(claim.output[1].value, 1000);
+        assert_eq!(claim.output[1].script_pubkey, Script::new_p2pkh(&operator));
+        assert_eq!(
+            claim.output[0].value,
+            100000 - 1000 - contract.swaplock.mining_fee
+        );
+        assert!(matches!(
+            contract.analyze_tx(claim),
+            Some((_, TransactionType::SwapLockToAlice))
+        ));
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                claim.clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(bob.state, bob::State::SwapSuccess(..)));
+    }
+
+    #[test]
+    fn should_negotiate_strict_contracts() {
+        let negotiate = |alice_strict: bool, bob_strict: bool| {
+            let mut alice = Alice::new(Swap {
+                strict_contract: alice_strict,
+                ..swap(Network::Regtest)
+            });
+            let mut bob = Bob::new(Swap {
+                strict_contract: bob_strict,
+                ..swap(Network::Regtest)
+            });
+            for _ in 0..3 {
+                if let Some(transition) = alice.get_transition() {
+                    bob = bob.transition(transition).0;
+                }
+                if let Some(transition) = bob.get_transition() {
+                    alice = alice.transition(transition).0;
+                }
+            }
+            (alice, bob)
+        };
+
+        let (alice, bob) = negotiate(true, true);
+        assert_eq!(alice.swap.contract_version, Some(STRICT_CONTRACT_VERSION));
+        assert_eq!(bob.swap.contract_version, Some(STRICT_CONTRACT_VERSION));
+        let contract = bob.get_contract_pair().unwrap();
+        assert_eq!(contract.swaplock.strict_amount, Some(100000));
+        assert!(contract.refund.strict_amount.is_none());
+        assert_eq!(
+            contract.swaplock.cash_address(),
+            alice.get_contract_pair().unwrap().swaplock.cash_address()
+        );
+        // halves only the timelock path could spend
+        let half = TxOut {
+            value: 50000,
+            script_pubkey: Script::from(contract.swaplock.locking_script()),
+            token: None,
+        };
+        let split = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![half.clone(), half],
+        };
+        assert!(matches!(
+            contract.analyze_tx(&split),
+            Some((_, TransactionType::Rejected(Rejection::SplitFunding)))
+        ));
+
+        // one side without it, both fall back to the previous contracts
+        let (alice, bob) = negotiate(true, false);
+        assert_eq!(alice.swap.contract_version, Some(4));
+        assert_eq!(bob.swap.contract_version, Some(4));
+        let contract = alice.get_contract_pair().unwrap();
+        assert!(contract.swaplock.strict_amount.is_none());
+        assert_eq!(
+            contract.swaplock.cash_address(),
+            bob.get_contract_pair().unwrap().swaplock.cash_address()
+        );
+    }
+
+    #[test]
+    fn should_punish_after_refund_expiry() {
+        let expiry = 1_700_000_000;
+        let with_expiry = || Swap {
+            refund_expiry: Some(expiry),
+            ..swap(Network::Regtest)
+        };
+        let mut alice = Alice::new(with_expiry());
+        let mut bob = Bob::new(with_expiry());
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+
+        let contract = bob.get_contract_pair().unwrap();
+        assert_eq!(contract.refund.expiry, Some(expiry));
+        assert!(contract.swaplock.expiry.is_none());
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        let timelock1 = bob.swap.timelock1;
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding,
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        let (to_refund, _) = bob.refund().unwrap().remove(0);
+
+        // a single confirmation, far from timelock2, until the expiry is past
+        let at_expiry = BchBlock {
+            tip_mtp: expiry,
+            ..Default::default()
+        };
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(to_refund.clone(), 1, at_expiry))
+            .0;
+        assert!(matches!(alice.state, alice::State::BchLocked(_)));
+        let past = BchBlock {
+            tip_mtp: expiry + 1,
+            ..Default::default()
+        };
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(to_refund, 1, past))
+            .0;
+        assert!(matches!(alice.state, alice::State::Punish(_)));
+
+        let punish = alice.punish();
+        assert_eq!(punish.len(), 1);
+        let (sequence, lock_time) = contract.refund.expiry_lock().unwrap();
+        assert_eq!(punish[0].lock_time, lock_time);
+        assert_eq!(punish[0].input[0].sequence, sequence);
+        assert!(matches!(
+            contract.analyze_tx(&punish[0]),
+            Some((_, TransactionType::RefundToAlice))
+        ));
+        contract
+            .check_spend(&punish[0], &MempoolPolicy::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn should_presign_refunds_and_claims() {
+        let mut alice = Alice::new(swap(Network::Regtest));
+        let mut bob = Bob::new(swap(Network::Regtest));
+        for _ in 0..3 {
+            if let Some(transition) = alice.get_transition() {
+                bob = bob.transition(transition).0;
+            }
+            if let Some(transition) = bob.get_transition() {
+                alice = alice.transition(transition).0;
+            }
+        }
+        assert!(bob.presigned.is_empty());
+
+        let contract = bob.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: bob.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        // a single confirmation is enough, the refund waits for timelock1
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        assert!(matches!(bob.state, bob::State::VerifiedEncSig(_)));
+        assert_eq!(bob.presigned.refund.len(), 2);
+        let to_refund: Transaction =
+            bitcoincash::consensus::deserialize(&hex::decode(&bob.presigned.refund[0]).unwrap())
+                .unwrap();
+        let refund: Transaction =
+            bitcoincash::consensus::deserialize(&hex::decode(&bob.presigned.refund[1]).unwrap())
+                .unwrap();
+        assert_eq!(to_refund.input[0].previous_output.txid, funding.txid());
+        assert_eq!(refund.input[0].previous_output.txid, to_refund.txid());
+        assert!(matches!(
+            contract.analyze_tx(&refund),
+            Some((_, TransactionType::ToBob))
+        ));
+
+        // seen again, nothing is added
+        let bob = bob
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                2,
+                BchBlock::default(),
+            ))
+            .0;
+        assert_eq!(bob.presigned.refund.len(), 2);
+
+        let alice = alice
+            .transition(Transition::BchConfirmedTx(funding, 1, BchBlock::default()))
+            .0;
+        assert!(alice.presigned.claims.is_empty());
+        let block = XmrLockBlock {
+            txid: "00".repeat(32),
+            height: 1,
+            block_hash: "00".repeat(32),
+        };
+        let amount = bob.swap.xmr_amount;
+        let bob = bob.transition(Transition::XmrLockVerified(amount, block)).0;
+        let alice = alice.transition(bob.get_transition().unwrap()).0;
+        assert!(matches!(alice.state, alice::State::ValidEncSig(..)));
+        let claims: Vec<_> = alice
+            .get_unlock_normal_txs()
+            .iter()
+            .map(encode_tx)
+            .collect();
+        assert_eq!(alice.presigned.claims, claims);
+        assert!(alice.presigned.refund.is_empty());
+    }
+
+    fn spend(previous_output: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value,
+                script_pubkey: Script::new_p2pkh(&PubkeyHash::from_slice(&[7u8; 20]).unwrap()),
+                token: None,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn should_broadcast_batch_spending_itself() {
+        let chain = MockChain::new();
+        let funding = spend(OutPoint::null(), 100000);
+        chain.add_mempool_tx(funding.clone());
+        chain.mine_block();
+
+        // the child's spent value only exists in the batch
+        let parent = spend(OutPoint::new(funding.txid(), 0), 99000);
+        let child = spend(OutPoint::new(parent.txid(), 0), 98000);
+        let batch = [parent.clone(), child.clone()];
+        let policy = MempoolPolicy::default();
+
+        let events = broadcast_txs(&chain, &batch, Some(&policy), None).await;
+        assert_eq!(
+            events,
+            vec![
+                Event::Broadcasted {
+                    txid: parent.txid().to_string()
+                },
+                Event::Broadcasted {
+                    txid: child.txid().to_string()
+                },
+            ]
+        );
+        assert_eq!(chain.broadcasted(), batch.to_vec());
+
+        // already known, reported instead of failing the transition
+        let events = broadcast_txs(&chain, &batch[..1], None, None).await;
+        assert!(matches!(events[..], [Event::Error { .. }]));
+    }
+}
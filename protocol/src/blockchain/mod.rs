@@ -1,18 +1,75 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use self::{
+    metrics::{ElectrumMetrics, ElectrumStats},
+    transport::{BoxedRead, BoxedWrite, CertVerification},
+};
 use bitcoincash::Transaction;
+use lru::LruCache;
 use serde::Deserialize;
 use serde_json::json;
+
 use tokio::{
     io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
-    sync::{broadcast, oneshot, Mutex},
-    time::sleep,
+    net::TcpStream,
+    sync::{broadcast, oneshot, AcquireError, Mutex, Semaphore},
+    time::{sleep, timeout},
 };
 
+pub mod backend;
+pub mod bchn;
+pub mod fee;
+pub mod history;
+pub mod metrics;
+pub mod mock;
+pub mod policy;
+pub mod reorg;
+pub mod transport;
+
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
+/// Consecutive unanswered pings before the connection is considered dead
+const MAX_MISSED_PINGS: u32 = 3;
+
+#[derive(Clone)]
+pub struct ElectrumConfig {
+    /// Notifications kept for slow consumers before they get `Notification::ResyncRequired`
+    pub notification_capacity: usize,
+    pub metrics: Arc<dyn ElectrumMetrics>,
+    /// Requests sent without waiting for a response. Others wait in a fifo queue,
+    /// servers ban clients flooding them
+    pub max_in_flight: usize,
+    /// A request unanswered after it fails with `TcpElectrumError::Timeout` and frees its slot
+    pub request_timeout: Duration,
+    pub cert_verification: CertVerification,
+    /// Transactions kept by `scan_address_conf_tx` between polls
+    pub tx_cache_size: usize,
+    /// SOCKS5 proxy `host:port`, e.g. Tor at 127.0.0.1:9050
+    pub proxy: Option<String>,
+}
+
+impl Default for ElectrumConfig {
+    fn default() -> Self {
+        ElectrumConfig {
+            notification_capacity: 10,
+            metrics: Arc::new(ElectrumStats::default()),
+            max_in_flight: 16,
+            request_timeout: Duration::from_secs(30),
+            cert_verification: CertVerification::default(),
+            tx_cache_size: 1000,
+            proxy: None,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 struct HasId {
     id: u64,
@@ -20,60 +77,174 @@ struct HasId {
 
 pub struct TcpElectrum {
     futures: Arc<Mutex<HashMap<u64, oneshot::Sender<String>>>>,
-    producer: broadcast::Sender<String>,
+    producer: broadcast::Sender<Notification>,
 
     id: Arc<Mutex<u64>>,
-    stream_write: Arc<Mutex<OwnedWriteHalf>>,
+    stream_write: Arc<Mutex<BoxedWrite>>,
+
+    /// Only known when created with `TcpElectrum::connect`, required to reconnect
+    address: Option<Arc<String>>,
+    cert_verification: Arc<CertVerification>,
+    proxy: Option<Arc<String>>,
+    alive: Arc<AtomicBool>,
+    /// Incremented on every reconnect so stale readers don't mark the new connection dead
+    generation: Arc<AtomicU64>,
+    /// Replayed after reconnecting since the server forgets them
+    subscriptions: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    metrics: Arc<dyn ElectrumMetrics>,
+    /// Pings and subscription replays bypass it so keepalive isn't stuck behind a burst
+    in_flight: Arc<Semaphore>,
+    request_timeout: Duration,
+    tx_cache: Arc<std::sync::Mutex<LruCache<String, CachedTx>>>,
+}
+
+#[derive(Clone)]
+struct CachedTx {
+    tx: Transaction,
+    /// Height it was mined at when fetched. A different height in the history means a reorg
+    height: i64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum TcpElectrumError {
-    IoError(io::Error),
-    RecvError(oneshot::error::RecvError),
+    #[error("IoError {0}")]
+    IoError(#[source] io::Error),
+    /// The connection closed before the response
+    #[error("RecvError {0}")]
+    RecvError(#[source] oneshot::error::RecvError),
+    /// No response within `ElectrumConfig::request_timeout`
+    #[error("Timeout after {0:?}")]
+    Timeout(Duration),
+    /// The request queue was closed
+    #[error("Closed {0}")]
+    Closed(#[source] AcquireError),
 }
 
-impl std::fmt::Display for TcpElectrumError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::IoError(e) => write!(f, "IoError {e}"),
-            Self::RecvError(e) => write!(f, "RecvError {e}"),
-        }
+#[derive(Debug, thiserror::Error)]
+pub enum ScanError {
+    #[error("Electrum {0}")]
+    Electrum(#[from] TcpElectrumError),
+    /// Error object returned by the server
+    #[error("Server error {0}")]
+    Server(serde_json::Value),
+    #[error("InvalidJson {0}")]
+    InvalidJson(#[source] serde_json::Error),
+    #[error("MissingField {0}")]
+    MissingField(&'static str),
+    #[error("InvalidHex {0}")]
+    InvalidHex(#[source] hex::FromHexError),
+    #[error("InvalidTx {0}")]
+    InvalidTx(#[source] bitcoincash::consensus::encode::Error),
+}
+
+/// `result` of a response, the server error if any
+fn scan_result(response: &str) -> Result<serde_json::Value, ScanError> {
+    let mut response =
+        serde_json::from_str::<serde_json::Value>(response).map_err(ScanError::InvalidJson)?;
+    if !response["error"].is_null() {
+        return Err(ScanError::Server(response["error"].take()));
     }
+    Ok(response["result"].take())
+}
+
+#[derive(Debug, Clone)]
+pub enum Notification {
+    Message(String),
+    /// Notifications were lost, either because the consumer was too slow
+    /// or the connection was reestablished. Watched state must be rescanned
+    ResyncRequired,
 }
 
-impl std::error::Error for TcpElectrumError {}
+pub struct NotificationReceiver(broadcast::Receiver<Notification>);
+
+impl NotificationReceiver {
+    /// None when the client is gone
+    pub async fn recv(&mut self) -> Option<Notification> {
+        match self.0.recv().await {
+            Ok(notification) => Some(notification),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                eprintln!("[ELECTRUM] Consumer lagged, {skipped} notifications lost");
+                Some(Notification::ResyncRequired)
+            }
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+}
 
 impl TcpElectrum {
     pub fn new(stream: TcpStream) -> Self {
-        let (producer, _) = broadcast::channel(10);
-        let (stream_read, stream_write) = stream.into_split();
+        TcpElectrum::from_stream(transport::split(stream), None, ElectrumConfig::default())
+    }
 
-        let id = Arc::new(Mutex::new(0));
-        let futures = Arc::new(Mutex::new(HashMap::new()));
-        let stream_write = Arc::new(Mutex::new(stream_write));
+    /// Same as `TcpElectrum::new` but the connection is reestablished when it dies.
+    /// See `transport::connect` for the supported addresses
+    pub async fn connect(address: &str) -> io::Result<Self> {
+        TcpElectrum::connect_with(address, ElectrumConfig::default()).await
+    }
 
-        tokio::spawn({
-            let producer = producer.clone();
-            let futures = futures.clone();
-            async move {
-                let stream_read = BufReader::new(stream_read);
-                TcpElectrum::process_reads(stream_read, producer, futures).await;
-            }
-        });
+    pub async fn connect_with(address: &str, config: ElectrumConfig) -> io::Result<Self> {
+        let stream =
+            transport::connect(address, &config.cert_verification, config.proxy.as_deref()).await?;
+        Ok(TcpElectrum::from_stream(
+            stream,
+            Some(Arc::new(address.to_owned())),
+            config,
+        ))
+    }
+
+    fn from_stream(
+        (stream_read, stream_write): (BoxedRead, BoxedWrite),
+        address: Option<Arc<String>>,
+        config: ElectrumConfig,
+    ) -> Self {
+        let (producer, _) = broadcast::channel(config.notification_capacity);
 
         let server = TcpElectrum {
-            id,
-            futures,
+            id: Arc::new(Mutex::new(0)),
+            futures: Arc::new(Mutex::new(HashMap::new())),
             producer,
-            stream_write,
+            stream_write: Arc::new(Mutex::new(stream_write)),
+            address,
+            cert_verification: Arc::new(config.cert_verification),
+            proxy: config.proxy.map(Arc::new),
+            alive: Arc::new(AtomicBool::new(true)),
+            generation: Arc::new(AtomicU64::new(0)),
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            metrics: config.metrics,
+            in_flight: Arc::new(Semaphore::new(config.max_in_flight.max(1))),
+            request_timeout: config.request_timeout,
+            tx_cache: Arc::new(std::sync::Mutex::new(LruCache::new(
+                NonZeroUsize::new(config.tx_cache_size).unwrap_or(NonZeroUsize::MIN),
+            ))),
         };
+        server.spawn_reader(stream_read);
 
         tokio::spawn({
             let server = server.clone();
             async move {
+                let mut missed = 0;
                 loop {
-                    let _ = server.send("server.ping", json!([])).await;
-                    sleep(Duration::from_secs(5)).await;
+                    sleep(PING_INTERVAL).await;
+                    match server.request("server.ping", json!([]), PING_TIMEOUT).await {
+                        Ok(_) => missed = 0,
+                        Err(_) => missed += 1,
+                    }
+
+                    if missed < MAX_MISSED_PINGS && server.is_alive() {
+                        continue;
+                    }
+
+                    server.alive.store(false, Ordering::SeqCst);
+                    eprintln!("[ELECTRUM] Connection dead. missed pings: {missed}");
+                    let reconnected = server.reconnect().await;
+                    server.metrics.reconnect(reconnected.is_ok());
+                    match reconnected {
+                        Ok(_) => {
+                            println!("[ELECTRUM] Reconnected");
+                            missed = 0;
+                        }
+                        Err(e) => eprintln!("[ELECTRUM] Reconnect failed: {e}"),
+                    }
                 }
             }
         });
@@ -81,21 +252,41 @@ impl TcpElectrum {
         server
     }
 
+    fn spawn_reader(&self, stream_read: BoxedRead) {
+        let generation = self.generation.load(Ordering::SeqCst);
+        tokio::spawn({
+            let server = self.clone();
+            async move {
+                let stream_read = BufReader::new(stream_read);
+                TcpElectrum::process_reads(
+                    stream_read,
+                    server.producer.clone(),
+                    server.futures.clone(),
+                )
+                .await;
+
+                if server.generation.load(Ordering::SeqCst) == generation {
+                    server.alive.store(false, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
     async fn process_reads(
-        mut reader: BufReader<OwnedReadHalf>,
-        producer: broadcast::Sender<String>,
+        mut reader: BufReader<BoxedRead>,
+        producer: broadcast::Sender<Notification>,
         futures: Arc<Mutex<HashMap<u64, oneshot::Sender<String>>>>,
     ) {
         loop {
             let mut buf = String::new();
-            let _ = reader.read_line(&mut buf).await.unwrap();
-            if buf == "" {
-                break;
+            match reader.read_line(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
             }
 
             match serde_json::from_str::<HasId>(&buf) {
                 Err(_) => {
-                    let _ = producer.send(buf);
+                    let _ = producer.send(Notification::Message(buf));
                 }
                 Ok(HasId { id }) => {
                     if let Some(recv) = futures.lock().await.remove(&id) {
@@ -106,14 +297,100 @@ impl TcpElectrum {
         }
     }
 
-    pub fn subscribe(&self) -> broadcast::Receiver<String> {
-        self.producer.subscribe()
+    pub fn metrics(&self) -> Arc<dyn ElectrumMetrics> {
+        self.metrics.clone()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Open a new socket to the same server and replay subscriptions.
+    /// Requests waiting on the old socket fail with `RecvError`
+    pub async fn reconnect(&self) -> io::Result<()> {
+        let address = match &self.address {
+            Some(address) => address,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "no address to reconnect",
+                ))
+            }
+        };
+
+        // a changed certificate fails here, the connection stays dead
+        let (stream_read, stream_write) = transport::connect(
+            address,
+            &self.cert_verification,
+            self.proxy.as_ref().map(|proxy| proxy.as_str()),
+        )
+        .await?;
+
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        *self.stream_write.lock().await = stream_write;
+        self.futures.lock().await.clear();
+        self.alive.store(true, Ordering::SeqCst);
+        self.spawn_reader(stream_read);
+
+        let subscriptions = self.subscriptions.lock().await.clone();
+        for (method, params) in subscriptions {
+            let _ = self.request(&method, params, self.request_timeout).await;
+        }
+
+        // whatever happened while disconnected is lost
+        let _ = self.producer.send(Notification::ResyncRequired);
+
+        Ok(())
+    }
+
+    pub fn subscribe(&self) -> NotificationReceiver {
+        NotificationReceiver(self.producer.subscribe())
     }
 
     pub async fn send(
         &self,
         method: &str,
         params: serde_json::Value,
+    ) -> Result<String, TcpElectrumError> {
+        if method.ends_with(".subscribe") {
+            let mut guard = self.subscriptions.lock().await;
+            let subscription = (method.to_owned(), params.clone());
+            if !guard.contains(&subscription) {
+                guard.push(subscription);
+            }
+        }
+
+        // tokio semaphore is fair, requests are served in arrival order
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .map_err(TcpElectrumError::Closed)?;
+        // a server that never answers would otherwise hold the permit forever
+        self.request(method, params, self.request_timeout).await
+    }
+
+    /// Fails with `TcpElectrumError::Timeout` once `limit` passed
+    async fn request(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        limit: Duration,
+    ) -> Result<String, TcpElectrumError> {
+        let start = Instant::now();
+        let result = match timeout(limit, self.request_inner(method, params)).await {
+            Ok(result) => result,
+            Err(_) => Err(TcpElectrumError::Timeout(limit)),
+        };
+        self.metrics
+            .request(method, start.elapsed(), result.is_ok());
+        result
+    }
+
+    async fn request_inner(
+        &self,
+        method: &str,
+        params: serde_json::Value,
     ) -> Result<String, TcpElectrumError> {
         let mut guard = self.id.lock().await;
         let id = guard.clone();
@@ -128,12 +405,16 @@ impl TcpElectrum {
         let mut guard = self.futures.lock().await;
         let _ = guard.insert(id, sender);
         drop(guard);
+        let _pending = Pending {
+            futures: self.futures.clone(),
+            id,
+        };
 
         let mut guard = self.stream_write.lock().await;
-        let _ = guard
-            .write(&payload)
-            .await
-            .map_err(|e| TcpElectrumError::IoError(e))?;
+        guard.write_all(&payload).await.map_err(|e| {
+            self.alive.store(false, Ordering::SeqCst);
+            TcpElectrumError::IoError(e)
+        })?;
         drop(guard);
 
         let result = recv.await.map_err(|e| TcpElectrumError::RecvError(e))?;
@@ -141,6 +422,30 @@ impl TcpElectrum {
     }
 }
 
+/// Forgets the response awaited by request `id` however the request ends:
+/// answered, failed or dropped by a timeout
+struct Pending {
+    futures: Arc<Mutex<HashMap<u64, oneshot::Sender<String>>>>,
+    id: u64,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        let id = self.id;
+        match self.futures.try_lock() {
+            Ok(mut futures) => {
+                futures.remove(&id);
+            }
+            Err(_) => {
+                let futures = self.futures.clone();
+                tokio::spawn(async move {
+                    futures.lock().await.remove(&id);
+                });
+            }
+        }
+    }
+}
+
 impl Clone for TcpElectrum {
     fn clone(&self) -> Self {
         TcpElectrum {
@@ -148,60 +453,255 @@ impl Clone for TcpElectrum {
             futures: self.futures.clone(),
             producer: self.producer.clone(),
             stream_write: self.stream_write.clone(),
+            address: self.address.clone(),
+            cert_verification: self.cert_verification.clone(),
+            proxy: self.proxy.clone(),
+            alive: self.alive.clone(),
+            generation: self.generation.clone(),
+            subscriptions: self.subscriptions.clone(),
+            metrics: self.metrics.clone(),
+            in_flight: self.in_flight.clone(),
+            request_timeout: self.request_timeout,
+            tx_cache: self.tx_cache.clone(),
         }
     }
 }
 
-#[derive(Deserialize)]
-pub struct TxInfo0 {
-    confirmations: u32,
-    #[serde(with = "hex")]
-    hex: Vec<u8>,
-}
-
-#[derive(Deserialize)]
-pub struct TxInfo {
-    result: TxInfo0,
-}
-
 pub async fn scan_address_conf_tx(
     bch_server: &TcpElectrum,
     address: &str,
     min_conf: u32,
-) -> Vec<(Transaction, u32)> {
+) -> Result<Vec<(Transaction, i64)>, ScanError> {
     let response = bch_server
         .send("blockchain.address.get_history", json!([address, true]))
-        .await
-        .unwrap();
+        .await?;
+    let history = scan_result(&response)?;
+    let entries = history
+        .as_array()
+        .ok_or(ScanError::MissingField("result"))?;
+
+    // confirmations are derived from the tip so cached txs need no request
+    let tip = tip_height(bch_server).await?;
 
-    let tx_hashes = serde_json::from_str::<serde_json::Value>(&response).unwrap()["result"]
+    let mut txs = Vec::new();
+    for entry in entries {
+        // in mempool, -1 when it has unconfirmed parents
+        let height = entry["height"]
+            .as_i64()
+            .ok_or(ScanError::MissingField("height"))?;
+        if height <= 0 {
+            continue;
+        }
+
+        let confirmations = (tip - height + 1).max(0);
+        if confirmations < min_conf as i64 {
+            continue;
+        }
+
+        let tx_hash = entry["tx_hash"]
+            .as_str()
+            .ok_or(ScanError::MissingField("tx_hash"))?;
+        let transaction = fetch_tx(bch_server, tx_hash, height).await?;
+        txs.push((transaction, confirmations));
+    }
+
+    Ok(txs)
+}
+
+pub(crate) async fn tip_height(bch_server: &TcpElectrum) -> Result<i64, ScanError> {
+    let response = bch_server
+        .send("blockchain.headers.subscribe", json!([]))
+        .await?;
+    scan_result(&response)?["height"]
+        .as_i64()
+        .ok_or(ScanError::MissingField("height"))
+}
+
+async fn get_tx(bch_server: &TcpElectrum, tx_hash: &str) -> Result<Transaction, ScanError> {
+    let response = bch_server
+        .send("blockchain.transaction.get", json!([tx_hash, false]))
+        .await?;
+    let result = scan_result(&response)?;
+    let tx_hex = result.as_str().ok_or(ScanError::MissingField("result"))?;
+    let bytes = hex::decode(tx_hex).map_err(ScanError::InvalidHex)?;
+    bitcoincash::consensus::deserialize::<Transaction>(&bytes).map_err(ScanError::InvalidTx)
+}
+
+/// `height` the tx is mined at according to the history, the cache is bypassed if it changed
+pub(crate) async fn fetch_tx(
+    bch_server: &TcpElectrum,
+    tx_hash: &str,
+    height: i64,
+) -> Result<Transaction, ScanError> {
+    let cached = bch_server.tx_cache.lock().unwrap().get(tx_hash).cloned();
+    if let Some(cached) = cached {
+        if cached.height == height {
+            return Ok(cached.tx);
+        }
+    }
+
+    let transaction = get_tx(bch_server, tx_hash).await?;
+    bch_server.tx_cache.lock().unwrap().put(
+        tx_hash.to_owned(),
+        CachedTx {
+            tx: transaction.clone(),
+            height,
+        },
+    );
+    Ok(transaction)
+}
+
+const DSPROOF_SUBSCRIBE: &str = "blockchain.transaction.dsproof.subscribe";
+
+/// Subscribe for double spend proof of `txid`. Returns true if a proof already exists
+pub async fn subscribe_dsproof(
+    bch_server: &TcpElectrum,
+    txid: &str,
+) -> Result<bool, TcpElectrumError> {
+    let response = bch_server.send(DSPROOF_SUBSCRIBE, json!([txid])).await?;
+    let response = serde_json::from_str::<serde_json::Value>(&response).unwrap_or_default();
+    Ok(!response["result"].is_null())
+}
+
+/// Txid of a double spend proof notification received through `TcpElectrum::subscribe`
+pub fn dsproof_notification_txid(notification: &str) -> Option<String> {
+    let data = serde_json::from_str::<serde_json::Value>(notification).ok()?;
+    if data["method"].as_str()? != DSPROOF_SUBSCRIBE || data["params"][1].is_null() {
+        return None;
+    }
+
+    Some(data["params"][0].as_str()?.to_owned())
+}
+
+/// Mempool transactions of `address` without any known double spend proof.
+/// Every returned tx is subscribed so a later proof arrives as notification
+pub async fn scan_address_mempool_tx(
+    bch_server: &TcpElectrum,
+    address: &str,
+) -> Result<Vec<Transaction>, ScanError> {
+    let response = bch_server
+        .send("blockchain.address.get_mempool", json!([address]))
+        .await?;
+    let mempool = scan_result(&response)?;
+    let entries = mempool
         .as_array()
-        .unwrap()
-        .to_owned();
+        .ok_or(ScanError::MissingField("result"))?;
 
     let mut txs = Vec::new();
-    for tx in tx_hashes {
-        // in mempool
-        if tx["height"].as_u64().unwrap() == 0 {
+    for entry in entries {
+        let tx_hash = entry["tx_hash"]
+            .as_str()
+            .ok_or(ScanError::MissingField("tx_hash"))?;
+        if subscribe_dsproof(bch_server, tx_hash).await.unwrap_or(true) {
             continue;
         }
 
-        let tx_hash = tx["tx_hash"].as_str().unwrap();
-        let tx_info = bch_server
-            .send("blockchain.transaction.get", json!([tx_hash, true]))
+        txs.push(get_tx(bch_server, tx_hash).await?);
+    }
+
+    Ok(txs)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::{
+        io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf},
+        time::{sleep, timeout},
+    };
+
+    use super::{transport, ElectrumConfig, TcpElectrum, TcpElectrumError};
+
+    type Server = Lines<BufReader<ReadHalf<DuplexStream>>>;
+
+    fn electrum(config: ElectrumConfig) -> (TcpElectrum, Server, io::WriteHalf<DuplexStream>) {
+        let (client, server) = io::duplex(4096);
+        let electrum = TcpElectrum::from_stream(transport::split(client), None, config);
+        let (read, write) = io::split(server);
+        (electrum, BufReader::new(read).lines(), write)
+    }
+
+    /// Next request received by the server, None if the client sent nothing
+    async fn next_request(server: &mut Server) -> Option<(u64, String)> {
+        let line = timeout(Duration::from_millis(100), server.next_line())
+            .await
+            .ok()?
+            .unwrap()?;
+        let request: serde_json::Value = serde_json::from_str(&line).unwrap();
+        Some((
+            request["id"].as_u64().unwrap(),
+            request["method"].as_str().unwrap().to_owned(),
+        ))
+    }
+
+    fn spawn_send(
+        electrum: &TcpElectrum,
+        method: &'static str,
+    ) -> tokio::task::JoinHandle<Result<String, TcpElectrumError>> {
+        let electrum = electrum.clone();
+        tokio::spawn(async move { electrum.send(method, json!([])).await })
+    }
+
+    #[tokio::test]
+    async fn should_queue_requests_in_order() {
+        let config = ElectrumConfig {
+            max_in_flight: 2,
+            ..Default::default()
+        };
+        let (electrum, mut server, mut write) = electrum(config);
+
+        let mut sent = Vec::new();
+        for method in ["first", "second", "third", "fourth"] {
+            sent.push(spawn_send(&electrum, method));
+            // lets each request reach the queue before the next one
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        let (first, method) = next_request(&mut server).await.unwrap();
+        assert_eq!(method, "first");
+        let (second, method) = next_request(&mut server).await.unwrap();
+        assert_eq!(method, "second");
+        assert!(next_request(&mut server).await.is_none());
+
+        write
+            .write_all(format!("{{\"id\":{second},\"result\":null}}\n").as_bytes())
             .await
             .unwrap();
+        let (third, method) = next_request(&mut server).await.unwrap();
+        assert_eq!(method, "third");
+        assert!(next_request(&mut server).await.is_none());
 
-        let tx_info = serde_json::from_str::<TxInfo>(&tx_info).unwrap().result;
-        if tx_info.confirmations < min_conf {
-            continue;
+        for id in [first, third] {
+            write
+                .write_all(format!("{{\"id\":{id},\"result\":null}}\n").as_bytes())
+                .await
+                .unwrap();
         }
+        let (_, method) = next_request(&mut server).await.unwrap();
+        assert_eq!(method, "fourth");
 
-        txs.push((
-            bitcoincash::consensus::deserialize::<bitcoincash::Transaction>(&tx_info.hex).unwrap(),
-            tx_info.confirmations,
-        ));
+        for handle in sent.drain(..3) {
+            assert!(handle.await.unwrap().is_ok());
+        }
     }
 
-    txs
+    #[tokio::test]
+    async fn should_free_slot_on_timeout() {
+        let config = ElectrumConfig {
+            max_in_flight: 1,
+            request_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (electrum, mut server, _write) = electrum(config);
+
+        let result = electrum.send("first", json!([])).await;
+        assert!(matches!(result, Err(TcpElectrumError::Timeout(_))));
+        assert!(electrum.futures.lock().await.is_empty());
+
+        let _second = spawn_send(&electrum, "second");
+        assert_eq!(next_request(&mut server).await.unwrap().1, "first");
+        assert_eq!(next_request(&mut server).await.unwrap().1, "second");
+    }
 }
@@ -0,0 +1,172 @@
+use bitcoincash::{
+    blockdata::{opcodes, script::Instruction},
+    consensus::serialize,
+    secp256k1::ecdsa,
+    Transaction, TxOut,
+};
+
+/// Local equivalent of `testmempoolaccept`. Default values follow BCHN relay policy
+#[derive(Debug, Clone)]
+pub struct MempoolPolicy {
+    pub max_tx_size: usize,
+    pub min_tx_size: usize,
+    pub max_script_sig_size: usize,
+    /// Largest push of a scriptSig, the redeem script of a p2sh spend included
+    pub max_element_size: usize,
+    /// sat/byte
+    pub min_relay_fee: u64,
+    /// dust = (output size + input size) * dust_relay_fee
+    pub dust_relay_fee: u64,
+}
+
+impl Default for MempoolPolicy {
+    fn default() -> Self {
+        MempoolPolicy {
+            max_tx_size: 100_000,
+            min_tx_size: 65,
+            max_script_sig_size: 1650,
+            max_element_size: 520,
+            min_relay_fee: 1,
+            dust_relay_fee: 3,
+        }
+    }
+}
+
+/// Why BCHN would refuse to relay a transaction
+#[derive(Debug, thiserror::Error)]
+pub enum PolicyError {
+    #[error("transaction version {0} isn't standard")]
+    InvalidVersion(i32),
+    #[error("transaction of {0} bytes is too large")]
+    TxTooLarge(usize),
+    #[error("transaction of {0} bytes is too small")]
+    TxTooSmall(usize),
+    #[error("scriptSig of input {input} is {size} bytes, too large")]
+    ScriptSigTooLarge { input: usize, size: usize },
+    #[error("scriptSig of input {0} isn't push only")]
+    NonPushScriptSig(usize),
+    #[error("push of {size} bytes in input {input} is over the {max} bytes element limit")]
+    ElementTooLarge {
+        input: usize,
+        size: usize,
+        max: usize,
+    },
+    #[error("input {0} has a non minimal push, a relay could change its txid")]
+    NonMinimalPush(usize),
+    #[error("signature of input {0} isn't strict DER with a low S")]
+    NonCanonicalSignature(usize),
+    #[error("input {0} pushes data its timelock path ignores, a relay could change its txid")]
+    MalleableUnlocker(usize),
+    #[error(
+        "scriptSig of input {0} isn't the unlocker and the contract alone, failing cleanstack"
+    )]
+    UncleanStack(usize),
+    #[error("input {input} does {sigchecks} sigchecks, over {max} for its scriptSig size")]
+    TooManySigChecks {
+        input: usize,
+        sigchecks: usize,
+        max: usize,
+    },
+    #[error("output {output} of {value} sats is dust, below {min} sats")]
+    Dust { output: usize, value: u64, min: u64 },
+    #[error("fee of {fee} sats is below the {required} sats relay fee")]
+    FeeTooLow { fee: i64, required: u64 },
+}
+
+/// Size of the input spending a p2pkh output, used for dust calculation
+const SPEND_INPUT_SIZE: u64 = 148;
+
+/// Strict DER encoding with a low S, as LOW_S and STRICTENC require. A high S
+/// is as valid a signature, any relay could swap it and change the txid
+pub fn is_canonical_signature(sig: &[u8]) -> bool {
+    ecdsa::Signature::from_der(sig).is_ok_and(|parsed| {
+        let mut low = parsed;
+        low.normalize_s();
+        low == parsed && parsed.serialize_der().as_ref() == sig
+    })
+}
+
+impl MempoolPolicy {
+    /// Smallest value `output` can carry. Its token prefix counts in its
+    /// size, as BCHN counts it
+    pub fn dust(&self, output: &TxOut) -> u64 {
+        let output = TxOut {
+            value: 0,
+            ..output.clone()
+        };
+        (serialize(&output).len() as u64 + SPEND_INPUT_SIZE) * self.dust_relay_fee
+    }
+
+    /// Signature checks an input of `script_sig_size` bytes may do
+    pub fn max_sigchecks(&self, script_sig_size: usize) -> usize {
+        (script_sig_size + 60) / 43
+    }
+
+    /// `input_values` are the amount of each spent output, in input order
+    pub fn check(&self, tx: &Transaction, input_values: &[u64]) -> Result<(), PolicyError> {
+        if tx.version != 1 && tx.version != 2 {
+            return Err(PolicyError::InvalidVersion(tx.version));
+        }
+
+        let size = serialize(tx).len();
+        if size > self.max_tx_size {
+            return Err(PolicyError::TxTooLarge(size));
+        }
+        if size < self.min_tx_size {
+            return Err(PolicyError::TxTooSmall(size));
+        }
+
+        for (index, input) in tx.input.iter().enumerate() {
+            let script_size = input.script_sig.len();
+            if script_size > self.max_script_sig_size {
+                return Err(PolicyError::ScriptSigTooLarge {
+                    input: index,
+                    size: script_size,
+                });
+            }
+
+            let push_only = input.script_sig.instructions().all(|i| match i {
+                Ok(Instruction::PushBytes(_)) => true,
+                Ok(Instruction::Op(op)) => op.to_u8() <= opcodes::all::OP_PUSHNUM_16.to_u8(),
+                Err(_) => false,
+            });
+            if !push_only {
+                return Err(PolicyError::NonPushScriptSig(index));
+            }
+            for instruction in input.script_sig.instructions_minimal() {
+                match instruction {
+                    Ok(Instruction::PushBytes(data)) if data.len() > self.max_element_size => {
+                        return Err(PolicyError::ElementTooLarge {
+                            input: index,
+                            size: data.len(),
+                            max: self.max_element_size,
+                        })
+                    }
+                    Ok(_) => {}
+                    Err(_) => return Err(PolicyError::NonMinimalPush(index)),
+                }
+            }
+        }
+
+        for (index, output) in tx.output.iter().enumerate() {
+            let min = self.dust(output);
+            if output.value < min {
+                return Err(PolicyError::Dust {
+                    output: index,
+                    value: output.value,
+                    min,
+                });
+            }
+        }
+
+        let input_total: u64 = input_values.iter().sum();
+        let output_total: u64 = tx.output.iter().map(|o| o.value).sum();
+        let fee = input_total as i64 - output_total as i64;
+        let required = size as u64 * self.min_relay_fee;
+        if fee < required as i64 {
+            return Err(PolicyError::FeeTooLow { fee, required });
+        }
+
+        Ok(())
+    }
+}
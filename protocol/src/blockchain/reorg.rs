@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::backend::{ChainBackend, ChainError};
+use crate::protocol::Event;
+
+/// Block hashes confirming the swap transactions. Saved with the trade,
+/// a tx found in another block (or back in mempool) was reorganized out
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ReorgWatch {
+    /// txid -> block hash
+    confirmed: BTreeMap<String, String>,
+}
+
+impl ReorgWatch {
+    pub fn track(&mut self, txid: String, block_hash: String) {
+        self.confirmed.insert(txid, block_hash);
+    }
+
+    pub fn is_tracked(&self, txid: &str) -> bool {
+        self.confirmed.contains_key(txid)
+    }
+
+    /// `Event::Reorged` for every tracked tx whose block changed. Those are
+    /// forgotten, `update` tracks them again at their new block
+    pub async fn check(&mut self, bch: &dyn ChainBackend) -> Result<Vec<Event>, ChainError> {
+        let mut events = Vec::new();
+        for (txid, block_hash) in self.confirmed.clone() {
+            if bch.block_hash(&txid).await?.as_ref() != Some(&block_hash) {
+                self.confirmed.remove(&txid);
+                events.push(Event::Reorged { txid });
+            }
+        }
+
+        Ok(events)
+    }
+
+    /// Check the tracked txs, then track the newly confirmed txs of `addresses`.
+    /// To call on every new header
+    pub async fn update(
+        &mut self,
+        bch: &dyn ChainBackend,
+        addresses: &[String],
+    ) -> Result<Vec<Event>, ChainError> {
+        let events = self.check(bch).await?;
+
+        for address in addresses {
+            for (tx, _) in bch.address_history(address, 1).await? {
+                let txid = tx.txid().to_string();
+                if self.is_tracked(&txid) {
+                    continue;
+                }
+                if let Some(block_hash) = bch.block_hash(&txid).await? {
+                    self.track(txid, block_hash);
+                }
+            }
+        }
+
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoincash::{
+        hashes::Hash, OutPoint, PackedLockTime, PubkeyHash, Script, Transaction, TxIn, TxOut,
+    };
+
+    use super::ReorgWatch;
+    use crate::{blockchain::mock::MockChain, keys::bitcoin::address, protocol::Event};
+
+    #[tokio::test]
+    async fn should_detect_reorged_funding() {
+        let chain = MockChain::new();
+        let hash = [7u8; 20];
+        let address = address::encode(&hash, "bchreg", 0);
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey: Script::new_p2pkh(&PubkeyHash::from_slice(&hash).unwrap()),
+                token: None,
+            }],
+        };
+        chain.add_mempool_tx(tx.clone());
+        chain.mine_blocks(2);
+
+        let mut watch = ReorgWatch::default();
+        assert!(watch
+            .update(&chain, &[address.clone()])
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(watch.is_tracked(&tx.txid().to_string()));
+
+        // deeper than the tx
+        chain.reorg(2);
+        chain.mine_block();
+        assert_eq!(
+            watch.update(&chain, &[address.clone()]).await.unwrap(),
+            vec![Event::Reorged {
+                txid: tx.txid().to_string()
+            }]
+        );
+        // tracked again at its new block
+        assert!(watch.update(&chain, &[address]).await.unwrap().is_empty());
+        assert!(watch.is_tracked(&tx.txid().to_string()));
+    }
+}
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{
+        self,
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, ring, WebPkiSupportedAlgorithms},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    },
+    TlsConnector,
+};
+use tokio_tungstenite::{tungstenite::Message, Connector};
+
+pub type BoxedRead = Box<dyn AsyncRead + Send + Unpin>;
+pub type BoxedWrite = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// Bytes buffered between `TcpElectrum` and the websocket tasks
+const WS_BRIDGE_BUFFER: usize = 64 * 1024;
+
+/// How the server certificate is checked on `ssl://` and `wss://`
+#[derive(Debug, Clone, Default)]
+pub enum CertVerification {
+    #[default]
+    WebPki,
+    /// SHA256 of the DER certificate, for self-signed servers.
+    /// The hostname and expiry are not checked, only the exact certificate is accepted
+    Pinned([u8; 32]),
+}
+
+impl CertVerification {
+    /// `AB:CD:..` or plain hex, as printed by `openssl x509 -fingerprint -sha256`
+    pub fn pinned(fingerprint: &str) -> Result<Self, hex::FromHexError> {
+        let mut pin = [0u8; 32];
+        hex::decode_to_slice(fingerprint.replace(':', ""), &mut pin)?;
+        Ok(CertVerification::Pinned(pin))
+    }
+
+    fn client_config(&self) -> Arc<ClientConfig> {
+        let config = match self {
+            CertVerification::WebPki => {
+                let mut roots = RootCertStore::empty();
+                roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+                ClientConfig::builder()
+                    .with_root_certificates(roots)
+                    .with_no_client_auth()
+            }
+            CertVerification::Pinned(fingerprint) => ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCert {
+                    fingerprint: *fingerprint,
+                    algorithms: ring::default_provider().signature_verification_algorithms,
+                }))
+                .with_no_client_auth(),
+        };
+        Arc::new(config)
+    }
+}
+
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: [u8; 32],
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if fingerprint != self.fingerprint {
+            return Err(rustls::Error::General(format!(
+                "certificate fingerprint mismatch, got {}",
+                hex::encode(fingerprint)
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// `host:port` or `tcp://host:port` for raw tcp, `ssl://host:port` for tls,
+/// `ws://` or `wss://` for websocket.
+/// Through the SOCKS5 `proxy` (`host:port`, e.g. Tor) when given
+pub async fn connect(
+    address: &str,
+    verification: &CertVerification,
+    proxy: Option<&str>,
+) -> io::Result<(BoxedRead, BoxedWrite)> {
+    if address.starts_with("ws://") || address.starts_with("wss://") {
+        return Ok(split(connect_ws(address, verification, proxy).await?));
+    }
+
+    if let Some(address) = address.strip_prefix("ssl://") {
+        let host = address
+            .rsplit_once(':')
+            .map(|(host, _)| host)
+            .unwrap_or(address);
+        let server_name = ServerName::try_from(host.to_owned())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        let stream = connect_tcp(address, proxy).await?;
+        let stream = TlsConnector::from(verification.client_config())
+            .connect(server_name, stream)
+            .await?;
+        return Ok(split(stream));
+    }
+
+    let address = address.strip_prefix("tcp://").unwrap_or(address);
+    Ok(split(connect_tcp(address, proxy).await?))
+}
+
+async fn connect_tcp(address: &str, proxy: Option<&str>) -> io::Result<TcpStream> {
+    match proxy {
+        Some(proxy) => socks5_connect(proxy, address).await,
+        None => TcpStream::connect(address).await,
+    }
+}
+
+/// CONNECT without authentication. The proxy resolves the hostname, nothing
+/// leaks through the local resolver and .onion addresses work
+pub async fn socks5_connect(proxy: &str, address: &str) -> io::Result<TcpStream> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidInput, msg.to_owned());
+    let (host, port) = address
+        .rsplit_once(':')
+        .ok_or_else(|| invalid("missing port"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("invalid port"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.len() > 255 {
+        return Err(invalid("hostname too long"));
+    }
+
+    let mut stream = TcpStream::connect(proxy).await?;
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, 0] {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "socks5 proxy requires authentication",
+        ));
+    }
+
+    let mut request = vec![5, 1, 0, 3, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("socks5 proxy error {}", reply[1]),
+        ));
+    }
+    // bound address, unused
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        _ => return Err(invalid("invalid socks5 reply")),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+/// `host:port` of a websocket url, with the scheme default port
+fn ws_host_port(url: &str) -> io::Result<String> {
+    let (default_port, rest) = match url.split_once("://") {
+        Some(("wss", rest)) => (443, rest),
+        Some(("ws", rest)) => (80, rest),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a websocket url",
+            ))
+        }
+    };
+    let authority = rest.split(['/', '?']).next().unwrap_or(rest);
+    match authority.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => Ok(authority.to_owned()),
+        _ => Ok(format!("{authority}:{default_port}")),
+    }
+}
+
+pub fn split<S>(stream: S) -> (BoxedRead, BoxedWrite)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (read, write) = io::split(stream);
+    (Box::new(read), Box::new(write))
+}
+
+/// Electrum over websocket sends one json per message instead of one per line.
+/// Bridge it to a newline delimited byte stream so the client works unchanged
+async fn connect_ws(
+    url: &str,
+    verification: &CertVerification,
+    proxy: Option<&str>,
+) -> io::Result<io::DuplexStream> {
+    let connector = Connector::Rustls(verification.client_config());
+    let stream = connect_tcp(&ws_host_port(url)?, proxy).await?;
+    let (ws, _) =
+        tokio_tungstenite::client_async_tls_with_config(url, stream, None, Some(connector))
+            .await
+            .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e))?;
+    let (mut ws_write, mut ws_read) = ws.split();
+
+    let (client, bridge) = io::duplex(WS_BRIDGE_BUFFER);
+    let (bridge_read, mut bridge_write) = io::split(bridge);
+
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(bridge_read).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if ws_write.send(Message::Text(line)).await.is_err() {
+                break;
+            }
+        }
+        let _ = ws_write.close().await;
+    });
+
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = ws_read.next().await {
+            let mut data = match message {
+                Message::Text(text) => text.into_bytes(),
+                Message::Binary(data) => data,
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            data.push(b'\n');
+            if bridge_write.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        // lets the reader see EOF
+        let _ = bridge_write.shutdown().await;
+    });
+
+    Ok(client)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ws_host_port, CertVerification};
+
+    #[test]
+    fn should_parse_fingerprint() {
+        let openssl = "AB:".repeat(31) + "AB";
+        match CertVerification::pinned(&openssl) {
+            Ok(CertVerification::Pinned(pin)) => assert_eq!(pin, [0xab; 32]),
+            _ => panic!("fingerprint not parsed"),
+        }
+
+        assert!(CertVerification::pinned(&"ab".repeat(31)).is_err());
+    }
+
+    #[test]
+    fn should_default_ws_port() {
+        assert_eq!(ws_host_port("wss://host/path").unwrap(), "host:443");
+        assert_eq!(ws_host_port("ws://host:50003").unwrap(), "host:50003");
+        assert_eq!(ws_host_port("ws://[::1]").unwrap(), "[::1]:80");
+        assert!(ws_host_port("tcp://host:50001").is_err());
+    }
+}
@@ -1,28 +1,45 @@
-use std::{fmt, time::Duration};
+use std::{fmt, path::Path, time::Duration};
 
-use anyhow::bail;
 use bitcoin_hashes::{sha256::Hash as sha256, Hash};
-use bitcoincash::{
-    consensus::Encodable, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut,
-};
+use bitcoincash::{PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut};
 use ecdsa_fun::adaptor::EncryptedSignature;
-use hex::ToHex;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use tokio::{sync::Mutex, time::sleep};
+use tokio::sync::Mutex;
 
 use crate::{
     adaptor_signature::AdaptorSignature,
     bitcoincash::{secp256k1::ecdsa, OutPoint},
-    blockchain::{scan_address_conf_tx, TcpElectrum},
-    contract::{ContractPair, TransactionType},
+    blockchain::{
+        backend::{broadcast_txs, timelock_block, with_retry, ChainBackend},
+        policy::{MempoolPolicy, PolicyError},
+        reorg::ReorgWatch,
+    },
+    clock::{Clock, SystemClock},
+    contract::{ContractPair, ContractTokens, TransactionType},
+    error::SwapError,
     keys::{KeyPublic, KeyPublicWithoutProof},
+    monerod::{transfer_height, MoneroError, MonerodPool},
+    outcome::{OutcomeKind, SwapOutcome, SwapTxids},
+    peer::{PeerInfo, PROTOCOL_VERSION},
+    persist::SwapStore,
+    presigned::PresignedTxs,
+    progress::{Chain, Confirmations, Progress},
     proof,
-    protocol::{Action, Error, Swap, SwapEvents, Transition},
+    protocol::{
+        check_msg0, funding_events, print_event, xmr_restore_height, Action, BchBlock, Error,
+        Event, EventSink, SignedTransition, Swap, SwapEvents, SwapWrapper, Transition,
+        XmrLockBlock, XmrLockProof,
+    },
+    redact::Redacted,
+    runner::SwapRunner,
+    transcript::{Transcript, TranscriptEntry},
     utils::{get_signature, monero_key_pair, monero_view_pair},
+    watchdog::{unix_now, Watchdog},
+    xmr_scanner::{XmrScanner, XmrTransfer},
+    xmr_wallet::{self, RecoveredBalance, SweepAmount, WalletRetention, XmrRecovery},
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value0 {
     alice_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -33,7 +50,19 @@ pub struct Value0 {
     xmr_restore_height: u64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value0 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value0")
+            .field("alice_keys", &self.alice_keys)
+            .field("alice_bch_recv", &self.alice_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("xmr_restore_height", &self.xmr_restore_height)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value1 {
     alice_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -43,9 +72,26 @@ pub struct Value1 {
     pub shared_keypair: monero::ViewPair,
     xmr_restore_height: u64,
     dec_sig: ecdsa::Signature,
+    /// Sent by Alice, checked instead of the view wallet balance
+    #[serde(default)]
+    xmr_lock_proof: Option<XmrLockProof>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value1")
+            .field("alice_keys", &self.alice_keys)
+            .field("alice_bch_recv", &self.alice_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("xmr_restore_height", &self.xmr_restore_height)
+            .field("dec_sig", &self.dec_sig)
+            .field("xmr_lock_proof", &self.xmr_lock_proof)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value2 {
     alice_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -55,9 +101,26 @@ pub struct Value2 {
     shared_keypair: monero::ViewPair,
     xmr_restore_height: u64,
     dec_sig: ecdsa::Signature,
+    /// None for trades verified before it was recorded, never rechecked
+    #[serde(default)]
+    xmr_lock: Option<XmrLockBlock>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value2 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value2")
+            .field("alice_keys", &self.alice_keys)
+            .field("alice_bch_recv", &self.alice_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("xmr_restore_height", &self.xmr_restore_height)
+            .field("dec_sig", &self.dec_sig)
+            .field("xmr_lock", &self.xmr_lock)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Value3 {
     alice_keys: KeyPublicWithoutProof,
     #[serde(with = "hex")]
@@ -68,9 +131,33 @@ pub struct Value3 {
     xmr_restore_height: u64,
     dec_sig: ecdsa::Signature,
     outpoint: OutPoint,
+    /// Every SwapLock output refunded with its sats, when `outpoint` doesn't hold
+    /// `Swap::bch_amount`: the parts of a split funding or a wrong deposit
+    #[serde(default)]
+    parts: Vec<(OutPoint, u64)>,
+    /// The SwapLock spend to the Refund once confirmed, whoever sent it.
+    /// Only its spend to us is left to broadcast
+    #[serde(default)]
+    refund_tx: Option<Transaction>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl fmt::Debug for Value3 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Value3")
+            .field("alice_keys", &self.alice_keys)
+            .field("alice_bch_recv", &self.alice_bch_recv)
+            .field("contract_pair", &self.contract_pair)
+            .field("shared_keypair", &Redacted)
+            .field("xmr_restore_height", &self.xmr_restore_height)
+            .field("dec_sig", &self.dec_sig)
+            .field("outpoint", &self.outpoint)
+            .field("parts", &self.parts)
+            .field("refund_tx", &self.refund_tx)
+            .finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum State {
     Init,
     WithAliceKey(Value0),
@@ -78,6 +165,8 @@ pub enum State {
     VerifiedEncSig(Value1),
     MoneroLocked(Value2),
     ProceedRefund(Value3),
+    /// Backed out before locking, with the keys of the view wallet if created
+    Aborted(Option<Value0>),
     SwapSuccess(
         #[serde(with = "monero_key_pair")] monero::KeyPair,
         monero::Address,
@@ -85,6 +174,27 @@ pub enum State {
     ),
 }
 
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            State::Init => write!(f, "Init"),
+            State::WithAliceKey(v) => f.debug_tuple("WithAliceKey").field(v).finish(),
+            State::ContractMatch(v) => f.debug_tuple("ContractMatch").field(v).finish(),
+            State::VerifiedEncSig(v) => f.debug_tuple("VerifiedEncSig").field(v).finish(),
+            State::MoneroLocked(v) => f.debug_tuple("MoneroLocked").field(v).finish(),
+            State::ProceedRefund(v) => f.debug_tuple("ProceedRefund").field(v).finish(),
+            State::Aborted(v) => f.debug_tuple("Aborted").field(v).finish(),
+            // the keys spending the claimed XMR
+            State::SwapSuccess(_, address, height) => f
+                .debug_tuple("SwapSuccess")
+                .field(&Redacted)
+                .field(address)
+                .field(height)
+                .finish(),
+        }
+    }
+}
+
 impl fmt::Display for State {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -95,6 +205,7 @@ impl fmt::Display for State {
             State::MoneroLocked(_) => write!(f, "BobState::MoneroLocked"),
             State::SwapSuccess(_, _, _) => write!(f, "BobState::SwapSuccess"),
             State::ProceedRefund(_) => write!(f, "BobState::ProceedRefund"),
+            State::Aborted(_) => write!(f, "BobState::Aborted"),
         }
     }
 }
@@ -103,18 +214,109 @@ impl fmt::Display for State {
 pub struct Bob {
     pub state: State,
     pub swap: Swap,
+    /// Sweep to `Swap::xmr_payout_address`, empty until done
+    #[serde(default)]
+    pub xmr_payout_txids: Vec<String>,
+    /// Of our SwapLock funding as last seen, see `BchConfirmations::enc_sig`
+    #[serde(default)]
+    pub swaplock_confirmations: u32,
+    /// Unix time the state was entered, 0 when unknown. See `Watchdog`
+    #[serde(default)]
+    pub state_since: u64,
+    /// Unix time of the trade creation, 0 when unknown
+    #[serde(default)]
+    pub started_at: u64,
+    #[serde(default)]
+    pub txids: SwapTxids,
+    /// Carried by our spends, see `TokenPolicy`
+    #[serde(default)]
+    pub tokens: ContractTokens,
+    /// Our spends, kept for a broadcast from the trade file alone
+    #[serde(default)]
+    pub presigned: PresignedTxs,
+    /// Reset on every state change
+    #[serde(default)]
+    pub confirmations: Option<Confirmations>,
+    /// Filled by the runners
+    #[serde(default)]
+    pub transcript: Transcript,
 }
 
 impl Bob {
     pub fn new(swap: Swap) -> Self {
+        let now = unix_now();
         Bob {
             state: State::Init,
             swap,
+            xmr_payout_txids: vec![],
+            swaplock_confirmations: 0,
+            state_since: now,
+            started_at: now,
+            txids: SwapTxids::default(),
+            tokens: ContractTokens::default(),
+            presigned: PresignedTxs::default(),
+            confirmations: None,
+            transcript: Transcript::default(),
         }
     }
 
+    /// Stage of the trade and the confirmations it waits for, the XMR lock
+    /// in `VerifiedEncSig`
+    pub fn progress(&self) -> Progress {
+        let stage = match self.state {
+            State::Init => 0,
+            State::WithAliceKey(_) => 1,
+            State::ContractMatch(_) => 2,
+            State::VerifiedEncSig(_) => 3,
+            State::MoneroLocked(_) => 4,
+            State::ProceedRefund(_) | State::Aborted(_) | State::SwapSuccess(..) => 5,
+        };
+        Progress::new(stage, 5, self.confirmations)
+    }
+
+    /// Summary once the trade reached a final state, None before
+    pub fn outcome(&self) -> Option<SwapOutcome> {
+        let mut txids = self.txids.clone();
+        let bch_amount = self.swap.bch_amount.to_sat();
+        let (kind, bch_sent, bch_received, xmr_received, bch_fees) = match &self.state {
+            State::SwapSuccess(..) => (
+                OutcomeKind::Success,
+                bch_amount,
+                0,
+                self.swap.xmr_amount.as_pico(),
+                0,
+            ),
+            State::ProceedRefund(props) => {
+                let sent = match props.parts.is_empty() {
+                    true => bch_amount,
+                    false => props.parts.iter().map(|(_, value)| value).sum(),
+                };
+                let refunds = self.refund().unwrap_or_default();
+                let received = refunds.iter().map(|(_, tx2)| tx2.output[0].value).sum();
+                let fees = props.contract_pair.refund_path_fee() * refunds.len() as u64;
+                for (tx1, tx2) in refunds {
+                    txids.add_refunds(&[tx1, tx2]);
+                }
+                (OutcomeKind::Refunded, sent, received, 0, fees)
+            }
+            State::Aborted(_) => (OutcomeKind::Aborted, 0, 0, 0, 0),
+            _ => return None,
+        };
+
+        Some(SwapOutcome {
+            kind,
+            bch_sent,
+            bch_received,
+            xmr_sent: 0,
+            xmr_received,
+            txids,
+            bch_fees,
+            duration_secs: SwapOutcome::duration_secs(self.started_at, self.state_since),
+        })
+    }
+
     pub fn get_public_keys(&self) -> KeyPublic {
-        KeyPublic::from(self.swap.keys.clone())
+        KeyPublic::from(&self.swap.keys)
     }
 
     pub fn get_contract(&self) -> Option<(String, monero::Address)> {
@@ -130,6 +332,169 @@ impl Bob {
         ))
     }
 
+    /// Alice's key signing her messages, known from her Msg0
+    pub fn get_peer_ves(&self) -> Option<bitcoincash::PublicKey> {
+        match &self.state {
+            State::WithAliceKey(v) | State::ContractMatch(v) | State::Aborted(Some(v)) => {
+                Some(v.alice_keys.ves)
+            }
+            State::VerifiedEncSig(v) => Some(v.alice_keys.ves),
+            State::MoneroLocked(v) => Some(v.alice_keys.ves),
+            State::ProceedRefund(v) => Some(v.alice_keys.ves),
+            _ => None,
+        }
+    }
+
+    /// `get_transition` signed for Alice
+    pub fn get_signed_transition(&self) -> Option<SignedTransition> {
+        self.get_transition()
+            .map(|transition| SignedTransition::sign(transition, &self.swap))
+    }
+
+    /// Shared view keys and restore height while waiting for Alice to lock XMR
+    pub fn get_xmr_view(&self) -> Option<(monero::ViewPair, u64)> {
+        match &self.state {
+            State::VerifiedEncSig(props) => {
+                Some((props.shared_keypair.clone(), props.xmr_restore_height))
+            }
+            _ => None,
+        }
+    }
+
+    /// Shared view keys, restore height and block of the verified XMR lock
+    pub fn get_xmr_lock(&self) -> Option<(monero::ViewPair, u64, XmrLockBlock)> {
+        match &self.state {
+            State::MoneroLocked(props) => Some((
+                props.shared_keypair.clone(),
+                props.xmr_restore_height,
+                props.xmr_lock.clone()?,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Shared view keys and restore height once the trade is over
+    pub fn get_finished_xmr_view(&self) -> Option<(monero::ViewPair, u64)> {
+        match &self.state {
+            State::SwapSuccess(keypair, _, restore_height) => Some((
+                monero::ViewPair {
+                    view: keypair.view,
+                    spend: monero::PublicKey::from_private_key(&keypair.spend),
+                },
+                *restore_height,
+            )),
+            State::ProceedRefund(props) => {
+                Some((props.shared_keypair.clone(), props.xmr_restore_height))
+            }
+            State::Aborted(Some(props)) => {
+                Some((props.shared_keypair.clone(), props.xmr_restore_height))
+            }
+            _ => None,
+        }
+    }
+
+    /// Export of the claimed XMR wallet, after SwapSuccess
+    pub fn get_xmr_recovery(&self) -> Option<XmrRecovery> {
+        match &self.state {
+            State::SwapSuccess(keypair, _, restore_height) => Some(XmrRecovery::new(
+                &self.swap.id,
+                self.swap.xmr_network,
+                keypair,
+                *restore_height,
+            )),
+            _ => None,
+        }
+    }
+
+    /// Export of the wallet generated for the payout sweep, in any state.
+    /// Holds the XMR once `Runner::sweep_payout` went through
+    pub fn get_xmr_payout_recovery(&self) -> Option<XmrRecovery> {
+        self.swap.xmr_payout_keys.as_ref().map(|keys| {
+            XmrRecovery::new(
+                &format!("{}_payout", self.swap.id),
+                self.swap.xmr_network,
+                &keys.keypair(),
+                self.swap.xmr_restore_height,
+            )
+        })
+    }
+
+    pub fn get_xmr_lock_proof(&self) -> Option<&XmrLockProof> {
+        match &self.state {
+            State::VerifiedEncSig(props) => props.xmr_lock_proof.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Message from Alice already applied, redelivered by the network.
+    /// Those leave the state as is instead of failing the transition
+    fn is_replay(&self, transition: &Transition) -> bool {
+        if let Transition::KeyCommitment(commitment) = transition {
+            return self.swap.peer_commitment == Some(*commitment);
+        }
+
+        let (alice_keys, alice_bch_recv, contract_pair, shared_keypair, dec_sig) = match &self.state
+        {
+            State::WithAliceKey(v) | State::ContractMatch(v) | State::Aborted(Some(v)) => (
+                &v.alice_keys,
+                &v.alice_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                None,
+            ),
+            State::VerifiedEncSig(v) => (
+                &v.alice_keys,
+                &v.alice_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                Some(v.dec_sig),
+            ),
+            State::MoneroLocked(v) => (
+                &v.alice_keys,
+                &v.alice_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                Some(v.dec_sig),
+            ),
+            State::ProceedRefund(v) => (
+                &v.alice_keys,
+                &v.alice_bch_recv,
+                &v.contract_pair,
+                &v.shared_keypair,
+                Some(v.dec_sig),
+            ),
+            _ => return false,
+        };
+
+        match transition {
+            Transition::Msg0 {
+                keys, receiving, ..
+            } => {
+                KeyPublicWithoutProof::from(keys.clone()) == *alice_keys
+                    && receiving.as_bytes() == alice_bch_recv.as_slice()
+            }
+            Transition::Contract {
+                bch_address,
+                xmr_address,
+            } => {
+                !matches!(self.state, State::WithAliceKey(_))
+                    && contract_pair.swaplock.cash_address() == *bch_address
+                    && monero::Address::from_viewpair(self.swap.xmr_network, shared_keypair)
+                        == *xmr_address
+            }
+            // the signature is deterministic, a redelivery decrypts to the same one
+            Transition::EncSig(enc_sig) => {
+                let decrypted = AdaptorSignature::decrypt_signature(
+                    &self.swap.keys.monero_spend,
+                    enc_sig.clone(),
+                );
+                dec_sig.is_some()
+                    && ecdsa::Signature::from_compact(&decrypted.to_bytes()).ok() == dec_sig
+            }
+            _ => false,
+        }
+    }
+
     pub fn get_swaplock_enc_sig(&self) -> Option<EncryptedSignature> {
         if let State::MoneroLocked(props) = &self.state {
             let hash = sha256::hash(&props.alice_bch_recv).to_byte_array();
@@ -156,55 +521,220 @@ impl Bob {
         }
     }
 
-    pub fn refund(&self) -> Option<(Transaction, Transaction)> {
+    /// A refund pair per SwapLock output, parts too small to cover the fees are left
+    pub fn refund(&self) -> Option<Vec<(Transaction, Transaction)>> {
         if let State::ProceedRefund(props) = &self.state {
-            let mining_fee = props.contract_pair.mining_fee;
-
-            let tx1 = {
-                let unlocker = props.contract_pair.swaplock.unlocking_script(&[]);
-                Transaction {
-                    version: 2,
-                    lock_time: PackedLockTime(0), // TODO: Should we use current time?
-                    input: vec![TxIn {
-                        sequence: Sequence(props.contract_pair.swaplock.timelock),
-                        previous_output: props.outpoint,
-                        script_sig: Script::from(unlocker),
-                        ..Default::default()
-                    }],
-                    output: vec![TxOut {
-                        value: self.swap.bch_amount.to_sat() - mining_fee,
-                        script_pubkey: Script::from(props.contract_pair.refund.locking_script()),
-                        token: None,
-                    }],
-                }
+            if let Some(refund_tx) = &props.refund_tx {
+                let outpoint = OutPoint::new(refund_tx.txid(), 0);
+                let tx2 = self.build_refund_spend(
+                    &props.contract_pair,
+                    &props.dec_sig,
+                    outpoint,
+                    &refund_tx.output[0],
+                );
+                return Some(vec![(refund_tx.clone(), tx2)]);
+            }
+            let parts = match props.parts.is_empty() {
+                true => vec![(props.outpoint, self.swap.bch_amount.to_sat())],
+                false => props.parts.clone(),
             };
+            let fees = props.contract_pair.refund_path_fee();
+            return Some(
+                parts
+                    .into_iter()
+                    .filter(|(_, value)| *value > fees)
+                    .map(|(outpoint, value)| {
+                        self.build_refund(&props.contract_pair, &props.dec_sig, outpoint, value)
+                    })
+                    .collect(),
+            );
+        }
 
-            let tx2 = {
-                let unlocker = props
-                    .contract_pair
-                    .refund
-                    .unlocking_script(&props.dec_sig.serialize_der());
-                Transaction {
-                    version: 2,
-                    lock_time: PackedLockTime(0), // TODO: Should we use current time?
-                    input: vec![TxIn {
-                        sequence: Sequence(0),
-                        previous_output: OutPoint::new(tx1.txid(), 0),
-                        script_sig: Script::from(unlocker),
-                        ..Default::default()
-                    }],
-                    output: vec![TxOut {
-                        value: self.swap.bch_amount.to_sat() - (mining_fee * 2),
-                        script_pubkey: self.swap.bch_recv.clone(),
-                        token: None,
-                    }],
-                }
-            };
+        None
+    }
+
+    fn refund_actions(&self) -> Vec<Action> {
+        let confirmed = match &self.state {
+            State::ProceedRefund(props) => props.refund_tx.as_ref().map(Transaction::txid),
+            _ => None,
+        };
+        self.refund()
+            .into_iter()
+            .flatten()
+            .flat_map(|(tx1, tx2)| [tx1, tx2])
+            .filter(|tx| Some(tx.txid()) != confirmed)
+            .map(Action::BroadcastTx)
+            .collect()
+    }
 
-            return Some((tx1, tx2));
+    /// A SwapLock deposit of the wrong amount, or `TransactionType::Rejected`,
+    /// can only go back through the refund path. That reveals our key share to Alice, the trade ends with it
+    fn refund_misfunded(
+        mut self,
+        props: Value1,
+        transaction: &Transaction,
+        conf: u32,
+        block: BchBlock,
+    ) -> (Self, Vec<Action>, Option<Error>) {
+        let parts = props.contract_pair.swaplock_outputs(transaction);
+        // left to the chain when it doesn't cover the fees
+        let fees = props.contract_pair.refund_path_fee();
+        let refundable = parts.iter().any(|(_, value)| *value > fees);
+        if !block.timelock_expired(self.swap.timelock1, conf) || !refundable {
+            return (self, vec![], Some(Error::InvalidBchAmount));
         }
 
-        None
+        self.state = State::ProceedRefund(Value3 {
+            alice_keys: props.alice_keys,
+            alice_bch_recv: props.alice_bch_recv,
+            contract_pair: props.contract_pair,
+            shared_keypair: props.shared_keypair,
+            dec_sig: props.dec_sig,
+            xmr_restore_height: props.xmr_restore_height,
+            outpoint: parts[0].0,
+            parts,
+            refund_tx: None,
+        });
+        let actions = self.refund_actions();
+        (self, actions, None)
+    }
+
+    /// Anyone can move the SwapLock to the Refund after timelock1, with
+    /// another transaction than ours
+    fn is_foreign_refund(&self, props: &Value3, transaction: &Transaction) -> bool {
+        let ours = self.refund().unwrap_or_default();
+        props.refund_tx.is_none()
+            && props.parts.is_empty()
+            && matches!(
+                props.contract_pair.analyze_tx(transaction),
+                Some((_, TransactionType::ToRefund))
+            )
+            && !ours.iter().any(|(tx1, _)| tx1.txid() == transaction.txid())
+    }
+
+    /// Same as `Bob::refund` but available as soon as Alice signature is verified.
+    /// The SwapLock outpoint is a placeholder until known, only use it for validation
+    pub fn refund_template(&self) -> Option<(Transaction, Transaction)> {
+        match &self.state {
+            State::VerifiedEncSig(props) => Some(self.build_refund(
+                &props.contract_pair,
+                &props.dec_sig,
+                OutPoint::null(),
+                self.swap.bch_amount.to_sat(),
+            )),
+            State::MoneroLocked(props) => Some(self.build_refund(
+                &props.contract_pair,
+                &props.dec_sig,
+                OutPoint::null(),
+                self.swap.bch_amount.to_sat(),
+            )),
+            State::ProceedRefund(_) => self.refund()?.into_iter().next(),
+            _ => None,
+        }
+    }
+
+    fn build_refund(
+        &self,
+        contract_pair: &ContractPair,
+        dec_sig: &ecdsa::Signature,
+        outpoint: OutPoint,
+        value: u64,
+    ) -> (Transaction, Transaction) {
+        let mining_fee = contract_pair.swaplock.mining_fee;
+        // the Refund output takes the tokens, its spend gives them back
+        let mut refund_output = TxOut {
+            value: value - mining_fee,
+            script_pubkey: Script::from(contract_pair.refund.locking_script()),
+            token: None,
+        };
+        self.tokens.carry(&outpoint, &mut refund_output);
+
+        let tx1 = {
+            let unlocker = contract_pair.swaplock.unlocking_script(&[]);
+            Transaction {
+                version: 2,
+                lock_time: PackedLockTime(0), // TODO: Should we use current time?
+                input: vec![TxIn {
+                    sequence: contract_pair.swaplock.sequence(),
+                    previous_output: outpoint,
+                    script_sig: Script::from(unlocker),
+                    ..Default::default()
+                }],
+                output: vec![refund_output.clone()],
+            }
+        };
+
+        let outpoint = OutPoint::new(tx1.txid(), 0);
+        let tx2 = self.build_refund_spend(contract_pair, dec_sig, outpoint, &refund_output);
+        (tx1, tx2)
+    }
+
+    /// Spend of the Refund output `outpoint` to us, tokens included
+    fn build_refund_spend(
+        &self,
+        contract_pair: &ContractPair,
+        dec_sig: &ecdsa::Signature,
+        outpoint: OutPoint,
+        refund_output: &TxOut,
+    ) -> Transaction {
+        let unlocker = contract_pair
+            .refund
+            .unlocking_script(&dec_sig.serialize_der());
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0), // TODO: Should we use current time?
+            input: vec![TxIn {
+                sequence: Sequence(0),
+                previous_output: outpoint,
+                script_sig: Script::from(unlocker),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: refund_output.value - contract_pair.refund.mining_fee,
+                script_pubkey: self.swap.bch_recv.clone(),
+                token: refund_output.token.clone(),
+            }],
+        }
+    }
+
+    /// Keep the refund chain of our SwapLock funding once seen, Alice's
+    /// signature is all it needs. The same parts as `Bob::refund`
+    fn presign_refund(
+        &mut self,
+        contract_pair: &ContractPair,
+        transaction: &Transaction,
+        outpoint: OutPoint,
+    ) {
+        let dec_sig = match &self.state {
+            State::VerifiedEncSig(props) => props.dec_sig,
+            State::MoneroLocked(props) => props.dec_sig,
+            _ => return,
+        };
+        let mut parts = contract_pair.funding_parts(transaction);
+        if parts.is_empty() {
+            parts.push((outpoint, self.swap.bch_amount.to_sat()));
+        }
+        let fees = contract_pair.refund_path_fee();
+        let chains: Vec<_> = parts
+            .into_iter()
+            .filter(|(_, value)| *value > fees)
+            .map(|(outpoint, value)| self.build_refund(contract_pair, &dec_sig, outpoint, value))
+            .collect();
+        self.presigned.record_refund(&chains);
+    }
+
+    /// Check refund transactions against `policy` long before they are needed
+    pub fn precheck_refund(&self, policy: &MempoolPolicy) -> Result<(), PolicyError> {
+        if let Some((tx1, tx2)) = self.refund_template() {
+            policy.check(&tx1, &[self.swap.bch_amount.to_sat()])?;
+            policy.check(&tx2, &[tx1.output[0].value])?;
+            if let Some(contracts) = self.get_contract_pair() {
+                contracts.check_spend(&tx1, policy)?;
+                contracts.check_spend(&tx2, policy)?;
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -212,8 +742,6 @@ impl Bob {
 impl SwapEvents for Bob {
     type State = Bob;
     fn transition(mut self, transition: Transition) -> (Self::State, Vec<Action>, Option<Error>) {
-        println!("{} - {}", &self.state, &transition);
-
         if let Transition::SetXmrRestoreHeight(height) = transition {
             match &mut self.state {
                 State::WithAliceKey(ref mut v) => v.xmr_restore_height = height,
@@ -224,31 +752,94 @@ impl SwapEvents for Bob {
             }
             return (self, vec![], None);
         }
+        if let (Transition::BchConfirmedTx(transaction, conf, _), Some(contract)) =
+            (&transition, self.get_contract_pair())
+        {
+            self.txids.observe(&contract, transaction);
+            self.tokens.observe(&contract, transaction);
+            if let Some((outpoint, TransactionType::ToSwapLock)) = contract.analyze_tx(transaction)
+            {
+                self.swaplock_confirmations = *conf;
+                self.presign_refund(&contract, transaction, outpoint);
+            }
+        }
+        if self.is_replay(&transition) {
+            return (self, vec![], None);
+        }
 
         match (self.state.clone(), transition) {
-            (State::Init, Transition::Msg0 { keys, receiving }) => {
+            (State::Init, Transition::KeyCommitment(commitment))
+                if self.swap.key_commitment && self.swap.peer_commitment.is_none() =>
+            {
+                self.swap.peer_commitment = Some(commitment);
+                return (self, vec![], None);
+            }
+            (
+                State::Init,
+                Transition::Msg0 {
+                    keys,
+                    receiving,
+                    peer,
+                },
+            ) => {
+                let ours = self.get_public_keys();
+                if let Err(e) = check_msg0(&ours, &keys, &receiving) {
+                    return (self, vec![Action::SafeDelete], Some(e));
+                }
                 let is_valid_keys = proof::verify(&keys.proof, keys.spend_bch, keys.monero_spend);
 
                 if !is_valid_keys {
                     return (self, vec![Action::SafeDelete], Some(Error::InvalidProof));
                 }
 
+                let version = match PROTOCOL_VERSION.negotiate(&peer.protocol_version) {
+                    Some(version) => version,
+                    None => {
+                        return (
+                            self,
+                            vec![Action::SafeDelete],
+                            Some(Error::IncompatibleVersion),
+                        )
+                    }
+                };
+                if self.swap.key_commitment && self.swap.peer_commitment != Some(keys.commitment())
+                {
+                    return (
+                        self,
+                        vec![Action::SafeDelete],
+                        Some(Error::InvalidCommitment),
+                    );
+                }
+
+                self.swap.protocol_version = Some(version);
+                self.swap.contract_version =
+                    PeerInfo::for_swap(self.swap.strict_contract).common_contract_version(&peer);
+                self.swap.peer = Some(peer);
+                self.swap.swap_id = Some(self.swap.derive_id(&ours, &keys));
+
                 let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
-                let contract_pair = ContractPair::create(
-                    1000,
-                    self.swap.bch_recv.clone().into_bytes(),
-                    self.swap.keys.ves.public_key(&secp),
-                    receiving.clone().into_bytes(),
-                    keys.ves.clone(),
-                    self.swap.timelock1,
-                    self.swap.timelock2,
-                    self.swap.bch_network,
-                    self.swap.bch_amount,
-                );
+                let contract_pair = self
+                    .swap
+                    .contract_options()
+                    .and_then(|options| {
+                        ContractPair::create(
+                            self.swap.contract_mining_fee(),
+                            self.swap.bch_recv.clone().into_bytes(),
+                            self.swap.keys.ves.public_key(&secp),
+                            receiving.clone().into_bytes(),
+                            keys.ves.clone(),
+                            self.swap.timelock1,
+                            self.swap.timelock2,
+                            self.swap.bch_network,
+                            self.swap.bch_amount,
+                            options,
+                        )
+                    })
+                    .map(|contract_pair| contract_pair.with_token_policy(self.swap.token_policy));
 
                 match contract_pair {
-                    None => return (self, vec![Action::SafeDelete], Some(Error::InvalidTimelock)),
-                    Some(contract_pair) => {
+                    Err(e) => return (self, vec![Action::SafeDelete], Some(e.into())),
+                    Ok(contract_pair) => {
                         let shared_keypair = monero::ViewPair {
                             view: self.swap.keys.monero_view + keys.monero_view,
                             spend: monero::PublicKey::from_private_key(
@@ -262,7 +853,7 @@ impl SwapEvents for Bob {
 
                             shared_keypair,
                             alice_keys: keys.into(),
-                            xmr_restore_height: 0,
+                            xmr_restore_height: self.swap.xmr_restore_height,
                         });
 
                         return (self, vec![Action::CreateXmrView(shared_keypair)], None);
@@ -333,6 +924,7 @@ impl SwapEvents for Bob {
                     xmr_restore_height: props.xmr_restore_height,
 
                     dec_sig,
+                    xmr_lock_proof: None,
                 });
                 let bch_amount = self.swap.bch_amount;
                 return (
@@ -345,11 +937,26 @@ impl SwapEvents for Bob {
                 );
             }
 
-            (State::VerifiedEncSig(props), Transition::XmrLockVerified(amount)) => {
-                if amount != self.swap.xmr_amount {
+            (State::VerifiedEncSig(mut props), Transition::XmrLockProof(proof)) => {
+                props.xmr_lock_proof = Some(proof);
+                self.state = State::VerifiedEncSig(props);
+                return (self, vec![], None);
+            }
+            // Alice keeps sending it until she gets the EncSig
+            (State::MoneroLocked(_), Transition::XmrLockProof(_)) => {
+                return (self, vec![], None);
+            }
+
+            (State::VerifiedEncSig(props), Transition::XmrLockVerified(amount, block)) => {
+                if !self
+                    .swap
+                    .xmr_overpayment
+                    .accepts(amount, self.swap.xmr_amount)
+                {
                     return (self, vec![], Some(Error::InvalidXmrAmount));
                 }
 
+                self.txids.xmr_lock = Some(block.txid.clone());
                 self.state = State::MoneroLocked(Value2 {
                     alice_keys: props.alice_keys,
                     alice_bch_recv: props.alice_bch_recv,
@@ -357,21 +964,47 @@ impl SwapEvents for Bob {
                     shared_keypair: props.shared_keypair,
                     dec_sig: props.dec_sig,
                     xmr_restore_height: props.xmr_restore_height,
+                    xmr_lock: Some(block),
+                });
+                return (self, vec![], None);
+            }
+            // mined again in another block
+            (State::MoneroLocked(mut props), Transition::XmrLockVerified(_, block)) => {
+                self.txids.xmr_lock = Some(block.txid.clone());
+                props.xmr_lock = Some(block);
+                self.state = State::MoneroLocked(props);
+                return (self, vec![], None);
+            }
+            // the EncSig is no longer served and the refund path opens again
+            // once timelock1 expires
+            (State::MoneroLocked(props), Transition::XmrLockReorged) => {
+                self.state = State::VerifiedEncSig(Value1 {
+                    alice_keys: props.alice_keys,
+                    alice_bch_recv: props.alice_bch_recv,
+                    contract_pair: props.contract_pair,
+                    shared_keypair: props.shared_keypair,
+                    xmr_restore_height: props.xmr_restore_height,
+                    dec_sig: props.dec_sig,
+                    xmr_lock_proof: None,
                 });
                 return (self, vec![], None);
             }
 
-            (State::VerifiedEncSig(props), Transition::BchConfirmedTx(transaction, conf)) => {
+            (
+                State::VerifiedEncSig(props),
+                Transition::BchConfirmedTx(transaction, conf, block),
+            ) => {
                 // The runner are still giving prev transaction while alice havent lock xmr
                 // we use it to track if tx sent to swaplock has enough age for refund
 
                 match props.contract_pair.analyze_tx(&transaction) {
                     // When timelock1 expire
                     Some((outpoint, TransactionType::ToSwapLock)) => {
-                        if conf < self.swap.timelock1 {
+                        if !block.timelock_expired(self.swap.timelock1, conf) {
                             return (self, vec![], None);
                         }
 
+                        let parts = props.contract_pair.funding_parts(&transaction);
                         self.state = State::ProceedRefund(Value3 {
                             alice_keys: props.alice_keys,
                             alice_bch_recv: props.alice_bch_recv,
@@ -380,9 +1013,11 @@ impl SwapEvents for Bob {
                             dec_sig: props.dec_sig,
                             xmr_restore_height: props.xmr_restore_height,
                             outpoint,
+                            parts,
+                            refund_tx: None,
                         });
-
-                        return (self, vec![Action::UnlockBchFallback], None);
+                        let actions = self.refund_actions();
+                        return (self, actions, None);
                     }
                     // when tx send to refund
                     Some((outpoint, TransactionType::ToRefund)) => {
@@ -394,18 +1029,73 @@ impl SwapEvents for Bob {
                             dec_sig: props.dec_sig,
                             xmr_restore_height: props.xmr_restore_height,
                             outpoint,
+                            parts: vec![],
+                            refund_tx: Some(transaction.clone()),
                         });
-                        return (self, vec![Action::UnlockBchFallback], None);
+                        let actions = self.refund_actions();
+                        return (self, actions, None);
+                    }
+                    Some((_, TransactionType::Underfunded(_)))
+                    | Some((_, TransactionType::Overfunded(_)))
+                    | Some((_, TransactionType::Rejected(_))) => {
+                        return self.refund_misfunded(props, &transaction, conf, block);
                     }
                     _ => return (self, vec![], None),
                 }
             }
 
-            (State::MoneroLocked(props), Transition::BchConfirmedTx(transaction, _)) => {
+            (State::MoneroLocked(props), Transition::BchConfirmedTx(transaction, conf, block)) => {
                 let scriptsig = match props.contract_pair.analyze_tx(&transaction) {
+                    Some((_, TransactionType::SwapLockToAlice))
+                        if conf < self.swap.bch_confirmations.claim =>
+                    {
+                        return (self, vec![], None);
+                    }
                     Some((_, TransactionType::SwapLockToAlice)) => {
                         transaction.input[0].script_sig.clone()
                     }
+                    // Alice never claimed, the refund path opens at timelock1 as
+                    // if the XMR had never been locked
+                    Some((outpoint, TransactionType::ToSwapLock)) => {
+                        if !block.timelock_expired(self.swap.timelock1, conf) {
+                            return (self, vec![], None);
+                        }
+
+                        let parts = props.contract_pair.funding_parts(&transaction);
+                        self.state = State::ProceedRefund(Value3 {
+                            alice_keys: props.alice_keys,
+                            alice_bch_recv: props.alice_bch_recv,
+                            contract_pair: props.contract_pair,
+                            shared_keypair: props.shared_keypair,
+                            dec_sig: props.dec_sig,
+                            xmr_restore_height: props.xmr_restore_height,
+                            outpoint,
+                            parts,
+                            refund_tx: None,
+                        });
+                        let actions = self.refund_actions();
+                        return (self, actions, None);
+                    }
+                    Some((outpoint, TransactionType::ToRefund)) => {
+                        self.state = State::ProceedRefund(Value3 {
+                            alice_keys: props.alice_keys,
+                            alice_bch_recv: props.alice_bch_recv,
+                            contract_pair: props.contract_pair,
+                            shared_keypair: props.shared_keypair,
+                            dec_sig: props.dec_sig,
+                            xmr_restore_height: props.xmr_restore_height,
+                            outpoint,
+                            parts: vec![],
+                            refund_tx: Some(transaction.clone()),
+                        });
+                        let actions = self.refund_actions();
+                        return (self, actions, None);
+                    }
+                    // refunding it would hand alice our key share
+                    Some((_, TransactionType::Underfunded(_)))
+                    | Some((_, TransactionType::Overfunded(_))) => {
+                        return (self, vec![], Some(Error::InvalidBchAmount));
+                    }
                     _ => return (self, vec![], Some(Error::InvalidTransaction)),
                 };
 
@@ -440,17 +1130,46 @@ impl SwapEvents for Bob {
                 return (self, vec![Action::TradeSuccess], None);
             }
 
+            // a SwapLock spend to the Refund confirmed before ours, only its
+            // output is left to spend
+            (State::ProceedRefund(mut props), Transition::BchConfirmedTx(transaction, ..))
+                if self.is_foreign_refund(&props, &transaction) =>
+            {
+                props.refund_tx = Some(transaction);
+                self.state = State::ProceedRefund(props);
+                let actions = self.refund_actions();
+                return (self, actions, None);
+            }
+
+            (State::Init, Transition::Abort) => {
+                self.state = State::Aborted(None);
+                return (self, vec![], None);
+            }
+            // nothing locked before a valid EncSig
+            (State::WithAliceKey(props) | State::ContractMatch(props), Transition::Abort) => {
+                self.state = State::Aborted(Some(props));
+                return (self, vec![], None);
+            }
+            (State::Aborted(_), Transition::Abort) => return (self, vec![], None),
             (_, _) => return (self, vec![], Some(Error::InvalidStateTransition)),
         }
     }
 
     fn get_transition(&self) -> Option<Transition> {
         match &self.state {
+            // revealed in WithAliceKey, once Alice revealed hers
+            State::Init if self.swap.key_commitment => Some(Transition::KeyCommitment(
+                self.get_public_keys().commitment(),
+            )),
             State::Init => None,
             State::WithAliceKey(_) => {
                 let keys = self.get_public_keys();
                 let receiving = self.swap.bch_recv.clone();
-                Some(Transition::Msg0 { keys, receiving })
+                Some(Transition::Msg0 {
+                    keys,
+                    receiving,
+                    peer: PeerInfo::for_swap(self.swap.strict_contract),
+                })
             }
             State::ContractMatch(_) => {
                 let (bch_address, xmr_address) = self.get_contract().unwrap();
@@ -459,10 +1178,16 @@ impl SwapEvents for Bob {
                     xmr_address,
                 })
             }
+            State::MoneroLocked(_)
+                if self.swaplock_confirmations < self.swap.bch_confirmations.enc_sig =>
+            {
+                None
+            }
             State::MoneroLocked(_) => {
                 let enc_sig = self.get_swaplock_enc_sig().unwrap();
                 Some(Transition::EncSig(enc_sig))
             }
+            State::Aborted(_) => Some(Transition::Abort),
             _ => None,
         }
     }
@@ -471,56 +1196,444 @@ impl SwapEvents for Bob {
 pub struct Runner<'a> {
     pub inner: Bob,
     pub trade_id: String,
-    pub bch: &'a TcpElectrum,
-    pub monerod: &'a monero_rpc::DaemonJsonRpcClient,
+    pub bch: &'a dyn ChainBackend,
+    pub monerod: &'a MonerodPool,
     pub monero_wallet: &'a Mutex<monero_rpc::WalletClient>,
+    /// Scan with the view key through monerod instead of `monero_wallet`
+    pub xmr_scanner: Option<&'a XmrScanner>,
     pub min_bch_conf: u32,
+    /// Validate refund transactions as soon as they can be built. None to skip
+    pub mempool_policy: Option<MempoolPolicy>,
+    /// Checkpointed after every applied transition. None to leave it to the caller
+    pub store: Option<&'a dyn SwapStore>,
+    /// Progress, broadcasts and failures. None prints them
+    pub events: Option<&'a dyn EventSink>,
+    /// Takes over the side effects of the transitions. None handles them here
+    pub effects: Option<&'a dyn SwapRunner>,
+    /// Time of the deadlines and the transcript. None for `SystemClock`
+    pub clock: Option<&'a dyn Clock>,
 }
 
-impl Runner<'_> {
-    pub async fn check_xmr(&mut self) -> anyhow::Result<()> {
+impl<'a> Runner<'a> {
+    /// Continue `trade_id` from its checkpoint in `store`: the view wallet is
+    /// generated again if the wallet rpc lost it, then both chains are rescanned.
+    /// The optional fields start at their defaults
+    pub async fn resume(
+        trade_id: &str,
+        store: &'a dyn SwapStore,
+        bch: &'a dyn ChainBackend,
+        monerod: &'a MonerodPool,
+        monero_wallet: &'a Mutex<monero_rpc::WalletClient>,
+    ) -> Result<Runner<'a>, SwapError> {
+        let loaded = store.load(trade_id).await;
+        let inner = match loaded.map_err(|e| SwapError::Store(e.into()))? {
+            Some(SwapWrapper::Bob(inner)) => inner,
+            Some(SwapWrapper::Alice(_)) => return Err(SwapError::WrongSide(trade_id.to_owned())),
+            None => return Err(SwapError::NoCheckpoint(trade_id.to_owned())),
+        };
+
+        let mut runner = Runner {
+            inner,
+            trade_id: trade_id.to_owned(),
+            bch,
+            monerod,
+            monero_wallet,
+            xmr_scanner: None,
+            min_bch_conf: 1,
+            mempool_policy: Some(MempoolPolicy::default()),
+            store: Some(store),
+            events: None,
+            effects: None,
+            clock: None,
+        };
+        runner.reopen_view_wallet().await?;
+        runner.resync().await?;
+        Ok(runner)
+    }
+
+    /// Unix time from `clock`
+    pub fn now(&self) -> u64 {
+        self.clock.unwrap_or(&SystemClock).now()
+    }
+
+    /// Wait `duration` on `clock`, for the polling loops
+    pub async fn sleep(&self, duration: Duration) {
+        self.clock.unwrap_or(&SystemClock).sleep(duration).await
+    }
+
+    fn emit(&self, event: Event) {
+        match self.events {
+            Some(events) => events.emit(event),
+            None => print_event(&format!("[{}]: ", self.trade_id), &event),
+        }
+    }
+
+    /// `{swap_id}_{suffix}`, or `{trade_id}_{suffix}` for trades from before the
+    /// canonical id (`Swap::swap_id`) so their wallets are still found
+    fn wallet_name(&self, suffix: &str) -> String {
+        match &self.inner.swap.swap_id {
+            Some(swap_id) => format!("{swap_id}_{suffix}"),
+            None => format!("{}_{suffix}", self.trade_id),
+        }
+    }
+
+    async fn generate_view_wallet(
+        &self,
+        keypair: &monero::ViewPair,
+        restore_height: u64,
+    ) -> Result<(), SwapError> {
+        let address = monero::Address::from_viewpair(self.inner.swap.xmr_network, keypair);
         let monero_wallet = self.monero_wallet.lock().await;
+        let _ = monero_wallet
+            .generate_from_keys(monero_rpc::GenerateFromKeysArgs {
+                address,
+                restore_height: Some(restore_height),
+                autosave_current: Some(true),
+                filename: self.wallet_name("view"),
+                password: "".to_owned(),
+                spendkey: None,
+                viewkey: keypair.view,
+            })
+            .await
+            .map_err(MoneroError::from)?;
         monero_wallet
-            .open_wallet(format!("{}_view", self.trade_id), Some("".to_owned()))
-            .await?;
+            .close_wallet()
+            .await
+            .map_err(MoneroError::from)?;
+        Ok(())
+    }
 
-        let balance = monero_wallet.get_balance(0, None).await?;
-        drop(monero_wallet);
+    /// Restore height of the view wallet, which is generated unless the lock
+    /// is scanned with the view key
+    async fn create_xmr_view(&self, keypair: &monero::ViewPair) -> Result<u64, SwapError> {
+        if let Some(effects) = self.effects {
+            if let Some(height) = effects.create_xmr_view(&self.trade_id, keypair).await? {
+                return Ok(height);
+            }
+        }
+        let height = match self.inner.swap.xmr_restore_height {
+            0 => xmr_restore_height(self.monerod.block_count().await?),
+            height => height,
+        };
+        if self.xmr_scanner.is_none() {
+            self.generate_view_wallet(keypair, height).await?;
+        }
+        Ok(height)
+    }
 
-        println!(
-            "[{}]: Balance: {} Unlocked: {} Expected: {}",
-            self.trade_id, balance.balance, balance.unlocked_balance, self.inner.swap.xmr_amount
-        );
+    /// Generate the `{swap_id}_view` wallet again while the XMR lock is
+    /// still watched, when it can't be opened
+    async fn reopen_view_wallet(&self) -> Result<(), SwapError> {
+        let (keypair, restore_height) = match self.inner.get_xmr_lock() {
+            Some((keypair, restore_height, _)) => (keypair, restore_height),
+            None => match self.inner.get_xmr_view() {
+                Some(view) => view,
+                None => return Ok(()),
+            },
+        };
+        if self.xmr_scanner.is_some() {
+            return Ok(());
+        }
 
-        let balance = match self.inner.swap.xmr_network {
-            monero::Network::Mainnet => balance.unlocked_balance,
-            _ => balance.balance,
+        let opened = {
+            let monero_wallet = self.monero_wallet.lock().await;
+            let filename = self.wallet_name("view");
+            match monero_wallet
+                .open_wallet(filename, Some("".to_owned()))
+                .await
+            {
+                Ok(()) => monero_wallet.close_wallet().await.is_ok(),
+                Err(_) => false,
+            }
         };
+        if !opened {
+            self.generate_view_wallet(&keypair, restore_height).await?;
+        }
+        Ok(())
+    }
 
-        if balance != self.inner.swap.xmr_amount {
-            return Ok(());
+    /// `Event::XmrDaemonNotSynced` while the heights of monerod can't be trusted
+    async fn daemon_not_synced(&self) -> Result<Option<Event>, SwapError> {
+        let status = self.monerod.sync_status().await?;
+        if self.monerod.synced(&status) {
+            return Ok(None);
+        }
+        Ok(Some(Event::XmrDaemonNotSynced {
+            height: status.height,
+            target_height: status.target_height.max(status.height),
+        }))
+    }
+
+    /// Verify the XMR lock once it has `Swap::xmr_confirmations`, then
+    /// recheck it after reorgs until Alice claims.
+    /// Returns `Event::XmrConfirmations` while it is still too shallow and
+    /// `Event::XmrDaemonNotSynced` without checking while monerod syncs
+    pub async fn check_xmr(&mut self) -> Result<Vec<Event>, SwapError> {
+        let verified = self.inner.get_xmr_lock();
+        let (keys, restore_height) = match (self.inner.get_xmr_view(), &verified) {
+            (Some(view), _) => view,
+            (None, Some((keys, restore_height, _))) => (keys.clone(), *restore_height),
+            (None, None) => return Ok(vec![]),
+        };
+        if let Some(event) = self.daemon_not_synced().await? {
+            return Ok(vec![event]);
+        }
+        if let Some((_, _, block)) = verified {
+            return self.recheck_xmr_lock(&keys, restore_height, block).await;
+        }
+
+        let transfers = match self.inner.get_xmr_lock_proof().cloned() {
+            Some(proof) => vec![self.xmr_lock_proof_funding(&keys, &proof).await?],
+            None => self.xmr_transfers(&keys, restore_height).await?,
+        };
+        if transfers.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let expected = self.inner.swap.xmr_amount;
+        let required = self.inner.swap.xmr_confirmations;
+        let address = monero::Address::from_viewpair(self.inner.swap.xmr_network, &keys);
+        for transfer in &transfers {
+            self.emit(Event::FundingSeen {
+                address: address.to_string(),
+                txid: transfer.txid.clone(),
+                confirmations: transfer.confirmations,
+            });
+        }
+
+        // a single transfer must carry the whole amount, a balance can be
+        // made of dust or of outputs Bob can't tell apart from the lock.
+        // Less may still be topped up by a new lock, more is up to the policy
+        let policy = self.inner.swap.xmr_overpayment;
+        let lock = transfers
+            .iter()
+            .filter(|transfer| policy.accepts(transfer.amount, expected))
+            .max_by_key(|transfer| transfer.confirmations);
+        let lock = match lock {
+            Some(lock) => lock,
+            None if transfers.len() > 1 => {
+                return Ok(vec![Event::XmrSplitLock {
+                    transfers: transfers.len(),
+                    total: transfers.iter().map(|t| t.amount.as_pico()).sum(),
+                }])
+            }
+            None => {
+                return Ok(vec![Event::XmrAmountMismatch {
+                    expected: expected.as_pico(),
+                    received: transfers[0].amount.as_pico(),
+                }])
+            }
+        };
+
+        self.inner.confirmations = Some(Confirmations {
+            chain: Chain::Xmr,
+            seen: lock.confirmations,
+            required,
+        });
+        if lock.confirmations < required {
+            return Ok(vec![Event::XmrConfirmations {
+                confirmations: lock.confirmations,
+                required,
+            }]);
         }
 
+        let mut events = Vec::new();
+        if lock.amount != expected {
+            events.push(Event::XmrAmountMismatch {
+                expected: expected.as_pico(),
+                received: lock.amount.as_pico(),
+            });
+        }
+        let block = self.xmr_lock_block(lock).await?;
         let _ = self
-            .priv_transition(Transition::XmrLockVerified(balance))
+            .priv_transition(Transition::XmrLockVerified(lock.amount, block))
             .await;
-        Ok(())
+        Ok(events)
+    }
+
+    async fn xmr_lock_block(&self, transfer: &XmrTransfer) -> Result<XmrLockBlock, SwapError> {
+        let height = transfer_height(self.monerod.block_count().await?, transfer.confirmations)?;
+        let block_hash = self
+            .monerod
+            .block_hash(height)
+            .await?
+            .ok_or(MoneroError::AboveTip(height))?;
+        Ok(XmrLockBlock {
+            txid: transfer.txid.clone(),
+            height,
+            block_hash,
+        })
     }
 
-    pub async fn check_bch(&mut self) -> anyhow::Result<()> {
+    /// Nothing to do while the lock block is in the chain. Otherwise the lock
+    /// is followed to its new block, or the verification is reverted when
+    /// it is gone (back in the pool or double spent)
+    async fn recheck_xmr_lock(
+        &mut self,
+        keys: &monero::ViewPair,
+        restore_height: u64,
+        block: XmrLockBlock,
+    ) -> Result<Vec<Event>, SwapError> {
+        if self.monerod.block_hash(block.height).await?.as_ref() == Some(&block.block_hash) {
+            return Ok(vec![]);
+        }
+
+        let transfer = self
+            .xmr_transfers(keys, restore_height)
+            .await?
+            .into_iter()
+            .find(|transfer| transfer.txid == block.txid && transfer.confirmations > 0);
+        if let Some(transfer) = transfer {
+            let new_block = self.xmr_lock_block(&transfer).await?;
+            let _ = self
+                .priv_transition(Transition::XmrLockVerified(transfer.amount, new_block))
+                .await;
+            return Ok(vec![]);
+        }
+
+        let _ = self.priv_transition(Transition::XmrLockReorged).await;
+        Ok(vec![Event::XmrLockReorged { txid: block.txid }])
+    }
+
+    /// XMR sent to the shared address still in the tx pool of `pool`
+    pub async fn xmr_pool_events(&self, pool: &XmrScanner) -> Result<Vec<Event>, SwapError> {
+        let (keys, _) = match self.inner.get_xmr_view() {
+            Some(view) => view,
+            None => return Ok(vec![]),
+        };
+
+        Ok(pool
+            .pool_payments(&keys)
+            .await?
+            .into_iter()
+            .map(|(txid, received)| Event::XmrLockPending {
+                txid,
+                received: received.as_pico(),
+            })
+            .collect())
+    }
+
+    /// Confirmed transfers to the shared address
+    async fn xmr_transfers(
+        &self,
+        keys: &monero::ViewPair,
+        restore_height: u64,
+    ) -> Result<Vec<XmrTransfer>, SwapError> {
+        if let Some(scanner) = self.xmr_scanner {
+            return Ok(scanner.transfers(keys, restore_height).await?);
+        }
+
+        let monero_wallet = self.monero_wallet.lock().await;
+        monero_wallet
+            .open_wallet(self.wallet_name("view"), Some("".to_owned()))
+            .await
+            .map_err(MoneroError::from)?;
+        let mut transfers = monero_wallet
+            .get_transfers(monero_rpc::GetTransfersSelector {
+                category_selector: [(monero_rpc::GetTransfersCategory::In, true)].into(),
+                account_index: None,
+                subaddr_indices: None,
+                block_height_filter: None,
+            })
+            .await
+            .map_err(MoneroError::from)?;
+
+        Ok(transfers
+            .remove(&monero_rpc::GetTransfersCategory::In)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|transfer| XmrTransfer {
+                txid: transfer.txid.to_string(),
+                amount: transfer.amount,
+                confirmations: transfer.confirmations.unwrap_or(0),
+            })
+            .collect())
+    }
+
+    /// Only the output of Alice's lock tx counts, other transfers to the
+    /// shared address (dust, split funding) don't change the verified amount
+    async fn xmr_lock_proof_funding(
+        &self,
+        keys: &monero::ViewPair,
+        proof: &XmrLockProof,
+    ) -> Result<XmrTransfer, SwapError> {
+        if let Some(scanner) = self.xmr_scanner {
+            let (amount, confirmations) = scanner.check_tx(keys, &proof.txid).await?;
+            return Ok(XmrTransfer {
+                txid: proof.txid.clone(),
+                amount,
+                confirmations,
+            });
+        }
+
+        let address = monero::Address::from_viewpair(self.inner.swap.xmr_network, keys);
+        let txid = hex::decode(&proof.txid).map_err(|_| Error::InvalidProof)?;
+        let tx_key = hex::decode(&proof.tx_key).map_err(|_| Error::InvalidProof)?;
+        let monero_wallet = self.monero_wallet.lock().await;
+        monero_wallet
+            .open_wallet(self.wallet_name("view"), Some("".to_owned()))
+            .await
+            .map_err(MoneroError::from)?;
+        let (confirmations, _, received) = monero_wallet
+            .check_tx_key(txid, tx_key, address)
+            .await
+            .map_err(MoneroError::from)?;
+
+        Ok(XmrTransfer {
+            txid: proof.txid.clone(),
+            amount: monero::Amount::from_pico(received.get()),
+            confirmations: confirmations.get(),
+        })
+    }
+
+    pub async fn check_bch(&mut self) -> Result<(), SwapError> {
         let contract = self.inner.get_contract_pair();
         if let Some(contract) = contract {
             let swaplock = contract.swaplock.cash_address();
             let refund = contract.refund.cash_address();
             for address in [swaplock, refund].into_iter() {
-                let txs = scan_address_conf_tx(&self.bch, &address, self.min_bch_conf).await;
-                println!("[{}]: {}txs address {}", self.trade_id, txs.len(), address);
+                let (bch, min_conf) = (self.bch, self.min_bch_conf);
+                let mut txs = with_retry(|| bch.address_history(&address, min_conf)).await?;
+                // spends first, an expired funding must not start a refund
+                // once alice claimed
+                txs.sort_by_key(|(tx, _)| {
+                    matches!(
+                        contract.analyze_tx(tx),
+                        Some((_, TransactionType::ToSwapLock))
+                    )
+                });
+                // next to the right funding a wrong deposit is never refunded
+                let funded = txs.iter().any(|(tx, _)| {
+                    matches!(
+                        contract.analyze_tx(tx),
+                        Some((_, TransactionType::ToSwapLock))
+                    )
+                });
+                if funded {
+                    txs.retain(|(tx, _)| {
+                        !matches!(
+                            contract.analyze_tx(tx),
+                            Some((_, TransactionType::Underfunded(_)))
+                                | Some((_, TransactionType::Overfunded(_)))
+                                | Some((_, TransactionType::Rejected(_)))
+                        )
+                    });
+                }
                 for (tx, conf) in txs {
+                    self.emit(Event::FundingSeen {
+                        address: address.clone(),
+                        txid: tx.txid().to_string(),
+                        confirmations: conf.into(),
+                    });
+                    let timelock = contract.swaplock.timelock;
+                    let block = timelock_block(bch, &tx.txid().to_string(), timelock).await?;
                     let check_bch = self
-                        .priv_transition(Transition::BchConfirmedTx(tx, conf))
+                        .priv_transition(Transition::BchConfirmedTx(tx, conf, block))
                         .await;
-                    if let Err(check_bch_err) = check_bch {
-                        dbg!(check_bch_err);
+                    if let Err(e) = check_bch {
+                        self.emit(Event::Error {
+                            message: e.to_string(),
+                        });
                     }
                 }
             }
@@ -529,87 +1642,282 @@ impl Runner<'_> {
         Ok(())
     }
 
-    pub async fn pub_transition(&mut self, transition: Transition) -> anyhow::Result<()> {
+    /// Our SwapLock funding while it is unconfirmed
+    pub async fn mempool_events(&self) -> Result<Vec<Event>, SwapError> {
+        let contract = match (&self.inner.state, self.inner.get_contract_pair()) {
+            (State::VerifiedEncSig(_), Some(contract)) => contract,
+            _ => return Ok(vec![]),
+        };
+
+        let swaplock = contract.swaplock.cash_address();
+        Ok(funding_events(
+            &contract,
+            self.bch.address_mempool(&swaplock).await?,
+        ))
+    }
+
+    /// Track the confirmed swap transactions and report the ones reorganized out.
+    /// Confirmations are then verified again before any further transition
+    pub async fn check_reorg(&mut self, watch: &mut ReorgWatch) -> Result<Vec<Event>, SwapError> {
+        let contract = match self.inner.get_contract_pair() {
+            Some(contract) => contract,
+            None => return Ok(vec![]),
+        };
+
+        let addresses = [
+            contract.swaplock.cash_address(),
+            contract.refund.cash_address(),
+        ];
+        let events = watch.update(self.bch, &addresses).await?;
+        if !events.is_empty() {
+            self.check_bch().await?;
+        }
+
+        Ok(events)
+    }
+
+    /// Rescan everything after notifications were lost
+    pub async fn resync(&mut self) -> Result<(), SwapError> {
+        self.check_bch().await?;
+        self.check_xmr().await?;
+        Ok(())
+    }
+
+    /// Back out once Alice stayed silent past `watchdog`: abort before the
+    /// SwapLock can be funded, rescan after so the refund starts at timelock1.
+    /// Returns `Event::DeadlineExpired` when it acted
+    pub async fn check_deadlines(
+        &mut self,
+        watchdog: &Watchdog,
+    ) -> Result<Option<Event>, SwapError> {
+        let now = self.now();
+        if self.inner.state_since == 0 {
+            // trades from before the watchdog get the full timeout from now
+            self.inner.state_since = now;
+        }
+        if !watchdog.expired(self.inner.state_since, now) {
+            return Ok(None);
+        }
+
+        let state = self.inner.state.to_string();
+        match self.inner.state {
+            State::Init | State::WithAliceKey(_) | State::ContractMatch(_) => {
+                self.priv_transition(Transition::Abort).await?
+            }
+            State::VerifiedEncSig(_) | State::MoneroLocked(_) => self.resync().await?,
+            _ => return Ok(None),
+        }
+        Ok(Some(Event::DeadlineExpired { state }))
+    }
+
+    /// Network delivered transition, checked against the counterparty's key
+    pub async fn pub_transition(&mut self, signed: SignedTransition) -> Result<(), SwapError> {
+        let signer = self.inner.get_peer_ves();
+        signed.verify(&self.inner.swap, signer.as_ref())?;
+
+        let transition = signed.transition;
         match &transition {
+            Transition::KeyCommitment(_) => {}
             Transition::Msg0 { .. } => {}
             Transition::Contract { .. } => {}
             Transition::EncSig(_) => {}
-            _ => bail!("priv transition"),
+            Transition::XmrLockProof(_) => {}
+            Transition::Abort => {}
+            _ => return Err(SwapError::NotFromPeer(transition.to_string())),
         }
 
         self.priv_transition(transition).await
     }
 
-    pub async fn priv_transition(&mut self, transition: Transition) -> anyhow::Result<()> {
+    pub async fn priv_transition(&mut self, transition: Transition) -> Result<(), SwapError> {
+        let entry = TranscriptEntry::new(self.now(), &transition);
         let (mut new_state, actions, error) = self.inner.clone().transition(transition);
         if let Some(err) = error {
-            bail!(err);
+            return Err(err.into());
         }
 
+        let mut broadcasts = Vec::new();
         for action in actions {
             match action {
                 Action::CreateXmrView(keypair) => {
-                    let address =
-                        monero::Address::from_viewpair(self.inner.swap.xmr_network, &keypair);
-                    let height = self.monerod.get_block_count().await?.get();
-
-                    let monero_wallet = self.monero_wallet.lock().await;
-                    let _ = monero_wallet
-                        .generate_from_keys(monero_rpc::GenerateFromKeysArgs {
-                            address,
-                            restore_height: Some(height),
-                            autosave_current: Some(true),
-                            filename: format!("{}_view", self.trade_id),
-                            password: "".to_owned(),
-                            spendkey: None,
-                            viewkey: keypair.view,
-                        })
-                        .await?;
-                    monero_wallet.close_wallet().await?;
+                    // monerod or the wallet rpc briefly away must not fail the trade
+                    let runner = &*self;
+                    let height = with_retry(|| runner.create_xmr_view(&keypair)).await?;
                     new_state = new_state
                         .transition(Transition::SetXmrRestoreHeight(height))
                         .0;
                 }
                 Action::LockBch(amount, addr) => {
-                    let msg = format!("  Send {} to {}  ", amount, addr);
-                    println!("|{:=^width$}|", "", width = msg.len());
-                    println!("|{msg}|");
-                    println!("|{:=^width$}|", "", width = msg.len());
+                    let locked = match self.effects {
+                        Some(effects) => effects.lock_bch(&self.trade_id, amount, &addr).await,
+                        None => Ok(false),
+                    };
+                    if let Err(e) = &locked {
+                        self.emit(Event::Error {
+                            message: format!("BCH lock failed: {e}"),
+                        });
+                    }
+                    if !locked.unwrap_or(false) {
+                        self.emit(Event::NeedsUserAction {
+                            action: format!("Send {amount} to {addr}"),
+                        });
+                    }
                 }
-                Action::UnlockBchFallback => {
-                    let (tx1, tx2) = new_state.refund().unwrap();
-
-                    let mut buffer = Vec::new();
-                    tx1.consensus_encode(&mut buffer).unwrap();
-                    let tx_hex: String = buffer.encode_hex();
-
-                    println!("Broadcasting tx. SwapLock -> Refund: {}", tx1.txid());
-                    let transaction_resp = self
-                        .bch
-                        .send("blockchain.transaction.broadcast", json!([tx_hex]))
-                        .await
-                        .unwrap();
-                    dbg!(transaction_resp);
-
-                    sleep(Duration::from_secs(5)).await;
-
-                    let mut buffer = Vec::new();
-                    tx2.consensus_encode(&mut buffer).unwrap();
-                    let tx_hex: String = buffer.encode_hex();
-
-                    println!("Broadcasting tx. Refund -> Bob Output: {}", tx2.txid());
-                    let transaction_resp = self
-                        .bch
-                        .send("blockchain.transaction.broadcast", json!([tx_hex]))
-                        .await
-                        .unwrap();
-                    dbg!(transaction_resp);
+                Action::WatchXmr(address) => {
+                    if let Some(effects) = self.effects {
+                        effects.watch_xmr(&self.trade_id, &address).await;
+                    }
                 }
+                Action::BroadcastTx(transaction) => broadcasts.push(transaction),
                 _ => {}
             }
         }
+        for event in self.broadcast(&broadcasts).await {
+            self.emit(event);
+        }
 
+        let became_verified = match (&self.inner.state, &new_state.state) {
+            (State::VerifiedEncSig(_), _) => false,
+            (_, State::VerifiedEncSig(_)) => true,
+            _ => false,
+        };
+        let succeeded = !matches!(self.inner.state, State::SwapSuccess(..))
+            && matches!(new_state.state, State::SwapSuccess(..));
+        let (from, to) = (self.inner.state.to_string(), new_state.state.to_string());
+        if from != to {
+            new_state.state_since = self.now();
+            new_state.confirmations = None;
+        }
+        new_state
+            .transcript
+            .record(entry, &self.inner.state, &new_state.state);
+        let advertised = match (&self.inner.swap.peer, &new_state.swap.peer) {
+            (None, Some(peer)) => Some(peer.clone()),
+            _ => None,
+        };
         self.inner = new_state;
+        if let Some(store) = self.store {
+            let swap = SwapWrapper::Bob(self.inner.clone());
+            let (trade_id, swap) = (&self.trade_id, &swap);
+            let save = || async move {
+                let saved = store.save(trade_id, swap).await;
+                saved.map_err(|e| SwapError::Store(e.into()))
+            };
+            if let Err(e) = with_retry(save).await {
+                self.emit(Event::Error {
+                    message: format!("Checkpoint failed: {e}"),
+                });
+            }
+        }
+        if let Some(peer) = advertised {
+            self.emit(Event::PeerAdvertised(peer));
+        }
+        if from != to {
+            self.emit(Event::StateChanged { from, to });
+        }
+
+        if became_verified {
+            if let Some(policy) = &self.mempool_policy {
+                self.inner.precheck_refund(policy)?;
+            }
+        }
+
+        if succeeded {
+            // usually still locked, retried by the caller through `sweep_payout`
+            if let Err(e) = self.sweep_payout().await {
+                self.emit(Event::Error {
+                    message: format!("XMR sweep failed: {e}"),
+                });
+            }
+        }
+
         Ok(())
     }
+
+    async fn broadcast(&self, txs: &[Transaction]) -> Vec<Event> {
+        if txs.is_empty() {
+            return vec![];
+        }
+        if let Some(effects) = self.effects {
+            if let Some(events) = effects.broadcast(&self.trade_id, txs).await {
+                return events;
+            }
+        }
+        let contracts = self.inner.get_contract_pair();
+        broadcast_txs(
+            self.bch,
+            txs,
+            self.mempool_policy.as_ref(),
+            contracts.as_ref(),
+        )
+        .await
+    }
+
+    /// Sweep the claimed XMR to `Swap::xmr_payout_address` once, after SwapSuccess.
+    /// Returns the sweep txids when it happened on this call
+    pub async fn sweep_payout(&mut self) -> Result<Option<Vec<String>>, SwapError> {
+        let destination = match self.inner.swap.xmr_payout_address {
+            Some(destination) => destination,
+            None => return Ok(None),
+        };
+        let (keypair, restore_height) = match self.inner.state {
+            State::SwapSuccess(keypair, _, restore_height) => (keypair, restore_height),
+            _ => return Ok(None),
+        };
+        if !self.inner.xmr_payout_txids.is_empty() {
+            return Ok(None);
+        }
+
+        let txids = xmr_wallet::sweep(
+            self.monero_wallet,
+            &self.wallet_name("payout"),
+            self.inner.swap.xmr_network,
+            keypair,
+            restore_height,
+            destination,
+            SweepAmount::All,
+            self.inner.swap.xmr_fee_priority,
+            |_| true,
+        )
+        .await?;
+        for txid in &txids {
+            self.emit(Event::Broadcasted { txid: txid.clone() });
+        }
+
+        self.inner.xmr_payout_txids = txids.clone();
+        Ok(Some(txids))
+    }
+
+    /// Remove the `{swap_id}_view` wallet of a finished trade from `wallet_dir`,
+    /// see `xmr_wallet::cleanup`. Returns true when it was cleaned on this call
+    pub async fn cleanup_view_wallet(
+        &self,
+        wallet_dir: &Path,
+        retention: WalletRetention,
+    ) -> Result<bool, SwapError> {
+        let (keys, restore_height) = match self.inner.get_finished_xmr_view() {
+            Some(view) => view,
+            None => return Ok(false),
+        };
+
+        Ok(xmr_wallet::cleanup(
+            self.monero_wallet,
+            wallet_dir,
+            &self.wallet_name("view"),
+            self.inner.swap.xmr_network,
+            &keys,
+            restore_height,
+            retention,
+        )
+        .await?)
+    }
+
+    /// Regenerate the claimed XMR wallet after SwapSuccess and return its balance,
+    /// see `XmrRecovery::restore`. None in other states
+    pub async fn recover_xmr(&self) -> Result<Option<RecoveredBalance>, SwapError> {
+        match self.inner.get_xmr_recovery() {
+            Some(recovery) => Ok(Some(recovery.restore(self.monero_wallet).await?)),
+            None => Ok(None),
+        }
+    }
 }
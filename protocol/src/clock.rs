@@ -0,0 +1,64 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+use crate::watchdog::unix_now;
+
+/// Time as seen by the runners: the `Watchdog` deadlines, `state_since` and
+/// the transcript. `MockClock` lets tests skip ahead instead of waiting
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// Unix time in seconds
+    fn now(&self) -> u64;
+
+    async fn sleep(&self, duration: Duration);
+}
+
+/// Wall clock and tokio timers, what the runners use without a `Clock`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        unix_now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// Only moves when told to. `sleep` returns at once, having moved the clock
+/// by its duration
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new(now: u64) -> Self {
+        MockClock {
+            now: AtomicU64::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.now.fetch_add(duration.as_secs(), Ordering::SeqCst);
+    }
+}
+
+#[async_trait]
+impl Clock for MockClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+        tokio::task::yield_now().await
+    }
+}
@@ -0,0 +1,241 @@
+use serde::Serialize;
+
+use super::{Contract, ContractPair};
+
+/// Parameter of the constructor or of a function, as in a CashScript artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AbiInput {
+    pub name: &'static str,
+    /// CashScript type: `int`, `bytes` or `pubkey`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AbiFunction {
+    pub name: &'static str,
+    pub inputs: Vec<AbiInput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Compiler {
+    pub name: &'static str,
+    pub version: &'static str,
+}
+
+/// CashScript artifact of one contract, loadable by the CashScript SDK and
+/// Bitauth IDE. Besides the usual fields it carries the arguments of this
+/// swap, so the redeem script and address can be rebuilt and compared
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Artifact {
+    pub contract_name: &'static str,
+    /// Pushed in reverse order before `bytecode`, the first one ends on top
+    pub constructor_inputs: Vec<AbiInput>,
+    /// Only one function: no selector, the input sequence picks the path.
+    /// 0 needs `sig` for `pubkey_ves`, the timelock leaves it empty
+    pub abi: Vec<AbiFunction>,
+    /// ASM, constructor arguments excluded
+    pub bytecode: String,
+    pub compiler: Compiler,
+    /// Hex of `bytes` and `pubkey`, decimal of `int`, as `constructor_inputs`
+    pub constructor_args: Vec<String>,
+    /// Hex of the redeem script, arguments included
+    pub redeem_script: String,
+    pub address: String,
+}
+
+/// Both contracts of a swap, see `ContractPair::export_artifact`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContractArtifacts {
+    pub swaplock: Artifact,
+    pub refund: Artifact,
+}
+
+fn input(name: &'static str, kind: &'static str) -> AbiInput {
+    AbiInput { name, kind }
+}
+
+/// Names of the opcodes `Contract::bytecode` is made of
+fn opcode_name(opcode: u8) -> Option<&'static str> {
+    Some(match opcode {
+        0x00 => "OP_0",
+        0x51 => "OP_1",
+        0x52 => "OP_2",
+        0x53 => "OP_3",
+        0x54 => "OP_4",
+        0x63 => "OP_IF",
+        0x67 => "OP_ELSE",
+        0x68 => "OP_ENDIF",
+        0x6b => "OP_TOALTSTACK",
+        0x6c => "OP_FROMALTSTACK",
+        0x6d => "OP_2DROP",
+        0x75 => "OP_DROP",
+        0x78 => "OP_OVER",
+        0x79 => "OP_PICK",
+        0x88 => "OP_EQUALVERIFY",
+        0x94 => "OP_SUB",
+        0x9c => "OP_NUMEQUAL",
+        0x9d => "OP_NUMEQUALVERIFY",
+        0xa2 => "OP_GREATERTHANOREQUAL",
+        0xa8 => "OP_SHA256",
+        0xb1 => "OP_CHECKLOCKTIMEVERIFY",
+        0xb2 => "OP_CHECKSEQUENCEVERIFY",
+        0xbb => "OP_CHECKDATASIGVERIFY",
+        0xc3 => "OP_TXINPUTCOUNT",
+        0xc4 => "OP_TXOUTPUTCOUNT",
+        0xc5 => "OP_TXLOCKTIME",
+        0xc6 => "OP_UTXOVALUE",
+        0xcb => "OP_INPUTSEQUENCENUMBER",
+        0xcc => "OP_OUTPUTVALUE",
+        0xcd => "OP_OUTPUTBYTECODE",
+        0xce => "OP_UTXOTOKENCATEGORY",
+        0xd0 => "OP_UTXOTOKENAMOUNT",
+        0xd1 => "OP_OUTPUTTOKENCATEGORY",
+        0xd3 => "OP_OUTPUTTOKENAMOUNT",
+        _ => return None,
+    })
+}
+
+/// `bytecode` holds no push data, every byte is an opcode
+fn asm(bytecode: &[u8]) -> String {
+    bytecode
+        .iter()
+        .map(|opcode| match opcode_name(*opcode) {
+            Some(name) => name.to_owned(),
+            None => format!("0x{opcode:02x}"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl Contract {
+    fn artifact(&self, contract_name: &'static str) -> Artifact {
+        let mut constructor_inputs = vec![
+            input("miningFee", "int"),
+            input("successLockingBytecode", "bytes"),
+            input("pubkeyVes", "pubkey"),
+            input("timelock", "int"),
+            input("failLockingBytecode", "bytes"),
+        ];
+        let mut constructor_args = vec![
+            self.mining_fee.to_string(),
+            hex::encode(&self.success_output),
+            self.pubkey_ves.to_string(),
+            self.timelock.to_string(),
+            hex::encode(&self.failed_output),
+        ];
+        // pushed after the mining fee, they come first
+        if let Some(fee) = &self.claim_fee {
+            constructor_inputs.splice(
+                0..0,
+                [
+                    input("claimFee", "int"),
+                    input("claimFeeLockingBytecode", "bytes"),
+                ],
+            );
+            constructor_args.splice(0..0, [fee.amount.to_string(), hex::encode(&fee.output)]);
+        }
+        if let Some(amount) = self.strict_amount {
+            constructor_inputs.insert(0, input("swapAmount", "int"));
+            constructor_args.insert(0, amount.to_string());
+        }
+        if let Some(expiry) = self.expiry {
+            constructor_inputs.insert(0, input("expiry", "int"));
+            constructor_args.insert(0, expiry.to_string());
+        }
+
+        Artifact {
+            contract_name,
+            constructor_inputs,
+            abi: vec![AbiFunction {
+                name: "spend",
+                inputs: vec![input("sig", "bytes")],
+            }],
+            bytecode: asm(&self.bytecode()),
+            compiler: Compiler {
+                name: env!("CARGO_PKG_NAME"),
+                version: env!("CARGO_PKG_VERSION"),
+            },
+            constructor_args,
+            redeem_script: hex::encode(self.script()),
+            address: self.cash_address(),
+        }
+    }
+}
+
+impl ContractPair {
+    /// Both contracts as CashScript artifacts, for third parties to audit
+    /// what the swap locks the BCH in. Serialize to get the json
+    pub fn export_artifact(&self) -> ContractArtifacts {
+        ContractArtifacts {
+            swaplock: self.swaplock.artifact("SwapLock"),
+            refund: self.refund.artifact("Refund"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use bitcoincash::blockdata::script::Builder;
+
+    use super::asm;
+    use crate::{
+        contract::{
+            Contract, ContractOptions, ContractPair, MiningFee, CLAIM_FEE_BYTECODE,
+            CONTRACT_BYTECODE, EXPIRY_BYTECODE, STRICT_CHECK_BYTECODE, TOKEN_CHECK_BYTECODE,
+        },
+        keys::bitcoin::Network,
+    };
+
+    #[test]
+    fn should_rebuild_contracts_from_artifact() {
+        assert!(!asm(&CONTRACT_BYTECODE).contains("0x"));
+        assert!(!asm(&TOKEN_CHECK_BYTECODE).contains("0x"));
+        assert!(!asm(&CLAIM_FEE_BYTECODE).contains("0x"));
+        assert!(!asm(&STRICT_CHECK_BYTECODE).contains("0x"));
+        assert!(!asm(&EXPIRY_BYTECODE).contains("0x"));
+
+        let key = bitcoincash::PublicKey::from_str(
+            "02ee2cbe75e3d2a9b5049ac73122c229627a49bd289f71e05075b2c60090766128",
+        )
+        .unwrap();
+        let output = hex::decode("76a91447fe8a0ca161ebc0090c9d46f81582c579c594a788ac").unwrap();
+        let pair = ContractPair::create(
+            MiningFee::Fixed(1000),
+            output.clone(),
+            key,
+            output,
+            key,
+            2,
+            4,
+            Network::Regtest,
+            bitcoincash::Amount::from_sat(100000),
+            ContractOptions::default(),
+        )
+        .unwrap();
+        let artifacts = pair.export_artifact();
+        let json = serde_json::to_value(&artifacts).unwrap();
+        assert_eq!(json["refund"]["contractName"], "Refund");
+        assert_eq!(json["swaplock"]["constructorInputs"][3]["type"], "int");
+
+        // CashScript pushes the constructor arguments last to first
+        let rebuild = |contract: &Contract, args: &[String]| {
+            let mut builder = Builder::new();
+            for (input, arg) in artifacts.swaplock.constructor_inputs.iter().zip(args).rev() {
+                builder = match input.kind {
+                    "int" => builder.push_int(arg.parse().unwrap()),
+                    _ => builder.push_slice(&hex::decode(arg).unwrap()),
+                };
+            }
+            let mut script = builder.into_script().to_bytes();
+            script.extend_from_slice(&CONTRACT_BYTECODE);
+            assert_eq!(script, contract.script());
+        };
+        rebuild(&pair.swaplock, &artifacts.swaplock.constructor_args);
+        rebuild(&pair.refund, &artifacts.refund.constructor_args);
+        assert_eq!(artifacts.swaplock.address, pair.swaplock.cash_address());
+    }
+}
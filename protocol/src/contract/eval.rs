@@ -0,0 +1,802 @@
+//! Script interpreter for the contract test vectors: the opcodes
+//! `Contract::bytecode` is made of, with the BCH consensus rules they follow
+//! (minimal numbers and if arguments, strict DER datasig, clean stack, p2sh).
+//! An opcode it doesn't know fails the spend rather than being guessed.
+//!
+//! It is not a node: hand written and only as good as its tests. Where it departs from consensus:
+//! - no script, push, stack or op count limits and no sigchecks count
+//! - no sighash signatures, the contracts only check datasigs
+//! - the transaction itself isn't validated: amounts, dust, fees and token prefixes
+//!   are the node's job
+//!
+//! Lock time arguments follow the node: negative ones fail, bits of a
+//! OP_CHECKSEQUENCEVERIFY argument outside the disable flag, the type flag and
+//! the 16 bits of the lock are ignored
+
+use bitcoin_hashes::{hash160, sha256, Hash};
+use bitcoincash::{
+    blockdata::{opcodes, script::Instruction},
+    secp256k1::{ecdsa, Message, PublicKey, Secp256k1},
+    Script, Transaction, TxOut,
+};
+
+use super::{
+    token::TokenPrefix, LOCKTIME_THRESHOLD, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use crate::blockchain::policy::is_canonical_signature;
+
+/// Largest number the arithmetic opcodes take, 64 bits integers
+const MAX_NUM_SIZE: usize = 8;
+/// OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY read 5 bytes numbers
+const MAX_LOCK_SIZE: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum EvalError {
+    InvalidScript,
+    /// Opcode outside of what the contracts use
+    Unsupported(u8),
+    NotPushOnly,
+    StackUnderflow,
+    InvalidNumber,
+    InvalidIf,
+    UnbalancedIf,
+    /// An input or output index beyond the transaction
+    InvalidIndex(i64),
+    /// OP_EQUALVERIFY
+    Equal,
+    /// OP_NUMEQUALVERIFY
+    NumEqual,
+    DataSig,
+    Sequence,
+    LockTime,
+    /// Negative OP_CHECKLOCKTIMEVERIFY or OP_CHECKSEQUENCEVERIFY argument
+    NegativeLockTime,
+    /// The script hash of a p2sh output doesn't match
+    ScriptHash,
+    /// Ends on false
+    EvalFalse,
+    /// Anything but a single true element at the end
+    CleanStack,
+}
+
+fn decode_num(bytes: &[u8], max: usize) -> Result<i64, EvalError> {
+    if bytes.len() > max {
+        return Err(EvalError::InvalidNumber);
+    }
+    let Some(last) = bytes.last() else {
+        return Ok(0);
+    };
+    // minimal: no zero byte on top unless the sign needs it
+    if last & 0x7f == 0 && (bytes.len() == 1 || bytes[bytes.len() - 2] & 0x80 == 0) {
+        return Err(EvalError::InvalidNumber);
+    }
+    let mut value = 0i64;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    match last & 0x80 {
+        0 => Ok(value),
+        _ => Ok(-(value & !(0x80i64 << (8 * (bytes.len() - 1))))),
+    }
+}
+
+fn encode_num(value: i64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut abs = value.unsigned_abs();
+    while abs > 0 {
+        bytes.push(abs as u8);
+        abs >>= 8;
+    }
+    // the sign bit, in a byte of its own if the top one is taken
+    let sign = if value < 0 { 0x80 } else { 0 };
+    if bytes.last().is_some_and(|last| last & 0x80 != 0) {
+        bytes.push(sign);
+    } else if let Some(last) = bytes.last_mut() {
+        *last |= sign;
+    }
+    bytes
+}
+
+fn is_true(bytes: &[u8]) -> bool {
+    match bytes.split_last() {
+        None => false,
+        // negative zero is false too
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || last & 0x7f != 0,
+    }
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    encode_num(value as i64)
+}
+
+/// Category as the VM sees it, in encoded order. NFTs would need their
+/// capability, no contract locks them
+fn token_category(output: &TxOut) -> Result<Vec<u8>, EvalError> {
+    match TokenPrefix::of(output) {
+        None => Ok(vec![]),
+        Some(prefix) if prefix.nft => Err(EvalError::Unsupported(0xce)),
+        Some(mut prefix) => {
+            prefix.category.reverse();
+            Ok(prefix.category.to_vec())
+        }
+    }
+}
+
+fn token_amount(output: &TxOut) -> i64 {
+    TokenPrefix::of(output).map_or(0, |prefix| prefix.amount as i64)
+}
+
+struct Machine<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    /// Spent outputs, in input order
+    utxos: &'a [TxOut],
+    stack: Vec<Vec<u8>>,
+    alt: Vec<Vec<u8>>,
+}
+
+impl Machine<'_> {
+    fn pop(&mut self) -> Result<Vec<u8>, EvalError> {
+        self.stack.pop().ok_or(EvalError::StackUnderflow)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, EvalError> {
+        decode_num(&self.pop()?, MAX_NUM_SIZE)
+    }
+
+    /// Kept on the stack, as the lock time opcodes do
+    fn top_lock(&self) -> Result<i64, EvalError> {
+        let top = self.stack.last().ok_or(EvalError::StackUnderflow)?;
+        decode_num(top, MAX_LOCK_SIZE)
+    }
+
+    fn pick(&self, depth: i64) -> Result<Vec<u8>, EvalError> {
+        let depth = usize::try_from(depth).map_err(|_| EvalError::StackUnderflow)?;
+        if depth >= self.stack.len() {
+            return Err(EvalError::StackUnderflow);
+        }
+        Ok(self.stack[self.stack.len() - 1 - depth].clone())
+    }
+
+    fn input_index(&self, index: i64) -> Result<usize, EvalError> {
+        usize::try_from(index)
+            .ok()
+            .filter(|i| *i < self.tx.input.len() && *i < self.utxos.len())
+            .ok_or(EvalError::InvalidIndex(index))
+    }
+
+    fn output(&self, index: i64) -> Result<&TxOut, EvalError> {
+        usize::try_from(index)
+            .ok()
+            .and_then(|i| self.tx.output.get(i))
+            .ok_or(EvalError::InvalidIndex(index))
+    }
+
+    /// In 64 bits like the node, a 5 bytes argument doesn't fit a sequence
+    fn check_sequence(&self, lock: i64) -> Result<(), EvalError> {
+        if lock < 0 {
+            return Err(EvalError::NegativeLockTime);
+        }
+        if lock & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 {
+            return Ok(());
+        }
+        let sequence = self.tx.input[self.index].sequence.0 as i64;
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+        let (lock, sequence_masked) = (lock & mask, sequence & mask);
+        let same_kind = (lock < type_flag) == (sequence_masked < type_flag);
+        if self.tx.version < 2
+            || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0
+            || !same_kind
+            || lock > sequence_masked
+        {
+            return Err(EvalError::Sequence);
+        }
+        Ok(())
+    }
+
+    fn check_lock_time(&self, lock: i64) -> Result<(), EvalError> {
+        if lock < 0 {
+            return Err(EvalError::NegativeLockTime);
+        }
+        let lock_time = self.tx.lock_time.0 as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        if (lock < threshold) != (lock_time < threshold)
+            || lock > lock_time
+            || self.tx.input[self.index].sequence.0 == u32::MAX
+        {
+            return Err(EvalError::LockTime);
+        }
+        Ok(())
+    }
+
+    fn check_data_sig(&mut self) -> Result<(), EvalError> {
+        let pubkey = self.pop()?;
+        let message = self.pop()?;
+        let sig = self.pop()?;
+        if !is_canonical_signature(&sig) {
+            return Err(EvalError::DataSig);
+        }
+        let sig = ecdsa::Signature::from_der(&sig).map_err(|_| EvalError::DataSig)?;
+        let pubkey = PublicKey::from_slice(&pubkey).map_err(|_| EvalError::DataSig)?;
+        let digest = sha256::Hash::hash(&message).to_byte_array();
+        let message = Message::from_slice(&digest).map_err(|_| EvalError::DataSig)?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(&message, &sig, &pubkey)
+            .map_err(|_| EvalError::DataSig)
+    }
+
+    fn run(&mut self, script: &Script) -> Result<(), EvalError> {
+        // one entry per open if, whether its branch runs
+        let mut branches: Vec<bool> = Vec::new();
+        for instruction in script.instructions_minimal() {
+            let executing = branches.iter().all(|run| *run);
+            let opcode = match instruction.map_err(|_| EvalError::InvalidScript)? {
+                Instruction::PushBytes(data) => {
+                    if executing {
+                        self.stack.push(data.to_vec());
+                    }
+                    continue;
+                }
+                Instruction::Op(op) => op.to_u8(),
+            };
+            match opcode {
+                // OP_IF
+                0x63 => {
+                    let condition = match executing {
+                        true => match self.pop()?[..] {
+                            [] => false,
+                            [1] => true,
+                            _ => return Err(EvalError::InvalidIf),
+                        },
+                        false => false,
+                    };
+                    branches.push(condition);
+                    continue;
+                }
+                // OP_ELSE
+                0x67 => {
+                    let branch = branches.last_mut().ok_or(EvalError::UnbalancedIf)?;
+                    *branch = !*branch;
+                    continue;
+                }
+                // OP_ENDIF
+                0x68 => {
+                    branches.pop().ok_or(EvalError::UnbalancedIf)?;
+                    continue;
+                }
+                _ if !executing => continue,
+                _ => {}
+            }
+
+            match opcode {
+                // OP_1NEGATE, OP_1 to OP_16
+                0x4f => self.stack.push(encode_num(-1)),
+                0x51..=0x60 => self.stack.push(encode_num((opcode - 0x50) as i64)),
+                // OP_TOALTSTACK
+                0x6b => {
+                    let top = self.pop()?;
+                    self.alt.push(top);
+                }
+                // OP_FROMALTSTACK
+                0x6c => {
+                    let top = self.alt.pop().ok_or(EvalError::StackUnderflow)?;
+                    self.stack.push(top);
+                }
+                // OP_2DROP
+                0x6d => {
+                    self.pop()?;
+                    self.pop()?;
+                }
+                // OP_DROP
+                0x75 => {
+                    self.pop()?;
+                }
+                // OP_OVER
+                0x78 => {
+                    let second = self.pick(1)?;
+                    self.stack.push(second);
+                }
+                // OP_PICK
+                0x79 => {
+                    let depth = self.pop_num()?;
+                    let picked = self.pick(depth)?;
+                    self.stack.push(picked);
+                }
+                // OP_EQUAL, OP_EQUALVERIFY
+                0x87 | 0x88 => {
+                    let equal = self.pop()? == self.pop()?;
+                    match opcode {
+                        0x87 => self.stack.push(encode_bool(equal)),
+                        _ if !equal => return Err(EvalError::Equal),
+                        _ => {}
+                    }
+                }
+                // OP_SUB
+                0x94 => {
+                    let b = self.pop_num()?;
+                    let a = self.pop_num()?;
+                    let difference = a.checked_sub(b).ok_or(EvalError::InvalidNumber)?;
+                    self.stack.push(encode_num(difference));
+                }
+                // OP_NUMEQUAL, OP_NUMEQUALVERIFY
+                0x9c | 0x9d => {
+                    let equal = self.pop_num()? == self.pop_num()?;
+                    match opcode {
+                        0x9c => self.stack.push(encode_bool(equal)),
+                        _ if !equal => return Err(EvalError::NumEqual),
+                        _ => {}
+                    }
+                }
+                // OP_GREATERTHANOREQUAL
+                0xa2 => {
+                    let b = self.pop_num()?;
+                    let a = self.pop_num()?;
+                    self.stack.push(encode_bool(a >= b));
+                }
+                // OP_SHA256
+                0xa8 => {
+                    let data = self.pop()?;
+                    let hash = sha256::Hash::hash(&data).to_byte_array();
+                    self.stack.push(hash.to_vec());
+                }
+                // OP_HASH160
+                0xa9 => {
+                    let data = self.pop()?;
+                    let hash = hash160::Hash::hash(&data).to_byte_array();
+                    self.stack.push(hash.to_vec());
+                }
+                // OP_CHECKLOCKTIMEVERIFY
+                0xb1 => self.check_lock_time(self.top_lock()?)?,
+                // OP_CHECKSEQUENCEVERIFY
+                0xb2 => self.check_sequence(self.top_lock()?)?,
+                // OP_CHECKDATASIGVERIFY
+                0xbb => self.check_data_sig()?,
+                // OP_TXINPUTCOUNT
+                0xc3 => self.stack.push(encode_num(self.tx.input.len() as i64)),
+                // OP_TXOUTPUTCOUNT
+                0xc4 => self.stack.push(encode_num(self.tx.output.len() as i64)),
+                // OP_TXLOCKTIME
+                0xc5 => self.stack.push(encode_num(self.tx.lock_time.0 as i64)),
+                // OP_UTXOVALUE
+                0xc6 => {
+                    let input = self.input_index(self.pop_num()?)?;
+                    self.stack.push(encode_num(self.utxos[input].value as i64));
+                }
+                // OP_INPUTSEQUENCENUMBER
+                0xcb => {
+                    let input = self.input_index(self.pop_num()?)?;
+                    let sequence = self.tx.input[input].sequence.0;
+                    self.stack.push(encode_num(sequence as i64));
+                }
+                // OP_OUTPUTVALUE
+                0xcc => {
+                    let value = self.output(self.pop_num()?)?.value;
+                    self.stack.push(encode_num(value as i64));
+                }
+                // OP_OUTPUTBYTECODE
+                0xcd => {
+                    let script = self.output(self.pop_num()?)?.script_pubkey.to_bytes();
+                    self.stack.push(script);
+                }
+                // OP_UTXOTOKENCATEGORY
+                0xce => {
+                    let input = self.input_index(self.pop_num()?)?;
+                    let category = token_category(&self.utxos[input])?;
+                    self.stack.push(category);
+                }
+                // OP_UTXOTOKENAMOUNT
+                0xd0 => {
+                    let input = self.input_index(self.pop_num()?)?;
+                    self.stack
+                        .push(encode_num(token_amount(&self.utxos[input])));
+                }
+                // OP_OUTPUTTOKENCATEGORY
+                0xd1 => {
+                    let category = token_category(self.output(self.pop_num()?)?)?;
+                    self.stack.push(category);
+                }
+                // OP_OUTPUTTOKENAMOUNT
+                0xd3 => {
+                    let amount = token_amount(self.output(self.pop_num()?)?);
+                    self.stack.push(encode_num(amount));
+                }
+                _ => return Err(EvalError::Unsupported(opcode)),
+            }
+        }
+        match branches.is_empty() {
+            true => Ok(()),
+            false => Err(EvalError::UnbalancedIf),
+        }
+    }
+}
+
+/// Evaluates input `index` of `tx` against the output it spends, `utxos`
+/// being every spent output in input order. Only p2sh and bare scripts
+pub(crate) fn verify_input(
+    tx: &Transaction,
+    index: usize,
+    utxos: &[TxOut],
+) -> Result<(), EvalError> {
+    let script_sig = &tx.input[index].script_sig;
+    let script_pubkey = &utxos[index].script_pubkey;
+    let mut machine = Machine {
+        tx,
+        index,
+        utxos,
+        stack: Vec::new(),
+        alt: Vec::new(),
+    };
+    let push_only = script_sig.instructions().all(|i| match i {
+        Ok(Instruction::PushBytes(_)) => true,
+        Ok(Instruction::Op(op)) => op.to_u8() <= opcodes::all::OP_PUSHNUM_16.to_u8(),
+        Err(_) => false,
+    });
+    if !push_only {
+        return Err(EvalError::NotPushOnly);
+    }
+    machine.run(script_sig)?;
+    let pushed = machine.stack.clone();
+
+    machine.run(script_pubkey)?;
+    if !machine.stack.last().map(Vec::as_slice).is_some_and(is_true) {
+        return Err(match script_pubkey.is_p2sh() {
+            true => EvalError::ScriptHash,
+            false => EvalError::EvalFalse,
+        });
+    }
+    if script_pubkey.is_p2sh() {
+        machine.stack = pushed;
+        machine.alt.clear();
+        let redeem = Script::from(machine.pop()?);
+        machine.run(&redeem)?;
+    }
+
+    match &machine.stack[..] {
+        [top] if is_true(top) => Ok(()),
+        [_] => Err(EvalError::EvalFalse),
+        _ => Err(EvalError::CleanStack),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin_hashes::{sha256, Hash};
+    use bitcoincash::{
+        blockdata::{
+            opcodes::{all, All},
+            script::Builder,
+        },
+        secp256k1::{Message, Secp256k1},
+        OutPoint, PackedLockTime, PrivateKey, Script, Sequence, Transaction, TxIn, TxOut,
+    };
+
+    use super::{decode_num, encode_num, verify_input, EvalError};
+    use crate::{
+        contract::{ClaimFee, Contract, ContractOptions, ContractPair, MiningFee},
+        keys::bitcoin::{random_private_key, Network},
+    };
+
+    const AMOUNT: u64 = 100000;
+
+    struct Vectors {
+        pair: ContractPair,
+        bob: PrivateKey,
+        alice: PrivateKey,
+    }
+
+    fn vectors(options: ContractOptions) -> Vectors {
+        let secp = Secp256k1::new();
+        let (bob, alice) = (
+            random_private_key(Network::Regtest),
+            random_private_key(Network::Regtest),
+        );
+        let recv = |key: &PrivateKey| Script::new_p2pkh(&key.public_key(&secp).pubkey_hash());
+        let pair = ContractPair::create(
+            MiningFee::Rate(1000),
+            recv(&bob).into_bytes(),
+            bob.public_key(&secp),
+            recv(&alice).into_bytes(),
+            alice.public_key(&secp),
+            2,
+            4,
+            Network::Regtest,
+            bitcoincash::Amount::from_sat(AMOUNT),
+            options,
+        )
+        .unwrap();
+        Vectors { pair, bob, alice }
+    }
+
+    /// Signature of the sig path, over the hash of the output it pays
+    fn sign(key: &PrivateKey, output: &[u8]) -> Vec<u8> {
+        let hash = sha256::Hash::hash(output).to_byte_array();
+        let hash = sha256::Hash::hash(&hash).to_byte_array();
+        let message = Message::from_slice(&hash).unwrap();
+        Secp256k1::new()
+            .sign_ecdsa(&message, &key.inner)
+            .serialize_der()
+            .to_vec()
+    }
+
+    fn funding(contract: &Contract, value: u64) -> TxOut {
+        TxOut {
+            value,
+            script_pubkey: Script::from(contract.locking_script()),
+            token: None,
+        }
+    }
+
+    fn spend(
+        contract: &Contract,
+        unlocker: &[u8],
+        sequence: Sequence,
+        output: &[u8],
+    ) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::from(contract.unlocking_script(unlocker)),
+                sequence,
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: AMOUNT - contract.mining_fee,
+                script_pubkey: Script::from(output.to_vec()),
+                token: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn should_encode_script_numbers() {
+        for value in [
+            0,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            255,
+            100000,
+            1_700_000_000,
+            i64::MAX,
+        ] {
+            assert_eq!(decode_num(&encode_num(value), 8), Ok(value));
+        }
+        assert_eq!(encode_num(128), [0x80, 0x00]);
+        assert_eq!(encode_num(-1), [0x81]);
+        assert_eq!(decode_num(&[0x01, 0x00], 8), Err(EvalError::InvalidNumber));
+        assert_eq!(decode_num(&[0x80], 8), Err(EvalError::InvalidNumber));
+        assert_eq!(decode_num(&[1; 6], 5), Err(EvalError::InvalidNumber));
+    }
+
+    #[test]
+    fn should_evaluate_swaplock_spends() {
+        let Vectors { pair, bob, alice } = vectors(ContractOptions::default());
+        let swaplock = &pair.swaplock;
+        let utxos = [funding(swaplock, AMOUNT)];
+        let claim = |sig: &[u8], output: &[u8]| spend(swaplock, sig, Sequence(0), output);
+
+        // alice claims with bob's signature over her output
+        let sig = sign(&bob, &swaplock.success_output);
+        let tx = claim(&sig, &swaplock.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+
+        // alice's own key, or bob's over another output
+        let wrong = sign(&alice, &swaplock.success_output);
+        let tx = claim(&wrong, &swaplock.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::DataSig));
+        let other = sign(&bob, &swaplock.failed_output);
+        let tx = claim(&other, &swaplock.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::DataSig));
+        let tx = claim(&[], &swaplock.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::DataSig));
+
+        // a valid signature doesn't redirect the payout
+        let tx = claim(&sig, &swaplock.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::Equal));
+
+        // the fee is exact
+        let mut greedy = claim(&sig, &swaplock.success_output);
+        greedy.output[0].value -= 1;
+        assert_eq!(verify_input(&greedy, 0, &utxos), Err(EvalError::NumEqual));
+        let mut extra = claim(&sig, &swaplock.success_output);
+        extra.output.push(extra.output[0].clone());
+        assert_eq!(verify_input(&extra, 0, &utxos), Err(EvalError::NumEqual));
+
+        // to the Refund once timelock0 passed, not a block before
+        let timelock = swaplock.sequence();
+        let tx = spend(swaplock, &[], timelock, &swaplock.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+        let early = spend(
+            swaplock,
+            &[],
+            Sequence(timelock.0 - 1),
+            &swaplock.failed_output,
+        );
+        assert_eq!(verify_input(&early, 0, &utxos), Err(EvalError::Sequence));
+        let mut version1 = tx.clone();
+        version1.version = 1;
+        assert_eq!(verify_input(&version1, 0, &utxos), Err(EvalError::Sequence));
+        let tx = spend(swaplock, &[], timelock, &swaplock.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::Equal));
+        // the timelock path takes no signature
+        let tx = spend(swaplock, &sig, timelock, &swaplock.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::Equal));
+
+        // another redeem script doesn't match the p2sh
+        let tx = spend(&pair.refund, &[], timelock, &swaplock.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::ScriptHash));
+    }
+
+    #[test]
+    fn should_evaluate_refund_spends() {
+        let Vectors { pair, bob, alice } = vectors(ContractOptions::default());
+        let refund = &pair.refund;
+        let utxos = [funding(refund, AMOUNT)];
+
+        // bob takes it back with alice's signature
+        let sig = sign(&alice, &refund.success_output);
+        let tx = spend(refund, &sig, Sequence(0), &refund.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+        let wrong = sign(&bob, &refund.success_output);
+        let tx = spend(refund, &wrong, Sequence(0), &refund.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::DataSig));
+        let tx = spend(refund, &sig, Sequence(0), &refund.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::Equal));
+
+        // alice punishes after timelock1
+        let timelock = refund.sequence();
+        let tx = spend(refund, &[], timelock, &refund.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+        let early = spend(refund, &[], Sequence(timelock.0 - 1), &refund.failed_output);
+        assert_eq!(verify_input(&early, 0, &utxos), Err(EvalError::Sequence));
+        let tx = spend(refund, &[], timelock, &refund.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Err(EvalError::Equal));
+    }
+
+    #[test]
+    fn should_evaluate_contract_options() {
+        let secp = Secp256k1::new();
+        let operator = random_private_key(Network::Regtest).public_key(&secp);
+        let fee_output = Script::new_p2pkh(&operator.pubkey_hash()).into_bytes();
+        let Vectors { pair, bob, .. } = vectors(ContractOptions {
+            claim_fee: Some(ClaimFee {
+                output: fee_output.clone(),
+                amount: 1000,
+            }),
+            strict: true,
+            ..Default::default()
+        });
+        let swaplock = &pair.swaplock;
+        let utxos = [funding(swaplock, AMOUNT)];
+        let sig = sign(&bob, &swaplock.success_output);
+
+        let mut claim = spend(swaplock, &sig, Sequence(0), &swaplock.success_output);
+        claim.output[0].value -= 1000;
+        claim.output.push(TxOut {
+            value: 1000,
+            script_pubkey: Script::from(fee_output.clone()),
+            token: None,
+        });
+        assert_eq!(verify_input(&claim, 0, &utxos), Ok(()));
+        // without the operator's output
+        let plain = spend(swaplock, &sig, Sequence(0), &swaplock.success_output);
+        assert_eq!(verify_input(&plain, 0, &utxos), Err(EvalError::NumEqual));
+        let mut redirected = claim.clone();
+        redirected.output[1].script_pubkey = Script::from(swaplock.success_output.clone());
+        assert_eq!(verify_input(&redirected, 0, &utxos), Err(EvalError::Equal));
+
+        // strict: the sig path only spends the swap amount, the timelock any part
+        let part = [funding(swaplock, AMOUNT / 2)];
+        let mut half = claim.clone();
+        half.output[0].value -= AMOUNT / 2;
+        assert_eq!(verify_input(&half, 0, &part), Err(EvalError::NumEqual));
+        let mut to_refund = spend(swaplock, &[], swaplock.sequence(), &swaplock.failed_output);
+        to_refund.output[0].value -= AMOUNT / 2;
+        assert_eq!(verify_input(&to_refund, 0, &part), Ok(()));
+    }
+
+    #[test]
+    fn should_evaluate_refund_expiry() {
+        let expiry = 1_700_000_000;
+        let Vectors { pair, alice, .. } = vectors(ContractOptions {
+            expiry: Some(expiry),
+            ..Default::default()
+        });
+        let refund = &pair.refund;
+        let utxos = [funding(refund, AMOUNT)];
+
+        // punished without timelock1 from the expiry on
+        let (sequence, lock_time) = refund.expiry_lock().unwrap();
+        let mut punish = spend(refund, &[], sequence, &refund.failed_output);
+        punish.lock_time = lock_time;
+        assert_eq!(verify_input(&punish, 0, &utxos), Ok(()));
+        let mut early = punish.clone();
+        early.lock_time = PackedLockTime(expiry - 1);
+        assert_eq!(verify_input(&early, 0, &utxos), Err(EvalError::Sequence));
+        let mut last = punish.clone();
+        last.input[0].sequence = Sequence(u32::MAX);
+        assert_eq!(verify_input(&last, 0, &utxos), Err(EvalError::LockTime));
+
+        // before it, timelock1 as usual
+        let tx = spend(refund, &[], refund.sequence(), &refund.failed_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+        let sig = sign(&alice, &refund.success_output);
+        let tx = spend(refund, &sig, Sequence(0), &refund.success_output);
+        assert_eq!(verify_input(&tx, 0, &utxos), Ok(()));
+    }
+
+    /// Bare `<lock> opcode OP_DROP OP_1` spent by input 0 of a version 2 tx
+    fn check_lock(opcode: All, lock: i64, sequence: u32, lock_time: u32) -> Result<(), EvalError> {
+        let script_pubkey = Builder::new()
+            .push_int(lock)
+            .push_opcode(opcode)
+            .push_opcode(all::OP_DROP)
+            .push_opcode(all::OP_PUSHNUM_1)
+            .into_script();
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(lock_time),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                sequence: Sequence(sequence),
+                ..Default::default()
+            }],
+            output: vec![],
+        };
+        let utxos = [TxOut {
+            value: AMOUNT,
+            script_pubkey,
+            token: None,
+        }];
+        verify_input(&tx, 0, &utxos)
+    }
+
+    #[test]
+    fn should_check_lock_arguments() {
+        let (csv, cltv) = (all::OP_CSV, all::OP_CLTV);
+        assert_eq!(check_lock(csv, 10, 10, 0), Ok(()));
+        assert_eq!(check_lock(csv, -1, 10, 0), Err(EvalError::NegativeLockTime));
+        assert_eq!(
+            check_lock(csv, -10, 10, 0),
+            Err(EvalError::NegativeLockTime)
+        );
+        // what a truncation to 32 bits would read as disabled
+        assert_eq!(
+            check_lock(csv, -(1 << 31), 0, 0),
+            Err(EvalError::NegativeLockTime)
+        );
+        // 5 bytes: the bits above the lock are ignored, the disable flag isn't
+        assert_eq!(check_lock(csv, (1 << 32) | 10, 10, 0), Ok(()));
+        assert_eq!(
+            check_lock(csv, (1 << 32) | 11, 10, 0),
+            Err(EvalError::Sequence)
+        );
+        assert_eq!(check_lock(csv, 1 << 31, 0, 0), Ok(()));
+        assert_eq!(
+            check_lock(csv, 1 << 40, 10, 0),
+            Err(EvalError::InvalidNumber)
+        );
+
+        assert_eq!(check_lock(cltv, 10, 0, 10), Ok(()));
+        assert_eq!(
+            check_lock(cltv, -1, 0, 10),
+            Err(EvalError::NegativeLockTime)
+        );
+        assert_eq!(check_lock(cltv, 11, 0, 10), Err(EvalError::LockTime));
+        // beyond any lock time a transaction can have
+        assert_eq!(
+            check_lock(cltv, 1 << 32, 0, u32::MAX),
+            Err(EvalError::LockTime)
+        );
+        assert_eq!(
+            check_lock(cltv, 1 << 40, 0, 10),
+            Err(EvalError::InvalidNumber)
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use bitcoin_hashes::{hash160, Hash};
 use bitcoincash::{
     blockdata::{
@@ -5,22 +7,247 @@ use bitcoincash::{
         script::{Builder, Instruction},
     },
     hashes::Hash as BHash,
-    Script, ScriptHash, Transaction,
+    OutPoint, PackedLockTime, Script, ScriptHash, Sequence, Transaction, TxIn, TxOut,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::keys::bitcoin::{address, Network};
+use crate::{
+    blockchain::policy::{is_canonical_signature, MempoolPolicy, PolicyError},
+    keys::bitcoin::{address, Network},
+};
+
+pub mod artifact;
+#[cfg(test)]
+mod eval;
+pub mod token;
+pub mod verify;
+
+use token::{CashToken, MAX_TOKEN_PREFIX_SIZE};
 
 const CONTRACT_BYTECODE: [u8; 47] = hex_literal::hex!("c3519dc4519d00c600cc949d00cb009c6300cd7888547978a85379bb675279b27500cd54798854790088686d6d7551");
-const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff; // bip68
+/// `CONTRACT_BYTECODE` with a second output on the sig path, paying
+/// `ClaimFee::output` exactly `ClaimFee::amount`, pushed after the mining fee.
+/// The timelock path still allows a single output
+const CLAIM_FEE_BYTECODE: [u8; 66] = hex_literal::hex!("c3519d00cb009c63c4529d51cc9d51cd8800c600cc9451cc949d00cd7888547978a85379bb676dc4519d00c600cc949d5279b27500cd54798854790088686d6d7551");
+/// `CONTRACT_BYTECODE` with an absolute expiry pushed after the mining fee,
+/// kept on the alt stack. The timelock path skips the relative timelock once
+/// the lock time reaches the expiry, checked with OP_CHECKLOCKTIMEVERIFY
+const EXPIRY_BYTECODE: [u8; 57] = hex_literal::hex!("6bc3519dc4519d00c600cc949d00cb009c6300cd7888547978a85379bb676cc578a263b1675379b275687500cd54798854790088686d6d7551");
+/// Prepended by strict contracts, with the swap amount pushed last: the sig
+/// path only spends an input of exactly that amount, the payout then follows
+/// from the fee checks. The timelock path still refunds any part
+const STRICT_CHECK_BYTECODE: [u8; 11] = hex_literal::hex!("00cb009c6300c69d677568");
+/// Prepended to `CONTRACT_BYTECODE` by token contracts: the output keeps the
+/// token category and amount of the input, whoever builds the spend
+const TOKEN_CHECK_BYTECODE: [u8; 10] = hex_literal::hex!("00ce00d18800d000d39d");
+pub(crate) const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff; // bip68
+/// Set, a relative timelock counts units of 512 seconds instead of blocks
+pub(crate) const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// Set, OP_CHECKSEQUENCEVERIFY passes whatever the input sequence
+const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// Lock times from there are unix times, below block heights
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Longest DER signature given to `Contract::unlocking_script`
+const MAX_UNLOCKER_SIZE: usize = 72;
+/// The refund path may spend at most this share of the swap amount on fees
+const MAX_FEE_SHARE: u64 = 10;
+
+/// What the contract spends pay. Committed in the scripts, both sides must agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MiningFee {
+    /// The same fee for every spend, for trades from before the fee rate
+    Fixed(u64),
+    /// Sats per 1000 bytes: each contract takes the fee of its own spend size
+    Rate(u64),
+}
+
+/// Relative `timelock` as pushed for OP_CHECKSEQUENCEVERIFY and set in the
+/// spends' sequence (bip68): blocks, or 512 seconds units with
+/// `SEQUENCE_LOCKTIME_TYPE_FLAG`. Any other bit would make the lock ignored
+/// or longer than meant
+pub fn encode_timelock(timelock: u32) -> Result<Sequence, ContractError> {
+    if timelock & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return Err(ContractError::TimelockDisabled(timelock));
+    }
+    if timelock & !SEQUENCE_LOCKTIME_TYPE_FLAG > SEQUENCE_LOCKTIME_MASK {
+        return Err(ContractError::TimelockTooLong(timelock));
+    }
+    if timelock & SEQUENCE_LOCKTIME_MASK == 0 {
+        return Err(ContractError::TimelockZero(timelock));
+    }
+    Ok(Sequence(timelock))
+}
+
+/// Fee of `size` bytes at `rate` sats per 1000 bytes
+pub fn fee_for_size(rate: u64, size: u64) -> u64 {
+    (rate * size).div_ceil(1000)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ContractError {
+    /// Above `SEQUENCE_LOCKTIME_MASK`, besides `SEQUENCE_LOCKTIME_TYPE_FLAG`
+    #[error("timelock {0} is above the 65535 blocks or 512 seconds units a relative timelock can encode")]
+    TimelockTooLong(u32),
+    #[error("timelock {0} has the bip68 disable flag, it would not be enforced")]
+    TimelockDisabled(u32),
+    #[error("timelock {0} of 0 blocks or seconds is expired from the start")]
+    TimelockZero(u32),
+    /// Below the relay fee of the spend, it would never confirm
+    #[error("mining fee {fee} sats is below the relay minimum of {minimum} sats")]
+    MiningFeeTooLow { fee: u64, minimum: u64 },
+    /// Both fees of the refund path over `MAX_FEE_SHARE` of the swap amount
+    #[error("mining fees {fee} sats are above {maximum} sats")]
+    MiningFeeTooHigh { fee: u64, maximum: u64 },
+    /// A spend output would be under dust after the fees, see
+    /// `ContractPair::minimum_amount`
+    #[error("swap amount {amount} sats is below the minimum of {minimum} sats")]
+    AmountTooLow { amount: u64, minimum: u64 },
+    #[error("claim fee {fee} sats is below the dust limit of {minimum} sats")]
+    ClaimFeeDust { fee: u64, minimum: u64 },
+    /// Over `MAX_FEE_SHARE` of the swap amount
+    #[error("claim fee {fee} sats is above {maximum} sats")]
+    ClaimFeeTooHigh { fee: u64, maximum: u64 },
+    /// Over the whole swap amount
+    #[error("claim fee of {0} basis points is above 10000")]
+    ClaimFeeBasisPoints(u64),
+    #[error("claim fee address {0} is not a p2pkh or p2sh cash address")]
+    InvalidClaimFeeAddress(String),
+    /// A block height, the expiry compares median time past
+    #[error("expiry {0} is not a unix time")]
+    ExpiryNotTime(u32),
+}
+
+/// Second output of Alice's claims, for the operator of the market
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClaimFee {
+    #[serde(with = "hex")]
+    pub output: Vec<u8>,
+    pub amount: u64,
+}
+
+/// What a swap locks besides plain BCH, see `ContractPair::create`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContractOptions {
+    /// Makes it a token swap: both contracts keep it on their spends
+    pub token: Option<CashToken>,
+    /// Taken from every SwapLock claim
+    pub claim_fee: Option<ClaimFee>,
+    /// Claims of the SwapLock pay exactly the swap amount less the fees, see
+    /// `STRICT_CHECK_BYTECODE`. Split fundings are refused
+    pub strict: bool,
+    /// Unix time from which the Refund timelock path no longer waits for its
+    /// relative timelock, see `Contract::expiry`
+    pub expiry: Option<u32>,
+}
 
 #[derive(Debug)]
 pub enum TransactionType {
     ToSwapLock,
+    /// Pays the SwapLock less than the swap amount, the sats deposited over
+    /// every output of the transaction
+    Underfunded(u64),
+    /// Pays the SwapLock more than the swap amount, the sats deposited over
+    /// every output of the transaction
+    Overfunded(u64),
     ToRefund,
     ToBob,
     SwapLockToAlice,
     RefundToAlice,
+    /// Pays the SwapLock in a way the protocol never does
+    Rejected(Rejection),
+}
+
+/// SwapLock fundings neither side accepts, see `TransactionType::Rejected`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    /// A SwapLock output carries CashTokens, under `TokenPolicy::Refuse`
+    Token,
+    /// Pays the Refund too, only SwapLock spends fund it
+    BothContracts,
+    /// No single SwapLock output holds the swap amount with exactly the
+    /// `CashToken` of a token swap
+    WrongToken,
+    /// Split over several SwapLock outputs, a strict SwapLock only claims the
+    /// whole amount at once
+    SplitFunding,
+    /// A part of a split funding below `ContractPair::minimum_amount`. Each part
+    /// is spent alone and pays the mining fee again, that one would end in dust
+    PartBelowMinimum,
+}
+
+/// How CashTokens on SwapLock outputs are treated. The contracts allow a
+/// single output per spend: whatever the policy, each spend moves the tokens
+/// of its input to that output, see `ContractTokens`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenPolicy {
+    /// Not a funding, `Rejection::Token`. Bob refunds it, tokens included
+    #[default]
+    Refuse,
+    /// Counted as a funding by its sats, the tokens go along with them to
+    /// whoever gets the BCH
+    Preserve,
+}
+
+/// Contract outputs seen carrying CashTokens, for the spends to keep them.
+/// A spend dropping them burns them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContractTokens(Vec<TokenOutput>);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TokenOutput {
+    outpoint: OutPoint,
+    /// Consensus encoding, token prefix included
+    #[serde(with = "hex")]
+    output: Vec<u8>,
+}
+
+impl ContractTokens {
+    /// Record the SwapLock and Refund outputs of `transaction` with tokens
+    pub fn observe(&mut self, contract: &ContractPair, transaction: &Transaction) {
+        for analysis in contract.analyze_outputs(transaction) {
+            let known = self.0.iter().any(|seen| seen.outpoint == analysis.outpoint);
+            if known
+                || !analysis.token
+                || !matches!(analysis.kind, OutputKind::SwapLock | OutputKind::Refund)
+            {
+                continue;
+            }
+            let output = &transaction.output[analysis.outpoint.vout as usize];
+            self.0.push(TokenOutput {
+                outpoint: analysis.outpoint,
+                output: bitcoincash::consensus::serialize(output),
+            });
+        }
+    }
+
+    /// Give `output`, spending `outpoint`, the tokens `outpoint` holds
+    pub fn carry(&self, outpoint: &OutPoint, output: &mut TxOut) {
+        let seen = self.0.iter().find(|seen| seen.outpoint == *outpoint);
+        if let Some(Ok(spent)) =
+            seen.map(|seen| bitcoincash::consensus::deserialize::<TxOut>(&seen.output))
+        {
+            output.token = spent.token;
+        }
+    }
+}
+
+/// Where an output pays, see `ContractPair::analyze_outputs`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    SwapLock,
+    Refund,
+    AliceReceiving,
+    BobReceiving,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputAnalysis {
+    pub outpoint: bitcoincash::OutPoint,
+    pub kind: OutputKind,
+    pub value: u64,
+    /// Carries CashTokens
+    pub token: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -32,6 +259,20 @@ pub struct Contract {
     pub timelock: u32,
     #[serde(with = "hex")]
     pub failed_output: Vec<u8>,
+    /// Checks its spend keeps the tokens, see `TOKEN_CHECK_BYTECODE`
+    #[serde(default)]
+    pub token: bool,
+    /// Paid by the sig path spends, only the SwapLock has one
+    #[serde(default)]
+    pub claim_fee: Option<ClaimFee>,
+    /// The only input value the sig path spends, of strict SwapLocks
+    #[serde(default)]
+    pub strict_amount: Option<u64>,
+    /// Median time past after which the timelock path is open whenever the
+    /// contract was funded, only the Refund has one. Moving the funds again
+    /// restarts relative timelocks, not this one
+    #[serde(default)]
+    pub expiry: Option<u32>,
 
     pub bch_network: Network,
 
@@ -40,21 +281,79 @@ pub struct Contract {
     _private: (),
 }
 
+/// Raw scripts of one contract for audits and external tools, see
+/// `ContractPair::swaplock_scripts`. Json has them in hex
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ContractScripts {
+    /// Pushed last by every spend, constructor arguments included
+    #[serde(with = "hex")]
+    pub redeem_script: Vec<u8>,
+    /// The p2sh output a funding pays
+    #[serde(with = "hex")]
+    pub locking_script: Vec<u8>,
+    /// hash160 of `redeem_script`, as in the locking script and cash address
+    #[serde(with = "hex")]
+    pub script_hash: [u8; 20],
+}
+
+impl ContractScripts {
+    pub fn redeem_script_hex(&self) -> String {
+        hex::encode(&self.redeem_script)
+    }
+
+    pub fn locking_script_hex(&self) -> String {
+        hex::encode(&self.locking_script)
+    }
+}
+
 impl Contract {
+    pub fn scripts(&self) -> ContractScripts {
+        ContractScripts {
+            redeem_script: self.script(),
+            locking_script: self.locking_script(),
+            script_hash: self.script_hash(),
+        }
+    }
+
     pub fn script(&self) -> Vec<u8> {
-        let mut contract = Builder::new()
+        let mut builder = Builder::new()
             .push_slice(&self.failed_output)
             .push_int(self.timelock as i64)
             .push_key(&self.pubkey_ves)
             .push_slice(&self.success_output)
-            .push_int(self.mining_fee as i64)
-            .into_script()
-            .to_bytes();
+            .push_int(self.mining_fee as i64);
+        if let Some(fee) = &self.claim_fee {
+            builder = builder.push_slice(&fee.output).push_int(fee.amount as i64);
+        }
+        if let Some(amount) = self.strict_amount {
+            builder = builder.push_int(amount as i64);
+        }
+        if let Some(expiry) = self.expiry {
+            builder = builder.push_int(expiry as i64);
+        }
+        let mut contract = builder.into_script().to_bytes();
 
-        contract.extend_from_slice(&CONTRACT_BYTECODE);
+        contract.extend_from_slice(&self.bytecode());
         contract
     }
 
+    /// What follows the constructor arguments
+    pub fn bytecode(&self) -> Vec<u8> {
+        let mut bytecode = Vec::new();
+        if self.strict_amount.is_some() {
+            bytecode.extend_from_slice(&STRICT_CHECK_BYTECODE);
+        }
+        if self.token {
+            bytecode.extend_from_slice(&TOKEN_CHECK_BYTECODE);
+        }
+        match (&self.claim_fee, self.expiry) {
+            (Some(_), _) => bytecode.extend_from_slice(&CLAIM_FEE_BYTECODE),
+            (None, Some(_)) => bytecode.extend_from_slice(&EXPIRY_BYTECODE),
+            (None, None) => bytecode.extend_from_slice(&CONTRACT_BYTECODE),
+        }
+        bytecode
+    }
+
     #[inline]
     pub fn script_hash(&self) -> [u8; 20] {
         hash160::Hash::hash(&self.script()).to_byte_array()
@@ -84,12 +383,149 @@ impl Contract {
             .to_bytes()
     }
 
+    /// Sequence of a spend through the timelock path, validated by
+    /// `ContractPair::create`
+    pub fn sequence(&self) -> Sequence {
+        Sequence(self.timelock)
+    }
+
+    /// Sequence and lock time of a timelock path spend through `expiry`,
+    /// final once the tip median time past is past it. The disable flag keeps
+    /// bip68 from enforcing the relative timelock anyway
+    pub fn expiry_lock(&self) -> Option<(Sequence, PackedLockTime)> {
+        let expiry = self.expiry?;
+        Some((
+            Sequence(SEQUENCE_LOCKTIME_DISABLE_FLAG),
+            PackedLockTime(expiry),
+        ))
+    }
+
+    /// Standardness of input `index` of `tx` spending this contract, what
+    /// `MempoolPolicy::check` can't tell without the script. The scriptSig
+    /// must be the unlocker then the script, which leaves the contract alone
+    /// on the stack. On the sig path the unlocker is a canonical signature,
+    /// on the timelock path nothing
+    pub fn check_input(
+        &self,
+        tx: &Transaction,
+        index: usize,
+        policy: &MempoolPolicy,
+    ) -> Result<(), PolicyError> {
+        let input = &tx.input[index];
+        let script = self.script();
+        if script.len() > policy.max_element_size {
+            return Err(PolicyError::ElementTooLarge {
+                input: index,
+                size: script.len(),
+                max: policy.max_element_size,
+            });
+        }
+        if Script::from(script.clone())
+            .instructions_minimal()
+            .any(|instruction| instruction.is_err())
+        {
+            return Err(PolicyError::NonMinimalPush(index));
+        }
+
+        let pushes = input
+            .script_sig
+            .instructions_minimal()
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| PolicyError::NonMinimalPush(index))?;
+        let unlocker = match pushes[..] {
+            [Instruction::PushBytes(unlocker), Instruction::PushBytes(redeem)]
+                if redeem == &script[..] =>
+            {
+                unlocker
+            }
+            _ => return Err(PolicyError::UncleanStack(index)),
+        };
+
+        // the sig path takes sequence 0, see `CONTRACT_BYTECODE`
+        let sigchecks = match input.sequence == Sequence(0) {
+            true if !is_canonical_signature(unlocker) => {
+                return Err(PolicyError::NonCanonicalSignature(index))
+            }
+            false if !unlocker.is_empty() => return Err(PolicyError::MalleableUnlocker(index)),
+            signed => signed as usize,
+        };
+        let max = policy.max_sigchecks(input.script_sig.len());
+        if sigchecks > max {
+            return Err(PolicyError::TooManySigChecks {
+                input: index,
+                sigchecks,
+                max,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Serialized size of a spend, with the longest unlocker and output the
+    /// contract allows. The token prefix is part of the output script field
+    pub fn spend_size(&self) -> u64 {
+        let mut output = self.success_output.len().max(self.failed_output.len());
+        if self.token {
+            output += MAX_TOKEN_PREFIX_SIZE;
+        }
+        let mut spend = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                script_sig: Script::from(self.unlocking_script(&[0; MAX_UNLOCKER_SIZE])),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 0,
+                script_pubkey: Script::from(vec![0; output]),
+                token: None,
+            }],
+        };
+        if let Some(fee) = &self.claim_fee {
+            spend.output.push(TxOut {
+                value: fee.amount,
+                script_pubkey: Script::from(fee.output.clone()),
+                token: None,
+            });
+        }
+        bitcoincash::consensus::serialize(&spend).len() as u64
+    }
+
+    /// The fee is part of the script, a bigger one may need a longer push.
+    /// Grows until the fee covers the spend with it
+    fn with_fee_rate(mut self, rate: u64) -> Self {
+        loop {
+            let fee = fee_for_size(rate, self.spend_size());
+            if fee <= self.mining_fee {
+                return self;
+            }
+            self.mining_fee = fee;
+        }
+    }
+
+    /// Relay minimum of the spend
+    fn check_fee(&self) -> Result<(), ContractError> {
+        let minimum = MempoolPolicy::default().min_relay_fee * self.spend_size();
+        if self.mining_fee < minimum {
+            return Err(ContractError::MiningFeeTooLow {
+                fee: self.mining_fee,
+                minimum,
+            });
+        }
+        Ok(())
+    }
+
+    /// Token contracts get a token-aware address, wallets only send tokens there
     pub fn cash_address(&self) -> String {
         let hash = self.script_hash();
+        let version = match self.token {
+            true => address::P2SH_TOKEN_VERSION,
+            false => address::P2SH_VERSION,
+        };
         match self.bch_network {
-            Network::Mainnet => address::encode(&hash, "bitcoincash", 8),
-            Network::Testnet => address::encode(&hash, "bchtest", 8),
-            Network::Regtest => address::encode(&hash, "bchreg", 8),
+            Network::Mainnet => address::encode(&hash, "bitcoincash", version),
+            Network::Testnet => address::encode(&hash, "bchtest", version),
+            Network::Regtest => address::encode(&hash, "bchreg", version),
         }
     }
 }
@@ -101,14 +537,18 @@ pub struct ContractPair {
     alice_receiving: Vec<u8>,
     bob_receiving: Vec<u8>,
     swaplock_in_sats: u64,
-    pub mining_fee: u64,
     bch_network: Network,
+    #[serde(default)]
+    token_policy: TokenPolicy,
+    /// Locked along with the sats in a token swap
+    #[serde(default)]
+    token: Option<CashToken>,
 }
 
 impl ContractPair {
-    // None variant is timelock > SEQUENCE_LOCKTIME_MASK
+    #[allow(clippy::too_many_arguments)]
     pub fn create(
-        mining_fee: u64,
+        mining_fee: MiningFee,
         bob_receiving: Vec<u8>,
         bob_pubkey_ves: bitcoincash::PublicKey,
         alice_receiving: Vec<u8>,
@@ -117,42 +557,238 @@ impl ContractPair {
         timelock1: u32,
         bch_network: Network,
         swaplock_in: bitcoincash::Amount,
-    ) -> Option<ContractPair> {
-        if timelock0 > SEQUENCE_LOCKTIME_MASK || timelock1 > SEQUENCE_LOCKTIME_MASK {
-            return None;
+        options: ContractOptions,
+    ) -> Result<ContractPair, ContractError> {
+        let ContractOptions {
+            token,
+            claim_fee,
+            strict,
+            expiry,
+        } = options;
+        if let Some(expiry) = expiry {
+            if expiry < LOCKTIME_THRESHOLD {
+                return Err(ContractError::ExpiryNotTime(expiry));
+            }
+        }
+        if let Some(fee) = &claim_fee {
+            let minimum = MempoolPolicy::default().dust(&TxOut {
+                value: 0,
+                script_pubkey: Script::from(fee.output.clone()),
+                token: None,
+            });
+            if fee.amount < minimum {
+                return Err(ContractError::ClaimFeeDust {
+                    fee: fee.amount,
+                    minimum,
+                });
+            }
+            let maximum = swaplock_in.to_sat() / MAX_FEE_SHARE;
+            if fee.amount > maximum {
+                return Err(ContractError::ClaimFeeTooHigh {
+                    fee: fee.amount,
+                    maximum,
+                });
+            }
         }
+        encode_timelock(timelock0)?;
+        encode_timelock(timelock1)?;
 
-        let refund = Contract {
-            mining_fee,
+        let fixed = match mining_fee {
+            MiningFee::Fixed(fee) => fee,
+            MiningFee::Rate(_) => 0,
+        };
+        let mut refund = Contract {
+            mining_fee: fixed,
             success_output: bob_receiving.clone(),
             pubkey_ves: alice_pubkey_ves,
             timelock: timelock1,
             failed_output: alice_receiving.clone(),
+            token: token.is_some(),
+            claim_fee: None,
+            strict_amount: None,
+            expiry,
             bch_network,
             _private: (),
         };
+        if let MiningFee::Rate(rate) = mining_fee {
+            refund = refund.with_fee_rate(rate);
+        }
 
-        let swaplock = Contract {
-            mining_fee,
+        let mut swaplock = Contract {
+            mining_fee: fixed,
             success_output: alice_receiving.clone(),
             pubkey_ves: bob_pubkey_ves,
             timelock: timelock0,
             failed_output: refund.locking_script(),
+            token: token.is_some(),
+            claim_fee,
+            strict_amount: strict.then_some(swaplock_in.to_sat()),
+            expiry: None,
             bch_network,
             _private: (),
         };
+        if let MiningFee::Rate(rate) = mining_fee {
+            swaplock = swaplock.with_fee_rate(rate);
+        }
 
-        Some(ContractPair {
+        swaplock.check_fee()?;
+        refund.check_fee()?;
+        let contract_pair = ContractPair {
             swaplock,
             refund,
             alice_receiving,
             bob_receiving,
             swaplock_in_sats: swaplock_in.to_sat(),
-            mining_fee,
             bch_network,
-        })
+            token_policy: TokenPolicy::default(),
+            token,
+        };
+        let minimum = contract_pair.minimum_amount();
+        if contract_pair.swaplock_in_sats < minimum {
+            return Err(ContractError::AmountTooLow {
+                amount: contract_pair.swaplock_in_sats,
+                minimum,
+            });
+        }
+        let maximum = contract_pair.swaplock_in_sats / MAX_FEE_SHARE;
+        if contract_pair.refund_path_fee() > maximum {
+            return Err(ContractError::MiningFeeTooHigh {
+                fee: contract_pair.refund_path_fee(),
+                maximum,
+            });
+        }
+        Ok(contract_pair)
     }
 
+    /// Smallest swap amount leaving every spend output above dust after its
+    /// fees: Alice's claim, the Refund funding, Bob's refund and the punish
+    pub fn minimum_amount(&self) -> u64 {
+        let policy = MempoolPolicy::default();
+        let sats_dust = |script: Vec<u8>| {
+            policy.dust(&TxOut {
+                value: 0,
+                script_pubkey: Script::from(script),
+                token: None,
+            })
+        };
+        // every spend output carries the tokens, the claim fee output aside
+        let dust = |script: Vec<u8>| match self.token {
+            Some(_) => sats_dust(script) + MAX_TOKEN_PREFIX_SIZE as u64 * policy.dust_relay_fee,
+            None => sats_dust(script),
+        };
+        let claim_fee = self.swaplock.claim_fee.as_ref().map_or(0, |fee| fee.amount);
+        let claim = self.swaplock.mining_fee + claim_fee + dust(self.alice_receiving.clone());
+        let to_refund = self.swaplock.mining_fee + dust(self.refund.locking_script());
+        let refund = self.refund_path_fee()
+            + dust(self.bob_receiving.clone()).max(dust(self.alice_receiving.clone()));
+        claim.max(to_refund).max(refund)
+    }
+
+    /// Not part of the scripts, each side applies its own
+    pub fn with_token_policy(mut self, token_policy: TokenPolicy) -> Self {
+        self.token_policy = token_policy;
+        self
+    }
+
+    /// Scripts of the SwapLock, the one Bob funds
+    pub fn swaplock_scripts(&self) -> ContractScripts {
+        self.swaplock.scripts()
+    }
+
+    /// Scripts of the Refund, funded by the SwapLock timelock path
+    pub fn refund_scripts(&self) -> ContractScripts {
+        self.refund.scripts()
+    }
+
+    /// `Contract::check_input` of every input of `tx` spending one of the
+    /// contracts, before it is broadcast
+    pub fn check_spend(&self, tx: &Transaction, policy: &MempoolPolicy) -> Result<(), PolicyError> {
+        let scripts = [
+            (&self.swaplock, self.swaplock.script()),
+            (&self.refund, self.refund.script()),
+        ];
+        for (index, input) in tx.input.iter().enumerate() {
+            let spent = scripts
+                .iter()
+                .find(|(_, script)| input.script_sig.as_bytes().ends_with(script));
+            if let Some((contract, _)) = spent {
+                contract.check_input(tx, index, policy)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Paid by a SwapLock output going back to Bob, the SwapLock then the
+    /// Refund spend take their fee
+    pub fn refund_path_fee(&self) -> u64 {
+        self.swaplock.mining_fee + self.refund.mining_fee
+    }
+
+    /// Every output of `transaction` paying the SwapLock, with its sats
+    pub fn swaplock_outputs(&self, transaction: &Transaction) -> Vec<(bitcoincash::OutPoint, u64)> {
+        let swaplock = self.swaplock.locking_script();
+        transaction
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(_, out)| out.script_pubkey.to_bytes() == swaplock)
+            .map(|(vout, out)| {
+                let outpoint = bitcoincash::OutPoint::new(transaction.txid(), vout as u32);
+                (outpoint, out.value)
+            })
+            .collect()
+    }
+
+    /// Parts of a SwapLock funding split over several outputs of `transaction`,
+    /// empty when one output holds the swap amount. Both contracts allow a single
+    /// input, each part is spent by its own transaction paying the mining fee.
+    /// `ContractPair::analyze_tx` rejects splits with a part too small for it
+    pub fn funding_parts(&self, transaction: &Transaction) -> Vec<(bitcoincash::OutPoint, u64)> {
+        let outputs = self.swaplock_outputs(transaction);
+        match outputs
+            .iter()
+            .any(|(_, value)| *value == self.swaplock_in_sats)
+        {
+            true => vec![],
+            false => outputs,
+        }
+    }
+
+    /// Every output of `transaction`, by what it pays
+    pub fn analyze_outputs(&self, transaction: &Transaction) -> Vec<OutputAnalysis> {
+        let txid = transaction.txid();
+        let (swaplock, refund) = (self.swaplock.locking_script(), self.refund.locking_script());
+        transaction
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, out)| {
+                let script = out.script_pubkey.as_bytes();
+                let kind = if script == swaplock.as_slice() {
+                    OutputKind::SwapLock
+                } else if script == refund.as_slice() {
+                    OutputKind::Refund
+                } else if script == self.alice_receiving.as_slice() {
+                    OutputKind::AliceReceiving
+                } else if script == self.bob_receiving.as_slice() {
+                    OutputKind::BobReceiving
+                } else {
+                    OutputKind::Other
+                };
+                OutputAnalysis {
+                    outpoint: bitcoincash::OutPoint::new(txid, vout as u32),
+                    kind,
+                    value: out.value,
+                    token: out.token.is_some(),
+                }
+            })
+            .collect()
+    }
+
+    /// The contract spend, or the SwapLock funding `transaction` is, with the
+    /// outpoint it creates. A funding may pay the SwapLock in any outputs,
+    /// see `analyze_outputs` for all of them
     pub fn analyze_tx(
         &self,
         transaction: &Transaction,
@@ -160,7 +796,12 @@ impl ContractPair {
         let swaplock = self.swaplock.locking_script();
         let refund = self.refund.locking_script();
 
-        if transaction.input.len() == 1 && transaction.output.len() == 1 {
+        // claims pay the claim fee in a second output
+        let outputs = match self.swaplock.claim_fee {
+            Some(_) => 1..=2,
+            None => 1..=1,
+        };
+        if transaction.input.len() == 1 && outputs.contains(&transaction.output.len()) {
             let outpoint = bitcoincash::OutPoint::new(transaction.txid(), 0);
             let input = &transaction.input[0];
 
@@ -177,8 +818,10 @@ impl ContractPair {
                 }
             };
 
-            // check for dummy tx
-            if input_bytes == swaplock && output.value == self.swaplock_in_sats - self.mining_fee {
+            // check for dummy tx, a part of a split funding spends less
+            if input_bytes == swaplock
+                && output.value <= self.swaplock_in_sats - self.swaplock.mining_fee
+            {
                 if output_bytes == self.alice_receiving {
                     return Some((outpoint, TransactionType::SwapLockToAlice));
                 } else if output_bytes == refund {
@@ -187,7 +830,7 @@ impl ContractPair {
             }
 
             if input_bytes == refund
-                && output.value == self.swaplock_in_sats - (self.mining_fee * 2)
+                && output.value <= self.swaplock_in_sats - self.refund_path_fee()
             {
                 if output_bytes == self.bob_receiving {
                     return Some((outpoint, TransactionType::ToBob));
@@ -197,16 +840,76 @@ impl ContractPair {
             }
         }
 
-        for (vout, out) in transaction.output.iter().enumerate() {
-            if out.script_pubkey.to_bytes() == swaplock && out.value == self.swaplock_in_sats {
-                return Some((
-                    bitcoincash::OutPoint::new(transaction.txid(), vout as u32),
-                    TransactionType::ToSwapLock,
-                ));
-            }
+        let analyzed = self.analyze_outputs(transaction);
+        let funding = analyzed
+            .iter()
+            .find(|output| output.kind == OutputKind::SwapLock)?;
+        if analyzed
+            .iter()
+            .any(|output| output.kind == OutputKind::Refund)
+        {
+            return Some((
+                funding.outpoint,
+                TransactionType::Rejected(Rejection::BothContracts),
+            ));
+        }
+        if let Some(token) = &self.token {
+            // a split would split the tokens too, one output holds them all
+            let locked = transaction
+                .output
+                .iter()
+                .zip(&analyzed)
+                .find(|(out, output)| {
+                    output.kind == OutputKind::SwapLock
+                        && output.value == self.swaplock_in_sats
+                        && token.is_held_by(out)
+                });
+            return Some(match locked {
+                Some((_, output)) => (output.outpoint, TransactionType::ToSwapLock),
+                None => (
+                    funding.outpoint,
+                    TransactionType::Rejected(Rejection::WrongToken),
+                ),
+            });
+        }
+        if self.token_policy == TokenPolicy::Refuse
+            && analyzed
+                .iter()
+                .any(|output| output.kind == OutputKind::SwapLock && output.token)
+        {
+            return Some((
+                funding.outpoint,
+                TransactionType::Rejected(Rejection::Token),
+            ));
         }
 
-        return None;
+        let outputs = self.swaplock_outputs(transaction);
+        if let Some((outpoint, _)) = outputs
+            .iter()
+            .find(|(_, value)| *value == self.swaplock_in_sats)
+        {
+            return Some((*outpoint, TransactionType::ToSwapLock));
+        }
+
+        // several outputs are accepted when they add up to the swap amount
+        let total: u64 = outputs.iter().map(|(_, value)| value).sum();
+        let (outpoint, _) = outputs.first()?;
+        if self.swaplock.strict_amount.is_some() && outputs.len() > 1 {
+            return Some((
+                *outpoint,
+                TransactionType::Rejected(Rejection::SplitFunding),
+            ));
+        }
+        let minimum = self.minimum_amount();
+        let kind = match total.cmp(&self.swaplock_in_sats) {
+            Ordering::Equal if outputs.iter().any(|(_, value)| *value < minimum) => {
+                TransactionType::Rejected(Rejection::PartBelowMinimum)
+            }
+            Ordering::Equal => TransactionType::ToSwapLock,
+            Ordering::Less => TransactionType::Underfunded(total),
+            Ordering::Greater => TransactionType::Overfunded(total),
+        };
+        return Some((*outpoint, kind));
     }
 }
 
@@ -214,7 +917,80 @@ impl ContractPair {
 mod test {
     use std::str::FromStr;
 
-    use crate::contract::Contract;
+    use bitcoincash::{
+        blockdata::script::Builder,
+        secp256k1::{ecdsa, Message, Secp256k1, SecretKey},
+        OutPoint, PackedLockTime, PrivateKey, PublicKey, Script, Sequence, Transaction, TxIn,
+        TxOut,
+    };
+
+    use super::{
+        encode_timelock, token::CashToken, ClaimFee, ContractError, ContractOptions, ContractPair,
+        MiningFee, OutputKind, Rejection, TransactionType, CLAIM_FEE_BYTECODE,
+        SEQUENCE_LOCKTIME_TYPE_FLAG, TOKEN_CHECK_BYTECODE,
+    };
+    use crate::{
+        blockchain::policy::{MempoolPolicy, PolicyError},
+        contract::Contract,
+        keys::bitcoin::{address, Network},
+    };
+
+    /// Fixed so the pairs of a test only differ by what it changes
+    fn private_key(byte: u8) -> PrivateKey {
+        let key = SecretKey::from_slice(&[byte; 32]).unwrap();
+        PrivateKey::new(key, bitcoincash::Network::Regtest)
+    }
+
+    fn p2pkh(key: &PublicKey) -> Vec<u8> {
+        Script::new_p2pkh(&key.pubkey_hash()).into_bytes()
+    }
+
+    /// Bob with key 1 and Alice with key 2, each paid to its own p2pkh
+    fn create(
+        mining_fee: MiningFee,
+        sats: u64,
+        options: ContractOptions,
+    ) -> Result<ContractPair, ContractError> {
+        let secp = Secp256k1::signing_only();
+        let (bob, alice) = (
+            private_key(1).public_key(&secp),
+            private_key(2).public_key(&secp),
+        );
+        ContractPair::create(
+            mining_fee,
+            p2pkh(&bob),
+            bob,
+            p2pkh(&alice),
+            alice,
+            2,
+            4,
+            Network::Regtest,
+            bitcoincash::Amount::from_sat(sats),
+            options,
+        )
+    }
+
+    /// 100000 sats at 1 sat per byte
+    fn pair(options: ContractOptions) -> ContractPair {
+        create(MiningFee::Rate(1000), 100000, options).unwrap()
+    }
+
+    /// Output without sats to `script`, holding the fungible `token`
+    fn token_output(token: &CashToken, script: &[u8]) -> TxOut {
+        let mut category = token.category;
+        category.reverse();
+        let mut field = vec![0xef];
+        field.extend_from_slice(&category);
+        // fungible only, an amount of 253 to 65535 as a 3 bytes compact size
+        field.push(0x10);
+        field.push(0xfd);
+        field.extend_from_slice(&(token.amount as u16).to_le_bytes());
+        field.extend_from_slice(script);
+        let mut encoded = vec![0; 8];
+        encoded.push(field.len() as u8);
+        encoded.extend(field);
+        bitcoincash::consensus::deserialize(&encoded).unwrap()
+    }
 
     #[test]
     fn should_have_correct_address() {
@@ -230,6 +1006,10 @@ mod test {
             pubkey_ves,
             timelock: 1000,
             failed_output: output,
+            token: false,
+            claim_fee: None,
+            strict_amount: None,
+            expiry: None,
             bch_network: crate::keys::bitcoin::Network::Testnet,
             _private: (),
         };
@@ -239,4 +1019,363 @@ mod test {
             "bchtest:prmnwxmmaq58h22jt7qrjmutnkrmrfm4j56sqj67jg"
         );
     }
+
+    #[test]
+    fn should_encode_timelocks() {
+        assert_eq!(encode_timelock(144).unwrap().0, 144);
+        let time_lock = SEQUENCE_LOCKTIME_TYPE_FLAG | 0xffff;
+        assert_eq!(encode_timelock(time_lock).unwrap().0, time_lock);
+        assert!(matches!(
+            encode_timelock(0x10000),
+            Err(ContractError::TimelockTooLong(_))
+        ));
+        // bits between the value and the type flag
+        assert!(matches!(
+            encode_timelock(SEQUENCE_LOCKTIME_TYPE_FLAG | 1 << 16 | 2),
+            Err(ContractError::TimelockTooLong(_))
+        ));
+        assert!(matches!(
+            encode_timelock(1 << 31 | 2),
+            Err(ContractError::TimelockDisabled(_))
+        ));
+        assert!(matches!(
+            encode_timelock(SEQUENCE_LOCKTIME_TYPE_FLAG),
+            Err(ContractError::TimelockZero(_))
+        ));
+    }
+
+    #[test]
+    fn should_size_fees_to_spends() {
+        let with_fee = |mining_fee| create(mining_fee, 100000, ContractOptions::default());
+
+        // 1 sat per byte, the fee is the spend size
+        let pair = pair(ContractOptions::default());
+        assert_eq!(pair.swaplock.mining_fee, pair.swaplock.spend_size());
+        assert_eq!(pair.refund.mining_fee, pair.refund.spend_size());
+        assert_eq!(
+            pair.refund_path_fee(),
+            pair.swaplock.mining_fee + pair.refund.mining_fee
+        );
+        let fixed = with_fee(MiningFee::Fixed(1000)).unwrap();
+        assert_eq!(fixed.refund_path_fee(), 2000);
+
+        assert!(matches!(
+            with_fee(MiningFee::Rate(500)),
+            Err(ContractError::MiningFeeTooLow { .. })
+        ));
+        assert!(matches!(
+            with_fee(MiningFee::Fixed(100)),
+            Err(ContractError::MiningFeeTooLow { .. })
+        ));
+        assert!(matches!(
+            with_fee(MiningFee::Rate(100_000)),
+            Err(ContractError::MiningFeeTooHigh { .. })
+        ));
+    }
+
+    #[test]
+    fn should_classify_every_output() {
+        let pair = pair(ContractOptions::default());
+        let output = |value, script: Vec<u8>| TxOut {
+            value,
+            script_pubkey: Script::from(script),
+            token: None,
+        };
+        let funding = |output: Vec<TxOut>| Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output,
+        };
+
+        // change first, the SwapLock in output 1
+        let tx = funding(vec![
+            output(5000, pair.bob_receiving.clone()),
+            output(100000, pair.swaplock.locking_script()),
+        ]);
+        match pair.analyze_tx(&tx) {
+            Some((outpoint, TransactionType::ToSwapLock)) => assert_eq!(outpoint.vout, 1),
+            other => panic!("expected the SwapLock funding, got {other:?}"),
+        }
+        let kinds: Vec<_> = pair
+            .analyze_outputs(&tx)
+            .iter()
+            .map(|output| (output.kind, output.value, output.token))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (OutputKind::BobReceiving, 5000, false),
+                (OutputKind::SwapLock, 100000, false)
+            ]
+        );
+
+        let tx = funding(vec![
+            output(100000, pair.swaplock.locking_script()),
+            output(100000, pair.refund.locking_script()),
+        ]);
+        assert!(matches!(
+            pair.analyze_tx(&tx),
+            Some((_, TransactionType::Rejected(Rejection::BothContracts)))
+        ));
+        // the Refund alone isn't a funding
+        let tx = funding(vec![output(100000, pair.refund.locking_script())]);
+        assert!(pair.analyze_tx(&tx).is_none());
+    }
+
+    #[test]
+    fn should_keep_tokens_of_token_swaps() {
+        let plain = pair(ContractOptions::default());
+        let pair = pair(ContractOptions {
+            token: Some(CashToken {
+                category: [1; 32],
+                amount: 1000,
+            }),
+            ..Default::default()
+        });
+
+        assert!(pair.swaplock.token && pair.refund.token);
+        assert!(pair.swaplock.script().ends_with(&pair.swaplock.bytecode()));
+        assert!(pair.refund.bytecode().starts_with(&TOKEN_CHECK_BYTECODE));
+        assert!(pair.swaplock.spend_size() > plain.swaplock.spend_size());
+        let (version, _) = address::decode(&pair.swaplock.cash_address()).unwrap();
+        assert_eq!(version, address::P2SH_TOKEN_VERSION);
+        assert_ne!(
+            pair.swaplock.locking_script(),
+            plain.swaplock.locking_script()
+        );
+
+        // the sats alone don't fund a token swap
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: 100000,
+                script_pubkey: Script::from(pair.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        assert!(matches!(
+            pair.analyze_tx(&tx),
+            Some((_, TransactionType::Rejected(Rejection::WrongToken)))
+        ));
+
+        // BCHN counts the token prefix in the dust of an output
+        let token = CashToken {
+            category: [1; 32],
+            amount: 1000,
+        };
+        let policy = MempoolPolicy::default();
+        let refund = token_output(&token, &pair.refund.locking_script());
+        assert!(token.is_held_by(&refund));
+        let sats_only = TxOut {
+            token: None,
+            ..refund.clone()
+        };
+        assert_eq!(
+            policy.dust(&refund),
+            policy.dust(&sats_only) + 37 * policy.dust_relay_fee
+        );
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn::default()],
+            output: vec![TxOut {
+                value: policy.dust(&sats_only),
+                ..refund
+            }],
+        };
+        assert!(matches!(
+            policy.check(&tx, &[100000]),
+            Err(PolicyError::Dust { output: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn should_commit_claim_fee() {
+        let operator = p2pkh(&private_key(3).public_key(&Secp256k1::signing_only()));
+        let with_claim_fee = |amount| {
+            let claim_fee = Some(ClaimFee {
+                output: operator.clone(),
+                amount,
+            });
+            create(
+                MiningFee::Rate(1000),
+                100000,
+                ContractOptions {
+                    claim_fee,
+                    ..Default::default()
+                },
+            )
+        };
+        let plain = pair(ContractOptions::default());
+        let pair = with_claim_fee(1000).unwrap();
+
+        assert!(pair.swaplock.script().ends_with(&CLAIM_FEE_BYTECODE));
+        assert!(pair.swaplock.spend_size() > plain.swaplock.spend_size());
+        assert_eq!(pair.refund.script(), plain.refund.script());
+        assert_ne!(pair.swaplock.cash_address(), plain.swaplock.cash_address());
+        assert!(matches!(
+            with_claim_fee(100),
+            Err(ContractError::ClaimFeeDust { .. })
+        ));
+        assert!(matches!(
+            with_claim_fee(20000),
+            Err(ContractError::ClaimFeeTooHigh { .. })
+        ));
+    }
+
+    #[test]
+    fn should_refuse_amounts_leaving_dust() {
+        let with_amount = |sats| create(MiningFee::Rate(1000), sats, ContractOptions::default());
+        let pair = pair(ContractOptions::default());
+        let dust = MempoolPolicy::default().dust(&TxOut {
+            value: 0,
+            script_pubkey: Script::from(pair.bob_receiving.clone()),
+            token: None,
+        });
+        assert_eq!(pair.minimum_amount(), pair.refund_path_fee() + dust);
+
+        match with_amount(1000) {
+            Err(ContractError::AmountTooLow { amount, minimum }) => {
+                assert_eq!((amount, minimum), (1000, pair.minimum_amount()))
+            }
+            other => panic!("expected AmountTooLow, got {other:?}"),
+        }
+
+        // the token prefix makes the outputs of a token swap larger
+        let token = CashToken {
+            category: [1; 32],
+            amount: 1000,
+        };
+        let pair = create(
+            MiningFee::Rate(1000),
+            100000,
+            ContractOptions {
+                token: Some(token.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let policy = MempoolPolicy::default();
+        let refunded = token_output(&token, &pair.bob_receiving);
+        assert!(pair.minimum_amount() - pair.refund_path_fee() >= policy.dust(&refunded));
+        let to_refund = token_output(&token, &pair.refund.locking_script());
+        assert!(pair.minimum_amount() - pair.swaplock.mining_fee >= policy.dust(&to_refund));
+    }
+
+    #[test]
+    fn should_expose_raw_scripts() {
+        let pair = pair(ContractOptions::default());
+
+        for (contract, scripts) in [
+            (&pair.swaplock, pair.swaplock_scripts()),
+            (&pair.refund, pair.refund_scripts()),
+        ] {
+            let locking = address::script_pubkey(&contract.cash_address()).unwrap();
+            assert_eq!(scripts.locking_script, locking.into_bytes());
+            // OP_HASH160 <20 bytes> OP_EQUAL
+            assert_eq!(&scripts.locking_script[2..22], &scripts.script_hash[..]);
+            assert_eq!(scripts.redeem_script, contract.script());
+            let json = serde_json::to_value(&scripts).unwrap();
+            assert_eq!(json["redeem_script"], scripts.redeem_script_hex());
+            assert_eq!(json["locking_script"], scripts.locking_script_hex());
+        }
+        assert_ne!(pair.swaplock_scripts(), pair.refund_scripts());
+    }
+
+    #[test]
+    fn should_keep_unlockers_standard() {
+        let secp = Secp256k1::new();
+        let private = private_key(1);
+        let pair = pair(ContractOptions::default());
+        let policy = MempoolPolicy::default();
+        let spend = |unlocker: &[u8], sequence| Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                sequence,
+                script_sig: Script::from(pair.swaplock.unlocking_script(unlocker)),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: 90000,
+                script_pubkey: Script::from(pair.swaplock.success_output.clone()),
+                token: None,
+            }],
+        };
+
+        // DER signatures shrink when r or s has leading zeros
+        let mut lengths = Vec::new();
+        for i in 0..=255 {
+            let message = Message::from_slice(&[i; 32]).unwrap();
+            let sig = secp.sign_ecdsa(&message, &private.inner).serialize_der();
+            let tx = spend(&sig, Sequence(0));
+            pair.check_spend(&tx, &policy).unwrap();
+            policy.check(&tx, &[100000]).unwrap();
+            lengths.push(sig.len());
+        }
+        lengths.dedup();
+        assert!(lengths.len() > 1);
+
+        // the same signature with a high S
+        let sig = secp.sign_ecdsa(&Message::from_slice(&[1; 32]).unwrap(), &private.inner);
+        let mut compact = sig.serialize_compact();
+        let high_s = SecretKey::from_slice(&compact[32..]).unwrap().negate();
+        compact[32..].copy_from_slice(&high_s.secret_bytes());
+        let high = ecdsa::Signature::from_compact(&compact)
+            .unwrap()
+            .serialize_der();
+        assert!(matches!(
+            pair.check_spend(&spend(&high, Sequence(0)), &policy),
+            Err(PolicyError::NonCanonicalSignature(0))
+        ));
+
+        let timelock = pair.swaplock.sequence();
+        pair.check_spend(&spend(&[], timelock), &policy).unwrap();
+        assert!(matches!(
+            pair.check_spend(&spend(&[1, 2], timelock), &policy),
+            Err(PolicyError::MalleableUnlocker(0))
+        ));
+
+        // an extra item under the unlocker stays on the stack
+        let mut unclean = spend(&[], timelock);
+        let mut script_sig = vec![0x51];
+        script_sig.extend_from_slice(unclean.input[0].script_sig.as_bytes());
+        unclean.input[0].script_sig = Script::from(script_sig);
+        assert!(matches!(
+            pair.check_spend(&unclean, &policy),
+            Err(PolicyError::UncleanStack(0))
+        ));
+
+        // OP_PUSHDATA1 for a 2 bytes push
+        let mut non_minimal = spend(&[], timelock);
+        let mut script_sig = vec![0x4c, 0x02, 0x01, 0x02];
+        script_sig.extend_from_slice(&non_minimal.input[0].script_sig.as_bytes()[1..]);
+        non_minimal.input[0].script_sig = Script::from(script_sig);
+        assert!(matches!(
+            pair.check_spend(&non_minimal, &policy),
+            Err(PolicyError::NonMinimalPush(0))
+        ));
+        assert!(matches!(
+            policy.check(&non_minimal, &[100000]),
+            Err(PolicyError::NonMinimalPush(0))
+        ));
+
+        let oversized = Transaction {
+            input: vec![TxIn {
+                script_sig: Builder::new().push_slice(&[0; 521]).into_script(),
+                ..Default::default()
+            }],
+            ..spend(&[], timelock)
+        };
+        assert!(matches!(
+            policy.check(&oversized, &[100000]),
+            Err(PolicyError::ElementTooLarge { size: 521, .. })
+        ));
+    }
 }
@@ -0,0 +1,149 @@
+use std::str::FromStr;
+
+use bitcoincash::TxOut;
+use serde::{Deserialize, Serialize};
+
+/// Starts the locking bytecode field of an output carrying tokens
+const PREFIX_TOKEN: u8 = 0xef;
+const HAS_AMOUNT: u8 = 0x10;
+const HAS_NFT: u8 = 0x20;
+const HAS_COMMITMENT_LENGTH: u8 = 0x40;
+/// Largest fungible-only prefix: marker, category, bitfield and a 9 byte amount
+pub(crate) const MAX_TOKEN_PREFIX_SIZE: usize = 1 + 32 + 1 + 9;
+
+/// Fungible CashTokens a token swap locks in the SwapLock, along with the
+/// sats of `Swap::bch_amount`. See `Swap::bch_token`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CashToken {
+    /// Hex as explorers show it, the reverse of the encoded bytes like a txid
+    #[serde(with = "hex")]
+    pub category: [u8; 32],
+    pub amount: u64,
+}
+
+/// Tokens of an output, from its token prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenPrefix {
+    /// As explorers show it
+    pub category: [u8; 32],
+    /// 0 without fungible tokens
+    pub amount: u64,
+    pub nft: bool,
+}
+
+fn read_compact_size(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let (first, rest) = bytes.split_first()?;
+    let size = match first {
+        0xfd => 2,
+        0xfe => 4,
+        0xff => 8,
+        _ => return Some((*first as u64, rest)),
+    };
+    let (value, rest) = (rest.get(..size)?, &rest[size..]);
+    let mut le = [0; 8];
+    le[..size].copy_from_slice(value);
+    Some((u64::from_le_bytes(le), rest))
+}
+
+/// `field` is the locking bytecode field of an output, prefix included
+fn parse_prefix(field: &[u8]) -> Option<TokenPrefix> {
+    let (marker, rest) = field.split_first()?;
+    if *marker != PREFIX_TOKEN || rest.len() < 33 {
+        return None;
+    }
+    let mut category: [u8; 32] = rest[..32].try_into().ok()?;
+    category.reverse();
+    let bitfield = rest[32];
+    let mut rest = &rest[33..];
+
+    if bitfield & HAS_COMMITMENT_LENGTH != 0 {
+        let (length, after) = read_compact_size(rest)?;
+        rest = after.get(length as usize..)?;
+    }
+    let amount = match bitfield & HAS_AMOUNT {
+        0 => 0,
+        _ => read_compact_size(rest)?.0,
+    };
+    Some(TokenPrefix {
+        category,
+        amount,
+        nft: bitfield & HAS_NFT != 0,
+    })
+}
+
+impl TokenPrefix {
+    /// Read from the consensus encoding, whatever the library keeps of it
+    pub fn of(output: &TxOut) -> Option<TokenPrefix> {
+        output.token.as_ref()?;
+        let encoded = bitcoincash::consensus::serialize(output);
+        // the sats, then the length of the locking bytecode field
+        let (_, field) = read_compact_size(encoded.get(8..)?)?;
+        parse_prefix(field)
+    }
+}
+
+/// `category:amount`, the category in hex
+impl FromStr for CashToken {
+    type Err = String;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid token {token}, expected category:amount");
+        let (category, amount) = token.split_once(':').ok_or_else(invalid)?;
+        let mut parsed = [0; 32];
+        hex::decode_to_slice(category, &mut parsed).map_err(|_| invalid())?;
+        Ok(CashToken {
+            category: parsed,
+            amount: amount.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl CashToken {
+    /// `output` holds exactly these tokens, and no NFT
+    pub fn is_held_by(&self, output: &TxOut) -> bool {
+        TokenPrefix::of(output).is_some_and(|prefix| {
+            !prefix.nft && prefix.category == self.category && prefix.amount == self.amount
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_prefix, CashToken, TokenPrefix};
+
+    #[test]
+    fn should_parse_token_prefixes() {
+        let mut encoded = [0x11; 32];
+        encoded[0] = 0x01;
+        let mut category = encoded;
+        category.reverse();
+
+        let mut fungible = vec![0xef];
+        fungible.extend_from_slice(&encoded);
+        fungible.extend_from_slice(&[0x10, 0xfd, 0xe8, 0x03, 0xa9]);
+        assert_eq!(
+            parse_prefix(&fungible),
+            Some(TokenPrefix {
+                category,
+                amount: 1000,
+                nft: false,
+            })
+        );
+
+        // mutable NFT with a 2 byte commitment, and 5 fungible tokens
+        let mut nft = vec![0xef];
+        nft.extend_from_slice(&encoded);
+        nft.extend_from_slice(&[0x71, 0x02, 0xaa, 0xbb, 0x05]);
+        assert_eq!(
+            parse_prefix(&nft).map(|prefix| (prefix.amount, prefix.nft)),
+            Some((5, true))
+        );
+
+        assert_eq!(parse_prefix(&[0xa9, 0x14]), None);
+        assert_eq!(parse_prefix(&fungible[..20]), None);
+
+        let token: CashToken = format!("{}:1000", hex::encode(category)).parse().unwrap();
+        assert_eq!((token.category, token.amount), (category, 1000));
+        assert!("11:1000".parse::<CashToken>().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+use super::{token::CashToken, ClaimFee, ContractError, ContractOptions, ContractPair, MiningFee};
+use crate::{
+    keys::{bitcoin::Network, KeyPublicWithoutProof},
+    utils::bch_amount,
+};
+
+/// Everything `ContractPair::create` takes, public once Msg0 is exchanged.
+/// Bob's SwapLock key and receiving script come first, as in `create`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractParams {
+    pub mining_fee: MiningFee,
+    #[serde(with = "hex")]
+    pub bob_receiving: Vec<u8>,
+    pub bob_pubkey_ves: bitcoincash::PublicKey,
+    #[serde(with = "hex")]
+    pub alice_receiving: Vec<u8>,
+    pub alice_pubkey_ves: bitcoincash::PublicKey,
+    /// Of the SwapLock then of the Refund, `Swap::timelock1` and `timelock2`
+    pub timelock1: u32,
+    pub timelock2: u32,
+    pub bch_network: Network,
+    #[serde(with = "bch_amount")]
+    pub bch_amount: bitcoincash::Amount,
+    /// Of a token swap, `Swap::bch_token`
+    #[serde(default)]
+    pub bch_token: Option<CashToken>,
+    /// See `Swap::operator_fee`
+    #[serde(default)]
+    pub claim_fee: Option<ClaimFee>,
+    /// Negotiated contract version 5, see `Swap::strict_contract`
+    #[serde(default)]
+    pub strict: bool,
+    /// See `Swap::refund_expiry`
+    #[serde(default)]
+    pub refund_expiry: Option<u32>,
+}
+
+/// `bchtest:p..` and `p..` alike
+fn without_prefix(address: &str) -> String {
+    let payload = address
+        .rsplit_once(':')
+        .map_or(address, |(_, payload)| payload);
+    payload.to_lowercase()
+}
+
+impl ContractPair {
+    pub fn from_params(params: &ContractParams) -> Result<ContractPair, ContractError> {
+        ContractPair::create(
+            params.mining_fee,
+            params.bob_receiving.clone(),
+            params.bob_pubkey_ves,
+            params.alice_receiving.clone(),
+            params.alice_pubkey_ves,
+            params.timelock1,
+            params.timelock2,
+            params.bch_network,
+            params.bch_amount,
+            ContractOptions {
+                token: params.bch_token.clone(),
+                claim_fee: params.claim_fee.clone(),
+                strict: params.strict,
+                expiry: params.refund_expiry,
+            },
+        )
+    }
+
+    /// Whether `bch_address` is the SwapLock built from `params`, without the
+    /// state machine: what a third party checks before trusting a funding
+    /// address. False when no contract can be built from them
+    pub fn verify(bch_address: &str, params: &ContractParams) -> bool {
+        ContractPair::from_params(params).is_ok_and(|contract_pair| {
+            without_prefix(&contract_pair.swaplock.cash_address()) == without_prefix(bch_address)
+        })
+    }
+}
+
+/// Whether `address` is the shared XMR lock address of both sides' Msg0 keys,
+/// the sum of their view and spend keys
+pub fn verify_xmr_address(
+    address: &monero::Address,
+    alice: &KeyPublicWithoutProof,
+    bob: &KeyPublicWithoutProof,
+) -> bool {
+    let shared = monero::ViewPair {
+        view: alice.monero_view + bob.monero_view,
+        spend: alice.monero_spend + bob.monero_spend,
+    };
+    monero::Address::from_viewpair(address.network, &shared) == *address
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoincash::Script;
+
+    use super::{verify_xmr_address, ContractParams};
+    use crate::{
+        contract::{ContractPair, MiningFee},
+        keys::{
+            bitcoin::{random_private_key, Network},
+            KeyPrivate, KeyPublic, KeyPublicWithoutProof,
+        },
+    };
+
+    #[test]
+    fn should_verify_addresses_from_params() {
+        let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+        let key = || random_private_key(Network::Regtest).public_key(&secp);
+        let recv = || Script::new_p2pkh(&key().pubkey_hash()).into_bytes();
+        let params = ContractParams {
+            mining_fee: MiningFee::Rate(1000),
+            bob_receiving: recv(),
+            bob_pubkey_ves: key(),
+            alice_receiving: recv(),
+            alice_pubkey_ves: key(),
+            timelock1: 2,
+            timelock2: 4,
+            bch_network: Network::Regtest,
+            bch_amount: bitcoincash::Amount::from_sat(100000),
+            bch_token: None,
+            claim_fee: None,
+            strict: false,
+            refund_expiry: None,
+        };
+        let contract_pair = ContractPair::from_params(&params).unwrap();
+        let address = contract_pair.swaplock.cash_address();
+        assert!(ContractPair::verify(&address, &params));
+        assert!(ContractPair::verify(&address.to_uppercase(), &params));
+        let (_, payload) = address.split_once(':').unwrap();
+        assert!(ContractPair::verify(payload, &params));
+        assert!(!ContractPair::verify(
+            &contract_pair.refund.cash_address(),
+            &params
+        ));
+        // one parameter off is another contract
+        let other = ContractParams {
+            timelock2: 5,
+            ..params.clone()
+        };
+        assert!(!ContractPair::verify(&address, &other));
+        let json = serde_json::to_string(&params).unwrap();
+        let parsed: ContractParams = serde_json::from_str(&json).unwrap();
+        assert!(ContractPair::verify(&address, &parsed));
+
+        // from the private keys the shared wallet is restored with
+        let (alice, bob) = (
+            KeyPrivate::random(Network::Regtest),
+            KeyPrivate::random(Network::Regtest),
+        );
+        let shared = monero::ViewPair {
+            view: alice.monero_view + bob.monero_view,
+            spend: monero::PublicKey::from_private_key(&(alice.monero_spend + bob.monero_spend)),
+        };
+        let address = monero::Address::from_viewpair(monero::Network::Stagenet, &shared);
+        let alice: KeyPublicWithoutProof = KeyPublic::from(&alice).into();
+        let bob: KeyPublicWithoutProof = KeyPublic::from(&bob).into();
+        assert!(verify_xmr_address(&address, &alice, &bob));
+        assert!(!verify_xmr_address(&address, &bob, &bob));
+    }
+}
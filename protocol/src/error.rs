@@ -0,0 +1,81 @@
+use crate::{
+    blockchain::{backend::ChainError, policy::PolicyError},
+    monerod::MoneroError,
+    protocol::Error,
+    xmr_scanner::XmrScanError,
+};
+
+/// Whether a failed call is worth making again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// Connection, daemon or wallet hiccup, the same call may succeed later
+    Recoverable,
+    /// The same call fails the same way: invalid message, rejected
+    /// transaction, wrong configuration. Needs the caller or the user
+    Fatal,
+}
+
+/// Errors telling their `ErrorClass`, see `blockchain::backend::with_retry`
+pub trait Classify {
+    fn class(&self) -> ErrorClass;
+
+    fn is_recoverable(&self) -> bool {
+        self.class() == ErrorClass::Recoverable
+    }
+}
+
+/// Failures of the Alice and Bob runners, by where they come from.
+/// `std::error::Error::source` leads to the underlying error
+#[derive(Debug, thiserror::Error)]
+pub enum SwapError {
+    /// Transition rejected by the state machine
+    #[error(transparent)]
+    Protocol(#[from] Error),
+    #[error(transparent)]
+    Chain(#[from] ChainError),
+    /// A transaction we built wouldn't be relayed
+    #[error(transparent)]
+    Policy(#[from] PolicyError),
+    #[error(transparent)]
+    Monero(#[from] MoneroError),
+    /// From the `persist::SwapStore`
+    #[error("checkpoint store: {0}")]
+    Store(#[source] Box<dyn std::error::Error + Send + Sync>),
+    #[error("no checkpoint of {0}")]
+    NoCheckpoint(String),
+    /// Resumed with the runner of the other side
+    #[error("{0} is a trade of the other side")]
+    WrongSide(String),
+    /// Network delivered transition only the runner itself can make
+    #[error("{0} is not accepted from the peer")]
+    NotFromPeer(String),
+    /// Alice got a double spend proof for the SwapLock funding, XMR must not be locked
+    #[error("double spend proof for SwapLock funding {0}. Do not lock xmr")]
+    DoubleSpendProof(String),
+    /// Neither the trade id nor the swap id of a `manager::SwapManager` trade
+    #[error("no managed swap {0}")]
+    UnknownSwap(String),
+}
+
+impl Classify for SwapError {
+    fn class(&self) -> ErrorClass {
+        match self {
+            SwapError::Chain(e) => e.class(),
+            SwapError::Monero(e) => e.class(),
+            SwapError::Store(_) => ErrorClass::Recoverable,
+            SwapError::Protocol(_)
+            | SwapError::Policy(_)
+            | SwapError::NoCheckpoint(_)
+            | SwapError::WrongSide(_)
+            | SwapError::NotFromPeer(_)
+            | SwapError::DoubleSpendProof(_)
+            | SwapError::UnknownSwap(_) => ErrorClass::Fatal,
+        }
+    }
+}
+
+impl From<XmrScanError> for SwapError {
+    fn from(value: XmrScanError) -> Self {
+        SwapError::Monero(value.into())
+    }
+}
@@ -1,3 +1,10 @@
+use bitcoincash::{hashes::Hash, PubkeyHash, Script, ScriptHash};
+
+pub const P2PKH_VERSION: u8 = 0;
+pub const P2SH_VERSION: u8 = 8;
+/// Token-aware P2SH, CHIP-2022-02-CashTokens
+pub const P2SH_TOKEN_VERSION: u8 = 24;
+
 pub fn encode(hash: &[u8], prefix: &str, version_bit: u8) -> String {
     let mut payload: Vec<u8> = vec![version_bit];
     payload.extend_from_slice(hash);
@@ -9,6 +16,25 @@ pub fn encode(hash: &[u8], prefix: &str, version_bit: u8) -> String {
     format!("{}:{}", prefix, b32encode(&payload))
 }
 
+/// Returns (version_bit, hash). None on invalid checksum
+pub fn decode(address: &str) -> Option<(u8, Vec<u8>)> {
+    let (prefix, payload) = address.split_once(':')?;
+    let payload = b32decode(&payload.to_lowercase())?;
+    if payload.len() < 8 {
+        return None;
+    }
+
+    let mut combined_data = prefix_expand(prefix);
+    combined_data.extend_from_slice(&payload);
+    if polymod(&combined_data) != 0 {
+        return None;
+    }
+
+    let data = bech32::convert_bits(&payload[..payload.len() - 8], 5, 8, false).ok()?;
+    let (version_bit, hash) = data.split_first()?;
+    Some((*version_bit, hash.to_vec()))
+}
+
 fn polymod(v: &[u8]) -> u64 {
     let mut c: u64 = 1;
 
@@ -60,6 +86,18 @@ fn calculate_checksum(prefix: &str, payload: &[u8]) -> Vec<u8> {
     out
 }
 
+/// Locking script of a p2pkh or p2sh cash address, token-aware ones included
+pub fn script_pubkey(address: &str) -> Option<Script> {
+    let (version, hash) = decode(address)?;
+    match version {
+        P2PKH_VERSION => Some(Script::new_p2pkh(&PubkeyHash::from_slice(&hash).ok()?)),
+        P2SH_VERSION | P2SH_TOKEN_VERSION => {
+            Some(Script::new_p2sh(&ScriptHash::from_slice(&hash).ok()?))
+        }
+        _ => None,
+    }
+}
+
 const CHARSET: [char; 32] = [
     'q', 'p', 'z', 'r', 'y', '9', 'x', '8', //  +0
     'g', 'f', '2', 't', 'v', 'd', 'w', '0', //  +8
@@ -76,3 +114,27 @@ fn b32encode(inputs: &[u8]) -> String {
 
     out
 }
+
+fn b32decode(inputs: &str) -> Option<Vec<u8>> {
+    inputs
+        .chars()
+        .map(|c| CHARSET.iter().position(|x| *x == c).map(|i| i as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+
+    #[test]
+    fn should_decode_encoded() {
+        let hash = [7u8; 20];
+        let address = encode(&hash, "bchtest", 8);
+        assert_eq!(decode(&address), Some((8, hash.to_vec())));
+
+        let mut corrupted = address.clone();
+        corrupted.pop();
+        corrupted.push(if address.ends_with('q') { 'p' } else { 'q' });
+        assert_eq!(decode(&corrupted), None);
+    }
+}
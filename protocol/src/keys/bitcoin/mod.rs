@@ -2,6 +2,7 @@ use ecdsa_fun::fun::Scalar;
 use serde::{Deserialize, Serialize};
 
 pub mod address;
+pub mod p2pkh;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Network {
@@ -0,0 +1,79 @@
+use bitcoin_hashes::{sha256d, Hash};
+use bitcoincash::{
+    blockdata::script::Builder, consensus::serialize, hashes::Hash as BHash, PrivateKey, Script,
+    Transaction,
+};
+
+use super::{address, Network};
+
+/// SIGHASH_ALL | SIGHASH_FORKID
+const SIGHASH_ALL_FORKID: u32 = 0x41;
+
+pub fn cash_address(pubkey: &bitcoincash::PublicKey, network: Network) -> String {
+    let hash = pubkey.pubkey_hash().into_inner();
+    match network {
+        Network::Mainnet => address::encode(&hash, "bitcoincash", 0),
+        Network::Testnet => address::encode(&hash, "bchtest", 0),
+        Network::Regtest => address::encode(&hash, "bchreg", 0),
+    }
+}
+
+/// BIP143 style digest with fork id, as used by bitcoin cash
+fn signature_hash(tx: &Transaction, index: usize, script_code: &Script, value: u64) -> [u8; 32] {
+    let mut prevouts = Vec::new();
+    let mut sequences = Vec::new();
+    for input in tx.input.iter() {
+        prevouts.extend(serialize(&input.previous_output));
+        sequences.extend(serialize(&input.sequence));
+    }
+
+    let mut outputs = Vec::new();
+    for output in tx.output.iter() {
+        outputs.extend(serialize(output));
+    }
+
+    let input = &tx.input[index];
+    let mut preimage = Vec::new();
+    preimage.extend(tx.version.to_le_bytes());
+    preimage.extend(sha256d::Hash::hash(&prevouts).to_byte_array());
+    preimage.extend(sha256d::Hash::hash(&sequences).to_byte_array());
+    preimage.extend(serialize(&input.previous_output));
+    preimage.extend(serialize(script_code));
+    preimage.extend(value.to_le_bytes());
+    preimage.extend(serialize(&input.sequence));
+    preimage.extend(sha256d::Hash::hash(&outputs).to_byte_array());
+    preimage.extend(tx.lock_time.0.to_le_bytes());
+    preimage.extend(SIGHASH_ALL_FORKID.to_le_bytes());
+
+    sha256d::Hash::hash(&preimage).to_byte_array()
+}
+
+/// Sign every input of `tx` assuming all of them are p2pkh owned by `key`.
+/// `values` are the amount of each spent output, in input order
+pub fn sign_inputs(tx: &mut Transaction, key: &PrivateKey, values: &[u64]) {
+    let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+    let pubkey = key.public_key(&secp);
+    let script_code = Script::new_p2pkh(&pubkey.pubkey_hash());
+
+    let mut script_sigs = Vec::new();
+    for (index, value) in values.iter().enumerate() {
+        let hash = signature_hash(tx, index, &script_code, *value);
+        let message = bitcoincash::secp256k1::Message::from_slice(&hash).unwrap();
+        let mut sig = secp
+            .sign_ecdsa(&message, &key.inner)
+            .serialize_der()
+            .to_vec();
+        sig.push(SIGHASH_ALL_FORKID as u8);
+
+        script_sigs.push(
+            Builder::new()
+                .push_slice(&sig)
+                .push_key(&pubkey)
+                .into_script(),
+        );
+    }
+
+    for (input, script_sig) in tx.input.iter_mut().zip(script_sigs) {
+        input.script_sig = script_sig;
+    }
+}
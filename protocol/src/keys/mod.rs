@@ -1,19 +1,26 @@
-use std::fmt::Debug;
+use std::{
+    fmt::Debug,
+    ptr,
+    sync::atomic::{self, Ordering},
+};
 
+use bitcoin_hashes::{sha256, Hash, HashEngine};
 use serde::{Deserialize, Serialize};
 use sigma_fun::{
     ed25519::curve25519_dalek::scalar::Scalar, ext::dl_secp256k1_ed25519_eq::CrossCurveDLEQProof,
 };
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
 use self::bitcoin::random_private_key;
 use crate::{
     proof,
+    redact::{Redacted, Reveal, REDACTED},
     utils::{monero_private_key, monero_public_key},
 };
 
 pub mod bitcoin;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct KeyPrivate {
     #[serde(with = "monero_private_key")]
     pub monero_spend: monero::PrivateKey,
@@ -35,6 +42,107 @@ impl KeyPrivate {
     }
 }
 
+/// Neither key type has a zeroize impl, they are overwritten with valid
+/// keys instead. Volatile so the writes survive the drop
+impl Zeroize for KeyPrivate {
+    fn zeroize(&mut self) {
+        let zero = monero::PrivateKey::from_slice(&[0; 32]).expect("canonical scalar");
+        unsafe {
+            ptr::write_volatile(&mut self.monero_spend, zero);
+            ptr::write_volatile(&mut self.monero_view, zero);
+            ptr::write_volatile(&mut self.ves.inner, bitcoincash::secp256k1::ONE_KEY);
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Drop for KeyPrivate {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for KeyPrivate {}
+
+impl Debug for KeyPrivate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyPrivate")
+            .field("monero_spend", &Redacted)
+            .field("monero_view", &Redacted)
+            .field("ves", &Redacted)
+            .finish()
+    }
+}
+
+impl Reveal for KeyPrivate {
+    fn fmt_revealed(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "KeyPrivate {{\n\
+                \tmonero_spend: monero::PrivateKey({}),\n\
+                \tmonero_view: monero::PrivateKey({}),\n\
+                \tves: bitcoincash::PrivateKey({}),\n\
+            }} \n\
+            ",
+            self.monero_spend, self.monero_view, self.ves
+        )
+    }
+}
+
+/// Monero wallet generated for a single swap, receiving Bob's payout sweep
+/// instead of one of the user's. See `SwapBuilder::fresh_xmr_payout`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PayoutKeys {
+    #[serde(with = "monero_private_key")]
+    pub spend: monero::PrivateKey,
+    #[serde(with = "monero_private_key")]
+    pub view: monero::PrivateKey,
+}
+
+impl PayoutKeys {
+    pub fn random() -> Self {
+        let mut rng = rand::thread_rng();
+        let spend = Scalar::random(&mut rng);
+        let view = Scalar::random(&mut rng);
+        PayoutKeys {
+            spend: monero::PrivateKey::from_slice(spend.as_bytes()).unwrap(),
+            view: monero::PrivateKey::from_slice(view.as_bytes()).unwrap(),
+        }
+    }
+
+    pub fn keypair(&self) -> monero::KeyPair {
+        monero::KeyPair {
+            view: self.view,
+            spend: self.spend,
+        }
+    }
+
+    pub fn address(&self, network: monero::Network) -> monero::Address {
+        monero::Address::from_keypair(network, &self.keypair())
+    }
+}
+
+/// See `KeyPrivate`
+impl Drop for PayoutKeys {
+    fn drop(&mut self) {
+        let zero = monero::PrivateKey::from_slice(&[0; 32]).expect("canonical scalar");
+        unsafe {
+            ptr::write_volatile(&mut self.spend, zero);
+            ptr::write_volatile(&mut self.view, zero);
+        }
+        atomic::compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Debug for PayoutKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PayoutKeys")
+            .field("spend", &Redacted)
+            .field("view", &Redacted)
+            .finish()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct KeyPublic {
     #[serde(with = "monero_public_key")]
@@ -47,8 +155,8 @@ pub struct KeyPublic {
     pub proof: CrossCurveDLEQProof,
 }
 
-impl From<KeyPrivate> for KeyPublic {
-    fn from(value: KeyPrivate) -> Self {
+impl From<&KeyPrivate> for KeyPublic {
+    fn from(value: &KeyPrivate) -> Self {
         let (proof, (spend_bch, _)) = proof::prove(&value.monero_spend);
         let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
         KeyPublic {
@@ -61,8 +169,38 @@ impl From<KeyPrivate> for KeyPublic {
     }
 }
 
+impl KeyPublic {
+    /// Sent ahead of the keys when `Swap::key_commitment` is set, so neither
+    /// side can pick its keys after seeing the other's
+    pub fn commitment(&self) -> [u8; 32] {
+        let mut engine = sha256::Hash::engine();
+        engine.input(self.monero_spend.as_bytes());
+        engine.input(self.monero_view.as_bytes());
+        engine.input(&self.ves.inner.serialize());
+        engine.input(&self.spend_bch.inner.serialize());
+        sha256::Hash::from_engine(engine).to_byte_array()
+    }
+}
+
+/// The monero view half is secret, only the counterparty gets it
 impl Debug for KeyPublic {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "KeyPublic {{\n\
+                \tmonero_spend: monero::PublicKey({}),\n\
+                \tmonero_view: {REDACTED},\n\
+                \tves: bitcoincash::PublicKey({}),\n\
+                \tspend_bch: bitcoincash::PublicKey({}),\n\
+            }} \n\
+            ",
+            self.monero_spend, self.ves, self.spend_bch
+        )
+    }
+}
+
+impl Reveal for KeyPublic {
+    fn fmt_revealed(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
             "KeyPublic {{\n\
@@ -77,7 +215,7 @@ impl Debug for KeyPublic {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct KeyPublicWithoutProof {
     #[serde(with = "monero_public_key")]
     pub monero_spend: monero::PublicKey,
@@ -88,6 +226,17 @@ pub struct KeyPublicWithoutProof {
     pub spend_bch: bitcoincash::PublicKey,
 }
 
+impl Debug for KeyPublicWithoutProof {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyPublicWithoutProof")
+            .field("monero_spend", &self.monero_spend)
+            .field("monero_view", &Redacted)
+            .field("ves", &self.ves)
+            .field("spend_bch", &self.spend_bch)
+            .finish()
+    }
+}
+
 impl From<KeyPublic> for KeyPublicWithoutProof {
     fn from(value: KeyPublic) -> Self {
         KeyPublicWithoutProof {
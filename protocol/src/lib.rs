@@ -1,15 +1,40 @@
 // #![allow(dead_code, unused_imports, unused_variables)]
 
+pub mod accounting;
 pub mod adaptor_signature;
 pub mod alice;
 pub mod blockchain;
 pub mod bob;
+pub mod clock;
 pub mod contract;
+pub mod error;
 pub mod keys;
+pub mod manager;
+pub mod monero_config;
+pub mod monerod;
+pub mod outbound;
+pub mod outcome;
+pub mod peer;
 pub mod persist;
+pub mod presigned;
+pub mod progress;
 pub mod proof;
 pub mod protocol;
+pub mod redact;
+pub mod runner;
+pub mod shutdown;
+pub mod statechart;
+pub mod transcript;
+#[cfg(test)]
+mod transition_matrix;
 pub(crate) mod utils;
+pub mod wallet_pool;
+pub mod wallet_rpc;
+pub mod watchdog;
+pub mod xmr_scanner;
+#[cfg(feature = "serai")]
+pub mod xmr_serai;
+pub mod xmr_wallet;
 
 pub use bitcoincash;
 pub use monero;
@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::{Mutex, Semaphore},
+    task::JoinSet,
+};
+
+use crate::{
+    alice,
+    blockchain::{backend::ChainBackend, policy::MempoolPolicy},
+    bob,
+    error::SwapError,
+    monerod::MonerodPool,
+    persist::SwapStore,
+    protocol::{Event, EventSink, SignedTransition, SwapWrapper},
+    shutdown::Shutdown,
+    wallet_pool::WalletPool,
+    watchdog::Watchdog,
+    xmr_wallet::LockOptions,
+};
+
+/// Limits on the resources shared by the trades of a `SwapManager`
+#[derive(Debug, Clone)]
+pub struct ManagerOptions {
+    pub min_bch_conf: u32,
+    pub mempool_policy: Option<MempoolPolicy>,
+    pub watchdog: Watchdog,
+    pub xmr_lock_options: LockOptions,
+    /// Between two rounds of checks of every swap, see `SwapManager::run`
+    pub check_interval: Duration,
+    /// Swaps scanning the BCH chain at once
+    pub max_bch_checks: usize,
+    /// Swaps checking their XMR lock at once. A wallet instance serves one
+    /// trade at a time on top of it
+    pub max_xmr_checks: usize,
+}
+
+impl Default for ManagerOptions {
+    fn default() -> Self {
+        ManagerOptions {
+            min_bch_conf: 1,
+            mempool_policy: Some(MempoolPolicy::default()),
+            watchdog: Watchdog::default(),
+            xmr_lock_options: LockOptions::default(),
+            check_interval: Duration::from_secs(20),
+            max_bch_checks: 8,
+            max_xmr_checks: 4,
+        }
+    }
+}
+
+struct Managed {
+    /// `Swap::swap_id` once Msg0 was applied, messages may be addressed by it
+    swap_id: Option<String>,
+    /// Held while the trade is loaded, a message and a check never run on
+    /// the same checkpoint at once
+    lock: Arc<Mutex<()>>,
+}
+
+/// Runs many trades of either side over one set of connections and one
+/// `SwapStore`: the trades live in the store, each message or check loads
+/// its checkpoint, runs the runner and saves it back.
+/// `run` checks them all periodically, `deliver` routes peer messages
+pub struct SwapManager {
+    bch: Arc<dyn ChainBackend>,
+    monerod: MonerodPool,
+    monero_wallets: WalletPool,
+    /// Funded wallet sending Alice's XMR locks, she asks the user without it
+    xmr_lock_wallet: Option<Mutex<monero_rpc::WalletClient>>,
+    store: Box<dyn SwapStore>,
+    events: Option<Box<dyn EventSink>>,
+    pub options: ManagerOptions,
+    /// By trade id, the unfinished ones
+    swaps: StdMutex<HashMap<String, Managed>>,
+    bch_checks: Semaphore,
+    xmr_checks: Semaphore,
+}
+
+fn trade_id(swap: &SwapWrapper) -> &str {
+    match swap {
+        SwapWrapper::Alice(alice) => &alice.swap.id,
+        SwapWrapper::Bob(bob) => &bob.swap.id,
+    }
+}
+
+fn swap_id(swap: &SwapWrapper) -> Option<String> {
+    match swap {
+        SwapWrapper::Alice(alice) => alice.swap.swap_id.clone(),
+        SwapWrapper::Bob(bob) => bob.swap.swap_id.clone(),
+    }
+}
+
+fn is_finished(swap: &SwapWrapper) -> bool {
+    match swap {
+        SwapWrapper::Alice(alice) => alice.progress().is_finished(),
+        SwapWrapper::Bob(bob) => bob.progress().is_finished(),
+    }
+}
+
+impl SwapManager {
+    pub fn new(
+        bch: Arc<dyn ChainBackend>,
+        monerod: MonerodPool,
+        monero_wallets: WalletPool,
+        store: Box<dyn SwapStore>,
+        options: ManagerOptions,
+    ) -> Self {
+        SwapManager {
+            bch,
+            monerod,
+            monero_wallets,
+            xmr_lock_wallet: None,
+            store,
+            events: None,
+            bch_checks: Semaphore::new(options.max_bch_checks.max(1)),
+            xmr_checks: Semaphore::new(options.max_xmr_checks.max(1)),
+            options,
+            swaps: StdMutex::default(),
+        }
+    }
+
+    pub fn with_xmr_lock_wallet(mut self, wallet: monero_rpc::WalletClient) -> Self {
+        self.xmr_lock_wallet = Some(Mutex::new(wallet));
+        self
+    }
+
+    /// Events of every trade, only `run` prints the XMR lock ones without
+    pub fn with_events(mut self, events: Box<dyn EventSink>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Take over a new trade or one resumed from the store, checkpointed
+    /// right away
+    pub async fn add(&self, swap: SwapWrapper) -> Result<(), SwapError> {
+        let trade_id = trade_id(&swap).to_owned();
+        self.save(&trade_id, &swap).await?;
+        self.swaps.lock().unwrap().insert(
+            trade_id,
+            Managed {
+                swap_id: swap_id(&swap),
+                lock: Arc::default(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Trades still running
+    pub fn trade_ids(&self) -> Vec<String> {
+        self.swaps.lock().unwrap().keys().cloned().collect()
+    }
+
+    /// Last checkpoint of the trade `id`
+    pub async fn swap(&self, id: &str) -> Result<SwapWrapper, SwapError> {
+        let (trade_id, lock) = self.find(id)?;
+        let _guard = lock.lock().await;
+        self.load(&trade_id).await
+    }
+
+    /// Trade addressed by its trade id or its `Swap::swap_id`
+    fn find(&self, id: &str) -> Result<(String, Arc<Mutex<()>>), SwapError> {
+        let swaps = self.swaps.lock().unwrap();
+        swaps
+            .iter()
+            .find(|(trade_id, managed)| *trade_id == id || managed.swap_id.as_deref() == Some(id))
+            .map(|(trade_id, managed)| (trade_id.clone(), managed.lock.clone()))
+            .ok_or_else(|| SwapError::UnknownSwap(id.to_owned()))
+    }
+
+    async fn load(&self, trade_id: &str) -> Result<SwapWrapper, SwapError> {
+        let loaded = self.store.load(trade_id).await;
+        loaded
+            .map_err(|e| SwapError::Store(e.into()))?
+            .ok_or_else(|| SwapError::NoCheckpoint(trade_id.to_owned()))
+    }
+
+    async fn save(&self, trade_id: &str, swap: &SwapWrapper) -> Result<(), SwapError> {
+        let saved = self.store.save(trade_id, swap).await;
+        saved.map_err(|e| SwapError::Store(e.into()))
+    }
+
+    /// Saves the trade back, and lets go of it once finished
+    async fn settle(&self, trade_id: &str, swap: SwapWrapper) -> Result<(), SwapError> {
+        self.save(trade_id, &swap).await?;
+        let mut swaps = self.swaps.lock().unwrap();
+        if is_finished(&swap) {
+            swaps.remove(trade_id);
+        } else if let Some(managed) = swaps.get_mut(trade_id) {
+            managed.swap_id = swap_id(&swap);
+        }
+        Ok(())
+    }
+
+    fn alice_runner(&self, inner: alice::Alice) -> alice::Runner<'_> {
+        alice::Runner {
+            inner,
+            bch: self.bch.as_ref(),
+            monero_wallet: self.xmr_lock_wallet.as_ref(),
+            xmr_lock_options: self.options.xmr_lock_options,
+            min_bch_conf: self.options.min_bch_conf,
+            zero_conf: false,
+            mempool_policy: self.options.mempool_policy.clone(),
+            store: Some(self.store.as_ref()),
+            events: self.events.as_deref(),
+            effects: None,
+            clock: None,
+        }
+    }
+
+    fn bob_runner(&self, trade_id: &str, inner: bob::Bob) -> bob::Runner<'_> {
+        bob::Runner {
+            inner,
+            trade_id: trade_id.to_owned(),
+            bch: self.bch.as_ref(),
+            monerod: &self.monerod,
+            monero_wallet: self.monero_wallets.get(trade_id),
+            xmr_scanner: None,
+            min_bch_conf: self.options.min_bch_conf,
+            mempool_policy: self.options.mempool_policy.clone(),
+            store: Some(self.store.as_ref()),
+            events: self.events.as_deref(),
+            effects: None,
+            clock: None,
+        }
+    }
+
+    /// Peer message for the trade `id`, its trade id or `Swap::swap_id`
+    pub async fn deliver(&self, id: &str, signed: SignedTransition) -> Result<(), SwapError> {
+        let (trade_id, lock) = self.find(id)?;
+        let _guard = lock.lock().await;
+
+        let swap = match self.load(&trade_id).await? {
+            SwapWrapper::Alice(inner) => {
+                let mut runner = self.alice_runner(inner);
+                runner.pub_transition(signed).await?;
+                SwapWrapper::Alice(runner.inner)
+            }
+            SwapWrapper::Bob(inner) => {
+                let mut runner = self.bob_runner(&trade_id, inner);
+                runner.pub_transition(signed).await?;
+                SwapWrapper::Bob(runner.inner)
+            }
+        };
+        self.settle(&trade_id, swap).await
+    }
+
+    /// Our current message to the peer of trade `id`, see `outbound::Outbox`
+    pub async fn outbound(&self, id: &str) -> Result<Option<SignedTransition>, SwapError> {
+        let (trade_id, lock) = self.find(id)?;
+        let _guard = lock.lock().await;
+        Ok(match self.load(&trade_id).await? {
+            SwapWrapper::Alice(alice) => alice.get_signed_transition(),
+            SwapWrapper::Bob(bob) => bob.get_signed_transition(),
+        })
+    }
+
+    /// Scan both chains for the trade and back out past the watchdog.
+    /// Returns the XMR lock events, a failed XMR check as `Event::Error`:
+    /// the BCH chain is still watched for the claim and the refund
+    pub async fn check(&self, trade_id: &str) -> Result<Vec<Event>, SwapError> {
+        let (trade_id, lock) = self.find(trade_id)?;
+        let _guard = lock.lock().await;
+
+        let mut events = Vec::new();
+        let swap = match self.load(&trade_id).await? {
+            SwapWrapper::Alice(inner) => {
+                let mut runner = self.alice_runner(inner);
+                let permit = self.bch_checks.acquire().await;
+                runner.check_bch().await?;
+                drop(permit);
+                events.extend(runner.check_deadlines(&self.options.watchdog).await?);
+                SwapWrapper::Alice(runner.inner)
+            }
+            SwapWrapper::Bob(inner) => {
+                let mut runner = self.bob_runner(&trade_id, inner);
+                let permit = self.xmr_checks.acquire().await;
+                match runner.check_xmr().await {
+                    Ok(xmr) => events.extend(xmr),
+                    Err(e) => events.push(Event::Error {
+                        message: e.to_string(),
+                    }),
+                }
+                drop(permit);
+                let permit = self.bch_checks.acquire().await;
+                runner.check_bch().await?;
+                drop(permit);
+                events.extend(runner.check_deadlines(&self.options.watchdog).await?);
+                SwapWrapper::Bob(runner.inner)
+            }
+        };
+        self.settle(&trade_id, swap).await?;
+        Ok(events)
+    }
+
+    /// `check` of every trade at once, within the limits of `options`
+    pub async fn check_all(self: &Arc<Self>) -> Vec<(String, Result<Vec<Event>, SwapError>)> {
+        let mut checks = JoinSet::new();
+        for trade_id in self.trade_ids() {
+            let manager = self.clone();
+            checks.spawn(async move {
+                let checked = manager.check(&trade_id).await;
+                (trade_id, checked)
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(checked) = checks.join_next().await {
+            match checked {
+                Ok(checked) => results.push(checked),
+                Err(e) => eprintln!("Swap check failed: {e}"),
+            }
+        }
+        results
+    }
+
+    /// `check_all` every `check_interval` until `shutdown`, letting the round
+    /// in flight finish
+    pub async fn run(self: Arc<Self>, shutdown: Shutdown) {
+        loop {
+            for (trade_id, checked) in self.check_all().await {
+                match checked {
+                    Ok(events) => events
+                        .into_iter()
+                        .for_each(|event| println!("[{trade_id}]: {event}")),
+                    Err(e) => eprintln!("[{trade_id}]: Check failed: {e}"),
+                }
+            }
+            if shutdown.sleep(self.options.check_interval).await {
+                break;
+            }
+        }
+    }
+}
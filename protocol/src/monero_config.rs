@@ -0,0 +1,185 @@
+use std::{env, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    monerod::{rpc_client, MoneroError, MonerodPool, RpcLogin, RpcOptions, MAX_TIP_AGE},
+    wallet_rpc::WalletRpcConfig,
+    xmr_scanner::{XmrScanError, XmrScanner},
+};
+
+/// Network the Monero side of the swaps runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneroNetwork {
+    Mainnet,
+    Stagenet,
+    Testnet,
+    /// `monerod --regtest`, addresses are mainnet ones
+    Regtest,
+}
+
+impl MoneroNetwork {
+    /// Of the addresses, saved in `Swap::xmr_network`
+    pub fn network(&self) -> monero::Network {
+        match self {
+            MoneroNetwork::Mainnet | MoneroNetwork::Regtest => monero::Network::Mainnet,
+            MoneroNetwork::Stagenet => monero::Network::Stagenet,
+            MoneroNetwork::Testnet => monero::Network::Testnet,
+        }
+    }
+
+    /// monerod default rpc on localhost
+    pub fn default_daemon(&self) -> &'static str {
+        match self {
+            MoneroNetwork::Mainnet | MoneroNetwork::Regtest => "http://localhost:18081",
+            MoneroNetwork::Stagenet => "http://localhost:38081",
+            MoneroNetwork::Testnet => "http://localhost:28081",
+        }
+    }
+
+    /// Passed to monero-wallet-rpc
+    pub fn wallet_rpc_args(&self) -> &'static [&'static str] {
+        match self {
+            MoneroNetwork::Mainnet => &[],
+            MoneroNetwork::Stagenet => &["--stagenet"],
+            MoneroNetwork::Testnet => &["--testnet"],
+            MoneroNetwork::Regtest => &["--allow-mismatched-daemon-version"],
+        }
+    }
+}
+
+impl FromStr for MoneroNetwork {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "mainnet" => Ok(MoneroNetwork::Mainnet),
+            "stagenet" => Ok(MoneroNetwork::Stagenet),
+            "testnet" => Ok(MoneroNetwork::Testnet),
+            "regtest" => Ok(MoneroNetwork::Regtest),
+            _ => Err(anyhow!("Invalid monero network {s}")),
+        }
+    }
+}
+
+/// Where and how the Monero side is reached, shared by the runners of a process
+#[derive(Debug, Clone)]
+pub struct MoneroConfig {
+    pub network: MoneroNetwork,
+    /// By preference, see `MonerodPool`
+    pub daemon_urls: Vec<String>,
+    /// monero-wallet-rpc trusts the daemon with the wallet activity, only for own nodes
+    pub trusted_daemon: bool,
+    /// Of monero-wallet-rpc, where the trade wallets are created
+    pub wallet_dir: PathBuf,
+    /// SOCKS5 `host:port`, see `RpcOptions`
+    pub proxy: Option<String>,
+    /// `--rpc-login` of the daemons
+    pub daemon_login: Option<RpcLogin>,
+    /// `--rpc-login` of monero-wallet-rpc
+    pub wallet_login: Option<RpcLogin>,
+}
+
+impl MoneroConfig {
+    pub fn new(network: MoneroNetwork) -> Self {
+        MoneroConfig {
+            network,
+            daemon_urls: vec![network.default_daemon().to_owned()],
+            trusted_daemon: false,
+            wallet_dir: PathBuf::from("wallet_dir"),
+            proxy: None,
+            daemon_login: None,
+            wallet_login: None,
+        }
+    }
+
+    /// `MONERO_NETWORK` (mainnet by default), `MONEROD` (comma separated urls),
+    /// `MONERO_TRUSTED_DAEMON`, `MONERO_WALLET_DIR`, `SOCKS5_PROXY`,
+    /// `MONEROD_LOGIN` and `MONERO_WALLET_RPC_LOGIN` (`username:password`)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let network = match env::var("MONERO_NETWORK") {
+            Ok(network) => network.parse()?,
+            Err(_) => MoneroNetwork::Mainnet,
+        };
+
+        let mut config = MoneroConfig::new(network);
+        if let Ok(urls) = env::var("MONEROD") {
+            config.daemon_urls = urls.split(',').map(String::from).collect();
+        }
+        if let Ok(trusted) = env::var("MONERO_TRUSTED_DAEMON") {
+            config.trusted_daemon = trusted.parse().context("MONERO_TRUSTED_DAEMON")?;
+        }
+        if let Ok(wallet_dir) = env::var("MONERO_WALLET_DIR") {
+            config.wallet_dir = wallet_dir.into();
+        }
+        config.proxy = env::var("SOCKS5_PROXY").ok();
+        if let Ok(login) = env::var("MONEROD_LOGIN") {
+            config.daemon_login = Some(login.parse().context("MONEROD_LOGIN")?);
+        }
+        if let Ok(login) = env::var("MONERO_WALLET_RPC_LOGIN") {
+            config.wallet_login = Some(login.parse().context("MONERO_WALLET_RPC_LOGIN")?);
+        }
+
+        Ok(config)
+    }
+
+    /// Preferred daemon
+    pub fn daemon_url(&self) -> &str {
+        &self.daemon_urls[0]
+    }
+
+    pub fn daemon_options(&self) -> RpcOptions {
+        RpcOptions {
+            proxy: self.proxy.clone(),
+            login: self.daemon_login.clone(),
+        }
+    }
+
+    pub fn wallet_options(&self) -> RpcOptions {
+        RpcOptions {
+            proxy: self.proxy.clone(),
+            login: self.wallet_login.clone(),
+        }
+    }
+
+    pub fn monerod(&self) -> Result<MonerodPool, MoneroError> {
+        let max_tip_age = match self.network {
+            MoneroNetwork::Regtest => None,
+            _ => Some(MAX_TIP_AGE),
+        };
+        Ok(
+            MonerodPool::new(self.daemon_urls.clone(), &self.daemon_options())?
+                .with_max_tip_age(max_tip_age),
+        )
+    }
+
+    /// Scanner of the preferred daemon
+    pub fn scanner(&self) -> Result<XmrScanner, XmrScanError> {
+        XmrScanner::new(self.daemon_url().to_owned(), &self.daemon_options())
+    }
+
+    /// monero-wallet-rpc at `url`
+    pub fn wallet(&self, url: &str) -> Result<monero_rpc::WalletClient, MoneroError> {
+        Ok(rpc_client(url, &self.wallet_options())?.wallet())
+    }
+
+    /// monero-wallet-rpc managed by `WalletRpcManager`, on the preferred daemon
+    pub fn wallet_rpc(&self, binary: PathBuf, port: u16) -> WalletRpcConfig {
+        WalletRpcConfig {
+            binary,
+            daemon_address: self.daemon_url().to_owned(),
+            port,
+            wallet_dir: self.wallet_dir.clone(),
+            trusted_daemon: self.trusted_daemon,
+            proxy: self.proxy.clone(),
+            daemon_login: self.daemon_login.clone(),
+            login: self.wallet_login.clone(),
+            extra_args: self
+                .network
+                .wallet_rpc_args()
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect(),
+        }
+    }
+}
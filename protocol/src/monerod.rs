@@ -0,0 +1,478 @@
+use std::{
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use diqwest::WithDigestAuth;
+use serde_json::{json, Value};
+use tokio::time::timeout;
+
+use crate::{
+    error::{Classify, ErrorClass},
+    xmr_scanner::XmrScanError,
+};
+
+/// A daemon further behind the highest one is not used
+const MAX_LAG: u64 = 2;
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Daemons are checked again after this long
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// A tip older than this is a daemon stuck or cut from its peers,
+/// a block is expected every 2 minutes
+pub const MAX_TIP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Failures reaching or using monerod and monero-wallet-rpc
+#[derive(Debug, thiserror::Error)]
+pub enum MoneroError {
+    /// From the monero-rpc client or the http layer
+    #[error("monero rpc: {0}")]
+    Rpc(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// monerod answered without the expected result
+    #[error("monerod {url}: {response}")]
+    InvalidResponse { url: String, response: String },
+    #[error("no monerod url")]
+    NoDaemon,
+    /// None is reachable, synced and close to the others
+    #[error("no usable monerod")]
+    NoUsableDaemon,
+    /// The daemon height moved under the request, usually a reorg
+    #[error("no block at height {0}, above the tip")]
+    AboveTip(u64),
+    /// A transfer deeper than the daemon's chain, read from a wallet ahead
+    /// of a lagging daemon
+    #[error("monerod block count {block_count} is below {confirmations} confirmations")]
+    BehindWallet {
+        block_count: u64,
+        confirmations: u64,
+    },
+    #[error("expected username:password")]
+    InvalidLogin,
+    #[error(transparent)]
+    Scan(#[from] XmrScanError),
+    #[error("unlocked balance {unlocked} can't cover {amount}")]
+    InsufficientBalance {
+        unlocked: monero::Amount,
+        amount: monero::Amount,
+    },
+    #[error("unlocked balance {unlocked} can't cover {amount} plus fee {fee}")]
+    FeeNotCovered {
+        unlocked: monero::Amount,
+        amount: monero::Amount,
+        fee: monero::Amount,
+    },
+    /// The wallet refused to build the transfer, usually for its fee
+    #[error("unlocked balance {unlocked} can't cover {amount} plus fee: {source}")]
+    TransferFailed {
+        unlocked: monero::Amount,
+        amount: monero::Amount,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error("invalid recovery document: {0}")]
+    InvalidRecovery(String),
+    /// Of the wallet files
+    #[error("wallet files: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl Classify for MoneroError {
+    /// Balances still unlocking count as recoverable
+    fn class(&self) -> ErrorClass {
+        match self {
+            MoneroError::NoDaemon | MoneroError::InvalidLogin | MoneroError::InvalidRecovery(_) => {
+                ErrorClass::Fatal
+            }
+            _ => ErrorClass::Recoverable,
+        }
+    }
+}
+
+impl From<anyhow::Error> for MoneroError {
+    fn from(value: anyhow::Error) -> Self {
+        MoneroError::Rpc(value.into())
+    }
+}
+
+impl From<reqwest::Error> for MoneroError {
+    fn from(value: reqwest::Error) -> Self {
+        MoneroError::Rpc(value.into())
+    }
+}
+
+/// Height of the block holding a transfer with `confirmations`
+pub(crate) fn transfer_height(block_count: u64, confirmations: u64) -> Result<u64, MoneroError> {
+    block_count
+        .checked_sub(confirmations)
+        .ok_or(MoneroError::BehindWallet {
+            block_count,
+            confirmations,
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DaemonStatus {
+    /// Block count
+    pub height: u64,
+    /// Height announced by the peers, 0 when there is none
+    pub target_height: u64,
+    /// Unix time of the tip
+    pub tip_timestamp: u64,
+    /// Reported by monerod. Also true when `--offline`, the tip age tells
+    /// a daemon cut from the network
+    pub synchronized: bool,
+}
+
+impl DaemonStatus {
+    /// Heights and balances read from the daemon can be trusted.
+    /// The tip age is not checked when `max_tip_age` is None
+    pub fn is_synced(&self, now: u64, max_tip_age: Option<Duration>) -> bool {
+        let fresh = match max_tip_age {
+            Some(max_tip_age) => now.saturating_sub(self.tip_timestamp) <= max_tip_age.as_secs(),
+            None => true,
+        };
+        self.synchronized && self.height >= self.target_height && fresh
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+struct Selection {
+    current: usize,
+    checked_at: Option<Instant>,
+}
+
+/// Hosts reached without the proxy, Tor refuses to connect to them
+const LOCAL_HOSTS: &str = "localhost,127.0.0.1,::1";
+
+fn is_local(url: &str) -> bool {
+    let host = url.split("://").last().unwrap_or(url);
+    let host = host.split(['/', '?']).next().unwrap_or(host);
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.contains(']') => host,
+        _ => host,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    LOCAL_HOSTS.split(',').any(|local| local == host)
+}
+
+/// `--rpc-login` of monerod and monero-wallet-rpc, sent with http digest auth
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcLogin {
+    pub username: String,
+    pub password: String,
+}
+
+impl FromStr for RpcLogin {
+    type Err = MoneroError;
+
+    /// `username:password`, same as `--rpc-login`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((username, password)) => Ok(RpcLogin {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            }),
+            None => Err(MoneroError::InvalidLogin),
+        }
+    }
+}
+
+/// How a Monero rpc is reached. TLS is used with https:// urls (`--rpc-ssl`),
+/// the certificate is checked against the system roots
+#[derive(Debug, Clone, Default)]
+pub struct RpcOptions {
+    /// SOCKS5 `host:port`, local urls bypass it
+    pub proxy: Option<String>,
+    pub login: Option<RpcLogin>,
+}
+
+/// monero-rpc client of `url`
+pub fn rpc_client(url: &str, options: &RpcOptions) -> Result<monero_rpc::RpcClient, MoneroError> {
+    let mut builder = monero_rpc::RpcClientBuilder::new();
+    if let Some(proxy) = options.proxy.as_ref().filter(|_| !is_local(url)) {
+        builder = builder.proxy_address(format!("socks5h://{proxy}"));
+    }
+    if let Some(login) = &options.login {
+        builder = builder.rpc_authentication(monero_rpc::RpcAuthentication::Credentials {
+            username: login.username.clone(),
+            password: login.password.clone(),
+        });
+    }
+    Ok(builder.build(url)?)
+}
+
+/// Same as `rpc_client` for the raw json rpc calls, see `post_json`
+pub fn http_client(options: &RpcOptions) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(
+            // socks5h: the proxy resolves hostnames
+            reqwest::Proxy::all(format!("socks5h://{proxy}"))?
+                .no_proxy(reqwest::NoProxy::from_string(LOCAL_HOSTS)),
+        );
+    }
+    builder.build()
+}
+
+/// POST `payload` to `url`, answering the digest challenge when `login` is given
+pub async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    login: Option<&RpcLogin>,
+    payload: &Value,
+) -> anyhow::Result<reqwest::Response> {
+    let request = client.post(url).json(payload);
+    Ok(match login {
+        Some(login) => {
+            request
+                .send_with_digest_auth(&login.username, &login.password)
+                .await?
+        }
+        None => request.send().await?,
+    })
+}
+
+/// Several monerod of the same network. The one in use is kept while it is
+/// reachable, synced and within `MAX_LAG` blocks of the others,
+/// otherwise the first usable one in configuration order replaces it
+pub struct MonerodPool {
+    urls: Vec<String>,
+    daemons: Vec<monero_rpc::DaemonJsonRpcClient>,
+    client: reqwest::Client,
+    login: Option<RpcLogin>,
+    max_tip_age: Option<Duration>,
+    selection: Mutex<Selection>,
+}
+
+impl MonerodPool {
+    /// `urls` of monerod e.g. http://localhost:18081, by preference
+    pub fn new(urls: Vec<String>, options: &RpcOptions) -> Result<Self, MoneroError> {
+        if urls.is_empty() {
+            return Err(MoneroError::NoDaemon);
+        }
+        let daemons = urls
+            .iter()
+            .map(|url| Ok(rpc_client(url, options)?.daemon()))
+            .collect::<Result<_, MoneroError>>()?;
+
+        Ok(MonerodPool {
+            urls,
+            daemons,
+            client: http_client(options)?,
+            login: options.login.clone(),
+            max_tip_age: Some(MAX_TIP_AGE),
+            selection: Mutex::new(Selection {
+                current: 0,
+                checked_at: None,
+            }),
+        })
+    }
+
+    /// Tip age allowed before a daemon is considered not synced, None to
+    /// disable the check e.g. on regtest where blocks are mined on demand
+    pub fn with_max_tip_age(mut self, max_tip_age: Option<Duration>) -> Self {
+        self.max_tip_age = max_tip_age;
+        self
+    }
+
+    async fn json_rpc(&self, url: &str, method: &str, params: Value) -> Result<Value, MoneroError> {
+        let mut response: Value = post_json(
+            &self.client,
+            &format!("{}/json_rpc", url.trim_end_matches('/')),
+            self.login.as_ref(),
+            &json!({"jsonrpc": "2.0", "id": "0", "method": method, "params": params}),
+        )
+        .await?
+        .json()
+        .await?;
+        if response["result"]["status"].as_str() != Some("OK") {
+            return Err(MoneroError::InvalidResponse {
+                url: url.to_owned(),
+                response: response.to_string(),
+            });
+        }
+        Ok(response["result"].take())
+    }
+
+    pub async fn status(&self, url: &str) -> Result<DaemonStatus, MoneroError> {
+        let info = self.json_rpc(url, "get_info", json!({})).await?;
+        let header = self
+            .json_rpc(url, "get_last_block_header", json!({}))
+            .await?;
+
+        Ok(DaemonStatus {
+            height: info["height"].as_u64().unwrap_or(0),
+            target_height: info["target_height"].as_u64().unwrap_or(0),
+            tip_timestamp: header["block_header"]["timestamp"].as_u64().unwrap_or(0),
+            synchronized: info["synchronized"].as_bool().unwrap_or(false),
+        })
+    }
+
+    /// Status of the daemon in use, or of the last used one when none is
+    /// synced. See `DaemonStatus::is_synced` with `synced`
+    pub async fn sync_status(&self) -> Result<DaemonStatus, MoneroError> {
+        let current = match self.current().await {
+            Ok(current) => current,
+            Err(_) => self.selection.lock().unwrap().current,
+        };
+        self.status(&self.urls[current]).await
+    }
+
+    /// `status` is synced, as required to pick a daemon
+    pub fn synced(&self, status: &DaemonStatus) -> bool {
+        status.is_synced(unix_now(), self.max_tip_age)
+    }
+
+    /// Check every daemon and switch when the current one is unusable.
+    /// Returns the index of the daemon in use
+    pub async fn check(&self) -> Result<usize, MoneroError> {
+        let mut heights = Vec::new();
+        for url in &self.urls {
+            let height = match timeout(CHECK_TIMEOUT, self.status(url)).await {
+                Ok(Ok(status)) if self.synced(&status) => Some(status.height),
+                _ => None,
+            };
+            heights.push(height);
+        }
+
+        let mut selection = self.selection.lock().unwrap();
+        let selected = match select(selection.current, &heights) {
+            Some(selected) => selected,
+            None => return Err(MoneroError::NoUsableDaemon),
+        };
+        if selected != selection.current {
+            eprintln!(
+                "monerod {} unreachable or lagging, switching to {}",
+                self.urls[selection.current], self.urls[selected]
+            );
+        }
+        selection.current = selected;
+        selection.checked_at = Some(Instant::now());
+
+        Ok(selected)
+    }
+
+    async fn current(&self) -> Result<usize, MoneroError> {
+        let (current, checked_at) = {
+            let selection = self.selection.lock().unwrap();
+            (selection.current, selection.checked_at)
+        };
+        match checked_at {
+            Some(checked_at) if checked_at.elapsed() < CHECK_INTERVAL => Ok(current),
+            _ => self.check().await,
+        }
+    }
+
+    /// Daemon in use, checked at most every `CHECK_INTERVAL`
+    pub async fn daemon(&self) -> Result<&monero_rpc::DaemonJsonRpcClient, MoneroError> {
+        Ok(&self.daemons[self.current().await?])
+    }
+
+    pub async fn url(&self) -> Result<&str, MoneroError> {
+        Ok(&self.urls[self.current().await?])
+    }
+
+    /// Hash of the block at `height` on the daemon in use, None above the tip
+    pub async fn block_hash(&self, height: u64) -> Result<Option<String>, MoneroError> {
+        if height >= self.block_count().await? {
+            return Ok(None);
+        }
+        let result = self
+            .json_rpc(
+                self.url().await?,
+                "get_block_header_by_height",
+                json!({ "height": height }),
+            )
+            .await?;
+        match result["block_header"]["hash"].as_str() {
+            Some(hash) => Ok(Some(hash.to_owned())),
+            None => Err(MoneroError::InvalidResponse {
+                url: self.url().await?.to_owned(),
+                response: result.to_string(),
+            }),
+        }
+    }
+
+    /// Block count of the daemon in use, another one answers if it fails
+    pub async fn block_count(&self) -> Result<u64, MoneroError> {
+        match self.daemon().await?.get_block_count().await {
+            Ok(count) => Ok(count.get()),
+            Err(_) => {
+                let current = self.check().await?;
+                Ok(self.daemons[current].get_block_count().await?.get())
+            }
+        }
+    }
+}
+
+/// Keep `current` when usable, else the first usable daemon.
+/// `heights` is None for unreachable or unsynchronized daemons
+fn select(current: usize, heights: &[Option<u64>]) -> Option<usize> {
+    let best = heights.iter().flatten().max()?;
+    let usable = |i: &usize| matches!(heights[*i], Some(height) if height + MAX_LAG >= *best);
+    match usable(&current) {
+        true => Some(current),
+        false => (0..heights.len()).find(usable),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{is_local, select, transfer_height, DaemonStatus, MoneroError};
+
+    #[test]
+    fn should_refuse_transfers_deeper_than_the_daemon() {
+        assert_eq!(transfer_height(100, 10).unwrap(), 90);
+        assert!(matches!(
+            transfer_height(5, 10),
+            Err(MoneroError::BehindWallet {
+                block_count: 5,
+                confirmations: 10
+            })
+        ));
+    }
+
+    #[test]
+    fn should_switch_from_lagging_daemon() {
+        assert_eq!(select(0, &[Some(100), Some(101)]), Some(0));
+        assert_eq!(select(0, &[Some(90), Some(100), Some(100)]), Some(1));
+        assert_eq!(select(1, &[None, Some(100)]), Some(1));
+        assert_eq!(select(0, &[None, Some(100)]), Some(1));
+        assert_eq!(select(0, &[None, None]), None);
+    }
+
+    #[test]
+    fn should_detect_syncing_daemon() {
+        let synced = DaemonStatus {
+            height: 100,
+            target_height: 0,
+            tip_timestamp: 1000,
+            synchronized: true,
+        };
+        let max_tip_age = Some(Duration::from_secs(600));
+        assert!(synced.is_synced(1500, max_tip_age));
+        // stale tip
+        assert!(!synced.is_synced(2000, max_tip_age));
+        assert!(synced.is_synced(2000, None));
+        let syncing = DaemonStatus {
+            target_height: 200,
+            ..synced
+        };
+        assert!(!syncing.is_synced(1500, max_tip_age));
+    }
+
+    #[test]
+    fn should_bypass_proxy_for_local_daemon() {
+        assert!(is_local("http://localhost:18081"));
+        assert!(is_local("http://[::1]:18081/json_rpc"));
+        assert!(!is_local("http://node.onion:18081"));
+        assert!(!is_local("http://localhost.example:18081"));
+    }
+}
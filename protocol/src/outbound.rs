@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+
+use crate::protocol::SignedTransition;
+
+/// Spacing of the resends of one message, doubling from `initial` up to `max_delay`
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub initial: Duration,
+    pub max_delay: Duration,
+    /// Sends of the same message before giving up on it, the `Watchdog`
+    /// backs out of the trade after that
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial: Duration::from_secs(5),
+            max_delay: Duration::from_secs(5 * 60),
+            max_attempts: 20,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait after the `attempt`th send
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// What `Outbox::poll` asks for
+#[derive(Debug, Clone)]
+pub enum Outbound {
+    Send(Box<SignedTransition>),
+    /// Nothing to send, or backing off
+    Idle,
+    /// The message went out `attempts` times without the state advancing.
+    /// Returned once, `Idle` after until the state changes
+    Exhausted {
+        transition: String,
+        attempts: u32,
+    },
+}
+
+/// Outbound message of one swap. The peer acknowledges by answering, which
+/// moves the state: until it does the same `get_signed_transition` is
+/// sent again with exponential backoff
+#[derive(Debug, Clone, Default)]
+pub struct Outbox {
+    pub policy: RetryPolicy,
+    /// State and transition of the message being sent
+    current: Option<(String, String)>,
+    attempts: u32,
+    next_at: Option<Instant>,
+}
+
+impl Outbox {
+    pub fn new(policy: RetryPolicy) -> Self {
+        Outbox {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// `state` the current state and `message` its `get_signed_transition`.
+    /// A new one is sent right away and counts as the first attempt
+    pub fn poll(
+        &mut self,
+        state: &str,
+        message: Option<SignedTransition>,
+        now: Instant,
+    ) -> Outbound {
+        let message = match message {
+            Some(message) => message,
+            None => {
+                *self = Outbox::new(self.policy);
+                return Outbound::Idle;
+            }
+        };
+
+        let key = (state.to_owned(), message.transition.to_string());
+        if self.current.as_ref() != Some(&key) {
+            *self = Outbox::new(self.policy);
+            self.current = Some(key);
+        }
+        if self.next_at.is_some_and(|next_at| now < next_at) {
+            return Outbound::Idle;
+        }
+        if self.attempts >= self.policy.max_attempts {
+            // the peer had the last backoff to answer
+            if self.next_at.take().is_some() {
+                return Outbound::Exhausted {
+                    transition: message.transition.to_string(),
+                    attempts: self.attempts,
+                };
+            }
+            return Outbound::Idle;
+        }
+
+        self.attempts += 1;
+        self.next_at = Some(now + self.policy.delay(self.attempts));
+        Outbound::Send(Box::new(message))
+    }
+
+    /// Sends of the current message so far
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use bitcoincash::Script;
+
+    use super::{Outbound, Outbox, RetryPolicy};
+    use crate::{
+        bob::Bob,
+        keys::bitcoin::{random_private_key, Network},
+        protocol::Swap,
+    };
+
+    #[test]
+    fn should_back_off_until_state_advances() {
+        let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+        let recv = random_private_key(Network::Regtest)
+            .public_key(&secp)
+            .pubkey_hash();
+        let swap = Swap::builder(
+            "outbox".to_owned(),
+            Network::Regtest,
+            monero::Network::Stagenet,
+        )
+        .amounts(
+            bitcoincash::Amount::from_sat(100000),
+            monero::Amount::from_pico(100000),
+        )
+        .timelocks(2, 4)
+        .bch_recv(Script::new_p2pkh(&recv))
+        .key_commitment(true)
+        .build()
+        .unwrap();
+        let bob = Bob::new(swap);
+        let message = bob.get_signed_transition();
+        assert!(message.is_some());
+        let mut outbox = Outbox::new(RetryPolicy {
+            initial: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+            max_attempts: 4,
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let state = bob.state.to_string();
+        let mut poll = |secs| match outbox.poll(&state, message.clone(), at(secs)) {
+            Outbound::Send(_) => "send",
+            Outbound::Idle => "idle",
+            Outbound::Exhausted { .. } => "exhausted",
+        };
+
+        // sent at 0, then after 1, 2 then 3 capped seconds
+        let polls: Vec<_> = (0..10).map(&mut poll).collect();
+        assert_eq!(
+            polls,
+            [
+                "send",
+                "send",
+                "idle",
+                "send",
+                "idle",
+                "idle",
+                "send",
+                "idle",
+                "idle",
+                "exhausted"
+            ]
+        );
+        assert_eq!(poll(100), "idle");
+
+        // the state advanced, its message goes out right away
+        assert!(matches!(
+            outbox.poll("BobState:WithAliceKey", message.clone(), at(100)),
+            Outbound::Send(_)
+        ));
+        assert_eq!(outbox.attempts(), 1);
+        assert!(matches!(
+            outbox.poll("BobState:ContractMatch", None, at(100)),
+            Outbound::Idle
+        ));
+        assert_eq!(outbox.attempts(), 0);
+    }
+}
@@ -0,0 +1,98 @@
+use bitcoincash::Transaction;
+use serde::{Deserialize, Serialize};
+
+use crate::contract::{ContractPair, TransactionType};
+
+/// How a finished swap ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OutcomeKind {
+    Success,
+    /// Bob took the BCH back, Alice the XMR
+    Refunded,
+    /// Bob didn't finish his refund, Alice took the BCH and the XMR stays locked
+    Punished,
+    Aborted,
+}
+
+/// Swap transactions seen or sent, saved with the trade
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SwapTxids {
+    /// SwapLock funding
+    pub lock: Option<String>,
+    pub xmr_lock: Option<String>,
+    /// Spends of the SwapLock to Alice, one per part of a split funding
+    pub claims: Vec<String>,
+    /// Moves to the Refund contract and its spends, by either side
+    pub refunds: Vec<String>,
+}
+
+impl SwapTxids {
+    /// Record `transaction` when it belongs to `contract`
+    pub fn observe(&mut self, contract: &ContractPair, transaction: &Transaction) {
+        let txid = transaction.txid().to_string();
+        // spends of the SwapLock tell the funding when it wasn't seen
+        let funding = || transaction.input[0].previous_output.txid.to_string();
+        match contract.analyze_tx(transaction) {
+            Some((_, TransactionType::ToSwapLock)) => {
+                self.lock.get_or_insert(txid);
+            }
+            Some((_, TransactionType::SwapLockToAlice)) => {
+                self.lock.get_or_insert_with(funding);
+                push_new(&mut self.claims, txid);
+            }
+            Some((_, TransactionType::ToRefund)) => {
+                self.lock.get_or_insert_with(funding);
+                push_new(&mut self.refunds, txid);
+            }
+            Some((_, TransactionType::ToBob)) | Some((_, TransactionType::RefundToAlice)) => {
+                push_new(&mut self.refunds, txid)
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn add_claims(&mut self, txs: &[Transaction]) {
+        for tx in txs {
+            push_new(&mut self.claims, tx.txid().to_string());
+        }
+    }
+
+    pub(crate) fn add_refunds(&mut self, txs: &[Transaction]) {
+        for tx in txs {
+            push_new(&mut self.refunds, tx.txid().to_string());
+        }
+    }
+}
+
+fn push_new(txids: &mut Vec<String>, txid: String) {
+    if !txids.contains(&txid) {
+        txids.push(txid);
+    }
+}
+
+/// Summary of a finished swap from our side, for display and bookkeeping.
+/// Amounts in sats and piconero, XMR amounts are the agreed ones
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SwapOutcome {
+    pub kind: OutcomeKind,
+    pub bch_sent: u64,
+    pub bch_received: u64,
+    pub xmr_sent: u64,
+    pub xmr_received: u64,
+    pub txids: SwapTxids,
+    /// BCH mining fees of the contract spends we made, the SwapLock funding and
+    /// the XMR transfers are paid by the wallets and not counted
+    pub bch_fees: u64,
+    /// From the trade creation to the final state, None for trades older than
+    /// the record
+    pub duration_secs: Option<u64>,
+}
+
+impl SwapOutcome {
+    pub(crate) fn duration_secs(started_at: u64, ended_at: u64) -> Option<u64> {
+        match started_at {
+            0 => None,
+            started_at => ended_at.checked_sub(started_at),
+        }
+    }
+}
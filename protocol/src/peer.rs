@@ -0,0 +1,172 @@
+use serde::{Deserialize, Serialize};
+
+/// Software identifier advertised to the counterparty on the handshake
+pub const SOFTWARE_VERSION: &str = concat!("bch-xmr-swap/", env!("CARGO_PKG_VERSION"));
+
+/// Contract versions this build is able to create and verify
+pub const CONTRACT_VERSIONS: [u8; 2] = [4, STRICT_CONTRACT_VERSION];
+
+/// SwapLock whose claims must spend exactly the swap amount, see
+/// `ContractOptions::strict`. Only advertised by swaps asking for it
+pub const STRICT_CONTRACT_VERSION: u8 = 5;
+
+/// Of this build. Peers of another major can't swap together,
+/// minors only add optional messages
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
+/// Bits of `PeerInfo::features`, the optional messages a peer understands
+pub mod feature {
+    /// `Transition::XmrLockProof`
+    pub const XMR_LOCK_PROOF: u64 = 1 << 0;
+    /// `Transition::Abort`
+    pub const ABORT: u64 = 1 << 1;
+}
+
+/// Everything this build supports of `feature`
+pub const FEATURES: u64 = feature::XMR_LOCK_PROOF | feature::ABORT;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+/// What peers from before the negotiation speak
+impl Default for ProtocolVersion {
+    fn default() -> Self {
+        ProtocolVersion { major: 1, minor: 0 }
+    }
+}
+
+impl ProtocolVersion {
+    /// Version both side speak, None across majors
+    pub fn negotiate(&self, other: &ProtocolVersion) -> Option<ProtocolVersion> {
+        match self.major == other.major {
+            true => Some(*self.min(other)),
+            false => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Capability flags. Kept as strings so older peers can skip flags they
+/// don't understand instead of failing to deserialize the handshake
+pub mod capability {
+    pub const COOPERATIVE_CLOSE: &str = "cooperative-close";
+    pub const BATCHING: &str = "batching";
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PeerInfo {
+    /// Empty when the peer didn't advertise anything (older software)
+    pub software: String,
+    pub contract_versions: Vec<u8>,
+    pub capabilities: Vec<String>,
+    #[serde(default)]
+    pub protocol_version: ProtocolVersion,
+    /// Bitset of `feature`, 0 on older peers
+    #[serde(default)]
+    pub features: u64,
+}
+
+impl PeerInfo {
+    /// What this build advertises
+    pub fn ours() -> Self {
+        PeerInfo {
+            software: SOFTWARE_VERSION.to_owned(),
+            contract_versions: CONTRACT_VERSIONS.to_vec(),
+            capabilities: vec![],
+            protocol_version: PROTOCOL_VERSION,
+            features: FEATURES,
+        }
+    }
+
+    /// `ours` for a swap, with the strict contract only when it asks for it,
+    /// see `Swap::strict_contract`
+    pub fn for_swap(strict_contract: bool) -> Self {
+        let mut ours = PeerInfo::ours();
+        ours.contract_versions
+            .retain(|version| strict_contract || *version != STRICT_CONTRACT_VERSION);
+        ours
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Capabilities both side advertised. Optional features must only be
+    /// used if they appear here
+    pub fn negotiate(&self, other: &PeerInfo) -> Vec<String> {
+        self.capabilities
+            .iter()
+            .filter(|c| other.supports(c))
+            .cloned()
+            .collect()
+    }
+
+    /// `feature` bits both side advertised
+    pub fn common_features(&self, other: &PeerInfo) -> u64 {
+        self.features & other.features
+    }
+
+    /// Highest contract version supported by both side
+    pub fn common_contract_version(&self, other: &PeerInfo) -> Option<u8> {
+        self.contract_versions
+            .iter()
+            .filter(|v| other.contract_versions.contains(v))
+            .max()
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{capability, feature, PeerInfo, ProtocolVersion, STRICT_CONTRACT_VERSION};
+
+    #[test]
+    fn should_negotiate_common_capabilities() {
+        let ours = PeerInfo {
+            software: "a".to_owned(),
+            contract_versions: vec![4, 5],
+            capabilities: vec![
+                capability::BATCHING.to_owned(),
+                capability::COOPERATIVE_CLOSE.to_owned(),
+            ],
+            protocol_version: ProtocolVersion { major: 1, minor: 2 },
+            features: feature::XMR_LOCK_PROOF | feature::ABORT,
+        };
+        let theirs = PeerInfo {
+            software: "b".to_owned(),
+            contract_versions: vec![4],
+            capabilities: vec![capability::BATCHING.to_owned(), "unknown".to_owned()],
+            protocol_version: ProtocolVersion { major: 1, minor: 0 },
+            features: feature::ABORT,
+        };
+
+        assert_eq!(ours.negotiate(&theirs), vec![capability::BATCHING]);
+        assert_eq!(ours.common_contract_version(&theirs), Some(4));
+        assert_eq!(ours.common_contract_version(&PeerInfo::default()), None);
+        assert_eq!(ours.common_features(&theirs), feature::ABORT);
+        assert_eq!(
+            ours.protocol_version.negotiate(&theirs.protocol_version),
+            Some(ProtocolVersion { major: 1, minor: 0 })
+        );
+        let next_major = ProtocolVersion { major: 2, minor: 0 };
+        assert_eq!(ours.protocol_version.negotiate(&next_major), None);
+
+        let strict = PeerInfo::for_swap(true);
+        assert_eq!(
+            strict.common_contract_version(&PeerInfo::for_swap(true)),
+            Some(STRICT_CONTRACT_VERSION)
+        );
+        assert_eq!(
+            strict.common_contract_version(&PeerInfo::for_swap(false)),
+            Some(4)
+        );
+    }
+}
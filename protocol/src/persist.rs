@@ -1,13 +1,15 @@
-use std::io::ErrorKind;
+use std::{collections::HashMap, io::ErrorKind, path::PathBuf, sync::Mutex};
 
+use async_trait::async_trait;
 use fs4::tokio::AsyncFileExt;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tokio::{
     fs::{self, File},
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
 };
 
-use crate::protocol::SwapWrapper;
+use crate::{accounting::SwapAccounting, blockchain::reorg::ReorgWatch, protocol::SwapWrapper};
 
 #[derive(Debug)]
 pub enum Error {
@@ -21,10 +23,51 @@ impl<T: ToString> From<T> for Error {
     }
 }
 
+/// Layout of the trade files and checkpoints. Bump it on every change serde
+/// defaults can't absorb, with the upgrade appended to `MIGRATIONS`
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` blob in place. Blobs are a
+/// `{"version", "swap", ..}` object, trade files and checkpoints alike
+const MIGRATIONS: [fn(&mut Value); SCHEMA_VERSION as usize] = [
+    // 0: before versioning, every field added since has a serde default
+    |_| {},
+];
+
+/// Upgrade `blob` to `SCHEMA_VERSION`. Blobs written by a newer release are
+/// refused rather than read with missing fields
+pub fn migrate(mut blob: Value) -> Result<Value, Error> {
+    let version = match &blob["version"] {
+        Value::Null => 0,
+        version => version
+            .as_u64()
+            .ok_or_else(|| Error::Unknown(format!("Invalid schema version {version}")))?
+            as u32,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(Error::Unknown(format!(
+            "Schema version {version} is newer than {SCHEMA_VERSION}, upgrade first"
+        )));
+    }
+
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut blob);
+    }
+    blob["version"] = SCHEMA_VERSION.into();
+    Ok(blob)
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    /// See `SCHEMA_VERSION`, 0 for files from before versioning
+    #[serde(default)]
+    pub version: u32,
     pub swap: SwapWrapper,
     pub refund_private_key: bitcoincash::PrivateKey,
+    #[serde(default)]
+    pub accounting: SwapAccounting,
+    #[serde(default)]
+    pub reorg: ReorgWatch,
 }
 
 pub struct TradePersist {
@@ -52,7 +95,7 @@ impl TradePersist {
 
                 Ok(TradePersist {
                     file,
-                    config: serde_json::from_slice(&buf)?,
+                    config: serde_json::from_value(migrate(serde_json::from_slice(&buf)?)?)?,
                     file_path,
                 })
             }
@@ -74,3 +117,101 @@ impl TradePersist {
         let _ = self.file.write(&serialized).await.unwrap();
     }
 }
+
+/// Where the runners checkpoint the swap after every applied transition, so a
+/// crash between a transition and the caller saving the trade loses nothing
+#[async_trait]
+pub trait SwapStore: Send + Sync {
+    async fn save(&self, trade_id: &str, swap: &SwapWrapper) -> anyhow::Result<()>;
+    /// None when the trade was never saved
+    async fn load(&self, trade_id: &str) -> anyhow::Result<Option<SwapWrapper>>;
+}
+
+/// One `{trade_id}.json` per trade in `dir`. Written to a temporary file then
+/// renamed, a crash while saving keeps the previous checkpoint
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileStore { dir: dir.into() }
+    }
+
+    fn path(&self, trade_id: &str) -> PathBuf {
+        self.dir.join(format!("{trade_id}.json"))
+    }
+}
+
+/// Versioned like the trade files
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    version: u32,
+    swap: SwapWrapper,
+}
+
+#[async_trait]
+impl SwapStore for FileStore {
+    async fn save(&self, trade_id: &str, swap: &SwapWrapper) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(trade_id);
+        let tmp = path.with_extension("json.tmp");
+
+        let checkpoint = Checkpoint {
+            version: SCHEMA_VERSION,
+            swap: swap.clone(),
+        };
+        let mut file = File::create(&tmp).await?;
+        file.write_all(&serde_json::to_vec_pretty(&checkpoint)?)
+            .await?;
+        file.sync_all().await?;
+        fs::rename(tmp, path).await?;
+        Ok(())
+    }
+
+    async fn load(&self, trade_id: &str) -> anyhow::Result<Option<SwapWrapper>> {
+        match fs::read(self.path(trade_id)).await {
+            Ok(buf) => {
+                let blob =
+                    migrate(serde_json::from_slice(&buf)?).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+                Ok(Some(serde_json::from_value::<Checkpoint>(blob)?.swap))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Kept in memory, for tests or embedders persisting elsewhere
+#[derive(Default)]
+pub struct MemoryStore {
+    swaps: Mutex<HashMap<String, SwapWrapper>>,
+}
+
+#[async_trait]
+impl SwapStore for MemoryStore {
+    async fn save(&self, trade_id: &str, swap: &SwapWrapper) -> anyhow::Result<()> {
+        let mut swaps = self.swaps.lock().unwrap();
+        swaps.insert(trade_id.to_owned(), swap.clone());
+        Ok(())
+    }
+
+    async fn load(&self, trade_id: &str) -> anyhow::Result<Option<SwapWrapper>> {
+        Ok(self.swaps.lock().unwrap().get(trade_id).cloned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{migrate, SCHEMA_VERSION};
+
+    #[test]
+    fn should_upgrade_unversioned_and_refuse_newer() {
+        let blob = migrate(json!({ "swap": {} })).unwrap();
+        assert_eq!(blob["version"], json!(SCHEMA_VERSION));
+
+        assert!(migrate(json!({ "version": SCHEMA_VERSION + 1, "swap": {} })).is_err());
+    }
+}
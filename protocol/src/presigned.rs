@@ -0,0 +1,41 @@
+use bitcoincash::Transaction;
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::backend::encode_tx;
+
+/// Fully signed contract spends as hex, saved with the trade as soon as they
+/// can be built. After a crash they only need a broadcast, even without the
+/// keys or wallet files to build them again
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresignedTxs {
+    /// Bob's move to the Refund then its spend back to him, per SwapLock
+    /// output. Relayed once timelock1 is past, the second right after
+    pub refund: Vec<String>,
+    /// Alice's claims, one per SwapLock output
+    pub claims: Vec<String>,
+}
+
+impl PresignedTxs {
+    pub(crate) fn record_refund(&mut self, chains: &[(Transaction, Transaction)]) {
+        for (to_refund, refund) in chains {
+            push_new(&mut self.refund, encode_tx(to_refund));
+            push_new(&mut self.refund, encode_tx(refund));
+        }
+    }
+
+    pub(crate) fn record_claims(&mut self, claims: &[Transaction]) {
+        for claim in claims {
+            push_new(&mut self.claims, encode_tx(claim));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.refund.is_empty() && self.claims.is_empty()
+    }
+}
+
+fn push_new(txs: &mut Vec<String>, tx: String) {
+    if !txs.contains(&tx) {
+        txs.push(tx);
+    }
+}
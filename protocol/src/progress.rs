@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+/// Average block times, for estimates only
+pub const BCH_BLOCK_SECS: u64 = 600;
+pub const XMR_BLOCK_SECS: u64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Chain {
+    Bch,
+    Xmr,
+}
+
+/// Of the transaction the current state waits for, as last seen by the runner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Confirmations {
+    pub chain: Chain,
+    pub seen: u64,
+    pub required: u64,
+}
+
+impl Confirmations {
+    pub fn pending(&self) -> u64 {
+        self.required.saturating_sub(self.seen)
+    }
+
+    pub fn eta_secs(&self) -> u64 {
+        let block_secs = match self.chain {
+            Chain::Bch => BCH_BLOCK_SECS,
+            Chain::Xmr => XMR_BLOCK_SECS,
+        };
+        self.pending() * block_secs
+    }
+}
+
+/// Where a swap stands, for progress bars. `stage` goes from 0 to `stages`,
+/// reached once the trade is finished whatever the outcome
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    pub stage: usize,
+    pub stages: usize,
+    pub confirmations: Option<Confirmations>,
+    /// Until the awaited confirmations, None while waiting for the counterparty
+    pub eta_secs: Option<u64>,
+}
+
+impl Progress {
+    pub(crate) fn new(stage: usize, stages: usize, confirmations: Option<Confirmations>) -> Self {
+        Progress {
+            stage,
+            stages,
+            confirmations,
+            eta_secs: confirmations.map(|confirmations| confirmations.eta_secs()),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.stage >= self.stages
+    }
+
+    pub fn percent(&self) -> u8 {
+        (self.stage.min(self.stages) * 100 / self.stages.max(1)) as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Chain, Confirmations, Progress, XMR_BLOCK_SECS};
+
+    #[test]
+    fn should_estimate_pending_blocks() {
+        let confirmations = Confirmations {
+            chain: Chain::Xmr,
+            seen: 4,
+            required: 10,
+        };
+        let progress = Progress::new(3, 5, Some(confirmations));
+        assert_eq!(progress.percent(), 60);
+        assert_eq!(progress.eta_secs, Some(6 * XMR_BLOCK_SECS));
+        assert!(!progress.is_finished());
+    }
+}
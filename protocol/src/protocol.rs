@@ -1,35 +1,285 @@
-use std::fmt::{self, Debug, Display};
+use std::{
+    fmt::{self, Debug, Display},
+    str::FromStr,
+    time::Duration,
+};
 
+use bitcoin_hashes::{sha256, sha256d, Hash, HashEngine};
+use bitcoincash::secp256k1::{ecdsa, Message, Secp256k1};
 use ecdsa_fun::{adaptor::EncryptedSignature, Signature};
 use monero::Address;
 use serde::{Deserialize, Serialize};
+use sigma_fun::ed25519::curve25519_dalek::edwards::CompressedEdwardsY;
 
 use crate::{
     alice::Alice,
+    blockchain::{fee::SPEND_TX_SIZE, policy::MempoolPolicy},
     bob::Bob,
-    keys::{bitcoin, KeyPublic},
+    contract::{
+        encode_timelock, fee_for_size, token::CashToken, ClaimFee, ContractError, ContractOptions,
+        ContractPair, MiningFee, TokenPolicy, TransactionType, SEQUENCE_LOCKTIME_MASK,
+        SEQUENCE_LOCKTIME_TYPE_FLAG,
+    },
+    keys::{bitcoin, KeyPrivate, KeyPublic, PayoutKeys},
+    peer::{PeerInfo, ProtocolVersion, STRICT_CONTRACT_VERSION},
+    redact::{Redacted, Reveal},
     utils::{bch_amount, monero_amount, monero_network},
+    xmr_wallet::FeePriority,
 };
 
-#[derive(Debug)]
+/// Rejected transitions, see `SwapEvents::transition`
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("invalid proof")]
     InvalidProof,
+    #[error("transition not expected in this state")]
     InvalidStateTransition,
+    #[error("invalid transaction")]
     InvalidTransaction,
+    #[error("invalid BCH address")]
     InvalidBchAddress,
+    #[error("invalid XMR address")]
     InvalidXmrAddress,
+    #[error("invalid signature")]
     InvalidSignature,
+    #[error("invalid XMR amount")]
     InvalidXmrAmount,
-    InvalidTimelock,
+    /// SwapLock funded with another amount than `Swap::bch_amount`
+    #[error("SwapLock funded with another amount than agreed")]
+    InvalidBchAmount,
+    /// The contracts can't be built from the agreed parameters
+    #[error(transparent)]
+    Contract(#[from] ContractError),
+    /// Msg0 keys differ from the `Transition::KeyCommitment` received before
+    #[error("keys differ from the commitment")]
+    InvalidCommitment,
+    /// `SignedTransition` not signed by the counterparty
+    #[error("message not signed by the counterparty")]
+    InvalidMessageSignature,
+    /// The peer speaks another major of `peer::PROTOCOL_VERSION`
+    #[error("incompatible protocol version")]
+    IncompatibleVersion,
+    /// Msg0 monero spend key isn't a point of ed25519
+    #[error("counterparty key is not a valid point")]
+    InvalidPoint,
+    /// Msg0 key of small order or zero, it would leave the shared key to us alone
+    #[error("counterparty key is the identity")]
+    IdentityKey,
+    /// Msg0 key equal to one of ours
+    #[error("counterparty key is one of ours")]
+    ReusedKey,
+    #[error("counterparty BCH receiving script is empty")]
+    EmptyBchRecv,
+    #[error("counterparty BCH receiving script is neither p2pkh nor p2sh")]
+    NonStandardBchRecv,
 }
 
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        Debug::fmt(self, f)
+/// Checks of a Msg0 besides the DLEQ proof, which expects valid points, so
+/// done first. `ours` our own public keys
+pub(crate) fn check_msg0(
+    ours: &KeyPublic,
+    keys: &KeyPublic,
+    receiving: &bitcoincash::Script,
+) -> Result<(), Error> {
+    let spend = CompressedEdwardsY::from_slice(keys.monero_spend.as_bytes())
+        .decompress()
+        .ok_or(Error::InvalidPoint)?;
+    if spend.is_small_order() || keys.monero_view.as_bytes() == &[0; 32] {
+        return Err(Error::IdentityKey);
+    }
+    // secp256k1 keys can't be invalid or the identity once parsed
+    if keys.monero_spend == ours.monero_spend
+        || keys.monero_view == ours.monero_view
+        || keys.ves == ours.ves
+        || keys.spend_bch == ours.spend_bch
+    {
+        return Err(Error::ReusedKey);
+    }
+
+    if receiving.is_empty() {
+        return Err(Error::EmptyBchRecv);
+    }
+    if !receiving.is_p2pkh() && !receiving.is_p2sh() {
+        return Err(Error::NonStandardBchRecv);
+    }
+    Ok(())
+}
+
+/// Progress information for UIs. Never causes a state transition
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Event {
+    /// SwapLock funding is in mempool, confirmations are still required
+    FundingUnconfirmed {
+        txid: String,
+    },
+    /// The block confirming `txid` is no longer in the best chain
+    Reorged {
+        txid: String,
+    },
+    /// XMR lock in the monerod tx pool, `received` in piconero
+    XmrLockPending {
+        txid: String,
+        received: u64,
+    },
+    /// XMR lock seen, not buried enough yet
+    XmrConfirmations {
+        confirmations: u64,
+        required: u64,
+    },
+    /// XMR lock differs from `Swap::xmr_amount`, in piconero
+    XmrAmountMismatch {
+        expected: u64,
+        received: u64,
+    },
+    /// No single transfer carries the XMR lock, `total` in piconero
+    /// over `transfers` transactions. Never verified
+    XmrSplitLock {
+        transfers: usize,
+        total: u64,
+    },
+    /// The verified XMR lock was reorganized out, verifying it again
+    XmrLockReorged {
+        txid: String,
+    },
+    /// monerod behind the network, XMR checks are paused
+    XmrDaemonNotSynced {
+        height: u64,
+        target_height: u64,
+    },
+    /// A transition was applied, states as displayed
+    StateChanged {
+        from: String,
+        to: String,
+    },
+    /// What the counterparty advertised on Msg0
+    PeerAdvertised(PeerInfo),
+    /// `txid` pays to the contract `address`, seen on every scan
+    FundingSeen {
+        address: String,
+        txid: String,
+        confirmations: u64,
+    },
+    Broadcasted {
+        txid: String,
+    },
+    /// Nothing moves until the user does this, e.g. a manual lock
+    NeedsUserAction {
+        action: String,
+    },
+    /// Failure of the runner the swap survives, retried or reported only
+    Error {
+        message: String,
+    },
+    /// The counterparty stayed silent in `state` past the `Watchdog` timeout
+    DeadlineExpired {
+        state: String,
+    },
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::FundingUnconfirmed { txid } => {
+                write!(f, "SwapLock funding {txid} seen, awaiting confirmations")
+            }
+            Event::Reorged { txid } => write!(f, "{txid} reorganized out of the chain"),
+            Event::XmrLockPending { txid, received } => write!(
+                f,
+                "XMR lock {txid} of {} in tx pool, awaiting a block",
+                monero::Amount::from_pico(*received)
+            ),
+            Event::XmrConfirmations {
+                confirmations,
+                required,
+            } => write!(f, "XMR lock has {confirmations}/{required} confirmations"),
+            Event::XmrAmountMismatch { expected, received } => write!(
+                f,
+                "XMR lock of {} instead of {}",
+                monero::Amount::from_pico(*received),
+                monero::Amount::from_pico(*expected)
+            ),
+            Event::XmrSplitLock { transfers, total } => write!(
+                f,
+                "XMR lock split over {transfers} transfers totaling {}, a single transfer is required",
+                monero::Amount::from_pico(*total)
+            ),
+            Event::XmrLockReorged { txid } => write!(
+                f,
+                "XMR lock {txid} reorganized out of the chain, waiting for it again"
+            ),
+            Event::XmrDaemonNotSynced {
+                height,
+                target_height,
+            } => write!(
+                f,
+                "monerod not synced ({height}/{target_height}), XMR checks paused"
+            ),
+            Event::StateChanged { from, to } => write!(f, "{from} -> {to}"),
+            Event::PeerAdvertised(peer) => write!(
+                f,
+                "Peer: {} protocol: {} contracts: {:?} capabilities: {:?}",
+                peer.software, peer.protocol_version, peer.contract_versions, peer.capabilities
+            ),
+            Event::FundingSeen {
+                address,
+                txid,
+                confirmations,
+            } => write!(f, "{txid} pays to {address}, {confirmations} confirmations"),
+            Event::Broadcasted { txid } => write!(f, "Broadcasted {txid}"),
+            Event::NeedsUserAction { action } => write!(f, "{action}"),
+            Event::Error { message } => write!(f, "{message}"),
+            Event::DeadlineExpired { state } => {
+                write!(f, "Counterparty silent in {state}, backing out")
+            }
+        }
+    }
+}
+
+/// Receives the runner events, for GUIs and daemons to react to the swap
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: Event);
+}
+
+impl EventSink for tokio::sync::mpsc::UnboundedSender<Event> {
+    fn emit(&self, event: Event) {
+        // the receiver stopped listening, the swap goes on
+        let _ = self.send(event);
+    }
+}
+
+/// How runners without a sink report `event`, user actions framed so they stand out
+pub fn print_event(prefix: &str, event: &Event) {
+    match event {
+        Event::NeedsUserAction { action } => {
+            let msg = format!("  {action}  ");
+            println!("{prefix}|{:=^width$}|", "", width = msg.len());
+            println!("{prefix}|{msg}|");
+            println!("{prefix}|{:=^width$}|", "", width = msg.len());
+        }
+        Event::Error { .. } => eprintln!("{prefix}{event}"),
+        event => println!("{prefix}{event}"),
     }
 }
 
-#[derive(Debug)]
+/// `Event::FundingUnconfirmed` for every tx funding the SwapLock of `contract`
+pub(crate) fn funding_events(
+    contract: &ContractPair,
+    mempool: Vec<bitcoincash::Transaction>,
+) -> Vec<Event> {
+    mempool
+        .into_iter()
+        .filter(|tx| {
+            matches!(
+                contract.analyze_tx(tx),
+                Some((_, TransactionType::ToSwapLock))
+            )
+        })
+        .map(|tx| Event::FundingUnconfirmed {
+            txid: tx.txid().to_string(),
+        })
+        .collect()
+}
+
 pub enum Action {
     SafeDelete,
     /// No further transition needed
@@ -48,15 +298,156 @@ pub enum Action {
     WatchXmr(monero::Address),
     CreateXmrView(monero::ViewPair),
 
-    UnlockBchNormal,
-    UnlockBchFallback,
+    /// Broadcast in order, a tx may spend an earlier one of the same
+    /// transition. See `blockchain::backend::broadcast_txs`
+    BroadcastTx(bitcoincash::Transaction),
+}
+
+impl Debug for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::SafeDelete => write!(f, "SafeDelete"),
+            Action::TradeSuccess => write!(f, "TradeSuccess"),
+            Action::WatchBchAddress { swaplock, refund } => f
+                .debug_struct("WatchBchAddress")
+                .field("swaplock", swaplock)
+                .field("refund", refund)
+                .finish(),
+            Action::Refund => write!(f, "Refund"),
+            Action::LockBch(amount, address) => f
+                .debug_tuple("LockBch")
+                .field(amount)
+                .field(address)
+                .finish(),
+            Action::LockXmr(amount, address) => f
+                .debug_tuple("LockXmr")
+                .field(amount)
+                .field(address)
+                .finish(),
+            Action::WatchXmr(address) => f.debug_tuple("WatchXmr").field(address).finish(),
+            // private view key of the shared wallet
+            Action::CreateXmrView(_) => f.debug_tuple("CreateXmrView").field(&Redacted).finish(),
+            Action::BroadcastTx(tx) => f.debug_tuple("BroadcastTx").field(tx).finish(),
+        }
+    }
+}
+
+/// Alice's XMR lock transaction and its secret key, hex encoded.
+/// Lets Bob check the exact amount it pays to the shared address
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrLockProof {
+    pub txid: String,
+    pub tx_key: String,
+}
+
+impl Debug for XmrLockProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XmrLockProof")
+            .field("txid", &self.txid)
+            .field("tx_key", &Redacted)
+            .finish()
+    }
+}
+
+/// Block the verified XMR lock was mined in. A different hash at `height`
+/// means a reorg, the lock is then looked up again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrLockBlock {
+    pub txid: String,
+    pub height: u64,
+    pub block_hash: String,
+}
+
+/// Block a `Transition::BchConfirmedTx` was mined in and the tip it was seen
+/// at, to evaluate the timelocks. 0 when unknown: block-based timelocks only
+/// need the confirmations, time-based ones never expire without it.
+/// See `ChainBackend::block_info`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BchBlock {
+    pub height: u32,
+    /// Header time
+    pub time: u32,
+    /// Median time past of the block before, time-based relative locks count from it
+    pub mtp: u32,
+    /// Median time past of the tip, what the next block is checked against
+    pub tip_mtp: u32,
+}
+
+impl BchBlock {
+    /// Whether an output of this block, confirmed `conf` times, can be spent
+    /// in the next block through the relative `timelock` (bip68, in blocks
+    /// or with `SEQUENCE_LOCKTIME_TYPE_FLAG` in units of 512 seconds)
+    pub fn timelock_expired(&self, timelock: u32, conf: u32) -> bool {
+        if !is_time_lock(timelock) {
+            return conf >= timelock;
+        }
+        let seconds = (timelock & SEQUENCE_LOCKTIME_MASK) * 512;
+        self.mtp != 0 && self.tip_mtp >= self.mtp.saturating_add(seconds)
+    }
+
+    /// Whether a spend locked until `expiry` can be mined in the next block
+    /// (bip113), see `Contract::expiry_lock`
+    pub fn expired(&self, expiry: u32) -> bool {
+        self.tip_mtp > expiry
+    }
+}
+
+/// `timelock` counts time rather than blocks
+pub fn is_time_lock(timelock: u32) -> bool {
+    timelock & SEQUENCE_LOCKTIME_TYPE_FLAG != 0
+}
+
+/// Time-based relative timelock of at least `duration`, rounded up to units
+/// of 512 seconds. Too long ones are refused by `SwapBuilder::build`
+pub fn time_lock(duration: Duration) -> u32 {
+    let units = duration.as_secs().div_ceil(512);
+    // past the mask rather than into the bip68 disable flag
+    let units = units.min(SEQUENCE_LOCKTIME_MASK as u64 + 1) as u32;
+    SEQUENCE_LOCKTIME_TYPE_FLAG | units
+}
+
+/// Blocks, or a duration ending in `s`, `m`, `h` or `d`: `144` or `1d`
+pub fn parse_timelock(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid timelock {s}, expected blocks or e.g. 2h");
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return s.parse().map_err(|_| invalid()),
+    };
+    let value: u64 = s[..s.len() - 1].parse().map_err(|_| invalid())?;
+    let secs = value.checked_mul(unit).ok_or_else(invalid)?;
+    Ok(time_lock(Duration::from_secs(secs)))
+}
+
+/// `timelock1,timelock2`, each as in `parse_timelock`: `2,4` or `1h,2h`
+pub fn parse_timelocks(s: &str) -> Result<(u32, u32), String> {
+    let (timelock1, timelock2) = s
+        .split_once(',')
+        .ok_or(format!("expected timelock1,timelock2, got {s}"))?;
+    Ok((parse_timelock(timelock1)?, parse_timelock(timelock2)?))
+}
+
+/// For humans: `20 blocks` or `1024 seconds`
+pub fn describe_timelock(timelock: u32) -> String {
+    let value = timelock & SEQUENCE_LOCKTIME_MASK;
+    match is_time_lock(timelock) {
+        true => format!("{} seconds", value * 512),
+        false => format!("{value} blocks"),
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum Transition {
+    /// `KeyPublic::commitment`, before Msg0 when `Swap::key_commitment` is set
+    KeyCommitment(#[serde(with = "hex")] [u8; 32]),
     Msg0 {
         keys: KeyPublic,
         receiving: bitcoincash::Script,
+        /// Missing on older peers
+        #[serde(default)]
+        peer: PeerInfo,
     },
     Contract {
         bch_address: String,
@@ -66,28 +457,273 @@ pub enum Transition {
     EncSig(EncryptedSignature),
     DecSig(Signature),
 
-    /// You are responsible to only use on confirmed tx
+    /// You are responsible to only use on confirmed tx. Confirmations and
+    /// the block it is in
     #[serde(skip)]
-    BchConfirmedTx(bitcoincash::Transaction, u32), // TODO: u32==confirmation. is it really u32?
-    XmrLockVerified(#[serde(with = "monero_amount")] monero::Amount),
+    BchConfirmedTx(bitcoincash::Transaction, u32, BchBlock),
+    XmrLockVerified(
+        #[serde(with = "monero_amount")] monero::Amount,
+        XmrLockBlock,
+    ),
+    /// The verified lock is no longer in the chain
+    XmrLockReorged,
+    XmrLockProof(XmrLockProof),
 
     SetXmrRestoreHeight(u64),
+
+    /// Back out before any fund is locked, sent on to the counterparty
+    Abort,
 }
 
 impl Display for Transition {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Transition::KeyCommitment(_) => write!(f, "Transition::KeyCommitment"),
             Transition::Msg0 { .. } => write!(f, "Transition::Msg0"),
             Transition::Contract { .. } => write!(f, "Transition::Contract"),
             Transition::EncSig(_) => write!(f, "Transition::EncSig"),
             Transition::DecSig(_) => write!(f, "Transition::DecSig"),
-            Transition::BchConfirmedTx(_, _) => write!(f, "Transition::BchConfirmedTx"),
-            Transition::XmrLockVerified(_) => write!(f, "Transition::XmrLockVerified"),
+            Transition::BchConfirmedTx(..) => write!(f, "Transition::BchConfirmedTx"),
+            Transition::XmrLockVerified(_, _) => write!(f, "Transition::XmrLockVerified"),
+            Transition::XmrLockReorged => write!(f, "Transition::XmrLockReorged"),
+            Transition::XmrLockProof(_) => write!(f, "Transition::XmrLockProof"),
             Transition::SetXmrRestoreHeight(_) => write!(f, "Transition::SetXmrRestoreHeight"),
+            Transition::Abort => write!(f, "Transition::Abort"),
+        }
+    }
+}
+
+/// Transition as sent to the counterparty, signed with the sender's ves key
+/// over the swap id and the transition. The key is learnt from Msg0, which
+/// is signed with the key it carries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "WireTransition", into = "WireTransition")]
+pub struct SignedTransition {
+    pub transition: Transition,
+    /// `transition` as json, the bytes signed and verified. Never encoded
+    /// again, peers of another version may not give the same json
+    payload: String,
+    pub signature: ecdsa::Signature,
+}
+
+/// How `SignedTransition` is sent
+#[derive(Clone, Serialize, Deserialize)]
+struct WireTransition {
+    payload: String,
+    signature: ecdsa::Signature,
+}
+
+impl TryFrom<WireTransition> for SignedTransition {
+    type Error = serde_json::Error;
+
+    fn try_from(wire: WireTransition) -> Result<Self, Self::Error> {
+        Ok(SignedTransition {
+            transition: serde_json::from_str(&wire.payload)?,
+            payload: wire.payload,
+            signature: wire.signature,
+        })
+    }
+}
+
+impl From<SignedTransition> for WireTransition {
+    fn from(signed: SignedTransition) -> Self {
+        WireTransition {
+            payload: signed.payload,
+            signature: signed.signature,
+        }
+    }
+}
+
+impl SignedTransition {
+    /// What the signature binds the transition to: the canonical
+    /// `Swap::swap_id`, unknown to the sender of the first messages, or
+    /// `Swap::id` for trades from before it
+    fn domain<'a>(swap: &'a Swap, transition: &Transition) -> &'a str {
+        match transition {
+            Transition::KeyCommitment(_) | Transition::Msg0 { .. } => "",
+            _ => swap.swap_id.as_deref().unwrap_or(&swap.id),
+        }
+    }
+
+    fn digest(domain: &str, payload: &str) -> Message {
+        let mut preimage = domain.as_bytes().to_vec();
+        preimage.extend_from_slice(payload.as_bytes());
+        Message::from_slice(&sha256d::Hash::hash(&preimage).to_byte_array()).unwrap()
+    }
+
+    pub fn sign(transition: Transition, swap: &Swap) -> Self {
+        let payload = serde_json::to_string(&transition)
+            .expect("transitions have no map keys that fail to serialize");
+        let secp = Secp256k1::signing_only();
+        let digest = Self::digest(Self::domain(swap, &transition), &payload);
+        SignedTransition {
+            signature: secp.sign_ecdsa(&digest, &swap.keys.ves.inner),
+            transition,
+            payload,
+        }
+    }
+
+    /// `signer` the counterparty's ves key, None until its Msg0 was applied
+    /// or in final states. Nothing but Msg0 can be checked then
+    pub fn verify(
+        &self,
+        swap: &Swap,
+        signer: Option<&bitcoincash::PublicKey>,
+    ) -> Result<(), Error> {
+        let signer = match (signer, &self.transition) {
+            (Some(signer), _) => signer,
+            (None, Transition::Msg0 { keys, .. }) => &keys.ves,
+            (None, _) => return Ok(()),
+        };
+
+        let secp = Secp256k1::verification_only();
+        let digest = Self::digest(Self::domain(swap, &self.transition), &self.payload);
+        secp.verify_ecdsa(&digest, &self.signature, &signer.inner)
+            .map_err(|_| Error::InvalidMessageSignature)
+    }
+}
+
+/// Used by trades created before the fee was negotiated
+pub const DEFAULT_MINING_FEE: u64 = 1000;
+
+fn default_mining_fee() -> u64 {
+    DEFAULT_MINING_FEE
+}
+
+/// BCH confirmations each decision waits for, on top of the runner's
+/// `min_bch_conf` which already hides shallower transactions. 0 adds nothing
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BchConfirmations {
+    /// Alice takes the SwapLock funding and locks the XMR. Above 0 it
+    /// overrides the runner's `zero_conf`
+    #[serde(default)]
+    pub funding: u32,
+    /// Bob hands Alice his encrypted signature, on his SwapLock funding
+    #[serde(default)]
+    pub enc_sig: u32,
+    /// Bob's SwapSuccess on Alice's claim and Alice's Refund on Bob's refund
+    /// spend, before using the key they reveal
+    #[serde(default)]
+    pub claim: u32,
+}
+
+impl FromStr for BchConfirmations {
+    type Err = String;
+
+    /// `funding,enc_sig,claim`, e.g. `1,3,6`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid bch confirmations {s}, funding,enc_sig,claim");
+        let confirmations = s
+            .split(',')
+            .map(|conf| conf.trim().parse::<u32>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        match confirmations[..] {
+            [funding, enc_sig, claim] => Ok(BchConfirmations {
+                funding,
+                enc_sig,
+                claim,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Cut of the market operator, or an affiliate, out of Alice's claims
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperatorFee {
+    /// Cash address
+    pub address: String,
+    pub amount: FeeAmount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FeeAmount {
+    Sats(u64),
+    /// Of `Swap::bch_amount`, rounded down
+    BasisPoints(u64),
+}
+
+impl OperatorFee {
+    /// The fee committed in the SwapLock of a swap of `bch_amount`
+    pub fn claim_fee(&self, bch_amount: bitcoincash::Amount) -> Result<ClaimFee, ContractError> {
+        let output = bitcoin::address::script_pubkey(&self.address)
+            .ok_or_else(|| ContractError::InvalidClaimFeeAddress(self.address.clone()))?;
+        let amount = match self.amount {
+            FeeAmount::Sats(sats) => sats,
+            FeeAmount::BasisPoints(bps) if bps > 10_000 => {
+                return Err(ContractError::ClaimFeeBasisPoints(bps))
+            }
+            // at most bch_amount, the product would overflow a u64
+            FeeAmount::BasisPoints(bps) => {
+                (bch_amount.to_sat() as u128 * bps as u128 / 10_000) as u64
+            }
+        };
+        Ok(ClaimFee {
+            output: output.into_bytes(),
+            amount,
+        })
+    }
+}
+
+impl FromStr for OperatorFee {
+    type Err = String;
+
+    /// `address,sats` or `address,bps` with the `bps` suffix, e.g.
+    /// `bitcoincash:qq..,1000` or `bitcoincash:qq..,50bps`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid operator fee {s}, address,sats or address,bps");
+        let (address, amount) = s.split_once(',').ok_or_else(invalid)?;
+        let amount = match amount.trim().strip_suffix("bps") {
+            Some(bps) => match bps.parse() {
+                Ok(bps) if bps <= 10_000 => FeeAmount::BasisPoints(bps),
+                _ => return Err(invalid()),
+            },
+            None => FeeAmount::Sats(amount.trim().parse().map_err(|_| invalid())?),
+        };
+        Ok(OperatorFee {
+            address: address.trim().to_owned(),
+            amount,
+        })
+    }
+}
+
+/// What Bob does when Alice locks more XMR than agreed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OverpaymentPolicy {
+    /// Proceed, Bob keeps the extra
+    #[default]
+    Accept,
+    /// Never verify the lock. Bob refunds once timelock1 expires, Alice then
+    /// recovers all her XMR
+    Refund,
+}
+
+impl OverpaymentPolicy {
+    /// An XMR lock of `received` verifies a swap of `expected`
+    pub fn accepts(&self, received: monero::Amount, expected: monero::Amount) -> bool {
+        match self {
+            OverpaymentPolicy::Accept => received >= expected,
+            OverpaymentPolicy::Refund => received == expected,
         }
     }
 }
 
+/// Unlock time of monero outputs
+pub const DEFAULT_XMR_CONFIRMATIONS: u64 = 10;
+
+fn default_xmr_confirmations() -> u64 {
+    DEFAULT_XMR_CONFIRMATIONS
+}
+
+/// Blocks scanned before the recorded height, covers monerod lagging behind
+/// the network and the time between the swap start and the XMR lock
+pub const XMR_RESTORE_HEIGHT_BUFFER: u64 = 20;
+
+/// Restore height of the swap wallets from the monerod block count
+pub fn xmr_restore_height(block_count: u64) -> u64 {
+    block_count.saturating_sub(XMR_RESTORE_HEIGHT_BUFFER)
+}
+
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Swap {
     pub id: String,
@@ -103,23 +739,93 @@ pub struct Swap {
     #[serde(with = "bch_amount")]
     pub bch_amount: bitcoincash::Amount,
 
+    /// Relative timelocks of the SwapLock and Refund contracts (bip68): in
+    /// blocks, or with `1 << 22` set in units of 512 seconds
     pub timelock1: u32,
     pub timelock2: u32,
+    /// Committed in both contracts, paid by every SwapLock and Refund spend.
+    /// See `blockchain::fee::fee_estimator`
+    #[serde(default = "default_mining_fee")]
+    pub mining_fee: u64,
+    /// Sats per 1000 bytes. When set, each contract is given the fee of its
+    /// spend size at this rate instead of `mining_fee`
+    #[serde(default)]
+    pub mining_fee_rate: Option<u64>,
+    /// Confirmations of the XMR lock before Bob locks in the BCH claim.
+    /// Without lock proof nor view scanner, monero-wallet-rpc can only tell
+    /// 1 and `DEFAULT_XMR_CONFIRMATIONS` apart
+    #[serde(default = "default_xmr_confirmations")]
+    pub xmr_confirmations: u64,
+    #[serde(default)]
+    pub xmr_overpayment: OverpaymentPolicy,
+    #[serde(default)]
+    pub bch_confirmations: BchConfirmations,
+    /// Whether SwapLock outputs with CashTokens count as a funding
+    #[serde(default)]
+    pub token_policy: TokenPolicy,
+    /// Makes it a token swap: the SwapLock holds these tokens on top of
+    /// `bch_amount`, which then only has to cover the fees and a token output
+    #[serde(default)]
+    pub bch_token: Option<CashToken>,
+    /// Paid by each of Alice's claims on top of the mining fee. Both sides
+    /// must agree on it, the SwapLock address depends on it
+    #[serde(default)]
+    pub operator_fee: Option<OperatorFee>,
+    /// Offer `STRICT_CONTRACT_VERSION`, used when the peer offers it too
+    #[serde(default)]
+    pub strict_contract: bool,
+    /// Negotiated on Msg0, None with peers advertising none
+    #[serde(default)]
+    pub contract_version: Option<u8>,
+    /// Unix time from which Alice may punish whenever Bob moved the BCH to
+    /// the Refund, compared to the median time past. Bob must finish his
+    /// refund before it, see `Contract::expiry`
+    #[serde(default)]
+    pub refund_expiry: Option<u32>,
+    /// Bob sweeps the claimed XMR there after SwapSuccess, Alice the XMR
+    /// recovered after Bob refunded. None to sweep manually
+    #[serde(default)]
+    pub xmr_payout_address: Option<monero::Address>,
+    /// Keys of `xmr_payout_address` when it was generated for this swap,
+    /// see `SwapBuilder::fresh_xmr_payout` and `Bob::get_xmr_payout_recovery`
+    #[serde(default)]
+    pub xmr_payout_keys: Option<PayoutKeys>,
+    /// Of the payout sweep
+    #[serde(default)]
+    pub xmr_fee_priority: FeePriority,
+    /// Recorded when the swap is created, see `xmr_restore_height`.
+    /// 0 when unknown, the tip at `Action::CreateXmrView` is used instead
+    #[serde(default)]
+    pub xmr_restore_height: u64,
+
+    /// What the counterparty advertised on Msg0
+    #[serde(default)]
+    pub peer: Option<PeerInfo>,
+    /// Agreed on Msg0, None before
+    #[serde(default)]
+    pub protocol_version: Option<ProtocolVersion>,
+    /// Exchange `Transition::KeyCommitment` before revealing the keys.
+    /// Both side must agree on it when the trade is created
+    #[serde(default)]
+    pub key_commitment: bool,
+    /// Received from the counterparty, Msg0 must match it
+    #[serde(default)]
+    pub peer_commitment: Option<[u8; 32]>,
+    /// Same on both sides, set on Msg0 with `Swap::derive_id`. None before
+    /// and on older trades, which keep using `id`
+    #[serde(default)]
+    pub swap_id: Option<String>,
 }
 
-impl Debug for Swap {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Swap {
+    fn fmt_with_keys(&self, f: &mut fmt::Formatter<'_>, keys: &dyn Debug) -> fmt::Result {
         write!(
             f,
             "Swap {{\n\
                 \tid: {:?},\n\
                 \txmr_network: {:?},\n\
                 \tbch_network: {:?},\n\
-                \tkeys: KeyPrivate {{\n\
-                    \t\tmonero_spend: monero::PrivateKey({}),\n\
-                    \t\tmonero_view: monero::PrivateKey({}),\n\
-                    \t\tves: bitcoincash::PrivateKey({}),\n\
-                \t}},\n\
+                \tkeys: {:?},\n\
                 \tbch_recv: {:?},\n\
                 \txmr_amount: {:?},\n\
                 \tbch_amount: {:?},\n\
@@ -128,9 +834,7 @@ impl Debug for Swap {
             self.id,
             self.xmr_network,
             self.bch_network,
-            self.keys.monero_spend,
-            self.keys.monero_view,
-            self.keys.ves,
+            keys,
             self.bch_recv,
             self.xmr_amount,
             self.bch_amount,
@@ -138,6 +842,341 @@ impl Debug for Swap {
     }
 }
 
+impl Debug for Swap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_with_keys(f, &self.keys)
+    }
+}
+
+impl Reveal for Swap {
+    fn fmt_revealed(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with_keys(f, &self.keys.reveal())
+    }
+}
+
+impl Swap {
+    /// Fees committed in the contracts
+    pub fn contract_mining_fee(&self) -> MiningFee {
+        match self.mining_fee_rate {
+            Some(rate) => MiningFee::Rate(rate),
+            None => MiningFee::Fixed(self.mining_fee),
+        }
+    }
+
+    /// The token and claim fee for `ContractPair::create`
+    pub fn contract_options(&self) -> Result<ContractOptions, ContractError> {
+        let claim_fee = self
+            .operator_fee
+            .as_ref()
+            .map(|fee| fee.claim_fee(self.bch_amount));
+        Ok(ContractOptions {
+            token: self.bch_token.clone(),
+            claim_fee: claim_fee.transpose()?,
+            strict: self.contract_version == Some(STRICT_CONTRACT_VERSION),
+            expiry: self.refund_expiry,
+        })
+    }
+
+    /// Upper bound of the fee of one contract spend, see `fee::SPEND_TX_SIZE`
+    pub fn max_spend_fee(&self) -> u64 {
+        match self.mining_fee_rate {
+            Some(rate) => fee_for_size(rate, SPEND_TX_SIZE),
+            None => self.mining_fee,
+        }
+    }
+
+    /// Canonical identifier of the trade, hash of both parties' public keys in
+    /// sorted order, the amounts and the timelocks. Unlike `id`, which each
+    /// side picks, both compute the same once Msg0 is applied
+    pub fn derive_id(&self, ours: &KeyPublic, theirs: &KeyPublic) -> String {
+        let key_bytes = |keys: &KeyPublic| {
+            let mut bytes = keys.spend_bch.inner.serialize().to_vec();
+            bytes.extend(keys.ves.inner.serialize());
+            bytes.extend(keys.monero_spend.as_bytes());
+            bytes
+        };
+        let mut keys = [key_bytes(ours), key_bytes(theirs)];
+        keys.sort();
+
+        let mut engine = sha256::Hash::engine();
+        for key in keys {
+            engine.input(&key);
+        }
+        engine.input(&self.xmr_amount.as_pico().to_be_bytes());
+        engine.input(&self.bch_amount.to_sat().to_be_bytes());
+        engine.input(&self.timelock1.to_be_bytes());
+        engine.input(&self.timelock2.to_be_bytes());
+        hex::encode(sha256::Hash::from_engine(engine).to_byte_array())
+    }
+
+    pub fn builder(
+        id: String,
+        bch_network: bitcoin::Network,
+        xmr_network: monero::Network,
+    ) -> SwapBuilder {
+        SwapBuilder::new(id, bch_network, xmr_network)
+    }
+}
+
+/// Rejected by `SwapBuilder::build`
+#[derive(Debug, thiserror::Error)]
+pub enum InvalidSwap {
+    #[error("timelock1 ({timelock1}) must be above 0 and below timelock2 ({timelock2})")]
+    Timelocks { timelock1: u32, timelock2: u32 },
+    #[error(
+        "timelock1 ({timelock1}) and timelock2 ({timelock2}) must both count blocks or both time"
+    )]
+    MixedTimelocks { timelock1: u32, timelock2: u32 },
+    #[error(transparent)]
+    Contract(#[from] ContractError),
+    /// Bob's refund, after both mining fees, would be dust
+    #[error("BCH amount {amount} sats is below {minimum} sats")]
+    BchAmountTooLow { amount: u64, minimum: u64 },
+    #[error("mining fee {fee} sats is below {minimum} sats")]
+    MiningFeeTooLow { fee: u64, minimum: u64 },
+    #[error("XMR amount is 0")]
+    XmrAmountZero,
+    #[error("BCH {bch_network:?} and XMR {xmr_network:?} networks don't go together")]
+    NetworkMismatch {
+        bch_network: bitcoin::Network,
+        xmr_network: monero::Network,
+    },
+    /// Only p2pkh and p2sh outputs are relayed by every node
+    #[error("BCH receiving script is neither p2pkh nor p2sh")]
+    NonStandardBchRecv,
+    #[error("XMR payout address is for {payout:?}, the swap is on {expected:?}")]
+    PayoutNetworkMismatch {
+        expected: monero::Network,
+        payout: monero::Network,
+    },
+}
+
+/// Builds a `Swap` checked for what would only fail once funds are locked.
+/// Keys are random unless set, the other optional fields default as when
+/// deserialized
+pub struct SwapBuilder {
+    swap: Swap,
+}
+
+impl SwapBuilder {
+    pub fn new(id: String, bch_network: bitcoin::Network, xmr_network: monero::Network) -> Self {
+        SwapBuilder {
+            swap: Swap {
+                id,
+                xmr_network,
+                bch_network,
+                keys: KeyPrivate::random(bch_network),
+                bch_recv: bitcoincash::Script::new(),
+                xmr_amount: monero::Amount::from_pico(0),
+                bch_amount: bitcoincash::Amount::from_sat(0),
+                timelock1: 0,
+                timelock2: 0,
+                mining_fee: DEFAULT_MINING_FEE,
+                mining_fee_rate: None,
+                xmr_confirmations: DEFAULT_XMR_CONFIRMATIONS,
+                xmr_overpayment: Default::default(),
+                bch_confirmations: Default::default(),
+                token_policy: Default::default(),
+                bch_token: None,
+                operator_fee: None,
+                strict_contract: false,
+                contract_version: None,
+                refund_expiry: None,
+                xmr_payout_address: None,
+                xmr_payout_keys: None,
+                xmr_fee_priority: Default::default(),
+                xmr_restore_height: 0,
+                peer: None,
+                protocol_version: None,
+                key_commitment: false,
+                peer_commitment: None,
+                swap_id: None,
+            },
+        }
+    }
+
+    pub fn keys(mut self, keys: KeyPrivate) -> Self {
+        self.swap.keys = keys;
+        self
+    }
+
+    pub fn amounts(mut self, bch_amount: bitcoincash::Amount, xmr_amount: monero::Amount) -> Self {
+        self.swap.bch_amount = bch_amount;
+        self.swap.xmr_amount = xmr_amount;
+        self
+    }
+
+    pub fn timelocks(mut self, timelock1: u32, timelock2: u32) -> Self {
+        self.swap.timelock1 = timelock1;
+        self.swap.timelock2 = timelock2;
+        self
+    }
+
+    /// Wall clock timelocks, see `time_lock`
+    pub fn timelock_durations(self, timelock1: Duration, timelock2: Duration) -> Self {
+        self.timelocks(time_lock(timelock1), time_lock(timelock2))
+    }
+
+    /// Where Bob's refund goes
+    pub fn bch_recv(mut self, bch_recv: bitcoincash::Script) -> Self {
+        self.swap.bch_recv = bch_recv;
+        self
+    }
+
+    pub fn mining_fee(mut self, mining_fee: u64) -> Self {
+        self.swap.mining_fee = mining_fee;
+        self
+    }
+
+    /// Sats per 1000 bytes, see `Swap::mining_fee_rate`
+    pub fn mining_fee_rate(mut self, rate: u64) -> Self {
+        self.swap.mining_fee_rate = Some(rate);
+        self
+    }
+
+    pub fn xmr_confirmations(mut self, xmr_confirmations: u64) -> Self {
+        self.swap.xmr_confirmations = xmr_confirmations;
+        self
+    }
+
+    pub fn xmr_overpayment(mut self, xmr_overpayment: OverpaymentPolicy) -> Self {
+        self.swap.xmr_overpayment = xmr_overpayment;
+        self
+    }
+
+    pub fn bch_confirmations(mut self, bch_confirmations: BchConfirmations) -> Self {
+        self.swap.bch_confirmations = bch_confirmations;
+        self
+    }
+
+    pub fn token_policy(mut self, token_policy: TokenPolicy) -> Self {
+        self.swap.token_policy = token_policy;
+        self
+    }
+
+    /// See `Swap::refund_expiry`
+    pub fn refund_expiry(mut self, refund_expiry: Option<u32>) -> Self {
+        self.swap.refund_expiry = refund_expiry;
+        self
+    }
+
+    /// See `Swap::strict_contract`
+    pub fn strict_contract(mut self, strict_contract: bool) -> Self {
+        self.swap.strict_contract = strict_contract;
+        self
+    }
+
+    pub fn operator_fee(mut self, operator_fee: Option<OperatorFee>) -> Self {
+        self.swap.operator_fee = operator_fee;
+        self
+    }
+
+    /// Swap these tokens rather than the BCH, see `Swap::bch_token`
+    pub fn bch_token(mut self, bch_token: Option<CashToken>) -> Self {
+        self.swap.bch_token = bch_token;
+        self
+    }
+
+    pub fn xmr_payout_address(mut self, xmr_payout_address: Option<monero::Address>) -> Self {
+        self.swap.xmr_payout_address = xmr_payout_address;
+        self.swap.xmr_payout_keys = None;
+        self
+    }
+
+    /// Sweep the payout to a wallet generated for this swap rather than an
+    /// address of the user, its keys are exported with the recovery
+    pub fn fresh_xmr_payout(mut self) -> Self {
+        let keys = PayoutKeys::random();
+        self.swap.xmr_payout_address = Some(keys.address(self.swap.xmr_network));
+        self.swap.xmr_payout_keys = Some(keys);
+        self
+    }
+
+    pub fn xmr_fee_priority(mut self, xmr_fee_priority: FeePriority) -> Self {
+        self.swap.xmr_fee_priority = xmr_fee_priority;
+        self
+    }
+
+    pub fn xmr_restore_height(mut self, xmr_restore_height: u64) -> Self {
+        self.swap.xmr_restore_height = xmr_restore_height;
+        self
+    }
+
+    pub fn key_commitment(mut self, key_commitment: bool) -> Self {
+        self.swap.key_commitment = key_commitment;
+        self
+    }
+
+    pub fn build(self) -> Result<Swap, InvalidSwap> {
+        let swap = self.swap;
+
+        if is_time_lock(swap.timelock1) != is_time_lock(swap.timelock2) {
+            return Err(InvalidSwap::MixedTimelocks {
+                timelock1: swap.timelock1,
+                timelock2: swap.timelock2,
+            });
+        }
+        if swap.timelock1 & SEQUENCE_LOCKTIME_MASK == 0 || swap.timelock1 >= swap.timelock2 {
+            return Err(InvalidSwap::Timelocks {
+                timelock1: swap.timelock1,
+                timelock2: swap.timelock2,
+            });
+        }
+        encode_timelock(swap.timelock1)?;
+        encode_timelock(swap.timelock2)?;
+        swap.contract_options()?;
+
+        let policy = MempoolPolicy::default();
+        let min_fee = policy.min_relay_fee * SPEND_TX_SIZE;
+        if swap.max_spend_fee() < min_fee {
+            return Err(InvalidSwap::MiningFeeTooLow {
+                fee: swap.max_spend_fee(),
+                minimum: min_fee,
+            });
+        }
+
+        if !swap.bch_recv.is_p2pkh() && !swap.bch_recv.is_p2sh() {
+            return Err(InvalidSwap::NonStandardBchRecv);
+        }
+
+        // the refund path pays the fee twice before reaching bch_recv
+        let recv = bitcoincash::TxOut {
+            value: 0,
+            script_pubkey: swap.bch_recv.clone(),
+            token: None,
+        };
+        let minimum = swap.max_spend_fee() * 2 + policy.dust(&recv);
+        if swap.bch_amount.to_sat() < minimum {
+            return Err(InvalidSwap::BchAmountTooLow {
+                amount: swap.bch_amount.to_sat(),
+                minimum,
+            });
+        }
+        if swap.xmr_amount.as_pico() == 0 {
+            return Err(InvalidSwap::XmrAmountZero);
+        }
+
+        let bch_mainnet = matches!(swap.bch_network, bitcoin::Network::Mainnet);
+        let xmr_mainnet = swap.xmr_network == monero::Network::Mainnet;
+        if bch_mainnet != xmr_mainnet {
+            return Err(InvalidSwap::NetworkMismatch {
+                bch_network: swap.bch_network,
+                xmr_network: swap.xmr_network,
+            });
+        }
+        if let Some(address) = &swap.xmr_payout_address {
+            if address.network != swap.xmr_network {
+                return Err(InvalidSwap::PayoutNetworkMismatch {
+                    expected: swap.xmr_network,
+                    payout: address.network,
+                });
+            }
+        }
+
+        Ok(swap)
+    }
+}
+
 pub trait SwapEvents {
     type State;
     /// Most of the time only one from the return type are `not None`
@@ -149,8 +1188,266 @@ pub trait SwapEvents {
     fn get_transition(&self) -> Option<Transition>;
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum SwapWrapper {
     Alice(Alice),
     Bob(Bob),
 }
+
+#[cfg(test)]
+mod test {
+    use bitcoincash::{hashes::Hash, secp256k1::Secp256k1, PubkeyHash, Script};
+
+    use std::time::Duration;
+
+    use super::{
+        check_msg0, describe_timelock, parse_timelock, parse_timelocks, time_lock, BchBlock,
+        ContractError, Error, FeeAmount, InvalidSwap, OperatorFee, SignedTransition, Swap,
+        Transition, SEQUENCE_LOCKTIME_TYPE_FLAG,
+    };
+    use crate::keys::{
+        bitcoin::{address, Network},
+        KeyPrivate, KeyPublic,
+    };
+
+    fn builder(bch_network: Network) -> super::SwapBuilder {
+        Swap::builder("test".to_owned(), bch_network, monero::Network::Stagenet)
+            .amounts(
+                bitcoincash::Amount::from_sat(100000),
+                monero::Amount::from_pico(100000),
+            )
+            .timelocks(2, 4)
+            .bch_recv(Script::new_p2pkh(
+                &PubkeyHash::from_slice(&[7u8; 20]).unwrap(),
+            ))
+    }
+
+    #[test]
+    fn should_verify_received_payloads() {
+        let mut swap = builder(Network::Regtest).build().unwrap();
+        let secp = Secp256k1::new();
+        let signer = swap.keys.ves.public_key(&secp);
+        let signed = SignedTransition::sign(Transition::Abort, &swap);
+        let wire = serde_json::to_string(&signed).unwrap();
+        let received: SignedTransition = serde_json::from_str(&wire).unwrap();
+        received.verify(&swap, Some(&signer)).unwrap();
+
+        // another encoding of the same transition, checked as it was signed
+        let payload = " \"Abort\" ".to_owned();
+        let domain = SignedTransition::domain(&swap, &Transition::Abort);
+        let digest = SignedTransition::digest(domain, &payload);
+        let wire = serde_json::json!({
+            "payload": payload,
+            "signature": secp.sign_ecdsa(&digest, &swap.keys.ves.inner),
+        });
+        let other: SignedTransition = serde_json::from_value(wire).unwrap();
+        assert!(matches!(other.transition, Transition::Abort));
+        other.verify(&swap, Some(&signer)).unwrap();
+
+        // bound to the canonical id once both keys are known
+        swap.swap_id = Some("00".repeat(32));
+        assert!(matches!(
+            received.verify(&swap, Some(&signer)),
+            Err(Error::InvalidMessageSignature)
+        ));
+        assert!(
+            serde_json::from_str::<SignedTransition>(r#"{"payload":"{","signature":"00"}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn should_expire_timelocks() {
+        let unknown = BchBlock::default();
+        assert!(!unknown.timelock_expired(2, 1));
+        assert!(unknown.timelock_expired(2, 2));
+        assert!(!unknown.timelock_expired(SEQUENCE_LOCKTIME_TYPE_FLAG | 2, 100));
+
+        let block = BchBlock {
+            height: 100,
+            time: 60_000,
+            mtp: 57_000,
+            tip_mtp: 57_000 + 1023,
+        };
+        assert!(!block.timelock_expired(SEQUENCE_LOCKTIME_TYPE_FLAG | 2, 0));
+        let tip = BchBlock {
+            tip_mtp: 57_000 + 1024,
+            ..block
+        };
+        // counted from the time, whatever the reorgs did to the confirmations
+        assert!(tip.timelock_expired(SEQUENCE_LOCKTIME_TYPE_FLAG | 2, 0));
+
+        // final once the tip median time past is strictly past the lock time
+        assert!(!tip.expired(tip.tip_mtp));
+        assert!(tip.expired(tip.tip_mtp - 1));
+        assert!(!unknown.expired(1));
+    }
+
+    #[test]
+    fn should_encode_time_locks() {
+        let hour = Duration::from_secs(3600);
+        // 7.03 units of 512 seconds, rounded up
+        assert_eq!(time_lock(hour), SEQUENCE_LOCKTIME_TYPE_FLAG | 8);
+        assert_eq!(parse_timelock("1h"), Ok(time_lock(hour)));
+        assert_eq!(parse_timelock("60m"), Ok(time_lock(hour)));
+        assert_eq!(parse_timelock("144"), Ok(144));
+        assert!(parse_timelock("1w").is_err());
+        assert!(parse_timelock("h").is_err());
+        assert!(parse_timelock(&format!("{}d", u64::MAX / 1000)).is_err());
+        assert!(parse_timelock("h").is_err());
+        assert_eq!(parse_timelocks("2,1h"), Ok((2, time_lock(hour))));
+        assert_eq!(describe_timelock(time_lock(hour)), "4096 seconds");
+        assert_eq!(describe_timelock(20), "20 blocks");
+
+        let swap = builder(Network::Regtest)
+            .timelock_durations(hour, hour * 2)
+            .build()
+            .unwrap();
+        assert_eq!(swap.timelock2, SEQUENCE_LOCKTIME_TYPE_FLAG | 15);
+        // 65535 units at most, about 388 days
+        assert!(matches!(
+            builder(Network::Regtest)
+                .timelock_durations(hour, hour * 24 * 400)
+                .build(),
+            Err(InvalidSwap::Contract(_))
+        ));
+        assert!(matches!(
+            builder(Network::Regtest)
+                .timelocks(time_lock(hour), parse_timelock("100000000d").unwrap())
+                .build(),
+            Err(InvalidSwap::Contract(ContractError::TimelockTooLong(_)))
+        ));
+    }
+
+    #[test]
+    fn should_check_msg0_keys() {
+        let ours = KeyPublic::from(&KeyPrivate::random(Network::Regtest));
+        let theirs = KeyPublic::from(&KeyPrivate::random(Network::Regtest));
+        let receiving = Script::new_p2pkh(&PubkeyHash::from_slice(&[7u8; 20]).unwrap());
+        assert!(check_msg0(&ours, &theirs, &receiving).is_ok());
+
+        assert!(matches!(
+            check_msg0(&ours, &ours, &receiving),
+            Err(Error::ReusedKey)
+        ));
+        let mut identity = [0u8; 32];
+        identity[0] = 1;
+        let mut keys = theirs.clone();
+        keys.monero_spend = monero::PublicKey::from_slice(&identity).unwrap();
+        assert!(matches!(
+            check_msg0(&ours, &keys, &receiving),
+            Err(Error::IdentityKey)
+        ));
+        assert!(matches!(
+            check_msg0(&ours, &theirs, &Script::new()),
+            Err(Error::EmptyBchRecv)
+        ));
+        assert!(matches!(
+            check_msg0(&ours, &theirs, &Script::new_op_return(&[1])),
+            Err(Error::NonStandardBchRecv)
+        ));
+    }
+
+    #[test]
+    fn should_validate_swap() {
+        assert!(builder(Network::Regtest).build().is_ok());
+        assert!(matches!(
+            builder(Network::Regtest).timelocks(4, 4).build(),
+            Err(InvalidSwap::Timelocks { .. })
+        ));
+        assert!(matches!(
+            builder(Network::Regtest)
+                .timelocks(2, SEQUENCE_LOCKTIME_TYPE_FLAG | 4)
+                .build(),
+            Err(InvalidSwap::MixedTimelocks { .. })
+        ));
+        assert!(builder(Network::Regtest)
+            .timelocks(
+                SEQUENCE_LOCKTIME_TYPE_FLAG | 2,
+                SEQUENCE_LOCKTIME_TYPE_FLAG | 4
+            )
+            .build()
+            .is_ok());
+        // ignored by OP_CHECKSEQUENCEVERIFY
+        assert!(matches!(
+            builder(Network::Regtest)
+                .timelocks(1 << 31 | 2, 1 << 31 | 4)
+                .build(),
+            Err(InvalidSwap::Contract(ContractError::TimelockDisabled(_)))
+        ));
+        assert!(matches!(
+            builder(Network::Regtest).timelocks(2, 0x10000).build(),
+            Err(InvalidSwap::Contract(ContractError::TimelockTooLong(_)))
+        ));
+        assert!(matches!(
+            builder(Network::Regtest).bch_recv(Script::new()).build(),
+            Err(InvalidSwap::NonStandardBchRecv)
+        ));
+        assert!(matches!(
+            builder(Network::Regtest)
+                .amounts(
+                    bitcoincash::Amount::from_sat(2000),
+                    monero::Amount::from_pico(1)
+                )
+                .build(),
+            Err(InvalidSwap::BchAmountTooLow { .. })
+        ));
+        assert!(matches!(
+            builder(Network::Mainnet).build(),
+            Err(InvalidSwap::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn should_cap_operator_fees() {
+        let address = address::encode(&[7u8; 20], "bchreg", address::P2PKH_VERSION);
+        let fee: OperatorFee = format!("{address},50bps").parse().unwrap();
+        assert_eq!(fee.amount, FeeAmount::BasisPoints(50));
+        assert!(format!("{address},10001bps")
+            .parse::<OperatorFee>()
+            .is_err());
+
+        // the whole of the largest amount
+        let amount = bitcoincash::Amount::from_sat(21_000_000 * 100_000_000);
+        let fee = OperatorFee {
+            address,
+            amount: FeeAmount::BasisPoints(10_000),
+        };
+        assert_eq!(fee.claim_fee(amount).unwrap().amount, amount.to_sat());
+        let fee = OperatorFee {
+            amount: FeeAmount::BasisPoints(u64::MAX),
+            ..fee
+        };
+        assert!(matches!(
+            fee.claim_fee(amount),
+            Err(ContractError::ClaimFeeBasisPoints(_))
+        ));
+    }
+
+    #[test]
+    fn should_generate_fresh_payout() {
+        let swap = builder(Network::Regtest)
+            .fresh_xmr_payout()
+            .build()
+            .unwrap();
+        let keys = swap.xmr_payout_keys.as_ref().unwrap();
+        assert_eq!(
+            swap.xmr_payout_address,
+            Some(keys.address(monero::Network::Stagenet))
+        );
+
+        let other = builder(Network::Regtest)
+            .fresh_xmr_payout()
+            .build()
+            .unwrap();
+        assert_ne!(swap.xmr_payout_address, other.xmr_payout_address);
+
+        // an address of the user replaces it
+        let swap = builder(Network::Regtest)
+            .fresh_xmr_payout()
+            .xmr_payout_address(other.xmr_payout_address)
+            .build()
+            .unwrap();
+        assert!(swap.xmr_payout_keys.is_none());
+    }
+}
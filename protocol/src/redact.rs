@@ -0,0 +1,48 @@
+use std::fmt::{self, Debug};
+
+/// Printed by Debug in place of secret keys
+pub const REDACTED: &str = "<redacted>";
+
+/// Types whose Debug hides their secret keys. `reveal` prints everything,
+/// for the few places meant to show them e.g. a key export
+pub trait Reveal {
+    fn fmt_revealed(&self, f: &mut fmt::Formatter) -> fmt::Result;
+
+    fn reveal(&self) -> Revealed<'_, Self> {
+        Revealed(self)
+    }
+}
+
+pub struct Revealed<'a, T: ?Sized>(&'a T);
+
+impl<T: Reveal + ?Sized> Debug for Revealed<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt_revealed(f)
+    }
+}
+
+/// `REDACTED` without the quotes of its `str` Debug
+pub(crate) struct Redacted;
+
+impl Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reveal, REDACTED};
+    use crate::keys::{bitcoin::Network, KeyPrivate};
+
+    #[test]
+    fn should_only_print_keys_on_reveal() {
+        let keys = KeyPrivate::random(Network::Regtest);
+        let spend = keys.monero_spend.to_string();
+
+        let redacted = format!("{keys:?}");
+        assert!(redacted.contains(REDACTED));
+        assert!(!redacted.contains(&spend));
+        assert!(format!("{:?}", keys.reveal()).contains(&spend));
+    }
+}
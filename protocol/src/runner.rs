@@ -0,0 +1,166 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
+
+use crate::{
+    blockchain::{backend::broadcast_txs, mock::MockChain},
+    error::SwapError,
+    protocol::{Event, XmrLockProof},
+};
+
+/// Side effects of the `protocol::Action` returned by `SwapEvents::transition`,
+/// for applications (GUI wallets, servers) bringing their own wallets and nodes
+/// while `alice::Runner` and `bob::Runner` drive the state machine.
+/// Every method defaults to leaving the action to the runner, implement only
+/// the ones to take over
+#[async_trait]
+pub trait SwapRunner: Send + Sync {
+    /// `Action::CreateXmrView`: Bob's wallet watching the XMR lock.
+    /// Returns its restore height, `Ok(None)` for the `{swap_id}_view`
+    /// wallet of the runner's wallet rpc
+    async fn create_xmr_view(
+        &self,
+        _trade_id: &str,
+        _keypair: &monero::ViewPair,
+    ) -> Result<Option<u64>, SwapError> {
+        Ok(None)
+    }
+
+    /// `Action::LockBch`: Bob funding the SwapLock `address`.
+    /// False to ask the user through `Event::NeedsUserAction`
+    async fn lock_bch(
+        &self,
+        _trade_id: &str,
+        _amount: bitcoincash::Amount,
+        _address: &str,
+    ) -> Result<bool, SwapError> {
+        Ok(false)
+    }
+
+    /// `Action::LockXmr`: Alice's transfer to the shared address, handed
+    /// to Bob with its proof. Must not be retried once it may have gone out.
+    /// `Ok(None)` for the runner's `monero_wallet`
+    async fn lock_xmr(
+        &self,
+        _trade_id: &str,
+        _amount: monero::Amount,
+        _address: &monero::Address,
+    ) -> Result<Option<XmrLockProof>, SwapError> {
+        Ok(None)
+    }
+
+    /// `Action::BroadcastTx`, the transactions of one transition in order.
+    /// Returns the events to report, None for `backend::broadcast_txs` on the
+    /// runner's `ChainBackend`
+    async fn broadcast(
+        &self,
+        _trade_id: &str,
+        _txs: &[bitcoincash::Transaction],
+    ) -> Option<Vec<Event>> {
+        None
+    }
+
+    /// `Action::WatchBchAddress`, to subscribe to notifications. The runner
+    /// still rescans the addresses on `check_bch`
+    async fn watch_bch(&self, _trade_id: &str, _swaplock: &str, _refund: &str) {}
+
+    /// `Action::WatchXmr`, to subscribe to notifications. The runner still
+    /// scans the lock on `check_xmr`
+    async fn watch_xmr(&self, _trade_id: &str, _address: &monero::Address) {}
+}
+
+/// Dry run: every side effect is simulated instead of performed. The would-be
+/// actions are printed and recorded, locks get fake txids while the transitions
+/// proceed as usual, for integrators to exercise their UI and persistence
+/// against the whole protocol. With a `MockChain` as the runner's
+/// `ChainBackend`, the simulated BCH lock and broadcasts land in its mempool
+/// so the contracts are seen funded and spent
+pub struct DryRun<'a> {
+    pub chain: Option<&'a MockChain>,
+    actions: Mutex<Vec<String>>,
+}
+
+impl<'a> DryRun<'a> {
+    pub fn new(chain: Option<&'a MockChain>) -> Self {
+        DryRun {
+            chain,
+            actions: Mutex::default(),
+        }
+    }
+
+    /// The simulated actions, oldest first
+    pub fn actions(&self) -> Vec<String> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    fn record(&self, trade_id: &str, action: String) {
+        println!("[{trade_id}]: dry run, would {action}");
+        self.actions.lock().unwrap().push(action);
+    }
+}
+
+/// Stands for the txid of a simulated `action`
+fn fake_txid(action: &str) -> String {
+    hex::encode(sha256::Hash::hash(action.as_bytes()).to_byte_array())
+}
+
+#[async_trait]
+impl SwapRunner for DryRun<'_> {
+    async fn create_xmr_view(
+        &self,
+        trade_id: &str,
+        keypair: &monero::ViewPair,
+    ) -> Result<Option<u64>, SwapError> {
+        let action = format!("create the view wallet of spend key {}", keypair.spend);
+        self.record(trade_id, action);
+        Ok(Some(0))
+    }
+
+    async fn lock_bch(
+        &self,
+        trade_id: &str,
+        amount: bitcoincash::Amount,
+        address: &str,
+    ) -> Result<bool, SwapError> {
+        self.record(trade_id, format!("lock {amount} to {address}"));
+        if let Some(chain) = self.chain {
+            chain.fund(address, amount)?;
+        }
+        Ok(true)
+    }
+
+    async fn lock_xmr(
+        &self,
+        trade_id: &str,
+        amount: monero::Amount,
+        address: &monero::Address,
+    ) -> Result<Option<XmrLockProof>, SwapError> {
+        let action = format!("lock {amount} to {address}");
+        self.record(trade_id, action.clone());
+        Ok(Some(XmrLockProof {
+            txid: fake_txid(&action),
+            tx_key: fake_txid(&format!("{action} key")),
+        }))
+    }
+
+    async fn broadcast(
+        &self,
+        trade_id: &str,
+        txs: &[bitcoincash::Transaction],
+    ) -> Option<Vec<Event>> {
+        for tx in txs {
+            self.record(trade_id, format!("broadcast {}", tx.txid()));
+        }
+        if let Some(chain) = self.chain {
+            return Some(broadcast_txs(chain, txs, None, None).await);
+        }
+        Some(
+            txs.iter()
+                .map(|tx| Event::Broadcasted {
+                    txid: tx.txid().to_string(),
+                })
+                .collect(),
+        )
+    }
+}
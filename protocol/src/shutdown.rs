@@ -0,0 +1,122 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::{progress::Progress, protocol::SwapWrapper};
+
+/// Stops the polling loops between two passes, cloned into each of them.
+/// A pass in flight finishes its broadcasts and checkpoint first, loops only
+/// look at it while idle
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown::default()
+    }
+
+    /// Triggered by ctrl-c
+    pub fn on_ctrl_c() -> Self {
+        let shutdown = Shutdown::new();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                let _ = tokio::signal::ctrl_c().await;
+                println!("Shutting down, finishing the checks in flight");
+                shutdown.trigger();
+            }
+        });
+        shutdown
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Returns once triggered
+    pub async fn triggered(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Wait between two passes, cut short by the shutdown.
+    /// True when the loop must stop
+    pub async fn sleep(&self, duration: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(duration) => self.is_triggered(),
+            _ = self.triggered() => true,
+        }
+    }
+}
+
+/// Swap left in a non-terminal state, to resume once restarted
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingSwap {
+    pub trade_id: String,
+    pub state: String,
+    pub progress: Progress,
+}
+
+impl fmt::Display for PendingSwap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} in {}, stage {}/{}",
+            self.trade_id, self.state, self.progress.stage, self.progress.stages
+        )
+    }
+}
+
+/// The unfinished ones among `swaps`, by trade id
+pub fn pending_swaps(swaps: impl IntoIterator<Item = (String, SwapWrapper)>) -> Vec<PendingSwap> {
+    swaps
+        .into_iter()
+        .filter_map(|(trade_id, swap)| {
+            let (state, progress) = match &swap {
+                SwapWrapper::Alice(alice) => (alice.state.to_string(), alice.progress()),
+                SwapWrapper::Bob(bob) => (bob.state.to_string(), bob.progress()),
+            };
+            (!progress.is_finished()).then_some(PendingSwap {
+                trade_id,
+                state,
+                progress,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Shutdown;
+
+    #[tokio::test]
+    async fn should_cut_sleep_short() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.sleep(Duration::from_millis(1)).await);
+
+        let waiting = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.sleep(Duration::from_secs(3600)).await }
+        });
+        shutdown.trigger();
+        assert!(waiting.await.unwrap());
+        assert!(shutdown.is_triggered());
+    }
+}
@@ -0,0 +1,286 @@
+use std::{collections::HashSet, fmt::Write, str::FromStr};
+
+/// A transition of `SwapEvents::transition` moving a state, or doing
+/// something in it. Replays and `SetXmrRestoreHeight` leave the state as is
+/// and are not drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub from: &'static str,
+    pub transition: &'static str,
+    pub to: &'static str,
+    /// `protocol::Action` returned with it
+    pub actions: &'static [&'static str],
+    /// Condition on the transition's content, empty when there is none
+    pub when: &'static str,
+}
+
+const fn edge(
+    from: &'static str,
+    transition: &'static str,
+    to: &'static str,
+    actions: &'static [&'static str],
+    when: &'static str,
+) -> Edge {
+    Edge {
+        from,
+        transition,
+        to,
+        actions,
+        when,
+    }
+}
+
+/// The graph of one side, the first state is where a trade starts
+#[derive(Debug, Clone, Copy)]
+pub struct Chart {
+    pub name: &'static str,
+    pub states: &'static [&'static str],
+    pub edges: &'static [Edge],
+}
+
+pub const ALICE: Chart = Chart {
+    name: "Alice",
+    states: &[
+        "Init",
+        "WithBobKeys",
+        "ContractMatch",
+        "BchLocked",
+        "ValidEncSig",
+        "Punish",
+        "Refund",
+        "Aborted",
+    ],
+    edges: &[
+        edge("Init", "KeyCommitment", "Init", &[], "Swap::key_commitment"),
+        edge("Init", "Msg0", "WithBobKeys", &[], ""),
+        edge(
+            "WithBobKeys",
+            "Contract",
+            "ContractMatch",
+            &["WatchBchAddress"],
+            "",
+        ),
+        edge(
+            "ContractMatch",
+            "BchConfirmedTx",
+            "BchLocked",
+            &["LockXmr"],
+            "SwapLock funded",
+        ),
+        edge("BchLocked", "XmrLockProof", "BchLocked", &[], ""),
+        edge("BchLocked", "EncSig", "ValidEncSig", &["BroadcastTx"], ""),
+        edge(
+            "BchLocked",
+            "BchConfirmedTx",
+            "Punish",
+            &["BroadcastTx"],
+            "Refund contract funded past timelock2",
+        ),
+        edge(
+            "BchLocked",
+            "BchConfirmedTx",
+            "Refund",
+            &[],
+            "Bob's refund, revealing his key",
+        ),
+        edge(
+            "Punish",
+            "BchConfirmedTx",
+            "Punish",
+            &["BroadcastTx"],
+            "another part of a split funding",
+        ),
+        edge("Init", "Abort", "Aborted", &[], ""),
+        edge("WithBobKeys", "Abort", "Aborted", &[], ""),
+        edge("ContractMatch", "Abort", "Aborted", &[], ""),
+    ],
+};
+
+pub const BOB: Chart = Chart {
+    name: "Bob",
+    states: &[
+        "Init",
+        "WithAliceKey",
+        "ContractMatch",
+        "VerifiedEncSig",
+        "MoneroLocked",
+        "ProceedRefund",
+        "Aborted",
+        "SwapSuccess",
+    ],
+    edges: &[
+        edge("Init", "KeyCommitment", "Init", &[], "Swap::key_commitment"),
+        edge("Init", "Msg0", "WithAliceKey", &["CreateXmrView"], ""),
+        edge("WithAliceKey", "Contract", "ContractMatch", &[], ""),
+        edge(
+            "ContractMatch",
+            "EncSig",
+            "VerifiedEncSig",
+            &["LockBch", "WatchXmr"],
+            "",
+        ),
+        edge("VerifiedEncSig", "XmrLockProof", "VerifiedEncSig", &[], ""),
+        edge(
+            "VerifiedEncSig",
+            "XmrLockVerified",
+            "MoneroLocked",
+            &[],
+            "accepted amount",
+        ),
+        edge(
+            "VerifiedEncSig",
+            "BchConfirmedTx",
+            "ProceedRefund",
+            &["BroadcastTx"],
+            "timelock1 expired",
+        ),
+        edge(
+            "MoneroLocked",
+            "XmrLockVerified",
+            "MoneroLocked",
+            &[],
+            "mined again",
+        ),
+        edge("MoneroLocked", "XmrLockReorged", "VerifiedEncSig", &[], ""),
+        edge(
+            "MoneroLocked",
+            "BchConfirmedTx",
+            "SwapSuccess",
+            &["TradeSuccess"],
+            "Alice's claim, revealing her key",
+        ),
+        edge(
+            "MoneroLocked",
+            "BchConfirmedTx",
+            "ProceedRefund",
+            &["BroadcastTx"],
+            "timelock1 expired",
+        ),
+        edge(
+            "ProceedRefund",
+            "BchConfirmedTx",
+            "ProceedRefund",
+            &["BroadcastTx"],
+            "SwapLock moved to the Refund by someone else",
+        ),
+        edge("Init", "Abort", "Aborted", &[], ""),
+        edge("WithAliceKey", "Abort", "Aborted", &[], ""),
+        edge("ContractMatch", "Abort", "Aborted", &[], ""),
+    ],
+};
+
+impl Chart {
+    /// States no edge leads to from the first one
+    pub fn unreachable(&self) -> Vec<&'static str> {
+        let mut reached = HashSet::from([self.states[0]]);
+        let mut todo = vec![self.states[0]];
+        while let Some(state) = todo.pop() {
+            for edge in self.edges.iter().filter(|edge| edge.from == state) {
+                if reached.insert(edge.to) {
+                    todo.push(edge.to);
+                }
+            }
+        }
+        self.states
+            .iter()
+            .filter(|state| !reached.contains(*state))
+            .copied()
+            .collect()
+    }
+
+    /// States no edge leaves for another, where a trade ends
+    pub fn terminal(&self) -> Vec<&'static str> {
+        let leaves = |state: &str| {
+            self.edges
+                .iter()
+                .any(|edge| edge.from == state && edge.to != state)
+        };
+        self.states
+            .iter()
+            .filter(|state| !leaves(state))
+            .copied()
+            .collect()
+    }
+
+    fn label(edge: &Edge) -> String {
+        let mut label = edge.transition.to_owned();
+        if !edge.when.is_empty() {
+            let _ = write!(label, " [{}]", edge.when);
+        }
+        if !edge.actions.is_empty() {
+            let _ = write!(label, " / {}", edge.actions.join(", "));
+        }
+        label
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Graphviz, `dot -Tsvg`
+    Dot,
+    /// Mermaid flowchart, rendered by GitHub and most markdown viewers
+    Mermaid,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Format::Dot),
+            "mermaid" => Ok(Format::Mermaid),
+            _ => Err(format!("unknown statechart format {s}, dot or mermaid")),
+        }
+    }
+}
+
+/// Both state machines as one graph, a cluster each, edges labelled
+/// `Transition [condition] / Actions`
+pub fn statechart(format: Format) -> String {
+    let mut out = String::new();
+    match format {
+        Format::Dot => {
+            out.push_str("digraph swap {\n    rankdir=LR;\n");
+            for chart in [ALICE, BOB] {
+                let _ = writeln!(out, "    subgraph cluster_{} {{", chart.name);
+                let _ = writeln!(out, "        label=\"{}\";", chart.name);
+                for state in chart.states {
+                    let _ = writeln!(out, "        {}_{state} [label=\"{state}\"];", chart.name);
+                }
+                for edge in chart.edges {
+                    let _ = writeln!(
+                        out,
+                        "        {name}_{} -> {name}_{} [label=\"{}\"];",
+                        edge.from,
+                        edge.to,
+                        Chart::label(edge),
+                        name = chart.name,
+                    );
+                }
+                out.push_str("    }\n");
+            }
+            out.push_str("}\n");
+        }
+        Format::Mermaid => {
+            out.push_str("flowchart LR\n");
+            for chart in [ALICE, BOB] {
+                let _ = writeln!(out, "    subgraph {}", chart.name);
+                for state in chart.states {
+                    let _ = writeln!(out, "        {}_{state}[{state}]", chart.name);
+                }
+                for edge in chart.edges {
+                    let _ = writeln!(
+                        out,
+                        "        {name}_{} -->|\"{}\"| {name}_{}",
+                        edge.from,
+                        Chart::label(edge).replace('"', "'"),
+                        edge.to,
+                        name = chart.name,
+                    );
+                }
+                out.push_str("    end\n");
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+
+use bitcoin_hashes::{sha256, Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::{protocol::Transition, redact::Redacted};
+
+/// One applied transition
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    /// Unix time it was applied
+    pub at: u64,
+    /// e.g. `Transition::Msg0`
+    pub transition: String,
+    /// The transition as exchanged, to replay it. None for `BchConfirmedTx`,
+    /// which is never sent and is recorded as `bch_tx` instead
+    pub message: Option<serde_json::Value>,
+    /// Txid and confirmations of a `BchConfirmedTx`
+    pub bch_tx: Option<(String, u32)>,
+    pub from: String,
+    pub to: String,
+    /// sha256 of the resulting state serialized as json
+    pub state_hash: String,
+}
+
+/// Everything applied to a swap, in order, saved with it. Only grows:
+/// transitions that leave the state as it was (redeliveries, rescans of
+/// shallow transactions) are not recorded.
+/// Messages include the counterparty's keys, the shared view key among them,
+/// only hand it to whom may see the trade
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Transcript(Vec<TranscriptEntry>);
+
+pub fn state_hash<S: Serialize>(state: &S) -> String {
+    let serialized = serde_json::to_vec(state).unwrap_or_default();
+    hex::encode(sha256::Hash::hash(&serialized).to_byte_array())
+}
+
+/// Messages carry keys, only the json export shows them
+impl fmt::Debug for TranscriptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TranscriptEntry")
+            .field("at", &self.at)
+            .field("transition", &self.transition)
+            .field("message", &self.message.as_ref().map(|_| Redacted))
+            .field("bch_tx", &self.bch_tx)
+            .field("from", &self.from)
+            .field("to", &self.to)
+            .field("state_hash", &self.state_hash)
+            .finish()
+    }
+}
+
+impl TranscriptEntry {
+    /// `transition` about to be applied at `at`, see `Transcript::record`
+    pub fn new(at: u64, transition: &Transition) -> Self {
+        let (message, bch_tx) = match transition {
+            Transition::BchConfirmedTx(tx, conf, _) => (None, Some((tx.txid().to_string(), *conf))),
+            transition => (serde_json::to_value(transition).ok(), None),
+        };
+        TranscriptEntry {
+            at,
+            transition: transition.to_string(),
+            message,
+            bch_tx,
+            from: String::new(),
+            to: String::new(),
+            state_hash: String::new(),
+        }
+    }
+}
+
+impl Transcript {
+    pub fn entries(&self) -> &[TranscriptEntry] {
+        &self.0
+    }
+
+    /// Append `entry` once applied, moving the state `from` to `to`.
+    /// Returns false when it left the state as it was, nothing is added then
+    pub fn record<S: Serialize + ToString>(
+        &mut self,
+        mut entry: TranscriptEntry,
+        from: &S,
+        to: &S,
+    ) -> bool {
+        let hash = state_hash(to);
+        if state_hash(from) == hash {
+            return false;
+        }
+
+        entry.from = from.to_string();
+        entry.to = to.to_string();
+        entry.state_hash = hash;
+        self.0.push(entry);
+        true
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
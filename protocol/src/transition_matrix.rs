@@ -0,0 +1,521 @@
+//! Every (State, Transition) pair of both state machines. The variants are
+//! listed by exhaustive matches, a new one doesn't compile until it is named
+//! here and the tables say which transitions it accepts
+
+use bitcoincash::{OutPoint, PackedLockTime, Script, Transaction, TxIn, TxOut};
+
+use crate::{
+    alice::{self, Alice},
+    bob::{self, Bob},
+    keys::bitcoin::{random_private_key, Network},
+    protocol::{Action, BchBlock, Error, Swap, SwapEvents, Transition, XmrLockBlock, XmrLockProof},
+    statechart::{self, Chart},
+};
+
+fn transition_kind(transition: &Transition) -> &'static str {
+    match transition {
+        Transition::KeyCommitment(_) => "KeyCommitment",
+        Transition::Msg0 { .. } => "Msg0",
+        Transition::Contract { .. } => "Contract",
+        Transition::EncSig(_) => "EncSig",
+        Transition::DecSig(_) => "DecSig",
+        Transition::BchConfirmedTx(..) => "BchConfirmedTx",
+        Transition::XmrLockVerified(_, _) => "XmrLockVerified",
+        Transition::XmrLockReorged => "XmrLockReorged",
+        Transition::XmrLockProof(_) => "XmrLockProof",
+        Transition::SetXmrRestoreHeight(_) => "SetXmrRestoreHeight",
+        Transition::Abort => "Abort",
+    }
+}
+
+const TRANSITIONS: [&str; 11] = [
+    "KeyCommitment",
+    "Msg0",
+    "Contract",
+    "EncSig",
+    "DecSig",
+    "BchConfirmedTx",
+    "XmrLockVerified",
+    "XmrLockReorged",
+    "XmrLockProof",
+    "SetXmrRestoreHeight",
+    "Abort",
+];
+
+fn action_kind(action: &Action) -> &'static str {
+    match action {
+        Action::SafeDelete => "SafeDelete",
+        Action::TradeSuccess => "TradeSuccess",
+        Action::WatchBchAddress { .. } => "WatchBchAddress",
+        Action::Refund => "Refund",
+        Action::LockBch(_, _) => "LockBch",
+        Action::LockXmr(_, _) => "LockXmr",
+        Action::WatchXmr(_) => "WatchXmr",
+        Action::CreateXmrView(_) => "CreateXmrView",
+        Action::BroadcastTx(_) => "BroadcastTx",
+    }
+}
+
+fn alice_kind(state: &alice::State) -> &'static str {
+    match state {
+        alice::State::Init => "Init",
+        alice::State::WithBobKeys(_) => "WithBobKeys",
+        alice::State::ContractMatch(_) => "ContractMatch",
+        alice::State::BchLocked(_) => "BchLocked",
+        alice::State::ValidEncSig(_) => "ValidEncSig",
+        alice::State::Punish(_) => "Punish",
+        alice::State::Refund(_, _) => "Refund",
+        alice::State::Aborted => "Aborted",
+    }
+}
+
+fn bob_kind(state: &bob::State) -> &'static str {
+    match state {
+        bob::State::Init => "Init",
+        bob::State::WithAliceKey(_) => "WithAliceKey",
+        bob::State::ContractMatch(_) => "ContractMatch",
+        bob::State::VerifiedEncSig(_) => "VerifiedEncSig",
+        bob::State::MoneroLocked(_) => "MoneroLocked",
+        bob::State::ProceedRefund(_) => "ProceedRefund",
+        bob::State::Aborted(_) => "Aborted",
+        bob::State::SwapSuccess(..) => "SwapSuccess",
+    }
+}
+
+/// What each Alice state applies, redelivered messages of Bob included.
+/// Every other pair is rejected
+const ALICE_ACCEPTS: [(&str, &[&str]); 8] = [
+    ("Init", &["Msg0", "Abort"]),
+    ("WithBobKeys", &["Msg0", "Contract", "Abort"]),
+    (
+        "ContractMatch",
+        &["Msg0", "Contract", "BchConfirmedTx", "Abort"],
+    ),
+    (
+        "BchLocked",
+        &[
+            "Msg0",
+            "Contract",
+            "EncSig",
+            "BchConfirmedTx",
+            "XmrLockProof",
+        ],
+    ),
+    (
+        "ValidEncSig",
+        &["Msg0", "Contract", "EncSig", "BchConfirmedTx"],
+    ),
+    ("Punish", &["Msg0", "Contract", "BchConfirmedTx"]),
+    ("Refund", &[]),
+    ("Aborted", &["Abort"]),
+];
+
+/// Same for Bob. SetXmrRestoreHeight is applied in every state.
+/// Aborted is the one with the view wallet keys, after Msg0
+const BOB_ACCEPTS: [(&str, &[&str]); 8] = [
+    ("Init", &["Msg0", "SetXmrRestoreHeight", "Abort"]),
+    (
+        "WithAliceKey",
+        &["Msg0", "Contract", "SetXmrRestoreHeight", "Abort"],
+    ),
+    (
+        "ContractMatch",
+        &["Msg0", "Contract", "EncSig", "SetXmrRestoreHeight", "Abort"],
+    ),
+    (
+        "VerifiedEncSig",
+        &[
+            "Msg0",
+            "Contract",
+            "EncSig",
+            "BchConfirmedTx",
+            "XmrLockVerified",
+            "XmrLockProof",
+            "SetXmrRestoreHeight",
+        ],
+    ),
+    (
+        "MoneroLocked",
+        &[
+            "Msg0",
+            "Contract",
+            "EncSig",
+            "BchConfirmedTx",
+            "XmrLockVerified",
+            "XmrLockReorged",
+            "XmrLockProof",
+            "SetXmrRestoreHeight",
+        ],
+    ),
+    (
+        "ProceedRefund",
+        &["Msg0", "Contract", "EncSig", "SetXmrRestoreHeight"],
+    ),
+    (
+        "Aborted",
+        &["Msg0", "Contract", "SetXmrRestoreHeight", "Abort"],
+    ),
+    ("SwapSuccess", &["SetXmrRestoreHeight"]),
+];
+
+fn accepts(table: &[(&str, &[&str])], state: &str, transition: &str) -> bool {
+    let (_, accepted) = table
+        .iter()
+        .find(|(kind, _)| *kind == state)
+        .unwrap_or_else(|| panic!("{state} missing from the table"));
+    accepted.contains(&transition)
+}
+
+fn swap() -> Swap {
+    let secp = bitcoincash::secp256k1::Secp256k1::signing_only();
+    let recv = random_private_key(Network::Regtest)
+        .public_key(&secp)
+        .pubkey_hash();
+    Swap::builder(
+        "matrix".to_owned(),
+        Network::Regtest,
+        monero::Network::Stagenet,
+    )
+    .amounts(
+        bitcoincash::Amount::from_sat(100000),
+        monero::Amount::from_pico(100000),
+    )
+    .timelocks(2, 4)
+    .bch_recv(Script::new_p2pkh(&recv))
+    .build()
+    .unwrap()
+}
+
+fn xmr_lock_block() -> XmrLockBlock {
+    XmrLockBlock {
+        txid: "00".repeat(32),
+        height: 1,
+        block_hash: "00".repeat(32),
+    }
+}
+
+/// One trade driven through every path, keeping each state reached
+struct Trade {
+    alice: Vec<Alice>,
+    bob: Vec<Bob>,
+    funding: Transaction,
+}
+
+impl Trade {
+    fn run() -> Self {
+        let alice_init = Alice::new(swap());
+        let bob_init = Bob::new(swap());
+
+        let bob_keys = bob_init
+            .clone()
+            .transition(alice_init.get_transition().unwrap())
+            .0;
+        let alice_keys = alice_init
+            .clone()
+            .transition(bob_keys.get_transition().unwrap())
+            .0;
+        let bob_contract = bob_keys
+            .clone()
+            .transition(alice_keys.get_transition().unwrap())
+            .0;
+        let alice_contract = alice_keys
+            .clone()
+            .transition(bob_contract.get_transition().unwrap())
+            .0;
+        let bob_verified = bob_contract
+            .clone()
+            .transition(alice_contract.get_transition().unwrap())
+            .0;
+
+        let contract = bob_verified.get_contract_pair().unwrap();
+        let funding = Transaction {
+            version: 2,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: bob_verified.swap.bch_amount.to_sat(),
+                script_pubkey: Script::from(contract.swaplock.locking_script()),
+                token: None,
+            }],
+        };
+        let alice_locked = alice_contract
+            .clone()
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                1,
+                BchBlock::default(),
+            ))
+            .0;
+        let amount = bob_verified.swap.xmr_amount;
+        let bob_xmr_locked = bob_verified
+            .clone()
+            .transition(Transition::XmrLockVerified(amount, xmr_lock_block()))
+            .0;
+        let alice_claimed = alice_locked
+            .clone()
+            .transition(bob_xmr_locked.get_transition().unwrap())
+            .0;
+        let claim = alice_claimed.get_unlock_normal_txs().remove(0);
+        let bob_success = bob_xmr_locked
+            .clone()
+            .transition(Transition::BchConfirmedTx(claim, 1, BchBlock::default()))
+            .0;
+
+        // Bob refunds once timelock1 expired, Alice punishes after timelock2
+        let timelock1 = bob_verified.swap.timelock1;
+        let bob_refund = bob_verified
+            .clone()
+            .transition(Transition::BchConfirmedTx(
+                funding.clone(),
+                timelock1,
+                BchBlock::default(),
+            ))
+            .0;
+        let (to_refund, to_bob) = bob_refund.refund().unwrap().remove(0);
+        let alice_refund = alice_locked
+            .clone()
+            .transition(Transition::BchConfirmedTx(to_bob, 1, BchBlock::default()))
+            .0;
+        let timelock2 = alice_locked.swap.timelock2;
+        let alice_punish = alice_locked
+            .clone()
+            .transition(Transition::BchConfirmedTx(
+                to_refund,
+                timelock2,
+                BchBlock::default(),
+            ))
+            .0;
+
+        let alice_aborted = alice_contract.clone().transition(Transition::Abort).0;
+        let bob_aborted = bob_contract.clone().transition(Transition::Abort).0;
+
+        Trade {
+            alice: vec![
+                alice_init,
+                alice_keys,
+                alice_contract,
+                alice_locked,
+                alice_claimed,
+                alice_punish,
+                alice_refund,
+                alice_aborted,
+            ],
+            bob: vec![
+                bob_init,
+                bob_keys,
+                bob_contract,
+                bob_verified,
+                bob_xmr_locked,
+                bob_refund,
+                bob_aborted,
+                bob_success,
+            ],
+            funding,
+        }
+    }
+
+    fn alice(&self, kind: &str) -> &Alice {
+        self.alice
+            .iter()
+            .find(|alice| alice_kind(&alice.state) == kind)
+            .unwrap()
+    }
+
+    fn bob(&self, kind: &str) -> &Bob {
+        self.bob
+            .iter()
+            .find(|bob| bob_kind(&bob.state) == kind)
+            .unwrap()
+    }
+
+    /// One of each transition, the messages sent by `peer_msg0`,
+    /// `peer_contract` and `peer_enc_sig` in their states
+    fn transitions(
+        &self,
+        peer_msg0: Transition,
+        peer_contract: Transition,
+        peer_enc_sig: Transition,
+    ) -> Vec<Transition> {
+        vec![
+            Transition::KeyCommitment([0; 32]),
+            peer_msg0,
+            peer_contract,
+            peer_enc_sig,
+            Transition::DecSig(ecdsa_fun::Signature::from_bytes([1; 64]).unwrap()),
+            Transition::BchConfirmedTx(self.funding.clone(), 1, BchBlock::default()),
+            Transition::XmrLockVerified(monero::Amount::from_pico(100000), xmr_lock_block()),
+            Transition::XmrLockReorged,
+            Transition::XmrLockProof(XmrLockProof {
+                txid: "00".repeat(32),
+                tx_key: "11".repeat(32),
+            }),
+            Transition::SetXmrRestoreHeight(1),
+            Transition::Abort,
+        ]
+    }
+
+    /// Sent by Bob
+    fn to_alice(&self) -> Vec<Transition> {
+        self.transitions(
+            self.bob("WithAliceKey").get_transition().unwrap(),
+            self.bob("ContractMatch").get_transition().unwrap(),
+            self.bob("MoneroLocked").get_transition().unwrap(),
+        )
+    }
+
+    /// Sent by Alice
+    fn to_bob(&self) -> Vec<Transition> {
+        self.transitions(
+            self.alice("Init").get_transition().unwrap(),
+            self.alice("WithBobKeys").get_transition().unwrap(),
+            self.alice("ContractMatch").get_transition().unwrap(),
+        )
+    }
+}
+
+#[test]
+fn should_cover_every_variant() {
+    let trade = Trade::run();
+    let kinds =
+        |transitions: Vec<Transition>| transitions.iter().map(transition_kind).collect::<Vec<_>>();
+    assert_eq!(kinds(trade.to_alice()), TRANSITIONS);
+    assert_eq!(kinds(trade.to_bob()), TRANSITIONS);
+
+    let alice: Vec<_> = trade.alice.iter().map(|a| alice_kind(&a.state)).collect();
+    let bob: Vec<_> = trade.bob.iter().map(|b| bob_kind(&b.state)).collect();
+    assert_eq!(alice, ALICE_ACCEPTS.map(|(kind, _)| kind));
+    assert_eq!(bob, BOB_ACCEPTS.map(|(kind, _)| kind));
+}
+
+#[test]
+fn should_reach_terminal_states() {
+    let trade = Trade::run();
+    // claimed by Alice, Bob recovers the XMR key from her claim
+    assert!(matches!(
+        trade.bob("SwapSuccess").state,
+        bob::State::SwapSuccess(..)
+    ));
+    assert!(trade.bob("SwapSuccess").get_transition().is_none());
+    // refunded by Bob, Alice recovers the XMR key from his refund
+    assert!(trade.bob("ProceedRefund").refund().is_some());
+    assert!(trade.alice("Refund").get_transition().is_none());
+    assert_eq!(trade.alice("Punish").punish().len(), 1);
+    assert!(matches!(
+        trade.alice("Aborted").get_transition(),
+        Some(Transition::Abort)
+    ));
+    assert!(matches!(
+        trade.bob("Aborted").get_transition(),
+        Some(Transition::Abort)
+    ));
+}
+
+#[test]
+fn should_reject_unexpected_alice_transitions() {
+    let trade = Trade::run();
+    for alice in &trade.alice {
+        let state = alice_kind(&alice.state);
+        for transition in trade.to_alice() {
+            let kind = transition_kind(&transition);
+            let before = serde_json::to_value(&alice.state).unwrap();
+            let (after, actions, error) = alice.clone().transition(transition);
+
+            if accepts(&ALICE_ACCEPTS, state, kind) {
+                assert!(error.is_none(), "Alice {state} + {kind}: {error:?}");
+                continue;
+            }
+            assert!(
+                matches!(error, Some(Error::InvalidStateTransition)),
+                "Alice {state} + {kind}: {error:?}"
+            );
+            assert!(actions.is_empty(), "Alice {state} + {kind}: {actions:?}");
+            assert_eq!(serde_json::to_value(&after.state).unwrap(), before);
+        }
+    }
+}
+
+#[test]
+fn should_reject_unexpected_bob_transitions() {
+    let trade = Trade::run();
+    for bob in &trade.bob {
+        let state = bob_kind(&bob.state);
+        for transition in trade.to_bob() {
+            let kind = transition_kind(&transition);
+            let before = serde_json::to_value(&bob.state).unwrap();
+            let (after, actions, error) = bob.clone().transition(transition);
+
+            if accepts(&BOB_ACCEPTS, state, kind) {
+                assert!(error.is_none(), "Bob {state} + {kind}: {error:?}");
+                continue;
+            }
+            assert!(
+                matches!(error, Some(Error::InvalidStateTransition)),
+                "Bob {state} + {kind}: {error:?}"
+            );
+            assert!(actions.is_empty(), "Bob {state} + {kind}: {actions:?}");
+            assert_eq!(serde_json::to_value(&after.state).unwrap(), before);
+        }
+    }
+}
+
+/// `chart` against the tables, and each edge applied to the samples unless it
+/// depends on the transaction or needs `Swap::key_commitment`
+fn check_chart<S: SwapEvents<State = S> + Clone>(
+    chart: &Chart,
+    table: &[(&str, &[&str])],
+    samples: &[S],
+    kind: impl Fn(&S) -> &'static str,
+    transitions: impl Fn() -> Vec<Transition>,
+) {
+    let states: Vec<_> = samples.iter().map(&kind).collect();
+    assert_eq!(chart.states, states);
+    assert!(chart.unreachable().is_empty(), "{:?}", chart.unreachable());
+
+    for edge in chart.edges {
+        if matches!(edge.transition, "KeyCommitment" | "BchConfirmedTx") {
+            continue;
+        }
+        assert!(accepts(table, edge.from, edge.transition), "{edge:?}");
+
+        let from = samples.iter().find(|s| kind(s) == edge.from).unwrap();
+        let transition = transitions()
+            .into_iter()
+            .find(|t| transition_kind(t) == edge.transition)
+            .unwrap();
+        let (to, actions, error) = from.clone().transition(transition);
+        assert!(error.is_none(), "{edge:?}: {error:?}");
+        assert_eq!(kind(&to), edge.to, "{edge:?}");
+        let actions: Vec<_> = actions.iter().map(action_kind).collect();
+        assert_eq!(actions, edge.actions, "{edge:?}");
+    }
+}
+
+#[test]
+fn should_match_statechart() {
+    let trade = Trade::run();
+    check_chart(
+        &statechart::ALICE,
+        &ALICE_ACCEPTS,
+        &trade.alice,
+        |alice| alice_kind(&alice.state),
+        || trade.to_alice(),
+    );
+    check_chart(
+        &statechart::BOB,
+        &BOB_ACCEPTS,
+        &trade.bob,
+        |bob| bob_kind(&bob.state),
+        || trade.to_bob(),
+    );
+    assert_eq!(
+        statechart::ALICE.terminal(),
+        ["ValidEncSig", "Punish", "Refund", "Aborted"]
+    );
+    assert_eq!(
+        statechart::BOB.terminal(),
+        ["ProceedRefund", "Aborted", "SwapSuccess"]
+    );
+}
@@ -0,0 +1,64 @@
+use tokio::sync::Mutex;
+
+/// monero-wallet-rpc instances shared by the trades. An instance serves one
+/// open wallet at a time, its `Mutex` queues the trades using it.
+/// A trade always maps to the same instance so its wallet files are never
+/// opened by two instances, even when they share the wallet dir
+pub struct WalletPool {
+    wallets: Vec<Mutex<monero_rpc::WalletClient>>,
+}
+
+impl WalletPool {
+    /// Panics without any wallet
+    pub fn new(wallets: Vec<monero_rpc::WalletClient>) -> Self {
+        assert!(!wallets.is_empty(), "WalletPool without wallet");
+        WalletPool {
+            wallets: wallets.into_iter().map(Mutex::new).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.wallets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.wallets.is_empty()
+    }
+
+    /// Instance serving `trade_id`
+    pub fn get(&self, trade_id: &str) -> &Mutex<monero_rpc::WalletClient> {
+        &self.wallets[index(trade_id, self.wallets.len())]
+    }
+
+    /// Close the wallet open on every instance, once the trades are done with
+    /// them. monero-wallet-rpc saves it on close
+    pub async fn close_all(&self) {
+        for wallet in &self.wallets {
+            // fails when none is open
+            let _ = wallet.lock().await.close_wallet().await;
+        }
+    }
+}
+
+/// FNV-1a, stable across restarts and rust versions
+fn index(trade_id: &str, len: usize) -> usize {
+    let hash = trade_id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    (hash % len as u64) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::index;
+
+    #[test]
+    fn should_keep_trade_on_its_instance() {
+        assert_eq!(index("aBcDeFgHiJ", 1), 0);
+        assert_eq!(index("aBcDeFgHiJ", 4), index("aBcDeFgHiJ", 4));
+
+        let used: std::collections::HashSet<_> =
+            (0..100).map(|i| index(&format!("trade{i}"), 4)).collect();
+        assert_eq!(used.len(), 4);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    path::PathBuf,
+    process::Stdio,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::monerod::{post_json, RpcLogin};
+use tokio::{
+    io,
+    process::{Child, Command},
+    sync::Mutex,
+    time::{sleep, timeout},
+};
+
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Consecutive failed health checks before the process is restarted
+const MAX_FAILED_CHECKS: u32 = 3;
+const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+pub struct WalletRpcConfig {
+    /// monero-wallet-rpc executable, looked up in PATH by default
+    pub binary: PathBuf,
+    /// e.g. http://localhost:18081
+    pub daemon_address: String,
+    pub port: u16,
+    pub wallet_dir: PathBuf,
+    /// `--trusted-daemon` instead of `--untrusted-daemon`
+    pub trusted_daemon: bool,
+    /// SOCKS5 `host:port` the wallet reaches the daemon through
+    pub proxy: Option<String>,
+    /// `--daemon-login`
+    pub daemon_login: Option<RpcLogin>,
+    /// `--rpc-login` required from the clients, login disabled when None
+    pub login: Option<RpcLogin>,
+    pub extra_args: Vec<String>,
+}
+
+impl Default for WalletRpcConfig {
+    fn default() -> Self {
+        WalletRpcConfig {
+            binary: PathBuf::from("monero-wallet-rpc"),
+            daemon_address: "http://localhost:18081".to_owned(),
+            port: 8081,
+            wallet_dir: PathBuf::from("wallet_dir"),
+            trusted_daemon: false,
+            proxy: None,
+            daemon_login: None,
+            login: None,
+            extra_args: vec![],
+        }
+    }
+}
+
+/// Runs monero-wallet-rpc as a child process. A supervisor task health checks it
+/// and restarts it when it crashes or stops answering, until `shutdown`
+pub struct WalletRpcManager {
+    config: WalletRpcConfig,
+    child: Mutex<Option<Child>>,
+    client: reqwest::Client,
+    stopped: AtomicBool,
+}
+
+impl WalletRpcManager {
+    /// Launch monero-wallet-rpc and wait until it answers
+    pub async fn start(config: WalletRpcConfig) -> io::Result<Arc<Self>> {
+        let manager = Arc::new(WalletRpcManager {
+            config,
+            child: Mutex::new(None),
+            client: reqwest::Client::new(),
+            stopped: AtomicBool::new(false),
+        });
+        manager.launch().await?;
+
+        tokio::spawn({
+            let manager = manager.clone();
+            async move { manager.supervise().await }
+        });
+
+        Ok(manager)
+    }
+
+    /// Url to build the `monero_rpc` client with
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.config.port)
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(&self.config.binary);
+        match &self.config.login {
+            Some(login) => {
+                command.arg(format!("--rpc-login={}:{}", login.username, login.password))
+            }
+            None => command.arg("--disable-rpc-login"),
+        };
+        if let Some(login) = &self.config.daemon_login {
+            command.arg(format!(
+                "--daemon-login={}:{}",
+                login.username, login.password
+            ));
+        }
+        command
+            .arg("--log-level=1")
+            .arg(format!("--daemon-address={}", self.config.daemon_address))
+            .arg(match self.config.trusted_daemon {
+                true => "--trusted-daemon",
+                false => "--untrusted-daemon",
+            })
+            .arg("--rpc-bind-ip=127.0.0.1")
+            .arg(format!("--rpc-bind-port={}", self.config.port))
+            .arg(format!("--wallet-dir={}", self.config.wallet_dir.display()))
+            .args(
+                self.config
+                    .proxy
+                    .iter()
+                    .map(|proxy| format!("--proxy={proxy}")),
+            )
+            .args(&self.config.extra_args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .kill_on_drop(true);
+        command
+    }
+
+    async fn launch(&self) -> io::Result<()> {
+        let mut child = self.child.lock().await;
+        if let Some(mut old) = child.take() {
+            let _ = old.kill().await;
+        }
+        *child = Some(self.command().spawn()?);
+        drop(child);
+
+        let start = Instant::now();
+        while !self.is_healthy().await {
+            if start.elapsed() > READY_TIMEOUT {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "monero-wallet-rpc not ready",
+                ));
+            }
+            if self.has_exited().await {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "monero-wallet-rpc exited on startup",
+                ));
+            }
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        Ok(())
+    }
+
+    async fn has_exited(&self) -> bool {
+        match self.child.lock().await.as_mut() {
+            Some(child) => !matches!(child.try_wait(), Ok(None)),
+            None => true,
+        }
+    }
+
+    async fn post(&self, method: &str) -> anyhow::Result<reqwest::Response> {
+        post_json(
+            &self.client,
+            &format!("{}/json_rpc", self.url()),
+            self.config.login.as_ref(),
+            &json!({"jsonrpc": "2.0", "id": "0", "method": method}),
+        )
+        .await
+    }
+
+    /// True when the rpc answers `get_version`
+    pub async fn is_healthy(&self) -> bool {
+        let request = self.post("get_version");
+        match timeout(HEALTH_TIMEOUT, request).await {
+            Ok(Ok(response)) => response.status().is_success(),
+            _ => false,
+        }
+    }
+
+    async fn supervise(&self) {
+        let mut failed_checks = 0;
+        loop {
+            sleep(HEALTH_INTERVAL).await;
+            if self.stopped.load(Ordering::SeqCst) {
+                return;
+            }
+
+            if self.has_exited().await {
+                eprintln!("monero-wallet-rpc exited. Restarting");
+                failed_checks = MAX_FAILED_CHECKS;
+            } else if self.is_healthy().await {
+                failed_checks = 0;
+            } else {
+                failed_checks += 1;
+            }
+
+            if failed_checks >= MAX_FAILED_CHECKS && !self.stopped.load(Ordering::SeqCst) {
+                match self.launch().await {
+                    Ok(()) => failed_checks = 0,
+                    Err(e) => eprintln!("monero-wallet-rpc restart failed: {e}"),
+                }
+            }
+        }
+    }
+
+    /// Ask the rpc to save and stop, killing it if it doesn't exit in time
+    pub async fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        let _ = self.post("stop_wallet").await;
+
+        if let Some(mut child) = self.child.lock().await.take() {
+            if timeout(STOP_TIMEOUT, child.wait()).await.is_err() {
+                let _ = child.kill().await;
+            }
+        }
+    }
+}
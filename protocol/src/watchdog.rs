@@ -0,0 +1,50 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long the counterparty may stay silent in a state before the runner
+/// backs out, see `Runner::check_deadlines`. Before anything is locked the trade
+/// is aborted. After, the contract timelocks are the only exit, the chain is
+/// rescanned so the refund or punish path starts as soon as they allow
+#[derive(Debug, Clone, Copy)]
+pub struct Watchdog {
+    pub timeout: Duration,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog {
+            timeout: Duration::from_secs(30 * 60),
+        }
+    }
+}
+
+impl Watchdog {
+    /// `since` the unix time the state was entered, 0 is never expired
+    pub fn expired(&self, since: u64, now: u64) -> bool {
+        since != 0 && now.saturating_sub(since) > self.timeout.as_secs()
+    }
+}
+
+pub(crate) fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Watchdog;
+
+    #[test]
+    fn should_expire_after_timeout_only() {
+        let watchdog = Watchdog {
+            timeout: Duration::from_secs(60),
+        };
+        assert!(!watchdog.expired(1000, 1060));
+        assert!(watchdog.expired(1000, 1061));
+        // trades from before the watchdog
+        assert!(!watchdog.expired(0, 1061));
+    }
+}
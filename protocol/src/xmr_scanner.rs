@@ -0,0 +1,272 @@
+use monero::{Amount, ViewPair};
+use serde_json::{json, Value};
+
+use crate::monerod::{http_client, post_json, RpcLogin, RpcOptions};
+
+/// Confirmations before received outputs can be spent
+pub const UNLOCK_CONFIRMATIONS: u64 = 10;
+
+/// Transactions requested per `/get_transactions` call
+const TRANSACTIONS_BATCH: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum XmrScanError {
+    /// Error returned by monerod or the http layer
+    #[error("monerod: {0}")]
+    Rpc(String),
+    #[error("invalid monerod response: {0}")]
+    InvalidResponse(String),
+}
+
+/// Same meaning as the monero-wallet-rpc `get_balance` fields
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XmrBalance {
+    /// Every confirmed output, mempool excluded
+    pub balance: Amount,
+    /// Outputs with at least `UNLOCK_CONFIRMATIONS`
+    pub unlocked_balance: Amount,
+    /// Of the least confirmed tx paying to the keys, 0 without any
+    pub confirmations: u64,
+}
+
+/// Confirmed transaction paying to the view keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmrTransfer {
+    pub txid: String,
+    /// Sum of the tx outputs owned by the keys
+    pub amount: Amount,
+    pub confirmations: u64,
+}
+
+/// Finds the outputs of a view key with only monerod, replacing a
+/// monero-wallet-rpc view wallet. Blocks are fetched from the restore height
+/// on every scan, swaps only last a few hundred blocks
+pub struct XmrScanner {
+    url: String,
+    client: reqwest::Client,
+    login: Option<RpcLogin>,
+    /// Account 0 subaddresses checked besides the primary address
+    subaddresses: u32,
+}
+
+impl XmrScanner {
+    /// `url` of monerod e.g. http://localhost:18081
+    pub fn new(url: String, options: &RpcOptions) -> Result<Self, XmrScanError> {
+        Ok(XmrScanner {
+            url,
+            client: http_client(options).map_err(|e| XmrScanError::Rpc(e.to_string()))?,
+            login: options.login.clone(),
+            subaddresses: 0,
+        })
+    }
+
+    /// Also recognize funds sent to subaddresses 1..=`count` of the keys,
+    /// some wallets pay to a subaddress derived from the shared keys
+    pub fn with_subaddresses(mut self, count: u32) -> Self {
+        self.subaddresses = count;
+        self
+    }
+
+    async fn post(&self, path: &str, payload: Value) -> Result<Value, XmrScanError> {
+        let url = format!("{}/{path}", self.url.trim_end_matches('/'));
+        post_json(&self.client, &url, self.login.as_ref(), &payload)
+            .await
+            .map_err(|e| XmrScanError::Rpc(e.to_string()))?
+            .json::<Value>()
+            .await
+            .map_err(|e| XmrScanError::InvalidResponse(e.to_string()))
+    }
+
+    async fn json_rpc(&self, method: &str, params: Value) -> Result<Value, XmrScanError> {
+        let payload = json!({"jsonrpc": "2.0", "id": "0", "method": method, "params": params});
+        let mut response = self.post("json_rpc", payload).await?;
+        if !response["error"].is_null() {
+            return Err(XmrScanError::Rpc(response["error"].to_string()));
+        }
+        Ok(response["result"].take())
+    }
+
+    /// Number of blocks, the tip is `height() - 1`
+    pub async fn height(&self) -> Result<u64, XmrScanError> {
+        let result = self.json_rpc("get_block_count", json!({})).await?;
+        result["count"]
+            .as_u64()
+            .ok_or(XmrScanError::InvalidResponse(result.to_string()))
+    }
+
+    async fn block_tx_hashes(&self, height: u64) -> Result<Vec<String>, XmrScanError> {
+        let result = self
+            .json_rpc("get_block", json!({ "height": height }))
+            .await?;
+        // missing when the block only has the coinbase
+        Ok(result["tx_hashes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|hash| hash.as_str().map(|hash| hash.to_owned()))
+            .collect())
+    }
+
+    /// Serialized transactions by hash with the height they were mined at,
+    /// None while in pool
+    async fn transactions(
+        &self,
+        hashes: &[String],
+    ) -> Result<Vec<(String, Vec<u8>, Option<u64>)>, XmrScanError> {
+        let mut txs = Vec::new();
+        for chunk in hashes.chunks(TRANSACTIONS_BATCH) {
+            let response = self
+                .post(
+                    "get_transactions",
+                    json!({ "txs_hashes": chunk, "decode_as_json": false }),
+                )
+                .await?;
+            if response["status"].as_str() != Some("OK") {
+                return Err(XmrScanError::Rpc(response["status"].to_string()));
+            }
+
+            for entry in response["txs"].as_array().into_iter().flatten() {
+                let invalid = || XmrScanError::InvalidResponse(entry.to_string());
+                let height = match entry["in_pool"].as_bool().unwrap_or(false) {
+                    true => None,
+                    false => Some(entry["block_height"].as_u64().ok_or_else(invalid)?),
+                };
+                let tx = hex::decode(entry["as_hex"].as_str().ok_or_else(invalid)?)
+                    .map_err(|_| invalid())?;
+                let hash = entry["tx_hash"].as_str().ok_or_else(invalid)?;
+                txs.push((hash.to_owned(), tx, height));
+            }
+        }
+
+        Ok(txs)
+    }
+
+    /// Transactions paying to `keys` in blocks from `restore_height` to the tip
+    pub async fn transfers(
+        &self,
+        keys: &ViewPair,
+        restore_height: u64,
+    ) -> Result<Vec<XmrTransfer>, XmrScanError> {
+        let count = self.height().await?;
+
+        let mut hashes = Vec::new();
+        for height in restore_height..count {
+            hashes.extend(self.block_tx_hashes(height).await?);
+        }
+
+        let mut transfers = Vec::new();
+        for (txid, tx, height) in self.transactions(&hashes).await? {
+            let height = match height {
+                Some(height) => height,
+                None => continue,
+            };
+            let received = received(&tx, keys, self.subaddresses)?;
+            if received == 0 {
+                continue;
+            }
+            transfers.push(XmrTransfer {
+                txid,
+                amount: Amount::from_pico(received),
+                confirmations: count - height,
+            });
+        }
+
+        Ok(transfers)
+    }
+
+    /// Outputs received by `keys` in blocks from `restore_height` to the tip
+    pub async fn balance(
+        &self,
+        keys: &ViewPair,
+        restore_height: u64,
+    ) -> Result<XmrBalance, XmrScanError> {
+        let transfers = self.transfers(keys, restore_height).await?;
+
+        let balance = transfers.iter().map(|transfer| transfer.amount.as_pico());
+        let unlocked_balance = transfers
+            .iter()
+            .filter(|transfer| transfer.confirmations >= UNLOCK_CONFIRMATIONS)
+            .map(|transfer| transfer.amount.as_pico());
+        Ok(XmrBalance {
+            balance: Amount::from_pico(balance.sum()),
+            unlocked_balance: Amount::from_pico(unlocked_balance.sum()),
+            confirmations: transfers
+                .iter()
+                .map(|transfer| transfer.confirmations)
+                .min()
+                .unwrap_or(0),
+        })
+    }
+
+    /// Pool transactions paying to `keys`, with the amount received
+    pub async fn pool_payments(
+        &self,
+        keys: &ViewPair,
+    ) -> Result<Vec<(String, Amount)>, XmrScanError> {
+        let response = self.post("get_transaction_pool_hashes", json!({})).await?;
+        if response["status"].as_str() != Some("OK") {
+            return Err(XmrScanError::Rpc(response["status"].to_string()));
+        }
+        // missing when the pool is empty
+        let hashes: Vec<String> = response["tx_hashes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|hash| hash.as_str().map(|hash| hash.to_owned()))
+            .collect();
+
+        let mut payments = Vec::new();
+        for (hash, tx, height) in self.transactions(&hashes).await? {
+            // mined since the hashes were listed
+            if height.is_some() {
+                continue;
+            }
+            let received = received(&tx, keys, self.subaddresses)?;
+            if received > 0 {
+                payments.push((hash, Amount::from_pico(received)));
+            }
+        }
+
+        Ok(payments)
+    }
+
+    /// Amount `txid` pays to `keys` and its confirmations, 0 while in pool
+    pub async fn check_tx(
+        &self,
+        keys: &ViewPair,
+        txid: &str,
+    ) -> Result<(Amount, u64), XmrScanError> {
+        let count = self.height().await?;
+        let (_, tx, height) = self
+            .transactions(&[txid.to_owned()])
+            .await?
+            .pop()
+            .ok_or(XmrScanError::Rpc(format!("unknown tx {txid}")))?;
+
+        let confirmations = height.map(|height| count - height).unwrap_or(0);
+        Ok((
+            Amount::from_pico(received(&tx, keys, self.subaddresses)?),
+            confirmations,
+        ))
+    }
+}
+
+#[cfg(not(feature = "serai"))]
+fn received(tx: &[u8], keys: &ViewPair, subaddresses: u32) -> Result<u64, XmrScanError> {
+    let tx = monero::consensus::deserialize::<monero::Transaction>(tx)
+        .map_err(|e| XmrScanError::InvalidResponse(e.to_string()))?;
+    let owned = tx
+        .check_outputs(keys, 0..1, 0..subaddresses.saturating_add(1))
+        .map_err(|e| XmrScanError::InvalidResponse(e.to_string()))?;
+    Ok(owned
+        .iter()
+        .filter_map(|output| output.amount())
+        .map(|amount| amount.as_pico())
+        .sum())
+}
+
+#[cfg(feature = "serai")]
+fn received(tx: &[u8], keys: &ViewPair, subaddresses: u32) -> Result<u64, XmrScanError> {
+    crate::xmr_serai::received(tx, keys, subaddresses)
+        .map_err(|e| XmrScanError::InvalidResponse(e.to_string()))
+}
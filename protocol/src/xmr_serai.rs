@@ -0,0 +1,76 @@
+//! monero-serai counterparts of the monero-rs key handling and output scanning,
+//! enabled with the `serai` feature. Keys stay `monero::ViewPair` in the swap
+//! state, they are converted at the boundary
+
+use anyhow::{anyhow, Context};
+use curve25519_dalek::{edwards::CompressedEdwardsY, Scalar};
+use monero_serai::{
+    transaction::Transaction,
+    wallet::{
+        address::{AddressSpec, Network, SubaddressIndex},
+        Scanner, ViewPair,
+    },
+};
+use zeroize::Zeroizing;
+
+pub fn network(network: monero::Network) -> Network {
+    match network {
+        monero::Network::Mainnet => Network::Mainnet,
+        monero::Network::Stagenet => Network::Stagenet,
+        monero::Network::Testnet => Network::Testnet,
+    }
+}
+
+pub fn view_pair(keys: &monero::ViewPair) -> anyhow::Result<ViewPair> {
+    let spend = CompressedEdwardsY(keys.spend.to_bytes())
+        .decompress()
+        .context("Invalid public spend key")?;
+    let view = Option::from(Scalar::from_canonical_bytes(keys.view.to_bytes()))
+        .context("Invalid private view key")?;
+    Ok(ViewPair::new(spend, Zeroizing::new(view)))
+}
+
+/// Primary address of `keys`, same as `monero::Address::from_viewpair`
+pub fn address(keys: &monero::ViewPair, net: monero::Network) -> anyhow::Result<String> {
+    Ok(view_pair(keys)?
+        .address(network(net), AddressSpec::Standard)
+        .to_string())
+}
+
+/// Piconero the serialized transaction `tx` pays to `keys`, primary address
+/// and subaddresses 1..=`subaddresses` of account 0
+pub fn received(tx: &[u8], keys: &monero::ViewPair, subaddresses: u32) -> anyhow::Result<u64> {
+    let tx = Transaction::read(&mut &tx[..]).map_err(|e| anyhow!("Invalid transaction: {e}"))?;
+
+    let mut scanner = Scanner::from_view(view_pair(keys)?, None);
+    for minor in 1..=subaddresses {
+        if let Some(index) = SubaddressIndex::new(0, minor) {
+            scanner.register_subaddress(index);
+        }
+    }
+
+    Ok(scanner
+        .scan_transaction(&tx)
+        .ignore_timelock()
+        .iter()
+        .map(|output| output.commitment().amount)
+        .sum())
+}
+
+#[cfg(test)]
+mod test {
+    use super::address;
+
+    #[test]
+    fn should_derive_same_address_as_monero_rs() {
+        let keypair = monero::KeyPair {
+            view: monero::PrivateKey::from_slice(&[1; 32]).unwrap(),
+            spend: monero::PrivateKey::from_slice(&[2; 32]).unwrap(),
+        };
+        let keys = monero::ViewPair::from(&keypair);
+        assert_eq!(
+            address(&keys, monero::Network::Stagenet).unwrap(),
+            monero::Address::from_viewpair(monero::Network::Stagenet, &keys).to_string()
+        );
+    }
+}
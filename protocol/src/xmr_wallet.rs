@@ -0,0 +1,470 @@
+use std::{fmt, path::Path};
+
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+use zeroize::Zeroize;
+
+use crate::{monerod::MoneroError, protocol::XmrLockProof, redact::Redacted};
+
+/// Fee priority of the XMR transactions built by the wallet
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum FeePriority {
+    #[default]
+    Default,
+    Low,
+    High,
+}
+
+impl From<FeePriority> for monero_rpc::TransferPriority {
+    fn from(value: FeePriority) -> Self {
+        match value {
+            FeePriority::Default => monero_rpc::TransferPriority::Default,
+            FeePriority::Low => monero_rpc::TransferPriority::Unimportant,
+            FeePriority::High => monero_rpc::TransferPriority::Priority,
+        }
+    }
+}
+
+/// How Alice's XMR lock is built
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LockOptions {
+    pub priority: FeePriority,
+    /// None for the wallet default. Only 16 is accepted by the network
+    /// since the v15 hardfork
+    pub ring_size: Option<u64>,
+}
+
+/// Send `amount` to the shared `address` in a single transaction from the
+/// open wallet. Returns the txid and tx key Bob verifies the lock with
+pub async fn lock(
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    address: monero::Address,
+    amount: monero::Amount,
+    options: LockOptions,
+) -> Result<XmrLockProof, MoneroError> {
+    let transfer = wallet
+        .lock()
+        .await
+        .transfer(
+            [(address, amount)].into(),
+            options.priority.into(),
+            monero_rpc::TransferOptions {
+                ring_size: options.ring_size,
+                ..Default::default()
+            },
+        )
+        .await?;
+
+    Ok(XmrLockProof {
+        txid: transfer.tx_hash.to_string(),
+        tx_key: transfer.tx_key.to_string(),
+    })
+}
+
+/// Bump when a field of `XmrRecovery` changes
+pub const RECOVERY_VERSION: u32 = 1;
+
+/// Keys of a shared wallet the swap left in our control, as a stable json
+/// document any Monero wallet can be restored from
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct XmrRecovery {
+    pub version: u32,
+    pub trade_id: String,
+    /// mainnet, stagenet or testnet
+    pub network: String,
+    pub address: String,
+    pub spend_key: String,
+    pub view_key: String,
+    /// 0 when unknown, the wallet then scans from genesis
+    pub restore_height: u64,
+}
+
+/// Keys redacted, the json document is the export
+impl fmt::Debug for XmrRecovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XmrRecovery")
+            .field("version", &self.version)
+            .field("trade_id", &self.trade_id)
+            .field("network", &self.network)
+            .field("address", &self.address)
+            .field("spend_key", &Redacted)
+            .field("view_key", &Redacted)
+            .field("restore_height", &self.restore_height)
+            .finish()
+    }
+}
+
+impl Drop for XmrRecovery {
+    fn drop(&mut self) {
+        self.spend_key.zeroize();
+        self.view_key.zeroize();
+    }
+}
+
+impl XmrRecovery {
+    pub fn new(
+        trade_id: &str,
+        network: monero::Network,
+        keypair: &monero::KeyPair,
+        restore_height: u64,
+    ) -> Self {
+        let network_name = match network {
+            monero::Network::Mainnet => "mainnet",
+            monero::Network::Stagenet => "stagenet",
+            monero::Network::Testnet => "testnet",
+        };
+        XmrRecovery {
+            version: RECOVERY_VERSION,
+            trade_id: trade_id.to_owned(),
+            network: network_name.to_owned(),
+            address: monero::Address::from_keypair(network, keypair).to_string(),
+            spend_key: keypair.spend.to_string(),
+            view_key: keypair.view.to_string(),
+            restore_height,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn network(&self) -> Result<monero::Network, MoneroError> {
+        match self.network.as_str() {
+            "mainnet" => Ok(monero::Network::Mainnet),
+            "stagenet" => Ok(monero::Network::Stagenet),
+            "testnet" => Ok(monero::Network::Testnet),
+            network => Err(MoneroError::InvalidRecovery(format!(
+                "invalid monero network {network}"
+            ))),
+        }
+    }
+
+    pub fn keypair(&self) -> Result<monero::KeyPair, MoneroError> {
+        let view = self.view_key.parse();
+        let spend = self.spend_key.parse();
+        match (view, spend) {
+            (Ok(view), Ok(spend)) => Ok(monero::KeyPair { view, spend }),
+            (Err(e), _) | (_, Err(e)) => Err(MoneroError::InvalidRecovery(e.to_string())),
+        }
+    }
+
+    /// Regenerate the wallet in monero-wallet-rpc as `{trade_id}_recover`.
+    /// See `restore`
+    pub async fn restore(
+        &self,
+        wallet: &Mutex<monero_rpc::WalletClient>,
+    ) -> Result<RecoveredBalance, MoneroError> {
+        restore(
+            wallet,
+            &format!("{}_recover", self.trade_id),
+            self.network()?,
+            &self.keypair()?,
+            self.restore_height,
+        )
+        .await
+    }
+}
+
+/// What a sweep sends to its destination
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SweepAmount {
+    /// The whole balance, the fee is subtracted from it
+    #[default]
+    All,
+    /// Exactly this, the fee comes on top. The rest stays in the wallet
+    Exact(monero::Amount),
+}
+
+/// Sweep built but not relayed yet
+#[derive(Debug, Clone, Copy)]
+pub struct SweepEstimate {
+    /// Received by the destination
+    pub amount: monero::Amount,
+    pub fee: monero::Amount,
+}
+
+async fn open_or_generate(
+    wallet: &monero_rpc::WalletClient,
+    filename: &str,
+    network: monero::Network,
+    keypair: &monero::KeyPair,
+    restore_height: u64,
+) -> Result<(), MoneroError> {
+    if wallet
+        .open_wallet(filename.to_owned(), Some("".to_owned()))
+        .await
+        .is_ok()
+    {
+        return Ok(());
+    }
+
+    wallet
+        .generate_from_keys(monero_rpc::GenerateFromKeysArgs {
+            address: monero::Address::from_keypair(network, keypair),
+            restore_height: Some(restore_height),
+            autosave_current: Some(true),
+            filename: filename.to_owned(),
+            password: "".to_owned(),
+            spendkey: Some(keypair.spend),
+            viewkey: keypair.view,
+        })
+        .await?;
+    Ok(())
+}
+
+/// Of a wallet regenerated from its keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecoveredBalance {
+    pub balance: monero::Amount,
+    /// Spendable now
+    pub unlocked_balance: monero::Amount,
+}
+
+/// Open the wallet `filename` of `keypair`, creating it at `restore_height`
+/// the first time, and return its balance once refreshed
+pub async fn restore(
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    filename: &str,
+    network: monero::Network,
+    keypair: &monero::KeyPair,
+    restore_height: u64,
+) -> Result<RecoveredBalance, MoneroError> {
+    let wallet = wallet.lock().await;
+    open_or_generate(&wallet, filename, network, keypair, restore_height).await?;
+
+    let balance = async {
+        wallet.refresh(Some(restore_height)).await?;
+        wallet.get_balance(0, None).await
+    }
+    .await;
+    wallet.close_wallet().await?;
+
+    let balance = balance?;
+    Ok(RecoveredBalance {
+        balance: balance.balance,
+        unlocked_balance: balance.unlocked_balance,
+    })
+}
+
+/// Open the wallet `filename` of `keypair`, creating it at `restore_height`
+/// the first time, and send `amount` to `destination`.
+/// The sweep is only relayed if `confirm` accepts its estimate. Returns the
+/// sweep txids, empty when declined
+#[allow(clippy::too_many_arguments)]
+pub async fn sweep<F>(
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    filename: &str,
+    network: monero::Network,
+    keypair: monero::KeyPair,
+    restore_height: u64,
+    destination: monero::Address,
+    amount: SweepAmount,
+    priority: FeePriority,
+    confirm: F,
+) -> Result<Vec<String>, MoneroError>
+where
+    F: FnOnce(&SweepEstimate) -> bool,
+{
+    let wallet = wallet.lock().await;
+    open_or_generate(&wallet, filename, network, &keypair, restore_height).await?;
+
+    wallet.refresh(Some(restore_height)).await?;
+    let result = match amount {
+        SweepAmount::All => relay_sweep(&wallet, destination, priority, confirm).await,
+        SweepAmount::Exact(amount) => {
+            relay_transfer(&wallet, destination, amount, priority, confirm).await
+        }
+    };
+    wallet.close_wallet().await?;
+    result
+}
+
+async fn relay_transfer<F>(
+    wallet: &monero_rpc::WalletClient,
+    destination: monero::Address,
+    amount: monero::Amount,
+    priority: FeePriority,
+    confirm: F,
+) -> Result<Vec<String>, MoneroError>
+where
+    F: FnOnce(&SweepEstimate) -> bool,
+{
+    let unlocked = wallet.get_balance(0, None).await?.unlocked_balance;
+    if unlocked < amount {
+        return Err(MoneroError::InsufficientBalance { unlocked, amount });
+    }
+
+    let transfer = wallet
+        .transfer(
+            [(destination, amount)].into(),
+            priority.into(),
+            monero_rpc::TransferOptions {
+                do_not_relay: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|e| MoneroError::TransferFailed {
+            unlocked,
+            amount,
+            source: e.into(),
+        })?;
+
+    let estimate = SweepEstimate {
+        amount,
+        fee: transfer.fee,
+    };
+    if unlocked < amount + transfer.fee {
+        return Err(MoneroError::FeeNotCovered {
+            unlocked,
+            amount,
+            fee: transfer.fee,
+        });
+    }
+    if !confirm(&estimate) {
+        return Ok(vec![]);
+    }
+
+    Ok(vec![wallet
+        .relay_tx(transfer.tx_metadata.to_string())
+        .await?
+        .to_string()])
+}
+
+async fn relay_sweep<F>(
+    wallet: &monero_rpc::WalletClient,
+    destination: monero::Address,
+    priority: FeePriority,
+    confirm: F,
+) -> Result<Vec<String>, MoneroError>
+where
+    F: FnOnce(&SweepEstimate) -> bool,
+{
+    let sweep = wallet
+        .sweep_all(monero_rpc::SweepAllArgs {
+            address: destination,
+            account_index: 0,
+            subaddr_indices: None,
+            priority: priority.into(),
+            mixin: 15,
+            ring_size: 16,
+            unlock_time: 0,
+            get_tx_keys: None,
+            below_amount: None,
+            do_not_relay: Some(true),
+            get_tx_hex: None,
+            get_tx_metadata: Some(true),
+        })
+        .await?;
+
+    let estimate = SweepEstimate {
+        amount: monero::Amount::from_pico(sweep.amount_list.iter().sum()),
+        fee: monero::Amount::from_pico(sweep.fee_list.iter().sum()),
+    };
+    if !confirm(&estimate) {
+        return Ok(vec![]);
+    }
+
+    let mut txids = Vec::new();
+    for metadata in sweep.tx_metadata_list.into_iter().flatten() {
+        txids.push(wallet.relay_tx(metadata.to_string()).await?.to_string());
+    }
+    Ok(txids)
+}
+
+/// What happens to a trade view wallet once the trade is over
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WalletRetention {
+    /// Leave the wallet files in place
+    #[default]
+    Keep,
+    /// Export the address and view key to `archive/`, then delete the files
+    Archive,
+    Delete,
+}
+
+#[derive(Serialize)]
+struct ArchivedWallet {
+    address: String,
+    view_key: String,
+    restore_height: u64,
+}
+
+/// Close the wallet `filename` and handle its files in `wallet_dir` as set by
+/// `retention`. Returns false when there was nothing to clean
+pub async fn cleanup(
+    wallet: &Mutex<monero_rpc::WalletClient>,
+    wallet_dir: &Path,
+    filename: &str,
+    network: monero::Network,
+    keys: &monero::ViewPair,
+    restore_height: u64,
+    retention: WalletRetention,
+) -> Result<bool, MoneroError> {
+    let path = wallet_dir.join(filename);
+    if retention == WalletRetention::Keep || !fs::try_exists(&path).await? {
+        return Ok(false);
+    }
+
+    // the rpc saves the open wallet back to disk on close
+    let wallet = wallet.lock().await;
+    let _ = wallet.close_wallet().await;
+
+    if retention == WalletRetention::Archive {
+        let archive_dir = wallet_dir.join("archive");
+        fs::create_dir_all(&archive_dir).await?;
+        let archived = ArchivedWallet {
+            address: monero::Address::from_viewpair(network, keys).to_string(),
+            view_key: keys.view.to_string(),
+            restore_height,
+        };
+        fs::write(
+            archive_dir.join(format!("{filename}.json")),
+            serde_json::to_vec_pretty(&archived).map_err(std::io::Error::from)?,
+        )
+        .await?;
+    }
+
+    for extension in ["", ".keys", ".address.txt"] {
+        match fs::remove_file(wallet_dir.join(format!("{filename}{extension}"))).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::XmrRecovery;
+
+    #[test]
+    fn should_export_stable_fields() {
+        let keypair = monero::KeyPair {
+            view: monero::PrivateKey::from_slice(&[1; 32]).unwrap(),
+            spend: monero::PrivateKey::from_slice(&[2; 32]).unwrap(),
+        };
+        let recovery = XmrRecovery::new("trade", monero::Network::Stagenet, &keypair, 42);
+        let json: serde_json::Value = serde_json::from_str(&recovery.to_json()).unwrap();
+
+        let mut fields: Vec<_> = json.as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+        assert_eq!(
+            fields,
+            [
+                "address",
+                "network",
+                "restore_height",
+                "spend_key",
+                "trade_id",
+                "version",
+                "view_key"
+            ]
+        );
+        assert_eq!(json["network"], "stagenet");
+        assert_eq!(json["spend_key"], "02".repeat(32));
+        assert!(recovery.address.starts_with('5'));
+        assert_eq!(recovery.keypair().unwrap(), keypair);
+    }
+}
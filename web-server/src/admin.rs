@@ -0,0 +1,457 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use protocol::{
+    accounting::{Pnl, RateSnapshot},
+    contract::artifact::ContractArtifacts,
+    outcome::SwapOutcome,
+    persist::{Error as PersistError, TradePersist},
+    protocol::{SwapEvents, SwapWrapper, Transition},
+    transcript::Transcript,
+    xmr_wallet::XmrRecovery,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    trader::get_file_path,
+    utils::{ApiResult, Error, JsonRej},
+    TAppState,
+};
+
+pub fn admin(state: TAppState) -> Router {
+    Router::new()
+        .route("/approvals", get(list))
+        .route("/approvals/:trade_id", post(approve))
+        .route("/rates", get(list_rates).post(record_rate))
+        .route("/pnl/:trade_id", get(pnl))
+        .route("/outcome/:trade_id", get(outcome))
+        .route("/transcript/:trade_id", get(transcript))
+        .route("/contracts/:trade_id", get(contracts))
+        .route("/recovery/:trade_id", get(recovery))
+        .route("/recovery/:trade_id/payout", get(payout_recovery))
+        .route("/abort/:trade_id", post(abort))
+        .with_state(state)
+}
+
+// ==========================================
+// SECTION: Approvals
+// ==========================================
+
+struct Pending {
+    amount: u64,
+    created: Instant,
+    approved_by: HashSet<String>,
+}
+
+pub enum Gate {
+    Approved,
+    Pending,
+    /// Nobody approved in time, the swap must be cancelled
+    Expired,
+}
+
+/// M-of-N operator approval before the maker moves funds.
+/// The maker only plays Bob, so only BCH funding is gated
+pub struct Approvals {
+    /// 0 disables the gate
+    required: usize,
+    /// token -> operator name
+    operators: HashMap<String, String>,
+    /// Swaps below this amount (sats) don't need approval
+    min_amount: u64,
+    timeout: Duration,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl Approvals {
+    /// ADMIN_OPERATORS="name:token,name:token"
+    /// ADMIN_APPROVALS_REQUIRED, ADMIN_APPROVAL_MIN_SATS, ADMIN_APPROVAL_TIMEOUT_SECS
+    pub fn from_env() -> Self {
+        let operators = env::var("ADMIN_OPERATORS")
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| entry.split_once(':'))
+            .map(|(name, token)| (token.to_owned(), name.to_owned()))
+            .collect::<HashMap<_, _>>();
+
+        let number = |key: &str, default: u64| {
+            env::var(key)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+
+        Approvals {
+            required: number("ADMIN_APPROVALS_REQUIRED", 0) as usize,
+            min_amount: number("ADMIN_APPROVAL_MIN_SATS", 0),
+            timeout: Duration::from_secs(number("ADMIN_APPROVAL_TIMEOUT_SECS", 3600)),
+            operators,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register the fund movement if unknown and tell if it can proceed
+    pub async fn gate(&self, trade_id: &str, amount: u64) -> Gate {
+        if self.required == 0 || amount < self.min_amount {
+            return Gate::Approved;
+        }
+
+        let mut pending = self.pending.lock().await;
+        let entry = pending.entry(trade_id.to_owned()).or_insert_with(|| {
+            println!("[ADMIN] {trade_id} needs {} approvals", self.required);
+            Pending {
+                amount,
+                created: Instant::now(),
+                approved_by: HashSet::new(),
+            }
+        });
+
+        if entry.approved_by.len() >= self.required {
+            pending.remove(trade_id);
+            return Gate::Approved;
+        }
+
+        if entry.created.elapsed() > self.timeout {
+            pending.remove(trade_id);
+            return Gate::Expired;
+        }
+
+        Gate::Pending
+    }
+
+    /// The trade waits on operators, not on the client: the watchdog leaves
+    /// it be until the approval times out
+    pub async fn awaiting(&self, trade_id: &str) -> bool {
+        let pending = self.pending.lock().await;
+        pending
+            .get(trade_id)
+            .is_some_and(|entry| entry.created.elapsed() <= self.timeout)
+    }
+
+    fn operator(&self, headers: &HeaderMap) -> ApiResult<String> {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .and_then(|token| self.operators.get(token))
+            .cloned()
+            .ok_or(Error::new(
+                StatusCode::UNAUTHORIZED,
+                "Invalid operator token",
+            ))
+    }
+}
+
+#[derive(Serialize)]
+struct PendingResponse {
+    trade_id: String,
+    amount: u64,
+    approved_by: Vec<String>,
+    required: usize,
+    expires_in_secs: u64,
+}
+
+async fn list(
+    State(state): State<TAppState>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<PendingResponse>>> {
+    let approvals = &state.approvals;
+    approvals.operator(&headers)?;
+
+    let pending = approvals.pending.lock().await;
+    let response = pending
+        .iter()
+        .map(|(trade_id, p)| PendingResponse {
+            trade_id: trade_id.clone(),
+            amount: p.amount,
+            approved_by: p.approved_by.iter().cloned().collect(),
+            required: approvals.required,
+            expires_in_secs: approvals
+                .timeout
+                .saturating_sub(p.created.elapsed())
+                .as_secs(),
+        })
+        .collect();
+
+    Ok(Json(response))
+}
+
+async fn approve(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<PendingResponse>> {
+    let approvals = &state.approvals;
+    let operator = approvals.operator(&headers)?;
+
+    let mut pending = approvals.pending.lock().await;
+    let entry = match pending.get_mut(&trade_id) {
+        Some(entry) => entry,
+        None => return Err(Error::new(StatusCode::NOT_FOUND, "No pending approval")),
+    };
+
+    println!("[ADMIN] {operator} approved {trade_id}");
+    entry.approved_by.insert(operator);
+
+    Ok(Json(PendingResponse {
+        trade_id,
+        amount: entry.amount,
+        approved_by: entry.approved_by.iter().cloned().collect(),
+        required: approvals.required,
+        expires_in_secs: approvals
+            .timeout
+            .saturating_sub(entry.created.elapsed())
+            .as_secs(),
+    }))
+}
+
+// ==========================================
+// SECTION: Accounting
+// ==========================================
+
+#[derive(Deserialize)]
+struct RateRequest {
+    source: String,
+    bch_per_xmr: f64,
+}
+
+async fn record_rate(
+    State(state): State<TAppState>,
+    headers: HeaderMap,
+    JsonRej(request): JsonRej<RateRequest>,
+) -> ApiResult<Json<RateSnapshot>> {
+    state.approvals.operator(&headers)?;
+
+    if !request.bch_per_xmr.is_finite() || request.bch_per_xmr <= 0.0 {
+        return Err(Error::new(StatusCode::BAD_REQUEST, "Invalid rate"));
+    }
+
+    let mut rates = state.rates.lock().await;
+    Ok(Json(rates.record(request.source, request.bch_per_xmr)))
+}
+
+async fn list_rates(
+    State(state): State<TAppState>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Vec<RateSnapshot>>> {
+    state.approvals.operator(&headers)?;
+
+    let rates = state.rates.lock().await;
+    Ok(Json(rates.snapshots().cloned().collect()))
+}
+
+async fn pnl(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Pnl>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let pnl = match &trade.config.swap {
+        SwapWrapper::Bob(bob) => trade.config.accounting.pnl(&bob.swap, true),
+        SwapWrapper::Alice(alice) => trade.config.accounting.pnl(&alice.swap, false),
+    };
+
+    pnl.map(Json).ok_or(Error::new(
+        StatusCode::CONFLICT,
+        "Rates not recorded for this trade",
+    ))
+}
+
+/// Amounts, transactions and duration of a finished trade
+async fn outcome(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<SwapOutcome>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let outcome = match &trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.outcome(),
+        SwapWrapper::Alice(alice) => alice.outcome(),
+    };
+
+    outcome
+        .map(Json)
+        .ok_or(Error::new(StatusCode::CONFLICT, "Trade not finished"))
+}
+
+/// Every transition applied to the trade, see `Transcript`
+async fn transcript(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<Transcript>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    Ok(Json(match trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.transcript,
+        SwapWrapper::Alice(alice) => alice.transcript,
+    }))
+}
+
+/// The trade's SwapLock and Refund as CashScript artifacts, see
+/// `ContractPair::export_artifact`
+async fn contracts(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<ContractArtifacts>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let contract_pair = match &trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.get_contract_pair(),
+        SwapWrapper::Alice(alice) => alice.get_contract_pair(),
+    };
+
+    contract_pair
+        .map(|contract_pair| Json(contract_pair.export_artifact()))
+        .ok_or(Error::new(
+            StatusCode::CONFLICT,
+            "Contracts not built in this state",
+        ))
+}
+
+// ==========================================
+// SECTION: Recovery
+// ==========================================
+
+/// Keys of the XMR the trade left to the server, see `XmrRecovery`
+async fn recovery(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<XmrRecovery>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let recovery = match &trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.get_xmr_recovery(),
+        SwapWrapper::Alice(alice) => alice.get_xmr_recovery(),
+    };
+
+    recovery.map(Json).ok_or(Error::new(
+        StatusCode::CONFLICT,
+        "No XMR to recover in this state",
+    ))
+}
+
+/// Keys of the wallet generated for the payout sweep, XMR_PAYOUT_ADDRESS=fresh
+async fn payout_recovery(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<Json<XmrRecovery>> {
+    state.approvals.operator(&headers)?;
+
+    let trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let recovery = match &trade.config.swap {
+        SwapWrapper::Bob(bob) => bob.get_xmr_payout_recovery(),
+        SwapWrapper::Alice(_) => None,
+    };
+
+    recovery.map(Json).ok_or(Error::new(
+        StatusCode::CONFLICT,
+        "The trade has no generated payout wallet",
+    ))
+}
+
+// ==========================================
+// SECTION: Abort
+// ==========================================
+
+/// Back out of a trade before anything is locked. The client receives the
+/// abort with the next transition it fetches
+async fn abort(
+    State(state): State<TAppState>,
+    Path(trade_id): Path<String>,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    state.approvals.operator(&headers)?;
+
+    let mut trade = match TradePersist::restore(get_file_path(&trade_id)).await {
+        Ok(v) => v,
+        Err(PersistError::NotFound) => {
+            return Err(Error::new(StatusCode::NOT_FOUND, "Trade id not found"))
+        }
+        Err(PersistError::Unknown(e)) => return Err(Error::from(e)),
+    };
+
+    let (swap, error) = match trade.config.swap {
+        SwapWrapper::Bob(bob) => {
+            let (bob, _, error) = bob.transition(Transition::Abort);
+            (SwapWrapper::Bob(bob), error)
+        }
+        SwapWrapper::Alice(alice) => {
+            let (alice, _, error) = alice.transition(Transition::Abort);
+            (SwapWrapper::Alice(alice), error)
+        }
+    };
+    trade.config.swap = swap;
+    if error.is_some() {
+        return Err(Error::new(
+            StatusCode::CONFLICT,
+            "Funds may be locked, too late to abort",
+        ));
+    }
+    trade.save().await;
+
+    println!("[{trade_id}]: Aborted by the operator");
+    Ok(StatusCode::OK)
+}
@@ -3,39 +3,86 @@ use std::{env, net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::Router;
 use protocol::{
+    accounting::RateHistory,
     alice,
-    blockchain::{self, TcpElectrum},
+    blockchain::{
+        backend::ChainBackend, bchn::BchnRpc, policy::MempoolPolicy, ElectrumConfig, Notification,
+        TcpElectrum,
+    },
     bob,
+    contract::TokenPolicy,
     keys::bitcoin::Network,
-    monero, monero_rpc,
-    persist::TradePersist,
-    protocol::SwapWrapper,
+    monero,
+    monero_config::{MoneroConfig, MoneroNetwork},
+    monero_rpc,
+    monerod::MonerodPool,
+    persist::{FileStore, SwapStore, TradePersist},
+    protocol::{
+        parse_timelocks, BchConfirmations, OperatorFee, OverpaymentPolicy, SwapWrapper,
+        DEFAULT_XMR_CONFIRMATIONS,
+    },
+    shutdown::{pending_swaps, PendingSwap, Shutdown},
+    wallet_pool::WalletPool,
+    wallet_rpc::WalletRpcManager,
+    watchdog::Watchdog,
+    xmr_scanner::XmrScanner,
+    xmr_wallet::{FeePriority, LockOptions, WalletRetention},
 };
 use serde_json::json;
-use tokio::{fs, net::TcpStream, sync::Mutex, time::sleep};
+use tokio::{fs, sync::Mutex, task::JoinSet};
 
 use trader::get_file_path;
 
+mod admin;
 mod trader;
 pub mod utils;
 
 pub struct AppState {
-    bch_server: TcpElectrum,
-    monerod: monero_rpc::DaemonJsonRpcClient,
-    monero_wallet: Mutex<monero_rpc::WalletClient>,
+    bch_server: Box<dyn ChainBackend>,
+    monerod: MonerodPool,
+    monero_wallets: WalletPool,
+    /// Replaces `monero_wallets` when set
+    xmr_scanner: Option<XmrScanner>,
+    /// Reports XMR locks before they are mined
+    xmr_pool: Option<XmrScanner>,
     bch_min_conf: u32,
-    monero_network: monero::Network,
+    monero_config: MoneroConfig,
     bch_network: Network,
 
     timelock1: u32,
     timelock2: u32,
+    /// Required on the XMR lock of every new trade
+    xmr_confirmations: u64,
+    xmr_overpayment: OverpaymentPolicy,
+    token_policy: TokenPolicy,
+    /// Taken from the claims of every trade, clients must agree to it
+    operator_fee: Option<OperatorFee>,
+    /// Offer contract version 5 to clients, see `Swap::strict_contract`
+    strict_contract: bool,
+    bch_confirmations: BchConfirmations,
+    xmr_payout_address: Option<monero::Address>,
+    /// Sweep to a wallet generated for each trade instead, see `Swap::xmr_payout_keys`
+    fresh_xmr_payout: bool,
+    xmr_fee_priority: FeePriority,
+    /// Sends the XMR of "bch->xmr" trades, those are refused without it
+    xmr_lock_wallet: Option<Mutex<monero_rpc::WalletClient>>,
+    view_wallet_retention: WalletRetention,
+    /// Backs out of trades the client stopped answering
+    watchdog: Watchdog,
+
+    /// Checkpoints of every trade, apart from the trade files
+    store: FileStore,
+    approvals: admin::Approvals,
+    rates: Mutex<RateHistory>,
 }
 
 type TAppState = Arc<AppState>;
 
+/// Trades run concurrently, only those sharing a monero-wallet-rpc instance wait on each other
 async fn check_xmr_wallets(state: &TAppState) {
     let base_path = "./.trades/ongoing/";
     let mut entries = fs::read_dir(base_path).await.unwrap();
+    let mut checks = JoinSet::new();
     while let Some(entry) = entries.next_entry().await.unwrap() {
         if !entry.path().is_file() {
             continue;
@@ -46,26 +93,73 @@ async fn check_xmr_wallets(state: &TAppState) {
         }
 
         let trade_id = filename.split("-").next().unwrap().to_string();
-        let mut trade = TradePersist::restore(get_file_path(&trade_id))
-            .await
-            .unwrap();
-        match trade.config.swap {
-            SwapWrapper::Bob(inner) => {
-                let mut runner = bob::Runner {
-                    inner,
-                    trade_id,
-                    bch: &state.bch_server,
-                    monero_wallet: &state.monero_wallet,
-                    monerod: &state.monerod,
-                    min_bch_conf: state.bch_min_conf,
-                };
-                let _ = runner.check_xmr().await;
-                trade.config.swap = SwapWrapper::Bob(runner.inner);
+        let state = state.clone();
+        checks.spawn(async move { check_xmr_wallet(&state, trade_id).await });
+    }
+    while let Some(check) = checks.join_next().await {
+        if let Err(e) = check {
+            eprintln!("XMR check failed: {e}");
+        }
+    }
+}
+
+async fn check_xmr_wallet(state: &TAppState, trade_id: String) {
+    let mut trade = TradePersist::restore(get_file_path(&trade_id))
+        .await
+        .unwrap();
+    match trade.config.swap {
+        SwapWrapper::Bob(inner) => {
+            let mut runner = bob::Runner {
+                inner,
+                monero_wallet: state.monero_wallets.get(&trade_id),
+                trade_id,
+                bch: state.bch_server.as_ref(),
+                monerod: &state.monerod,
+                xmr_scanner: state.xmr_scanner.as_ref(),
+                min_bch_conf: state.bch_min_conf,
+                mempool_policy: Some(MempoolPolicy::default()),
+                store: Some(&state.store),
+                events: None,
+                effects: None,
+                clock: None,
+            };
+            if let Some(pool) = &state.xmr_pool {
+                for event in runner.xmr_pool_events(pool).await.unwrap_or_default() {
+                    println!("[{}]: {event}", runner.trade_id);
+                }
+            }
+            for event in runner.check_xmr().await.unwrap_or_default() {
+                println!("[{}]: {event}", runner.trade_id);
+            }
+            // ADMIN_APPROVAL_TIMEOUT_SECS may outlast SWAP_SILENCE_TIMEOUT
+            let deadlines = if state.approvals.awaiting(&runner.trade_id).await {
+                Ok(None)
+            } else {
+                runner.check_deadlines(&state.watchdog).await
+            };
+            match deadlines {
+                Ok(Some(event)) => println!("[{}]: {event}", runner.trade_id),
+                Ok(None) => {}
+                Err(e) => eprintln!("[{}]: Deadline check failed: {e}", runner.trade_id),
             }
-            _ => {}
+            if let Err(e) = runner.sweep_payout().await {
+                eprintln!("[{}]: XMR sweep failed: {e}", runner.trade_id);
+            }
+            match runner
+                .cleanup_view_wallet(&state.monero_config.wallet_dir, state.view_wallet_retention)
+                .await
+            {
+                Ok(true) => println!("[{}]: XMR view wallet cleaned", runner.trade_id),
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!("[{}]: XMR view wallet cleanup failed: {e}", runner.trade_id)
+                }
+            }
+            trade.config.swap = SwapWrapper::Bob(runner.inner);
         }
-        trade.save().await;
+        _ => {}
     }
+    trade.save().await;
 }
 
 async fn check_bch_wallets(state: &TAppState) {
@@ -88,23 +182,70 @@ async fn check_bch_wallets(state: &TAppState) {
         match trade.config.swap {
             SwapWrapper::Bob(bob) => {
                 let mut runner = bob::Runner {
+                    monero_wallet: state.monero_wallets.get(&trade_id),
                     trade_id,
                     inner: bob,
-                    bch: &state.bch_server,
+                    bch: state.bch_server.as_ref(),
                     min_bch_conf: state.bch_min_conf,
                     monerod: &state.monerod,
-                    monero_wallet: &state.monero_wallet,
+                    xmr_scanner: state.xmr_scanner.as_ref(),
+                    mempool_policy: Some(MempoolPolicy::default()),
+                    store: Some(&state.store),
+                    events: None,
+                    effects: None,
+                    clock: None,
                 };
+                for event in runner.mempool_events().await.unwrap_or_default() {
+                    println!("[{}]: {event}", runner.trade_id);
+                }
                 let _ = runner.check_bch().await;
+                let reorg = &mut trade.config.reorg;
+                for event in runner.check_reorg(reorg).await.unwrap_or_default() {
+                    println!("[{}]: {event}", runner.trade_id);
+                }
+
+                let settled = matches!(runner.inner.state, bob::State::SwapSuccess(..));
+                let accounting = &mut trade.config.accounting;
+                if settled && accounting.settlement.is_none() {
+                    accounting.settlement = state.rates.lock().await.latest();
+                    if let Some(pnl) = accounting.pnl(&runner.inner.swap, true) {
+                        println!("[{}]: {:?}", runner.trade_id, pnl);
+                    }
+                }
                 trade.config.swap = SwapWrapper::Bob(runner.inner);
             }
             SwapWrapper::Alice(alice) => {
                 let mut runner = alice::Runner {
                     inner: alice,
-                    bch: &state.bch_server,
+                    bch: state.bch_server.as_ref(),
+                    monero_wallet: state.xmr_lock_wallet.as_ref(),
+                    xmr_lock_options: LockOptions {
+                        priority: state.xmr_fee_priority,
+                        ring_size: None,
+                    },
                     min_bch_conf: state.bch_min_conf,
+                    zero_conf: false,
+                    mempool_policy: Some(MempoolPolicy::default()),
+                    store: Some(&state.store),
+                    events: None,
+                    effects: None,
+                    clock: None,
                 };
                 let _ = runner.check_bch().await;
+                let reorg = &mut trade.config.reorg;
+                for event in runner.check_reorg(reorg).await.unwrap_or_default() {
+                    println!("[{trade_id}]: {event}");
+                }
+                match runner.check_deadlines(&state.watchdog).await {
+                    Ok(Some(event)) => println!("[{trade_id}]: {event}"),
+                    Ok(None) => {}
+                    Err(e) => eprintln!("[{trade_id}]: Deadline check failed: {e}"),
+                }
+                // in a swap wallet, the lock wallet stays open
+                let wallet = state.monero_wallets.get(&trade_id);
+                if let Err(e) = runner.sweep_payout(wallet).await {
+                    eprintln!("[{trade_id}]: XMR sweep failed: {e}");
+                }
                 trade.config.swap = SwapWrapper::Alice(runner.inner);
             }
         }
@@ -112,70 +253,254 @@ async fn check_bch_wallets(state: &TAppState) {
     }
 }
 
+/// Once the loops stopped: checkpoint every ongoing trade from its file, so
+/// the store holds the latest state, and list the unfinished ones
+async fn flush_trades(state: &TAppState) -> Vec<PendingSwap> {
+    let base_path = "./.trades/ongoing/";
+    let mut entries = fs::read_dir(base_path).await.unwrap();
+    let mut swaps = Vec::new();
+    while let Some(entry) = entries.next_entry().await.unwrap() {
+        let filename = entry.file_name().into_string().unwrap();
+        if !entry.path().is_file() || !filename.ends_with("-server.json") {
+            continue;
+        }
+
+        let trade_id = filename.split("-").next().unwrap().to_string();
+        let trade = TradePersist::restore(get_file_path(&trade_id))
+            .await
+            .unwrap();
+        if let Err(e) = state.store.save(&trade_id, &trade.config.swap).await {
+            eprintln!("[{trade_id}]: Checkpoint failed: {e}");
+        }
+        swaps.push((trade_id, trade.config.swap));
+    }
+    pending_swaps(swaps)
+}
+
 #[tokio::main]
 async fn main() {
     let bch_min_conf = 1;
 
-    let monerod_addr = "http://localhost:18081";
-    let monero_wallet_addr = "http://localhost:8081";
+    // MONERO_NETWORK, MONEROD.. see `MoneroConfig::from_env`
+    let monero_config = MoneroConfig::from_env().unwrap();
+    // MONERO_WALLET_RPC=url,url.. to spread the trades over several instances
+    let monero_wallet_addr =
+        env::var("MONERO_WALLET_RPC").unwrap_or("http://localhost:8081".to_owned());
     let fullcrum_tcp = "localhost:50001";
 
-    let monero_network = monero::Network::Mainnet;
     let bch_network = Network::Regtest;
 
-    let timelock1 = 2;
-    let timelock2 = 2;
+    // SWAP_TIMELOCKS=timelock1,timelock2 in blocks or wall clock, e.g. 2,4 or 1h,2h
+    let (timelock1, timelock2) = env::var("SWAP_TIMELOCKS")
+        .map(|timelocks| parse_timelocks(&timelocks).unwrap())
+        .unwrap_or((2, 4));
+
+    let xmr_confirmations = match monero_config.network {
+        MoneroNetwork::Mainnet => DEFAULT_XMR_CONFIRMATIONS,
+        _ => 1,
+    };
+    // XMR_OVERPAYMENT=refund to walk away from trades locking too much
+    let xmr_overpayment = match env::var("XMR_OVERPAYMENT").as_deref() {
+        Ok("refund") => OverpaymentPolicy::Refund,
+        _ => OverpaymentPolicy::Accept,
+    };
+    // BCH_TOKENS=preserve to take fundings carrying CashTokens, and hand the tokens on
+    let token_policy = match env::var("BCH_TOKENS").as_deref() {
+        Ok("preserve") => TokenPolicy::Preserve,
+        _ => TokenPolicy::Refuse,
+    };
+    // OPERATOR_FEE=address,sats or address,bps e.g. bchreg:qq..,50bps
+    let operator_fee: Option<OperatorFee> = env::var("OPERATOR_FEE")
+        .ok()
+        .map(|fee| fee.parse().unwrap());
+    // STRICT_CONTRACT=1 locks in SwapLocks only claimable for the exact amount,
+    // with clients offering it too
+    let strict_contract = env::var("STRICT_CONTRACT").is_ok();
+    // BCH_CONFIRMATIONS=funding,enc_sig,claim e.g. 1,3,6, see `BchConfirmations`
+    let bch_confirmations: BchConfirmations = env::var("BCH_CONFIRMATIONS")
+        .map(|confirmations| confirmations.parse().unwrap())
+        .unwrap_or_default();
+    // claimed XMR is swept there automatically, `fresh` for a new wallet per trade
+    let fresh_xmr_payout = env::var("XMR_PAYOUT_ADDRESS").as_deref() == Ok("fresh");
+    let xmr_payout_address = env::var("XMR_PAYOUT_ADDRESS")
+        .ok()
+        .filter(|_| !fresh_xmr_payout)
+        .map(|address| address.parse::<monero::Address>().unwrap());
+    // XMR_FEE_PRIORITY=low|high for the payout sweep and the XMR locks
+    let xmr_fee_priority = match env::var("XMR_FEE_PRIORITY").as_deref() {
+        Ok("low") => FeePriority::Low,
+        Ok("high") => FeePriority::High,
+        _ => FeePriority::Default,
+    };
+
+    // MONERO_LOCK_WALLET_RPC=url of a funded wallet to also take the "bch->xmr" trades,
+    // the server then locks the XMR and the client the BCH
+    let xmr_lock_wallet = match env::var("MONERO_LOCK_WALLET_RPC") {
+        Ok(url) => Some(Mutex::new(monero_config.wallet(&url).unwrap())),
+        Err(_) => None,
+    };
+
+    // XMR_VIEW_RETENTION=archive|delete to clean the view wallets of finished trades,
+    // archive keeps their address and view key in `{wallet_dir}/archive`
+    let view_wallet_retention = match env::var("XMR_VIEW_RETENTION").as_deref() {
+        Ok("archive") => WalletRetention::Archive,
+        Ok("delete") => WalletRetention::Delete,
+        _ => WalletRetention::Keep,
+    };
 
     // ===================================================
 
-    let monerod = monero_rpc::RpcClientBuilder::new()
-        .build(monerod_addr)
-        .unwrap()
-        .daemon();
-    let monero_wallet = Mutex::new(
-        monero_rpc::RpcClientBuilder::new()
-            .build(monero_wallet_addr)
-            .unwrap()
-            .wallet(),
+    let monerod = monero_config.monerod().unwrap();
+    // monero-wallet-rpc is launched and supervised by the server when its binary is given,
+    // MONERO_WALLET_RPC_INSTANCES=N spreads the trades over N of them
+    let mut wallet_rpcs = Vec::new();
+    let mut monero_wallet_addrs: Vec<String> =
+        monero_wallet_addr.split(',').map(String::from).collect();
+    if let Ok(binary) = env::var("MONERO_WALLET_RPC_BIN") {
+        let instances = env::var("MONERO_WALLET_RPC_INSTANCES")
+            .map(|instances| instances.parse::<u16>().unwrap())
+            .unwrap_or(1);
+        monero_wallet_addrs.clear();
+        for port in 8081..8081 + instances {
+            let manager =
+                WalletRpcManager::start(monero_config.wallet_rpc(binary.clone().into(), port))
+                    .await
+                    .unwrap();
+            monero_wallet_addrs.push(manager.url());
+            wallet_rpcs.push(manager);
+        }
+    }
+    let monero_wallets = WalletPool::new(
+        monero_wallet_addrs
+            .iter()
+            .map(|url| monero_config.wallet(url).unwrap())
+            .collect(),
     );
+    // XMR_SUBADDRESSES=N to also accept locks paid to the first N subaddresses
+    // SWAP_SILENCE_TIMEOUT=minutes a client may stay silent in a state, 30 by default
+    let watchdog = match env::var("SWAP_SILENCE_TIMEOUT") {
+        Ok(minutes) => Watchdog {
+            timeout: Duration::from_secs(minutes.parse::<u64>().unwrap() * 60),
+        },
+        Err(_) => Watchdog::default(),
+    };
+    let xmr_subaddresses = env::var("XMR_SUBADDRESSES")
+        .map(|count| count.parse::<u32>().unwrap())
+        .unwrap_or(0);
+    // monero-wallet-rpc isn't needed when XMR_VIEW_SCAN is set
+    let xmr_scanner = env::var("XMR_VIEW_SCAN").ok().map(|_| {
+        monero_config
+            .scanner()
+            .unwrap()
+            .with_subaddresses(xmr_subaddresses)
+    });
+    let xmr_pool = env::var("XMR_POOL_WATCH").ok().map(|_| {
+        monero_config
+            .scanner()
+            .unwrap()
+            .with_subaddresses(xmr_subaddresses)
+    });
 
-    let socket = TcpStream::connect(fullcrum_tcp).await.unwrap();
-    let bch_server = blockchain::TcpElectrum::new(socket);
+    // a full node replaces electrum when BCHN_RPC is set, blocks are then polled
+    let (bch_server, electrum): (Box<dyn ChainBackend>, _) = match env::var("BCHN_RPC") {
+        Ok(url) => {
+            let user = env::var("BCHN_RPC_USER").unwrap_or_default();
+            let password = env::var("BCHN_RPC_PASSWORD").unwrap_or_default();
+            (Box::new(BchnRpc::new(url, user, password)), None)
+        }
+        Err(_) => {
+            // SOCKS5_PROXY=127.0.0.1:9050 also sends electrum through tor
+            let electrum_config = ElectrumConfig {
+                proxy: monero_config.proxy.clone(),
+                ..Default::default()
+            };
+            let electrum = TcpElectrum::connect_with(fullcrum_tcp, electrum_config)
+                .await
+                .unwrap();
+            (Box::new(electrum.clone()), Some(electrum))
+        }
+    };
 
     let state = Arc::new(AppState {
-        bch_server: bch_server.clone(),
+        bch_server,
         monerod,
-        monero_wallet,
+        monero_wallets,
+        xmr_scanner,
+        xmr_pool,
         bch_min_conf,
-        monero_network,
+        monero_config,
         bch_network,
         timelock1,
         timelock2,
+        xmr_confirmations,
+        xmr_overpayment,
+        token_policy,
+        operator_fee,
+        strict_contract,
+        bch_confirmations,
+        xmr_payout_address,
+        fresh_xmr_payout,
+        xmr_fee_priority,
+        xmr_lock_wallet,
+        view_wallet_retention,
+        watchdog,
+        store: FileStore::new("./.trades/checkpoints/server"),
+        approvals: admin::Approvals::from_env(),
+        rates: Mutex::new(RateHistory::new(1000)),
     });
 
-    tokio::spawn({
+    // ctrl-c stops the checks between two passes, then the trades are flushed
+    let shutdown = Shutdown::on_ctrl_c();
+
+    let xmr_checks = tokio::spawn({
         let state = state.clone();
+        let shutdown = shutdown.clone();
         async move {
             loop {
                 println!("Checking Wallet XMR...");
                 check_xmr_wallets(&state).await;
-                sleep(Duration::from_secs(20)).await;
+                if shutdown.sleep(Duration::from_secs(20)).await {
+                    break;
+                }
             }
         }
     });
 
-    tokio::spawn({
+    let bch_checks = tokio::spawn({
         let state = state.clone();
-        let mut receiver = state.bch_server.subscribe();
-        let _ = state
-            .bch_server
-            .send("blockchain.headers.subscribe", json!([]))
-            .await
-            .unwrap();
-
+        let shutdown = shutdown.clone();
         async move {
+            let electrum = match electrum {
+                Some(electrum) => electrum,
+                None => loop {
+                    println!("Checking Wallet BCH...");
+                    check_bch_wallets(&state).await;
+                    if shutdown.sleep(Duration::from_secs(20)).await {
+                        return;
+                    }
+                },
+            };
+
+            let mut receiver = electrum.subscribe();
+            let _ = electrum
+                .send("blockchain.headers.subscribe", json!([]))
+                .await
+                .unwrap();
+
             loop {
-                let data = receiver.recv().await.unwrap();
+                let notification = tokio::select! {
+                    notification = receiver.recv() => notification,
+                    _ = shutdown.triggered() => break,
+                };
+                let data = match notification {
+                    Some(Notification::Message(data)) => data,
+                    Some(Notification::ResyncRequired) => {
+                        println!("Missed notifications. Rescanning addresses");
+                        check_bch_wallets(&state).await;
+                        continue;
+                    }
+                    None => break,
+                };
                 let data: serde_json::Value = serde_json::from_str(&data).unwrap();
 
                 if data["method"].as_str().unwrap() != "blockchain.headers.subscribe" {
@@ -188,7 +513,9 @@ async fn main() {
         }
     });
 
-    let app = Router::new().nest("/trader", trader::trader(state));
+    let app = Router::new()
+        .nest("/trader", trader::trader(state.clone()))
+        .nest("/admin", admin::admin(state.clone()));
 
     let port = env::var("PORT").unwrap_or("8080".to_owned());
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
@@ -200,6 +527,30 @@ async fn main() {
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
     )
+    .with_graceful_shutdown({
+        let shutdown = shutdown.clone();
+        async move { shutdown.triggered().await }
+    })
     .await
     .unwrap();
+
+    // the passes in flight finish their broadcasts and saves
+    let _ = xmr_checks.await;
+    let _ = bch_checks.await;
+    let pending = flush_trades(&state).await;
+    state.monero_wallets.close_all().await;
+    if let Some(wallet) = &state.xmr_lock_wallet {
+        let _ = wallet.lock().await.close_wallet().await;
+    }
+    if pending.is_empty() {
+        println!("No trade left to resume");
+    }
+    for swap in pending {
+        println!("To resume: {swap}");
+    }
+
+    for wallet_rpc in wallet_rpcs {
+        println!("Stopping monero-wallet-rpc");
+        wallet_rpc.shutdown().await;
+    }
 }
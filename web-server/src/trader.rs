@@ -1,4 +1,9 @@
-use std::{fs, io::Write, net::SocketAddr};
+use std::{
+    fs,
+    io::Write,
+    net::SocketAddr,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use axum::{
     extract::{ConnectInfo, Path, State},
@@ -7,20 +12,40 @@ use axum::{
     Json, Router,
 };
 use protocol::{
+    alice::{self, Alice},
     bitcoincash,
+    blockchain::{fee::SPEND_TX_SIZE, policy::MempoolPolicy},
     bob::{self, Bob},
-    keys::{bitcoin::random_private_key, KeyPrivate},
+    contract::{fee_for_size, token::CashToken},
+    error::{Classify, ErrorClass, SwapError},
+    keys::bitcoin::random_private_key,
     monero,
-    persist::{Config, Error as PersistError, TradePersist},
-    protocol::{Swap, SwapEvents, SwapWrapper, Transition},
+    persist::{Config, Error as PersistError, TradePersist, SCHEMA_VERSION},
+    protocol::{
+        xmr_restore_height, OperatorFee, SignedTransition, Swap, SwapWrapper, Transition,
+        DEFAULT_MINING_FEE,
+    },
+    xmr_wallet::LockOptions,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    admin::Gate,
     utils::{random_str, ApiResult, Error, JsonRej},
     TAppState,
 };
 
+/// Rejected transitions are the client's fault, the rest can be sent again
+fn transition_error(e: SwapError) -> Error {
+    match e.class() {
+        ErrorClass::Fatal => Error::new(StatusCode::BAD_REQUEST, e.to_string()),
+        ErrorClass::Recoverable => {
+            eprintln!("Transition failed: {e}");
+            Error::new(StatusCode::SERVICE_UNAVAILABLE, "Try again later")
+        }
+    }
+}
+
 pub fn trader(state: TAppState) -> Router {
     Router::new()
         .route("/", post(create))
@@ -46,6 +71,27 @@ struct CreateRequest {
     xmr_amount: monero::Amount,
     timelock1: u32,
     timelock2: u32,
+    #[serde(default = "default_mining_fee")]
+    mining_fee: u64,
+    /// See `Swap::mining_fee_rate`
+    #[serde(default)]
+    mining_fee_rate: Option<u64>,
+    /// See `Swap::key_commitment`
+    #[serde(default)]
+    key_commitment: bool,
+    /// See `Swap::bch_token`
+    #[serde(default)]
+    bch_token: Option<CashToken>,
+    /// Must be the server's, see `Swap::operator_fee`
+    #[serde(default)]
+    operator_fee: Option<OperatorFee>,
+    /// See `Swap::refund_expiry`
+    #[serde(default)]
+    refund_expiry: Option<u32>,
+}
+
+fn default_mining_fee() -> u64 {
+    DEFAULT_MINING_FEE
 }
 
 #[derive(Debug, Serialize)]
@@ -66,6 +112,32 @@ async fn create(
         return Err(Error::new(StatusCode::FORBIDDEN, "Invalid timelock"));
     }
 
+    // part of the SwapLock, the contracts would not match
+    if request.operator_fee != state.operator_fee {
+        return Err(Error::new(StatusCode::FORBIDDEN, "Invalid operator fee"));
+    }
+
+    // an expiry already past lets Alice punish any refund right away
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    if request
+        .refund_expiry
+        .is_some_and(|expiry| u64::from(expiry) <= now)
+    {
+        return Err(Error::new(StatusCode::FORBIDDEN, "Invalid refund expiry"));
+    }
+
+    // both refund transactions pay the fee, bob must still get something back
+    let min_fee = MempoolPolicy::default().min_relay_fee * SPEND_TX_SIZE;
+    let mining_fee = match request.mining_fee_rate {
+        Some(rate) => fee_for_size(rate, SPEND_TX_SIZE),
+        None => request.mining_fee,
+    };
+    if mining_fee < min_fee || mining_fee * 10 > request.bch_amount.to_sat() {
+        return Err(Error::new(StatusCode::FORBIDDEN, "Invalid mining fee"));
+    }
+
     let trade_id = random_str(10);
 
     let (refund_priv, refund_script) = {
@@ -76,24 +148,51 @@ async fn create(
         (refund_priv, script)
     };
 
-    let swap = Swap {
-        id: trade_id.clone(),
-        keys: KeyPrivate::random(state.bch_network),
-        bch_amount: request.bch_amount,
-        xmr_amount: request.xmr_amount,
-        xmr_network: state.monero_network,
-        bch_network: state.bch_network,
-        bch_recv: refund_script,
-        timelock1: request.timelock1,
-        timelock2: request.timelock2,
+    // before anything of the trade can reach the chain
+    let xmr_restore_height = match state.monerod.block_count().await {
+        Ok(count) => xmr_restore_height(count),
+        Err(_) => {
+            return Err(Error::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Monero daemon unavailable",
+            ))
+        }
     };
 
+    let mut builder = Swap::builder(
+        trade_id.clone(),
+        state.bch_network,
+        state.monero_config.network.network(),
+    )
+    .amounts(request.bch_amount, request.xmr_amount)
+    .timelocks(request.timelock1, request.timelock2)
+    .bch_recv(refund_script)
+    .mining_fee(request.mining_fee)
+    .xmr_confirmations(state.xmr_confirmations)
+    .xmr_overpayment(state.xmr_overpayment)
+    .token_policy(state.token_policy)
+    .bch_token(request.bch_token)
+    .operator_fee(request.operator_fee)
+    .strict_contract(state.strict_contract)
+    .refund_expiry(request.refund_expiry)
+    .bch_confirmations(state.bch_confirmations)
+    .xmr_payout_address(state.xmr_payout_address)
+    .xmr_fee_priority(state.xmr_fee_priority)
+    .xmr_restore_height(xmr_restore_height)
+    .key_commitment(request.key_commitment);
+    if let Some(rate) = request.mining_fee_rate {
+        builder = builder.mining_fee_rate(rate);
+    }
+    if state.fresh_xmr_payout {
+        builder = builder.fresh_xmr_payout();
+    }
+    let swap = builder
+        .build()
+        .map_err(|e| Error::new(StatusCode::FORBIDDEN, e.to_string()))?;
+
     let swap = match request.path.as_str() {
-        // TODO:
-        // "bch->xmr" => SwapWrapper::Alice(Alice {
-        //     state: alice::State::Init,
-        //     swap,
-        // }),
+        // the server sells its XMR, the client locks the BCH
+        "bch->xmr" if state.xmr_lock_wallet.is_some() => SwapWrapper::Alice(Alice::new(swap)),
         "xmr->bch" => SwapWrapper::Bob(Bob::new(swap)),
         _ => {
             return Err(Error::new(
@@ -104,8 +203,11 @@ async fn create(
     };
 
     let serialized = serde_json::to_vec_pretty(&Config {
+        version: SCHEMA_VERSION,
         swap,
         refund_private_key: refund_priv,
+        accounting: Default::default(),
+        reorg: Default::default(),
     })?;
 
     fs::OpenOptions::new()
@@ -127,12 +229,14 @@ async fn create(
 #[derive(Serialize)]
 struct TransitionResponse {
     error: bool,
+    /// `Swap::swap_id` of our side, for the client to check it agrees
+    swap_id: Option<String>,
 }
 
 async fn transition(
     State(state): State<TAppState>,
     Path(trade_id): Path<String>,
-    JsonRej(request): JsonRej<Transition>,
+    JsonRej(request): JsonRej<SignedTransition>,
 ) -> ApiResult<Json<TransitionResponse>> {
     // ! we always open the file even on private transition
     // ! we can put a matcher here to reduce file opening
@@ -147,36 +251,110 @@ async fn transition(
         },
     };
 
-    match trade.config.swap {
+    // Bob funds the SwapLock right after a valid EncSig
+    let gate = match (&trade.config.swap, &request.transition) {
+        (SwapWrapper::Bob(inner), Transition::EncSig(_)) => match inner.state {
+            bob::State::ContractMatch(_) => {
+                let amount = inner.swap.bch_amount.to_sat();
+                Some(state.approvals.gate(&trade_id, amount).await)
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+
+    match gate {
+        Some(Gate::Pending) => {
+            return Err(Error::new(
+                StatusCode::ACCEPTED,
+                "Pending operator approval",
+            ))
+        }
+        Some(Gate::Expired) => {
+            trade.delete().await;
+            return Err(Error::new(
+                StatusCode::GONE,
+                "Operator approval timed out, trade cancelled",
+            ));
+        }
+        Some(Gate::Approved) | None => {}
+    }
+
+    let swap_id = match trade.config.swap {
         SwapWrapper::Bob(inner) => {
             let mut bob = bob::Runner {
                 inner,
+                monero_wallet: state.monero_wallets.get(&trade_id),
                 trade_id,
-                bch: &state.bch_server,
-                monero_wallet: &state.monero_wallet,
+                bch: state.bch_server.as_ref(),
                 monerod: &state.monerod,
+                xmr_scanner: state.xmr_scanner.as_ref(),
                 min_bch_conf: state.bch_min_conf,
+                mempool_policy: Some(MempoolPolicy::default()),
+                store: Some(&state.store),
+                events: None,
+                effects: None,
+                clock: None,
             };
-            bob.pub_transition(request).await?;
+            bob.pub_transition(request)
+                .await
+                .map_err(transition_error)?;
 
+            // SwapLock gets funded on VerifiedEncSig
+            let locked = matches!(bob.inner.state, bob::State::VerifiedEncSig(_));
+            if locked && trade.config.accounting.lock.is_none() {
+                trade.config.accounting.lock = state.rates.lock().await.latest();
+            }
+
+            let swap_id = bob.inner.swap.swap_id.clone();
             trade.config.swap = SwapWrapper::Bob(bob.inner);
             trade.save().await;
+            swap_id
         }
-        SwapWrapper::Alice(_) => {}
-    }
+        SwapWrapper::Alice(inner) => {
+            let mut alice = alice::Runner {
+                inner,
+                bch: state.bch_server.as_ref(),
+                monero_wallet: state.xmr_lock_wallet.as_ref(),
+                xmr_lock_options: LockOptions {
+                    priority: state.xmr_fee_priority,
+                    ring_size: None,
+                },
+                min_bch_conf: state.bch_min_conf,
+                zero_conf: false,
+                mempool_policy: Some(MempoolPolicy::default()),
+                store: Some(&state.store),
+                events: None,
+                effects: None,
+                clock: None,
+            };
+            alice
+                .pub_transition(request)
+                .await
+                .map_err(transition_error)?;
+
+            let swap_id = alice.inner.swap.swap_id.clone();
+            trade.config.swap = SwapWrapper::Alice(alice.inner);
+            trade.save().await;
+            swap_id
+        }
+    };
 
-    Ok(Json(TransitionResponse { error: false }))
+    Ok(Json(TransitionResponse {
+        error: false,
+        swap_id,
+    }))
 }
 
 // ==========================================
 // SECTION: Get Transition
 // ==========================================
 
-async fn get_transition(Path(trade_id): Path<String>) -> ApiResult<Json<Option<Transition>>> {
+async fn get_transition(Path(trade_id): Path<String>) -> ApiResult<Json<Option<SignedTransition>>> {
     match TradePersist::restore(get_file_path(&trade_id)).await {
         Ok(value) => match value.config.swap {
-            SwapWrapper::Alice(alice) => Ok(Json(alice.get_transition())),
-            SwapWrapper::Bob(bob) => Ok(Json(bob.get_transition())),
+            SwapWrapper::Alice(alice) => Ok(Json(alice.get_signed_transition())),
+            SwapWrapper::Bob(bob) => Ok(Json(bob.get_signed_transition())),
         },
         Err(e) => match e {
             PersistError::NotFound => {